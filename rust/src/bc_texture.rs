@@ -0,0 +1,884 @@
+// Software decoders for the BCn (S3TC / RGTC / BPTC) block compressed formats, for use
+// where the GPU lacks support for them, or for golden-image tests.
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use crate::gx_texture::{halfblend, s3tcblend};
+use crate::tegra_texture::CompressionType;
+use crate::color;
+use crate::util::{self, ByteReader, OutOfBounds};
+
+pub(crate) type Block = [[u8; 4]; 16];
+
+// Each call to `decode_block` reads one block from `src`.
+pub(crate) fn decode_blocks<T, F>(src: &[u8], w: usize, h: usize, decode_block: F) -> Result<Vec<T>, OutOfBounds>
+where
+    T: Copy + Default,
+    F: Fn(&mut ByteReader, &mut [[T; 4]; 16]) -> Result<(), OutOfBounds>,
+{
+    let mut src = ByteReader::le(src);
+    let mut dst = vec![T::default(); w * h * 4];
+    let mut block = [[T::default(); 4]; 16];
+
+    for yy in (0..h).step_by(4) {
+        for xx in (0..w).step_by(4) {
+            decode_block(&mut src, &mut block)?;
+
+            for y in 0..4 {
+                for x in 0..4 {
+                    if xx + x >= w || yy + y >= h {
+                        continue;
+                    }
+
+                    let dst_offs = ((yy + y) * w + (xx + x)) * 4;
+                    dst[dst_offs..dst_offs + 4].copy_from_slice(&block[y * 4 + x]);
+                }
+            }
+        }
+    }
+
+    Ok(dst)
+}
+
+fn expand_rgb565(p: u16) -> [u8; 4] {
+    [
+        util::expand_n_to_8(5, ((p >> 11) & 0x1F) as u8),
+        util::expand_n_to_8(6, ((p >>  5) & 0x3F) as u8),
+        util::expand_n_to_8(5, (p & 0x1F) as u8),
+        0xFF,
+    ]
+}
+
+fn color_table_bc1(color1: u16, color2: u16, allow_alpha: bool) -> [[u8; 4]; 4] {
+    let c0 = expand_rgb565(color1);
+    let c1 = expand_rgb565(color2);
+
+    if color1 > color2 || !allow_alpha {
+        // Predict gradients.
+        let c2 = [s3tcblend(c1[0], c0[0]), s3tcblend(c1[1], c0[1]), s3tcblend(c1[2], c0[2]), 0xFF];
+        let c3 = [s3tcblend(c0[0], c1[0]), s3tcblend(c0[1], c1[1]), s3tcblend(c0[2], c1[2]), 0xFF];
+        [c0, c1, c2, c3]
+    } else {
+        let c2 = [halfblend(c0[0], c1[0]), halfblend(c0[1], c1[1]), halfblend(c0[2], c1[2]), 0xFF];
+        [c0, c1, c2, [0x00; 4]]
+    }
+}
+
+fn decode_color_block(src: &mut ByteReader, dst: &mut Block, allow_alpha: bool) -> Result<(), OutOfBounds> {
+    let color1 = src.read_u16()?;
+    let color2 = src.read_u16()?;
+    let color_table = color_table_bc1(color1, color2, allow_alpha);
+    let mut bits = src.read_u32()?;
+    for px in dst.iter_mut() {
+        let color = &color_table[(bits & 0x03) as usize];
+        if allow_alpha {
+            px.copy_from_slice(color);
+        } else {
+            px[0..3].copy_from_slice(&color[0..3]);
+        }
+        bits >>= 2;
+    }
+    Ok(())
+}
+
+// The BC3 alpha / BC4 / BC5 channel block: two 8-bit endpoints and 3-bit indices.
+fn decode_channel_block(src: &mut ByteReader, dst: &mut Block, channel: usize, is_signed: bool) -> Result<(), OutOfBounds> {
+    let mut table = [0i32; 8];
+    let (e0, e1) = if is_signed {
+        (src.read_i8()? as i32, src.read_i8()? as i32)
+    } else {
+        (src.read_u8()? as i32, src.read_u8()? as i32)
+    };
+    table[0] = e0;
+    table[1] = e1;
+    if e0 > e1 {
+        for i in 1..7 {
+            table[i + 1] = ((7 - i as i32) * e0 + (i as i32) * e1) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            table[i + 1] = ((5 - i as i32) * e0 + (i as i32) * e1) / 5;
+        }
+        table[6] = if is_signed { -128 } else { 0 };
+        table[7] = if is_signed { 127 } else { 255 };
+    }
+
+    let bits = src.read_u16()? as u64 | (src.read_u32()? as u64) << 16;
+    for (i, px) in dst.iter_mut().enumerate() {
+        px[channel] = table[((bits >> (i * 3)) & 0x07) as usize] as u8;
+    }
+    Ok(())
+}
+
+fn decode_bc1_block(src: &mut ByteReader, dst: &mut Block) -> Result<(), OutOfBounds> {
+    decode_color_block(src, dst, true)
+}
+
+fn decode_bc2_block(src: &mut ByteReader, dst: &mut Block) -> Result<(), OutOfBounds> {
+    let alpha_bits = src.read_u64()?;
+    for (i, px) in dst.iter_mut().enumerate() {
+        px[3] = util::expand_n_to_8(4, ((alpha_bits >> (i * 4)) & 0x0F) as u8);
+    }
+    decode_color_block(src, dst, false)
+}
+
+fn decode_bc3_block(src: &mut ByteReader, dst: &mut Block) -> Result<(), OutOfBounds> {
+    decode_channel_block(src, dst, 3, false)?;
+    decode_color_block(src, dst, false)
+}
+
+fn decode_bc4_block(src: &mut ByteReader, dst: &mut Block, is_signed: bool) -> Result<(), OutOfBounds> {
+    decode_channel_block(src, dst, 0, is_signed)?;
+    for px in dst.iter_mut() {
+        px[1] = px[0];
+        px[2] = px[0];
+        px[3] = if is_signed { 0x7F } else { 0xFF };
+    }
+    Ok(())
+}
+
+fn decode_bc5_block(src: &mut ByteReader, dst: &mut Block, is_signed: bool) -> Result<(), OutOfBounds> {
+    decode_channel_block(src, dst, 0, is_signed)?;
+    decode_channel_block(src, dst, 1, is_signed)?;
+    let one = if is_signed { 127 } else { 255 };
+    for px in dst.iter_mut() {
+        px[2] = one;
+        px[3] = one;
+    }
+    Ok(())
+}
+
+// BC7 mode descriptions, from the BPTC specification.
+struct Bc7Mode {
+    num_subsets: usize,
+    partition_bits: usize,
+    rotation_bits: usize,
+    index_selection_bits: usize,
+    color_bits: usize,
+    alpha_bits: usize,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: usize,
+    index_bits2: usize,
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { num_subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_pbits: true,  shared_pbits: false, index_bits: 3, index_bits2: 0 },
+    Bc7Mode { num_subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_pbits: false, shared_pbits: true,  index_bits: 3, index_bits2: 0 },
+    Bc7Mode { num_subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { num_subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_pbits: true,  shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { num_subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 3 },
+    Bc7Mode { num_subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 2 },
+    Bc7Mode { num_subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_pbits: true,  shared_pbits: false, index_bits: 4, index_bits2: 0 },
+    Bc7Mode { num_subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_pbits: true,  shared_pbits: false, index_bits: 2, index_bits2: 0 },
+];
+
+// Two-subset partitions; bit N is the subset of pixel N.
+const BC7_PARTITIONS2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80,
+    0xC800, 0xFFEC, 0xFE80, 0xE800, 0xFFE8, 0xFF00, 0xFFF0, 0xF000,
+    0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C,
+    0xAAAA, 0xF0F0, 0x5A5A, 0x33CC, 0x3C3C, 0x55AA, 0x9696, 0xA55A,
+    0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C,
+    0x9336, 0x9CC6, 0x817E, 0xE718, 0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+const BC7_PARTITIONS3: [[u8; 16]; 64] = [
+    [0,0,1,1,0,0,1,1,0,2,2,1,2,2,2,2], [0,0,0,1,0,0,1,1,2,2,1,1,2,2,2,1],
+    [0,0,0,0,2,0,0,1,2,2,1,1,2,2,1,1], [0,2,2,2,0,0,2,2,0,0,1,1,0,1,1,1],
+    [0,0,0,0,0,0,0,0,1,1,2,2,1,1,2,2], [0,0,1,1,0,0,1,1,0,0,2,2,0,0,2,2],
+    [0,0,2,2,0,0,2,2,1,1,1,1,1,1,1,1], [0,0,1,1,0,0,1,1,2,2,1,1,2,2,1,1],
+    [0,0,0,0,0,0,0,0,1,1,1,1,2,2,2,2], [0,0,0,0,1,1,1,1,1,1,1,1,2,2,2,2],
+    [0,0,0,0,1,1,1,1,2,2,2,2,2,2,2,2], [0,0,1,2,0,0,1,2,0,0,1,2,0,0,1,2],
+    [0,1,1,2,0,1,1,2,0,1,1,2,0,1,1,2], [0,1,2,2,0,1,2,2,0,1,2,2,0,1,2,2],
+    [0,0,1,1,0,1,1,2,1,1,2,2,1,2,2,2], [0,0,1,1,2,0,0,1,2,2,0,0,2,2,2,0],
+    [0,0,0,1,0,0,1,1,0,1,1,2,1,1,2,2], [0,1,1,1,0,0,1,1,2,0,0,1,2,2,0,0],
+    [0,0,0,0,1,1,2,2,1,1,2,2,1,1,2,2], [0,0,2,2,0,0,2,2,0,0,2,2,1,1,1,1],
+    [0,1,1,1,0,1,1,1,0,2,2,2,0,2,2,2], [0,0,0,1,0,0,0,1,2,2,2,1,2,2,2,1],
+    [0,0,0,0,0,0,1,1,0,1,2,2,0,1,2,2], [0,0,0,0,1,1,0,0,2,2,1,0,2,2,1,0],
+    [0,1,2,2,0,1,2,2,0,0,1,1,0,0,0,0], [0,0,1,2,0,0,1,2,1,1,2,2,2,2,2,2],
+    [0,1,1,0,1,2,2,1,1,2,2,1,0,1,1,0], [0,0,0,0,0,1,1,0,1,2,2,1,1,2,2,1],
+    [0,0,2,2,1,1,0,2,1,1,0,2,0,0,2,2], [0,1,1,0,0,1,1,0,2,0,0,2,2,2,2,2],
+    [0,0,1,1,0,1,2,2,0,1,2,2,0,0,1,1], [0,0,0,0,2,0,0,0,2,2,1,1,2,2,2,1],
+    [0,0,0,0,0,0,0,2,1,1,2,2,1,2,2,2], [0,2,2,2,0,0,2,2,0,0,1,2,0,0,1,1],
+    [0,0,1,1,0,0,1,2,0,0,2,2,0,2,2,2], [0,1,2,0,0,1,2,0,0,1,2,0,0,1,2,0],
+    [0,0,0,0,1,1,1,1,2,2,2,2,0,0,0,0], [0,1,2,0,1,2,0,1,2,0,1,2,0,1,2,0],
+    [0,1,2,0,2,0,1,2,1,2,0,1,0,1,2,0], [0,0,1,1,2,2,0,0,1,1,2,2,0,0,1,1],
+    [0,0,1,1,1,1,2,2,2,2,0,0,0,0,1,1], [0,1,0,1,0,1,0,1,2,2,2,2,2,2,2,2],
+    [0,0,0,0,0,0,0,0,2,1,2,1,2,1,2,1], [0,0,2,2,1,1,2,2,0,0,2,2,1,1,2,2],
+    [0,0,2,2,0,0,1,1,0,0,2,2,0,0,1,1], [0,2,2,0,1,2,2,1,0,2,2,0,1,2,2,1],
+    [0,1,0,1,2,2,2,2,2,2,2,2,0,1,0,1], [0,0,0,0,2,1,2,1,2,1,2,1,2,1,2,1],
+    [0,1,0,1,0,1,0,1,0,1,0,1,2,2,2,2], [0,2,2,2,0,1,1,1,0,2,2,2,0,1,1,1],
+    [0,0,0,2,1,1,1,2,0,0,0,2,1,1,1,2], [0,0,0,0,2,1,1,2,2,1,1,2,2,1,1,2],
+    [0,2,2,2,0,1,1,1,0,1,1,1,0,2,2,2], [0,0,0,2,1,1,1,2,1,1,1,2,0,0,0,2],
+    [0,1,1,0,0,1,1,0,0,1,1,0,2,2,2,2], [0,0,0,0,0,0,0,0,2,1,1,2,2,1,1,2],
+    [0,1,1,0,0,1,1,0,2,2,2,2,2,2,2,2], [0,0,2,2,0,0,1,1,0,0,1,1,0,0,2,2],
+    [0,0,2,2,1,1,2,2,1,1,2,2,0,0,2,2], [0,0,0,0,0,0,0,0,0,0,0,0,2,1,1,2],
+    [0,0,0,2,0,0,0,1,0,0,0,2,0,0,0,1], [0,2,2,2,1,2,2,2,0,2,2,2,1,2,2,2],
+    [0,1,0,1,2,2,2,2,2,2,2,2,2,2,2,2], [0,1,1,1,2,0,1,1,2,2,0,1,2,2,2,0],
+];
+
+const BC7_ANCHORS2: [u8; 64] = [
+    15,15,15,15,15,15,15,15, 15,15,15,15,15,15,15,15,
+    15, 2, 8, 2, 2, 8, 8,15,  2, 8, 2, 2, 8, 8, 2, 2,
+    15,15, 6, 8, 2, 8,15,15,  2, 8, 2, 2, 2,15,15, 6,
+     6, 2, 6, 8,15,15, 2, 2, 15,15,15,15,15, 2, 2,15,
+];
+
+const BC7_ANCHORS3_1: [u8; 64] = [
+     3, 3,15,15, 8, 3,15,15,  8, 8, 6, 6, 6, 5, 3, 3,
+     3, 3, 8,15, 3, 3, 6,10,  5, 8, 8, 6, 8, 5,15,15,
+     8,15, 3, 5, 6,10, 8,15, 15, 3,15, 5,15,15,15,15,
+     3,15, 5, 5, 5, 8, 5,10,  5,10, 8,13,15,12, 3, 3,
+];
+
+const BC7_ANCHORS3_2: [u8; 64] = [
+    15, 8, 8, 3,15,15, 3, 8, 15,15,15,15,15,15,15, 8,
+    15, 8,15, 3,15, 8,15, 8,  3,15, 6,10,15,15,10, 8,
+    15, 3,15,10,10, 8, 9,10,  6,15, 8,15, 3, 6, 6, 8,
+    15, 3,15,15,15,15,15,15, 15,15,15,15, 3,15,15, 8,
+];
+
+const BC7_WEIGHTS2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn bc7_subset(num_subsets: usize, partition: usize, px: usize) -> usize {
+    match num_subsets {
+        1 => 0,
+        2 => ((BC7_PARTITIONS2[partition] >> px) & 0x01) as usize,
+        3 => BC7_PARTITIONS3[partition][px] as usize,
+        _ => unreachable!(),
+    }
+}
+
+fn bc7_is_anchor(num_subsets: usize, partition: usize, px: usize) -> bool {
+    px == 0 || match num_subsets {
+        1 => false,
+        2 => px == BC7_ANCHORS2[partition] as usize,
+        3 => px == BC7_ANCHORS3_1[partition] as usize || px == BC7_ANCHORS3_2[partition] as usize,
+        _ => unreachable!(),
+    }
+}
+
+fn bc7_interpolate(e0: u8, e1: u8, index: usize, index_bits: usize) -> u8 {
+    let w = match index_bits {
+        2 => BC7_WEIGHTS2[index],
+        3 => BC7_WEIGHTS3[index],
+        4 => BC7_WEIGHTS4[index],
+        _ => unreachable!(),
+    };
+    (((64 - w) * (e0 as u32) + w * (e1 as u32) + 32) >> 6) as u8
+}
+
+struct BitReader {
+    bits: u128,
+}
+
+impl BitReader {
+    fn read_block(src: &mut ByteReader) -> Result<BitReader, OutOfBounds> {
+        let lo = src.read_u64()? as u128;
+        let hi = src.read_u64()? as u128;
+        Ok(BitReader { bits: lo | hi << 64 })
+    }
+
+    fn read(&mut self, n: usize) -> u32 {
+        let v = (self.bits & ((1u128 << n) - 1)) as u32;
+        self.bits >>= n;
+        v
+    }
+}
+
+fn decode_bc7_block(src: &mut ByteReader, dst: &mut Block) -> Result<(), OutOfBounds> {
+    let mut bits = BitReader::read_block(src)?;
+
+    let mode_byte = bits.bits as u8;
+    if mode_byte == 0 {
+        // Reserved mode; decodes to transparent black.
+        dst.iter_mut().for_each(|px| *px = [0x00; 4]);
+        return Ok(());
+    }
+    let mode_index = mode_byte.trailing_zeros() as usize;
+    let mode = &BC7_MODES[mode_index];
+    bits.read(mode_index + 1);
+
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    // endpoints[subset * 2 + i][channel]
+    let num_endpoints = mode.num_subsets * 2;
+    let mut endpoints = [[0u8; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in endpoints[0..num_endpoints].iter_mut() {
+            endpoint[channel] = bits.read(mode.color_bits) as u8;
+        }
+    }
+    for endpoint in endpoints[0..num_endpoints].iter_mut() {
+        endpoint[3] = if mode.alpha_bits > 0 { bits.read(mode.alpha_bits) as u8 } else { 0xFF };
+    }
+
+    let mut pbits = [0u8; 6];
+    if mode.endpoint_pbits {
+        for pbit in pbits[0..num_endpoints].iter_mut() {
+            *pbit = bits.read(1) as u8;
+        }
+    } else if mode.shared_pbits {
+        for subset in 0..mode.num_subsets {
+            let pbit = bits.read(1) as u8;
+            pbits[subset * 2] = pbit;
+            pbits[subset * 2 + 1] = pbit;
+        }
+    }
+
+    let has_pbits = mode.endpoint_pbits || mode.shared_pbits;
+    for (endpoint, pbit) in endpoints[0..num_endpoints].iter_mut().zip(pbits.iter()) {
+        for (channel, value) in endpoint.iter_mut().enumerate() {
+            let mut n = if channel < 3 { mode.color_bits } else { mode.alpha_bits };
+            if n == 0 {
+                continue;
+            }
+            if has_pbits {
+                *value = (*value << 1) | pbit;
+                n += 1;
+            }
+            if n < 8 {
+                *value = util::expand_n_to_8(n as u8, *value);
+            }
+        }
+    }
+
+    let mut indices = [0usize; 16];
+    for (px, index) in indices.iter_mut().enumerate() {
+        let n = if bc7_is_anchor(mode.num_subsets, partition, px) { mode.index_bits - 1 } else { mode.index_bits };
+        *index = bits.read(n) as usize;
+    }
+
+    let mut indices2 = [0usize; 16];
+    if mode.index_bits2 > 0 {
+        for (px, index) in indices2.iter_mut().enumerate() {
+            let n = if px == 0 { mode.index_bits2 - 1 } else { mode.index_bits2 };
+            *index = bits.read(n) as usize;
+        }
+    }
+
+    for (px, dst_px) in dst.iter_mut().enumerate() {
+        let subset = bc7_subset(mode.num_subsets, partition, px);
+        let e0 = &endpoints[subset * 2];
+        let e1 = &endpoints[subset * 2 + 1];
+
+        let (color_index, color_bits, alpha_index, alpha_bits) = if mode.index_bits2 == 0 {
+            (indices[px], mode.index_bits, indices[px], mode.index_bits)
+        } else if index_selection == 0 {
+            (indices[px], mode.index_bits, indices2[px], mode.index_bits2)
+        } else {
+            (indices2[px], mode.index_bits2, indices[px], mode.index_bits)
+        };
+
+        for channel in 0..3 {
+            dst_px[channel] = bc7_interpolate(e0[channel], e1[channel], color_index, color_bits);
+        }
+        dst_px[3] = bc7_interpolate(e0[3], e1[3], alpha_index, alpha_bits);
+
+        match rotation {
+            1 => dst_px.swap(0, 3),
+            2 => dst_px.swap(1, 3),
+            3 => dst_px.swap(2, 3),
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+// The BC6H endpoint fields: W and X are the first subset's endpoints, Y and Z the second's.
+#[derive(Clone, Copy)]
+enum Bc6hField { Rw, Gw, Bw, Rx, Gx, Bx, Ry, Gy, By, Rz, Gz, Bz }
+
+use Bc6hField::*;
+
+// BC6H mode descriptions, from the BC6H specification. `layout` lists where each run of header
+// bits after the mode number goes, as (field, first bit, bit count). Modes 13 and 14 store the
+// top bits of W reversed, so those are listed a bit at a time.
+struct Bc6hMode {
+    mode: u32,
+    num_subsets: usize,
+    transformed: bool,
+    endpoint_bits: u32,
+    delta_bits: [u32; 3],
+    layout: &'static [(Bc6hField, u8, u8)],
+}
+
+const BC6H_MODES: [Bc6hMode; 14] = [
+    Bc6hMode { mode: 0x00, num_subsets: 2, transformed: true, endpoint_bits: 10, delta_bits: [5, 5, 5], layout: &[
+        (Gy, 4, 1), (By, 4, 1), (Bz, 4, 1), (Rw, 0, 10), (Gw, 0, 10), (Bw, 0, 10), (Rx, 0, 5), (Gz, 4, 1), (Gy, 0, 4),
+        (Gx, 0, 5), (Bz, 0, 1), (Gz, 0, 4), (Bx, 0, 5), (Bz, 1, 1), (By, 0, 4), (Ry, 0, 5), (Bz, 2, 1), (Rz, 0, 5), (Bz, 3, 1),
+    ] },
+    Bc6hMode { mode: 0x01, num_subsets: 2, transformed: true, endpoint_bits: 7, delta_bits: [6, 6, 6], layout: &[
+        (Gy, 5, 1), (Gz, 4, 1), (Gz, 5, 1), (Rw, 0, 7), (Bz, 0, 1), (Bz, 1, 1), (By, 4, 1), (Gw, 0, 7), (By, 5, 1), (Bz, 2, 1),
+        (Gy, 4, 1), (Bw, 0, 7), (Bz, 3, 1), (Bz, 5, 1), (Bz, 4, 1), (Rx, 0, 6), (Gy, 0, 4), (Gx, 0, 6), (Gz, 0, 4), (Bx, 0, 6),
+        (By, 0, 4), (Ry, 0, 6), (Rz, 0, 6),
+    ] },
+    Bc6hMode { mode: 0x02, num_subsets: 2, transformed: true, endpoint_bits: 11, delta_bits: [5, 4, 4], layout: &[
+        (Rw, 0, 10), (Gw, 0, 10), (Bw, 0, 10), (Rx, 0, 5), (Rw, 10, 1), (Gy, 0, 4), (Gx, 0, 4), (Gw, 10, 1), (Bz, 0, 1),
+        (Gz, 0, 4), (Bx, 0, 4), (Bw, 10, 1), (Bz, 1, 1), (By, 0, 4), (Ry, 0, 5), (Bz, 2, 1), (Rz, 0, 5), (Bz, 3, 1),
+    ] },
+    Bc6hMode { mode: 0x06, num_subsets: 2, transformed: true, endpoint_bits: 11, delta_bits: [4, 5, 4], layout: &[
+        (Rw, 0, 10), (Gw, 0, 10), (Bw, 0, 10), (Rx, 0, 4), (Rw, 10, 1), (Gz, 4, 1), (Gy, 0, 4), (Gx, 0, 5), (Gw, 10, 1),
+        (Gz, 0, 4), (Bx, 0, 4), (Bw, 10, 1), (Bz, 1, 1), (By, 0, 4), (Ry, 0, 4), (Bz, 0, 1), (Bz, 2, 1), (Rz, 0, 4), (Gy, 4, 1),
+        (Bz, 3, 1),
+    ] },
+    Bc6hMode { mode: 0x0A, num_subsets: 2, transformed: true, endpoint_bits: 11, delta_bits: [4, 4, 5], layout: &[
+        (Rw, 0, 10), (Gw, 0, 10), (Bw, 0, 10), (Rx, 0, 4), (Rw, 10, 1), (By, 4, 1), (Gy, 0, 4), (Gx, 0, 4), (Gw, 10, 1),
+        (Bz, 0, 1), (Gz, 0, 4), (Bx, 0, 5), (Bw, 10, 1), (By, 0, 4), (Ry, 0, 4), (Bz, 1, 1), (Bz, 2, 1), (Rz, 0, 4), (Bz, 4, 1),
+        (Bz, 3, 1),
+    ] },
+    Bc6hMode { mode: 0x0E, num_subsets: 2, transformed: true, endpoint_bits: 9, delta_bits: [5, 5, 5], layout: &[
+        (Rw, 0, 9), (By, 4, 1), (Gw, 0, 9), (Gy, 4, 1), (Bw, 0, 9), (Bz, 4, 1), (Rx, 0, 5), (Gz, 4, 1), (Gy, 0, 4), (Gx, 0, 5),
+        (Bz, 0, 1), (Gz, 0, 4), (Bx, 0, 5), (Bz, 1, 1), (By, 0, 4), (Ry, 0, 5), (Bz, 2, 1), (Rz, 0, 5), (Bz, 3, 1),
+    ] },
+    Bc6hMode { mode: 0x12, num_subsets: 2, transformed: true, endpoint_bits: 8, delta_bits: [6, 5, 5], layout: &[
+        (Rw, 0, 8), (Gz, 4, 1), (By, 4, 1), (Gw, 0, 8), (Bz, 2, 1), (Gy, 4, 1), (Bw, 0, 8), (Bz, 3, 1), (Bz, 4, 1), (Rx, 0, 6),
+        (Gy, 0, 4), (Gx, 0, 5), (Bz, 0, 1), (Gz, 0, 4), (Bx, 0, 5), (Bz, 1, 1), (By, 0, 4), (Ry, 0, 6), (Rz, 0, 6),
+    ] },
+    Bc6hMode { mode: 0x16, num_subsets: 2, transformed: true, endpoint_bits: 8, delta_bits: [5, 6, 5], layout: &[
+        (Rw, 0, 8), (Bz, 0, 1), (By, 4, 1), (Gw, 0, 8), (Gy, 5, 1), (Gy, 4, 1), (Bw, 0, 8), (Gz, 5, 1), (Bz, 4, 1), (Rx, 0, 5),
+        (Gz, 4, 1), (Gy, 0, 4), (Gx, 0, 6), (Gz, 0, 4), (Bx, 0, 5), (Bz, 1, 1), (By, 0, 4), (Ry, 0, 5), (Bz, 2, 1), (Rz, 0, 5),
+        (Bz, 3, 1),
+    ] },
+    Bc6hMode { mode: 0x1A, num_subsets: 2, transformed: true, endpoint_bits: 8, delta_bits: [5, 5, 6], layout: &[
+        (Rw, 0, 8), (Bz, 1, 1), (By, 4, 1), (Gw, 0, 8), (By, 5, 1), (Gy, 4, 1), (Bw, 0, 8), (Bz, 5, 1), (Bz, 4, 1), (Rx, 0, 5),
+        (Gz, 4, 1), (Gy, 0, 4), (Gx, 0, 5), (Bz, 0, 1), (Gz, 0, 4), (Bx, 0, 6), (By, 0, 4), (Ry, 0, 5), (Bz, 2, 1), (Rz, 0, 5),
+        (Bz, 3, 1),
+    ] },
+    Bc6hMode { mode: 0x1E, num_subsets: 2, transformed: false, endpoint_bits: 6, delta_bits: [6, 6, 6], layout: &[
+        (Rw, 0, 6), (Gz, 4, 1), (Bz, 0, 1), (Bz, 1, 1), (By, 4, 1), (Gw, 0, 6), (Gy, 5, 1), (By, 5, 1), (Bz, 2, 1), (Gy, 4, 1),
+        (Bw, 0, 6), (Gz, 5, 1), (Bz, 3, 1), (Bz, 5, 1), (Bz, 4, 1), (Rx, 0, 6), (Gy, 0, 4), (Gx, 0, 6), (Gz, 0, 4), (Bx, 0, 6),
+        (By, 0, 4), (Ry, 0, 6), (Rz, 0, 6),
+    ] },
+    Bc6hMode { mode: 0x03, num_subsets: 1, transformed: false, endpoint_bits: 10, delta_bits: [10, 10, 10], layout: &[
+        (Rw, 0, 10), (Gw, 0, 10), (Bw, 0, 10), (Rx, 0, 10), (Gx, 0, 10), (Bx, 0, 10),
+    ] },
+    Bc6hMode { mode: 0x07, num_subsets: 1, transformed: true, endpoint_bits: 11, delta_bits: [9, 9, 9], layout: &[
+        (Rw, 0, 10), (Gw, 0, 10), (Bw, 0, 10), (Rx, 0, 9), (Rw, 10, 1), (Gx, 0, 9), (Gw, 10, 1), (Bx, 0, 9), (Bw, 10, 1),
+    ] },
+    Bc6hMode { mode: 0x0B, num_subsets: 1, transformed: true, endpoint_bits: 12, delta_bits: [8, 8, 8], layout: &[
+        (Rw, 0, 10), (Gw, 0, 10), (Bw, 0, 10), (Rx, 0, 8), (Rw, 11, 1), (Rw, 10, 1), (Gx, 0, 8), (Gw, 11, 1), (Gw, 10, 1),
+        (Bx, 0, 8), (Bw, 11, 1), (Bw, 10, 1),
+    ] },
+    Bc6hMode { mode: 0x0F, num_subsets: 1, transformed: true, endpoint_bits: 16, delta_bits: [4, 4, 4], layout: &[
+        (Rw, 0, 10), (Gw, 0, 10), (Bw, 0, 10),
+        (Rx, 0, 4), (Rw, 15, 1), (Rw, 14, 1), (Rw, 13, 1), (Rw, 12, 1), (Rw, 11, 1), (Rw, 10, 1),
+        (Gx, 0, 4), (Gw, 15, 1), (Gw, 14, 1), (Gw, 13, 1), (Gw, 12, 1), (Gw, 11, 1), (Gw, 10, 1),
+        (Bx, 0, 4), (Bw, 15, 1), (Bw, 14, 1), (Bw, 13, 1), (Bw, 12, 1), (Bw, 11, 1), (Bw, 10, 1),
+    ] },
+];
+
+const HALF_ONE: u16 = 0x3C00;
+
+fn sign_extend(value: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    (value << shift) >> shift
+}
+
+// Scales an endpoint up to 16 bits (unsigned) or 15 bits plus sign.
+fn bc6h_unquantize(value: i32, bits: u32, is_signed: bool) -> i32 {
+    if !is_signed {
+        if bits >= 15 || value == 0 {
+            value
+        } else if value == (1 << bits) - 1 {
+            0xFFFF
+        } else {
+            ((value << 16) + 0x8000) >> bits
+        }
+    } else {
+        if bits >= 16 || value == 0 {
+            return value;
+        }
+        let magnitude = value.abs();
+        let unq = if magnitude >= (1 << (bits - 1)) - 1 {
+            0x7FFF
+        } else {
+            ((magnitude << 15) + 0x4000) >> (bits - 1)
+        };
+        if value < 0 { -unq } else { unq }
+    }
+}
+
+// Scales an interpolated value into the finite half float range and returns its bits.
+fn bc6h_finish_unquantize(value: i32, is_signed: bool) -> u16 {
+    if !is_signed {
+        ((value * 31) >> 6) as u16
+    } else {
+        let magnitude = ((value.abs() * 31) >> 5) as u16;
+        if value < 0 { 0x8000 | magnitude } else { magnitude }
+    }
+}
+
+// Decodes to RGBA half floats, with alpha 1.
+fn decode_bc6h_block(src: &mut ByteReader, dst: &mut [[u16; 4]; 16], is_signed: bool) -> Result<(), OutOfBounds> {
+    let mut bits = BitReader::read_block(src)?;
+
+    let mut mode_number = bits.read(2);
+    if mode_number > 1 {
+        mode_number |= bits.read(3) << 2;
+    }
+    let mode = match BC6H_MODES.iter().find(|mode| mode.mode == mode_number) {
+        Some(mode) => mode,
+        None => {
+            // Reserved mode; decodes to black.
+            dst.iter_mut().for_each(|px| *px = [0, 0, 0, HALF_ONE]);
+            return Ok(());
+        },
+    };
+
+    // endpoints[endpoint * 3 + channel], in the order W, X, Y, Z.
+    let mut endpoints = [0i32; 12];
+    for &(field, first_bit, n) in mode.layout {
+        endpoints[field as usize] |= (bits.read(n as usize) as i32) << first_bit;
+    }
+    let partition = if mode.num_subsets == 2 { bits.read(5) as usize } else { 0 };
+
+    let num_values = mode.num_subsets * 2 * 3;
+    if is_signed {
+        for value in endpoints[0..3].iter_mut() {
+            *value = sign_extend(*value, mode.endpoint_bits);
+        }
+    }
+    if mode.transformed || is_signed {
+        for (i, value) in endpoints[3..num_values].iter_mut().enumerate() {
+            *value = sign_extend(*value, mode.delta_bits[i % 3]);
+        }
+    }
+    if mode.transformed {
+        // The other endpoints are stored as deltas from W.
+        let mask = (1 << mode.endpoint_bits) - 1;
+        for i in 3..num_values {
+            endpoints[i] = (endpoints[i] + endpoints[i % 3]) & mask;
+            if is_signed {
+                endpoints[i] = sign_extend(endpoints[i], mode.endpoint_bits);
+            }
+        }
+    }
+    for value in endpoints[0..num_values].iter_mut() {
+        *value = bc6h_unquantize(*value, mode.endpoint_bits, is_signed);
+    }
+
+    let index_bits = if mode.num_subsets == 2 { 3 } else { 4 };
+    for (px, dst_px) in dst.iter_mut().enumerate() {
+        let subset = bc7_subset(mode.num_subsets, partition, px);
+        let n = if bc7_is_anchor(mode.num_subsets, partition, px) { index_bits - 1 } else { index_bits };
+        let index = bits.read(n) as usize;
+        let w = if mode.num_subsets == 2 { BC7_WEIGHTS3[index] } else { BC7_WEIGHTS4[index] } as i32;
+
+        let e0 = &endpoints[subset * 6..subset * 6 + 3];
+        let e1 = &endpoints[subset * 6 + 3..subset * 6 + 6];
+        for channel in 0..3 {
+            let value = ((64 - w) * e0[channel] + w * e1[channel] + 32) >> 6;
+            dst_px[channel] = bc6h_finish_unquantize(value, is_signed);
+        }
+        dst_px[3] = HALF_ONE;
+    }
+    Ok(())
+}
+
+pub fn decode_bc1(src: &[u8], w: usize, h: usize) -> Result<Vec<u8>, OutOfBounds> {
+    decode_blocks(src, w, h, decode_bc1_block)
+}
+
+pub fn decode_bc2(src: &[u8], w: usize, h: usize) -> Result<Vec<u8>, OutOfBounds> {
+    decode_blocks(src, w, h, decode_bc2_block)
+}
+
+pub fn decode_bc3(src: &[u8], w: usize, h: usize) -> Result<Vec<u8>, OutOfBounds> {
+    decode_blocks(src, w, h, decode_bc3_block)
+}
+
+// Signed data is returned as two's complement bytes.
+pub fn decode_bc4(src: &[u8], w: usize, h: usize, is_signed: bool) -> Result<Vec<u8>, OutOfBounds> {
+    decode_blocks(src, w, h, |src, dst| decode_bc4_block(src, dst, is_signed))
+}
+
+// Signed data is returned as two's complement bytes.
+pub fn decode_bc5(src: &[u8], w: usize, h: usize, is_signed: bool) -> Result<Vec<u8>, OutOfBounds> {
+    decode_blocks(src, w, h, |src, dst| decode_bc5_block(src, dst, is_signed))
+}
+
+// RGBA half floats. Signed data keeps its sign bit.
+pub fn decode_bc6h(src: &[u8], w: usize, h: usize, is_signed: bool) -> Result<Vec<u16>, OutOfBounds> {
+    decode_blocks(src, w, h, |src, dst| decode_bc6h_block(src, dst, is_signed))
+}
+
+pub fn decode_bc7(src: &[u8], w: usize, h: usize) -> Result<Vec<u8>, OutOfBounds> {
+    decode_blocks(src, w, h, decode_bc7_block)
+}
+
+// Decodes linear (already deswizzled) BCn data to RGBA8.
+#[wasm_bindgen]
+pub fn decode_bc(compression_type: CompressionType, src: &[u8], w: usize, h: usize, is_signed: bool) -> Result<Vec<u8>, String> {
+    let bytes_per_block = crate::tegra_texture::get_format_bytes_per_block(compression_type);
+    let needed = w.div_ceil(4) * h.div_ceil(4) * bytes_per_block;
+    if src.len() < needed {
+        return Err(format!("{:?} data for {}x{} needs {} bytes, got {}", compression_type, w, h, needed, src.len()));
+    }
+
+    let dst = match compression_type {
+        CompressionType::Bc1 => decode_bc1(src, w, h)?,
+        CompressionType::Bc2 => decode_bc2(src, w, h)?,
+        CompressionType::Bc3 => decode_bc3(src, w, h)?,
+        CompressionType::Bc4 => decode_bc4(src, w, h, is_signed)?,
+        CompressionType::Bc5 => decode_bc5(src, w, h, is_signed)?,
+        // HDR values are clamped to [0, 1].
+        CompressionType::Bc6h => decode_bc6h(src, w, h, is_signed)?.into_iter().map(|half| color::f32_to_unorm8(color::half_to_f32(half))).collect(),
+        CompressionType::Bc7 => decode_bc7(src, w, h)?,
+        _ => return Err(format!("cannot software decode {:?}", compression_type)),
+    };
+    Ok(dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    #[test]
+    fn test_bc7_anchors_in_subset() {
+        for partition in 0..64 {
+            assert_eq!(bc7_subset(2, partition, 0), 0);
+            assert_eq!(bc7_subset(2, partition, BC7_ANCHORS2[partition] as usize), 1);
+            assert_eq!(bc7_subset(3, partition, 0), 0);
+            assert_eq!(bc7_subset(3, partition, BC7_ANCHORS3_1[partition] as usize), 1);
+            assert_eq!(bc7_subset(3, partition, BC7_ANCHORS3_2[partition] as usize), 2);
+        }
+    }
+
+    #[test]
+    fn test_bc1() {
+        // Pure red and pure blue endpoints, all pixels index 3 (3/8 red, 5/8 blue).
+        let block = [0x00, 0xF8, 0x1F, 0x00, 0xFF, 0xFF, 0xFF, 0xFF];
+        let dst = decode_bc1(&block, 4, 4).unwrap();
+        assert_eq!(&dst[0..4], &[0x5F, 0x00, 0x9F, 0xFF]);
+        assert!(decode_bc1(&block[..7], 4, 4).is_err());
+        assert!(decode_bc1(&block, 8, 4).is_err());
+    }
+
+    // (value, bit count) fields, LSB first.
+    type Fields<'a> = &'a [(u128, usize)];
+
+    // Packs fields into a 128-bit block, zero padded.
+    fn pack_block(fields: Fields) -> [u8; 16] {
+        let mut bits = 0u128;
+        let mut pos = 0;
+        for &(value, n) in fields {
+            assert!(value < (1 << n));
+            bits |= value << pos;
+            pos += n;
+        }
+        assert!(pos <= 128);
+        bits.to_le_bytes()
+    }
+
+    type Pixels<'a> = &'a [(usize, [u8; 4])];
+
+    fn pixel(dst: &[u8], px: usize) -> [u8; 4] {
+        dst[px * 4..px * 4 + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_bc2() {
+        // Alpha counts up by pixel. BC2 colors are always four-color, even with color0 <= color1.
+        let block = [0x10, 0x32, 0x54, 0x76, 0x98, 0xBA, 0xDC, 0xFE, 0x1F, 0x00, 0x00, 0xF8, 0xE4, 0xE4, 0xE4, 0xE4];
+        let dst = decode_bc2(&block, 4, 4).unwrap();
+        for px in 0..16 {
+            let rgb = [[0x00, 0x00, 0xFF], [0xFF, 0x00, 0x00], [0x5F, 0x00, 0x9F], [0x9F, 0x00, 0x5F]][px % 4];
+            assert_eq!(pixel(&dst, px), [rgb[0], rgb[1], rgb[2], px as u8 * 0x11]);
+        }
+    }
+
+    // Indices 0-7 for pixels 0-7 and again for 8-15.
+    const CHANNEL_INDICES: [u8; 6] = [0x88, 0xC6, 0xFA, 0x88, 0xC6, 0xFA];
+    // e0 > e1 interpolates eight values; e0 <= e1 interpolates six and adds 0 and 255.
+    const CHANNEL_TABLE8: [u8; 8] = [255, 0, 218, 182, 145, 109, 72, 36];
+    const CHANNEL_TABLE6: [u8; 8] = [0, 255, 51, 102, 153, 204, 0, 255];
+
+    fn channel_block(e0: u8, e1: u8) -> Vec<u8> {
+        [&[e0, e1][..], &CHANNEL_INDICES].concat()
+    }
+
+    #[test]
+    fn test_bc3() {
+        let block = [channel_block(0xFF, 0x00), vec![0x00, 0xF8, 0x1F, 0x00, 0x00, 0x00, 0x00, 0x00]].concat();
+        let dst = decode_bc3(&block, 4, 4).unwrap();
+        for px in 0..16 {
+            assert_eq!(pixel(&dst, px), [0xFF, 0x00, 0x00, CHANNEL_TABLE8[px % 8]]);
+        }
+    }
+
+    #[test]
+    fn test_bc4() {
+        let dst = decode_bc4(&channel_block(0x00, 0xFF), 4, 4, false).unwrap();
+        for px in 0..16 {
+            let v = CHANNEL_TABLE6[px % 8];
+            assert_eq!(pixel(&dst, px), [v, v, v, 0xFF]);
+        }
+
+        // -127 and 127: six values from -127 to 127, then -128 and 127.
+        let dst = decode_bc4(&channel_block(0x81, 0x7F), 4, 4, true).unwrap();
+        let table = [-127i8, 127, -76, -25, 25, 76, -128, 127];
+        for px in 0..16 {
+            let v = table[px % 8] as u8;
+            assert_eq!(pixel(&dst, px), [v, v, v, 0x7F]);
+        }
+    }
+
+    #[test]
+    fn test_bc5() {
+        let block = [channel_block(0xFF, 0x00), channel_block(0x00, 0xFF)].concat();
+        let dst = decode_bc5(&block, 4, 4, false).unwrap();
+        for px in 0..16 {
+            assert_eq!(pixel(&dst, px), [CHANNEL_TABLE8[px % 8], CHANNEL_TABLE6[px % 8], 0xFF, 0xFF]);
+        }
+        let dst = decode_bc5(&block, 4, 4, true).unwrap();
+        assert_eq!(pixel(&dst, 0), [0xFF, 0x00, 0x7F, 0x7F]);
+    }
+
+    fn half_pixel(dst: &[u16], px: usize) -> [u16; 4] {
+        dst[px * 4..px * 4 + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_bc6h_mode11() {
+        // One subset, endpoints stored directly: W = (1023, 512, 0), X = (0, 1023, 256).
+        // Pixel N has index N.
+        let mut fields = vec![(0x03, 5), (1023, 10), (512, 10), (0, 10), (0, 10), (1023, 10), (256, 10), (0, 3)];
+        fields.extend((1..16).map(|i| (i, 4)));
+        let block = pack_block(&fields);
+
+        let dst = decode_bc6h(&block, 4, 4, false).unwrap();
+        assert_eq!(half_pixel(&dst, 0), [0x7BFF, 0x3E0F, 0x0000, 0x3C00]);
+        assert_eq!(half_pixel(&dst, 8), [0x3A20, 0x5EF6, 0x1080, 0x3C00]);
+        assert_eq!(half_pixel(&dst, 15), [0x0000, 0x7BFF, 0x1F0F, 0x3C00]);
+
+        let dst = decode_bc(CompressionType::Bc6h, &block, 4, 4, false).unwrap();
+        assert_eq!(pixel(&dst, 8), [195, 255, 0, 255]);
+
+        // Signed: W = (-1, 511, -512) saturates the last two.
+        let mut fields = vec![(0x03, 5), (0x3FF, 10), (0x1FF, 10), (0x200, 10), (0, 30), (0, 3)];
+        fields.extend((1..16).map(|_| (15, 4)));
+        let dst = decode_bc6h(&pack_block(&fields), 4, 4, true).unwrap();
+        assert_eq!(half_pixel(&dst, 0), [0x805D, 0x7BFF, 0xFBFF, 0x3C00]);
+        assert_eq!(half_pixel(&dst, 1), [0x0000, 0x0000, 0x0000, 0x3C00]);
+    }
+
+    #[test]
+    fn test_bc6h_mode1() {
+        // Two subsets, partition 0 (the right two columns are subset 1). W = (100, 200, 300) and
+        // the other endpoints are 5-bit signed deltas from it: X = (+1, -1, 0), Y = (+15, -16, +2),
+        // Z = (0, 0, -1).
+        let block = pack_block(&[
+            (0b00, 2), (1, 1), (0, 1), (1, 1), (100, 10), (200, 10), (300, 10), (1, 5), (0, 1), (0, 4),
+            (0x1F, 5), (1, 1), (0, 4), (0, 5), (1, 1), (2, 4), (15, 5), (1, 1), (0, 5), (1, 1), (0, 5),
+            // Pixel 1 selects X, pixel 15 (the subset 1 anchor) interpolates 27/64 of the way to Z.
+            (0, 2), (7, 3), (0, 3 * 13), (3, 2),
+        ]);
+        let dst = decode_bc6h(&block, 4, 4, false).unwrap();
+        assert_eq!(half_pixel(&dst, 0), [0x0C2B, 0x1847, 0x2463, 0x3C00]);
+        assert_eq!(half_pixel(&dst, 1), [0x0C4A, 0x1828, 0x2463, 0x3C00]);
+        assert_eq!(half_pixel(&dst, 2), [0x0DFC, 0x1657, 0x24A1, 0x3C00]);
+        assert_eq!(half_pixel(&dst, 15), [0x0D38, 0x1728, 0x247A, 0x3C00]);
+
+        // Reserved mode 0x13.
+        let dst = decode_bc6h(&pack_block(&[(0x13, 5)]), 4, 4, false).unwrap();
+        assert!(dst.chunks_exact(4).all(|px| px == [0, 0, 0, 0x3C00]));
+    }
+
+    #[test]
+    fn test_bc7_modes() {
+        let zero = |n: usize| (0, n);
+        // Each case is a block and some (pixel, expected color) pairs.
+        let cases: [(Fields, Pixels); 6] = [
+            // Mode 0: three subsets (partition 0), 4-bit endpoints with a p-bit each.
+            (&[(0b1, 1), (0, 4),
+               (15, 4), zero(4), zero(4), zero(4), zero(4), (15, 4),
+               zero(4), zero(4), (15, 4), zero(4), zero(4), (15, 4),
+               zero(4), zero(4), zero(4), zero(4), (15, 4), (15, 4),
+               (0b100000, 6), zero(43), (3, 2)],
+             &[(0, [247, 0, 0, 255]), (2, [0, 247, 0, 255]), (9, [0, 0, 247, 255]), (15, [108, 108, 250, 255])]),
+            // Mode 1: two subsets (partition 13), 6-bit endpoints with shared p-bits.
+            (&[(0b10, 2), (13, 6),
+               (63, 6), zero(6), zero(6), zero(6),
+               zero(6), zero(6), (63, 6), zero(6),
+               zero(6), zero(6), zero(6), (63, 6),
+               (1, 1), (0, 1), zero(44), (3, 2)],
+             &[(0, [255, 2, 2, 255]), (8, [0, 253, 0, 255]), (15, [0, 146, 107, 255])]),
+            // Mode 2: three subsets (partition 0), 5-bit endpoints.
+            (&[(0b100, 3), (0, 6),
+               (31, 5), zero(5), zero(5), zero(5), zero(5), (31, 5),
+               zero(5), zero(5), (31, 5), zero(5), zero(5), (31, 5),
+               zero(5), zero(5), zero(5), zero(5), (31, 5), (31, 5),
+               zero(28), (1, 1)],
+             &[(0, [255, 0, 0, 255]), (2, [0, 255, 0, 255]), (9, [0, 0, 255, 255]), (15, [84, 84, 255, 255])]),
+            // Mode 3: two subsets (partition 13), 7-bit endpoints with a p-bit each.
+            (&[(0b1000, 4), (13, 6),
+               (127, 7), zero(7), zero(7), zero(7),
+               zero(7), zero(7), (127, 7), zero(7),
+               zero(7), zero(7), zero(7), (127, 7),
+               (1, 1), (0, 1), (0, 1), (1, 1),
+               zero(1), (3, 2), zero(26), (1, 1)],
+             &[(0, [255, 1, 1, 255]), (1, [0, 0, 0, 255]), (8, [0, 254, 0, 255]), (15, [0, 171, 84, 255])]),
+            // Mode 4: rotation 1 (swap red and alpha), index selection 1 (color uses the 3-bit indices).
+            (&[(0b10000, 5), (1, 2), (1, 1),
+               (31, 5), zero(5), zero(5), (31, 5), zero(5), zero(5), zero(6), (63, 6),
+               zero(29), (1, 2), zero(44), (7, 3)],
+             &[(0, [0, 0, 0, 255]), (15, [84, 255, 0, 0])]),
+            // Mode 5: separate color and alpha indices.
+            (&[(0b100000, 6), (0, 2),
+               (127, 7), zero(7), zero(7), (127, 7), zero(7), zero(7), (255, 8), zero(8),
+               zero(29), (1, 2), zero(29), (2, 2)],
+             &[(0, [255, 0, 0, 255]), (15, [171, 84, 0, 84])]),
+        ];
+        for (fields, expected) in cases {
+            let dst = decode_bc7(&pack_block(fields), 4, 4).unwrap();
+            for &(px, color) in expected {
+                assert_eq!(pixel(&dst, px), color, "{:?} pixel {}", &dst[0..4], px);
+            }
+        }
+
+        // Mode 7: two subsets (partition 13), 5-bit color and alpha with a p-bit each.
+        let block = pack_block(&[(0b10000000, 8), (13, 6),
+            (31, 5), zero(5), zero(5), zero(5),
+            zero(5), zero(5), (31, 5), zero(5),
+            zero(5), zero(5), zero(5), (31, 5),
+            (31, 5), zero(5), (15, 5), (31, 5),
+            (1, 1), (0, 1), (0, 1), (1, 1),
+            zero(29), (1, 1)]);
+        let dst = decode_bc7(&block, 4, 4).unwrap();
+        assert_eq!(pixel(&dst, 0), [255, 4, 4, 255]);
+        assert_eq!(pixel(&dst, 8), [0, 251, 0, 121]);
+        assert_eq!(pixel(&dst, 15), [1, 170, 84, 165]);
+    }
+
+    #[test]
+    fn test_bc7_mode6() {
+        // Mode 6 block: endpoint 0 all 0xFF, endpoint 1 all 0x00. The anchor pixel
+        // selects endpoint 0, every other pixel selects endpoint 1.
+        let mut bits = 1u128 << 6;
+        let mut pos = 7;
+        let mut push = |value: u128, n: usize| { bits |= value << pos; pos += n; };
+        for _ in 0..4 {
+            push(0x7F, 7);
+            push(0x00, 7);
+        }
+        push(1, 1);
+        push(0, 1);
+        push(0x00, 3);
+        for _ in 1..16 {
+            push(0x0F, 4);
+        }
+        assert_eq!(pos, 128);
+        let dst = decode_bc7(&bits.to_le_bytes(), 4, 4).unwrap();
+        assert_eq!(&dst[0..4], &[0xFF; 4]);
+        assert!(dst[4..].iter().all(|&v| v == 0x00));
+    }
+}
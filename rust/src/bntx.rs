@@ -0,0 +1,636 @@
+
+// Nintendo BNTX texture container, as used by NintendoWare for Switch.
+//
+// The file starts with a "BNTX" binary file header and an "NX  " container header
+// pointing at an array of BRTI texture info blocks and at the BRTD block holding the
+// (block-linear swizzled) texture data. Each BRTI has a table of absolute mip offsets.
+
+use byteorder::{ByteOrder, LittleEndian};
+use wasm_bindgen::prelude::*;
+
+use crate::tegra_texture::{self, CompressionType, SurfaceLayout};
+
+// A 2^31 texture would need 32 levels; anything past that is garbage.
+const MAX_MIP_COUNT: u32 = 32;
+
+#[wasm_bindgen(js_name = "BntxChannelFormat")]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ChannelFormat {
+    Undefined = 0x00,
+    R4G4 = 0x01,
+    R8 = 0x02,
+    R4G4B4A4 = 0x03,
+    A4B4G4R4 = 0x04,
+    R5G5B5A1 = 0x05,
+    A1B5G5R5 = 0x06,
+    R5G6B5 = 0x07,
+    B5G6R5 = 0x08,
+    R8G8 = 0x09,
+    R16 = 0x0A,
+    R8G8B8A8 = 0x0B,
+    B8G8R8A8 = 0x0C,
+    R9G9B9E5 = 0x0D,
+    R10G10B10A2 = 0x0E,
+    R11G11B10 = 0x0F,
+    B10G11R11 = 0x10,
+    R10G11B11 = 0x11,
+    R16G16 = 0x12,
+    R24G8 = 0x13,
+    R32 = 0x14,
+    R16G16B16A16 = 0x15,
+    R32G8X24 = 0x16,
+    R32G32 = 0x17,
+    R32G32B32 = 0x18,
+    R32G32B32A32 = 0x19,
+    Bc1 = 0x1A,
+    Bc2 = 0x1B,
+    Bc3 = 0x1C,
+    Bc4 = 0x1D,
+    Bc5 = 0x1E,
+    Bc6 = 0x1F,
+    Bc7 = 0x20,
+    EacR11 = 0x21,
+    EacR11G11 = 0x22,
+    Etc1 = 0x23,
+    Etc2 = 0x24,
+    Etc2Mask = 0x25,
+    Etc2Alpha = 0x26,
+    Pvrtc1_2Bpp = 0x27,
+    Pvrtc1_4Bpp = 0x28,
+    Pvrtc1Alpha2Bpp = 0x29,
+    Pvrtc1Alpha4Bpp = 0x2A,
+    Pvrtc2Alpha2Bpp = 0x2B,
+    Pvrtc2Alpha4Bpp = 0x2C,
+    Astc4x4 = 0x2D,
+    Astc5x4 = 0x2E,
+    Astc5x5 = 0x2F,
+    Astc6x5 = 0x30,
+    Astc6x6 = 0x31,
+    Astc8x5 = 0x32,
+    Astc8x6 = 0x33,
+    Astc8x8 = 0x34,
+    Astc10x5 = 0x35,
+    Astc10x6 = 0x36,
+    Astc10x8 = 0x37,
+    Astc10x10 = 0x38,
+    Astc12x10 = 0x39,
+    Astc12x12 = 0x3A,
+}
+
+impl ChannelFormat {
+    fn from_u8(v: u8) -> Option<ChannelFormat> {
+        use ChannelFormat::*;
+        const FORMATS: [ChannelFormat; 0x3B] = [
+            Undefined, R4G4, R8, R4G4B4A4, A4B4G4R4, R5G5B5A1, A1B5G5R5, R5G6B5, B5G6R5, R8G8, R16,
+            R8G8B8A8, B8G8R8A8, R9G9B9E5, R10G10B10A2, R11G11B10, B10G11R11, R10G11B11, R16G16, R24G8,
+            R32, R16G16B16A16, R32G8X24, R32G32, R32G32B32, R32G32B32A32, Bc1, Bc2, Bc3, Bc4, Bc5,
+            Bc6, Bc7, EacR11, EacR11G11, Etc1, Etc2, Etc2Mask, Etc2Alpha, Pvrtc1_2Bpp, Pvrtc1_4Bpp,
+            Pvrtc1Alpha2Bpp, Pvrtc1Alpha4Bpp, Pvrtc2Alpha2Bpp, Pvrtc2Alpha4Bpp, Astc4x4, Astc5x4,
+            Astc5x5, Astc6x5, Astc6x6, Astc8x5, Astc8x6, Astc8x8, Astc10x5, Astc10x6, Astc10x8,
+            Astc10x10, Astc12x10, Astc12x12,
+        ];
+        FORMATS.get(v as usize).copied()
+    }
+
+    // The Tegra surface format used to deswizzle this channel format, if we support it.
+    pub fn get_compression_type(&self) -> Option<CompressionType> {
+        use ChannelFormat::*;
+        Some(match self {
+            R8 => CompressionType::R8,
+            R8G8 => CompressionType::Rg8,
+            R5G6B5 | B5G6R5 => CompressionType::Rgb565,
+            R8G8B8A8 | B8G8R8A8 => CompressionType::Rgba8,
+            Bc1 => CompressionType::Bc1,
+            Bc2 => CompressionType::Bc2,
+            Bc3 => CompressionType::Bc3,
+            Bc4 => CompressionType::Bc4,
+            Bc5 => CompressionType::Bc5,
+            Bc6 => CompressionType::Bc6h,
+            Bc7 => CompressionType::Bc7,
+            Astc4x4 => CompressionType::Astc4x4,
+            Astc5x4 => CompressionType::Astc5x4,
+            Astc5x5 => CompressionType::Astc5x5,
+            Astc6x5 => CompressionType::Astc6x5,
+            Astc6x6 => CompressionType::Astc6x6,
+            Astc8x5 => CompressionType::Astc8x5,
+            Astc8x6 => CompressionType::Astc8x6,
+            Astc8x8 => CompressionType::Astc8x8,
+            Astc10x5 => CompressionType::Astc10x5,
+            Astc10x6 => CompressionType::Astc10x6,
+            Astc10x8 => CompressionType::Astc10x8,
+            Astc10x10 => CompressionType::Astc10x10,
+            Astc12x10 => CompressionType::Astc12x10,
+            Astc12x12 => CompressionType::Astc12x12,
+            _ => return None,
+        })
+    }
+}
+
+#[wasm_bindgen(js_name = "BntxTypeFormat")]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TypeFormat {
+    Undefined = 0x00,
+    Unorm = 0x01,
+    Snorm = 0x02,
+    Uint = 0x03,
+    Sint = 0x04,
+    Float = 0x05,
+    UnormSrgb = 0x06,
+    DepthStencil = 0x07,
+    UintToFloat = 0x08,
+    SintToFloat = 0x09,
+    Ufloat = 0x0A,
+}
+
+impl TypeFormat {
+    fn from_u8(v: u8) -> Option<TypeFormat> {
+        use TypeFormat::*;
+        const FORMATS: [TypeFormat; 0x0B] = [
+            Undefined, Unorm, Snorm, Uint, Sint, Float, UnormSrgb, DepthStencil, UintToFloat, SintToFloat, Ufloat,
+        ];
+        FORMATS.get(v as usize).copied()
+    }
+}
+
+#[wasm_bindgen(js_name = "BntxImageDimension")]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ImageDimension {
+    Dim1D = 0x00,
+    Dim2D = 0x01,
+    Dim3D = 0x02,
+    DimCube = 0x03,
+    Dim1DArray = 0x04,
+    Dim2DArray = 0x05,
+    Dim2DMultisample = 0x06,
+    Dim2DMultisampleArray = 0x07,
+    DimCubeArray = 0x08,
+}
+
+impl ImageDimension {
+    fn from_u8(v: u8) -> Option<ImageDimension> {
+        use ImageDimension::*;
+        const DIMENSIONS: [ImageDimension; 0x09] = [
+            Dim1D, Dim2D, Dim3D, DimCube, Dim1DArray, Dim2DArray, Dim2DMultisample, Dim2DMultisampleArray, DimCubeArray,
+        ];
+        DIMENSIONS.get(v as usize).copied()
+    }
+}
+
+#[wasm_bindgen(js_name = "BntxTileMode")]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TileMode {
+    Optimal = 0x00,
+    Linear = 0x01,
+}
+
+fn check_range(data: &[u8], offs: usize, size: usize) -> Result<(), String> {
+    match offs.checked_add(size) {
+        Some(end) if end <= data.len() => Ok(()),
+        _ => Err(format!("read of {} bytes at 0x{:x} is out of bounds (file size 0x{:x})", size, offs, data.len())),
+    }
+}
+
+fn read_u8(data: &[u8], offs: usize) -> Result<u8, String> {
+    check_range(data, offs, 1)?;
+    Ok(data[offs])
+}
+
+fn read_u16(data: &[u8], offs: usize) -> Result<u16, String> {
+    check_range(data, offs, 2)?;
+    Ok(LittleEndian::read_u16(&data[offs..]))
+}
+
+fn read_u32(data: &[u8], offs: usize) -> Result<u32, String> {
+    check_range(data, offs, 4)?;
+    Ok(LittleEndian::read_u32(&data[offs..]))
+}
+
+fn read_ptr(data: &[u8], offs: usize) -> Result<usize, String> {
+    check_range(data, offs, 8)?;
+    Ok(LittleEndian::read_u64(&data[offs..]) as usize)
+}
+
+// Offset of the i-th entry of a pointer array, which the header can put anywhere.
+fn ptr_array_entry(array_offs: usize, i: usize) -> Result<usize, String> {
+    i.checked_mul(8)
+        .and_then(|entry| array_offs.checked_add(entry))
+        .ok_or_else(|| format!("pointer array at 0x{:x} overflows", array_offs))
+}
+
+fn read_magic(data: &[u8], offs: usize, magic: &[u8; 4]) -> Result<(), String> {
+    check_range(data, offs, 4)?;
+    if &data[offs..offs + 4] != magic {
+        return Err(format!("expected magic {:?} at 0x{:x}, got {:?}", String::from_utf8_lossy(magic), offs, String::from_utf8_lossy(&data[offs..offs + 4])));
+    }
+    Ok(())
+}
+
+// Strings in the string pool are stored as a u16 length followed by the bytes.
+fn read_bin_str(data: &[u8], offs: usize) -> Result<String, String> {
+    let len = read_u16(data, offs)? as usize;
+    check_range(data, offs + 2, len)?;
+    Ok(String::from_utf8_lossy(&data[offs + 2..offs + 2 + len]).into_owned())
+}
+
+#[wasm_bindgen(js_name = "BntxTexture", getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct Texture {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub mip_count: u32,
+    pub array_size: u32,
+    pub channel_format: ChannelFormat,
+    pub type_format: TypeFormat,
+    pub dimension: ImageDimension,
+    pub tile_mode: TileMode,
+    pub block_height_log2: u32,
+    pub channel_mapping: u32,
+    mip_offsets: Vec<usize>,
+    data: Vec<u8>,
+}
+
+impl Texture {
+    fn parse(data: &[u8], offs: usize) -> Result<Texture, String> {
+        read_magic(data, offs, b"BRTI")?;
+        let tile_mode = match read_u16(data, offs + 0x12)? {
+            0 => TileMode::Optimal,
+            1 => TileMode::Linear,
+            x => return Err(format!("unknown tile mode {}", x)),
+        };
+        let mip_count = read_u16(data, offs + 0x16)? as u32;
+        let image_format = read_u32(data, offs + 0x1C)?;
+        let type_format = TypeFormat::from_u8((image_format & 0xFF) as u8)
+            .ok_or_else(|| format!("unknown type format in image format 0x{:x}", image_format))?;
+        let channel_format = ChannelFormat::from_u8(((image_format >> 8) & 0xFF) as u8)
+            .ok_or_else(|| format!("unknown channel format in image format 0x{:x}", image_format))?;
+        let width = read_u32(data, offs + 0x24)?;
+        let height = read_u32(data, offs + 0x28)?;
+        let depth = read_u32(data, offs + 0x2C)?;
+        let array_size = read_u32(data, offs + 0x30)?;
+        let texture_layout = read_u32(data, offs + 0x34)?;
+        let block_height_log2 = texture_layout & 0x07;
+        let image_size = read_u32(data, offs + 0x50)? as usize;
+        let channel_mapping = read_u32(data, offs + 0x58)?;
+        let dimension = ImageDimension::from_u8(read_u8(data, offs + 0x5C)?)
+            .ok_or_else(|| "unknown image dimension".to_string())?;
+        let name = read_bin_str(data, read_ptr(data, offs + 0x60)?)?;
+        let mip_offsets_table = read_ptr(data, offs + 0x70)?;
+        if mip_count == 0 || mip_count > MAX_MIP_COUNT {
+            return Err(format!("texture {} has {} mips", name, mip_count));
+        }
+
+        let mut mip_offsets = Vec::with_capacity(mip_count as usize);
+        for i in 0..mip_count as usize {
+            mip_offsets.push(read_ptr(data, ptr_array_entry(mip_offsets_table, i)?)?);
+        }
+
+        let data_start = mip_offsets[0];
+        check_range(data, data_start, image_size)?;
+        let data = data[data_start..data_start + image_size].to_vec();
+
+        // Make the mip offsets relative to the start of the surface.
+        for mip_offset in mip_offsets.iter_mut() {
+            *mip_offset = mip_offset.checked_sub(data_start)
+                .ok_or_else(|| format!("texture {} has mips before its first mip", name))?;
+        }
+
+        Ok(Texture {
+            name,
+            width,
+            height,
+            depth,
+            mip_count,
+            array_size,
+            channel_format,
+            type_format,
+            dimension,
+            tile_mode,
+            block_height_log2,
+            channel_mapping,
+            mip_offsets,
+            data,
+        })
+    }
+
+    fn get_surface_layout(&self) -> Result<SurfaceLayout, String> {
+        let compression_type = self.channel_format.get_compression_type()
+            .ok_or_else(|| format!("unsupported channel format {:?}", self.channel_format))?;
+        if self.tile_mode != TileMode::Optimal {
+            return Err(format!("texture {} uses unsupported tile mode {:?}", self.name, self.tile_mode));
+        }
+        if self.depth > 1 {
+            return Err(format!("3D textures are not supported (depth {})", self.depth));
+        }
+        let mut layout = SurfaceLayout::new(compression_type, self.width as usize, self.height as usize,
+            self.block_height_log2 as usize, self.mip_count as usize, self.array_size as usize);
+
+        // Trust the file's own mip offsets and layer stride over our computed ones.
+        for (level, &mip_offset) in layout.levels.iter_mut().zip(self.mip_offsets.iter()) {
+            level.offset = mip_offset;
+        }
+        layout.layer_size = self.data.len() / layout.layer_count;
+
+        for (i, level) in layout.levels.iter().enumerate() {
+            if level.offset.checked_add(level.size).is_none_or(|end| end > layout.layer_size) {
+                return Err(format!("texture {} mip {} runs past the end of its layer", self.name, i));
+            }
+        }
+        Ok(layout)
+    }
+}
+
+#[wasm_bindgen(js_class = "BntxTexture")]
+impl Texture {
+    pub fn get_compression_type(&self) -> Option<CompressionType> {
+        self.channel_format.get_compression_type()
+    }
+
+    pub fn is_srgb(&self) -> bool {
+        self.type_format == TypeFormat::UnormSrgb
+    }
+
+    pub fn is_cubemap(&self) -> bool {
+        matches!(self.dimension, ImageDimension::DimCube | ImageDimension::DimCubeArray)
+    }
+
+    pub fn get_swizzled_data(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    // Returns every layer and mip level deswizzled into a tightly packed buffer, ordered
+    // layer-major and then by mip level.
+    pub fn deswizzle(&self) -> Result<Vec<u8>, String> {
+        let layout = self.get_surface_layout()?;
+        tegra_texture::deswizzle_surface(&self.data, &layout)
+    }
+
+    pub fn deswizzle_level(&self, layer: usize, level: usize) -> Result<Vec<u8>, String> {
+        if layer >= self.array_size as usize || level >= self.mip_count as usize {
+            return Err(format!("texture {} has no layer {} level {}", self.name, layer, level));
+        }
+        let layout = self.get_surface_layout()?;
+        tegra_texture::deswizzle_level(&self.data, &layout, layer, level)
+    }
+}
+
+#[wasm_bindgen(js_name = "Bntx")]
+#[derive(Debug)]
+pub struct Bntx {
+    name: String,
+    textures: Vec<Texture>,
+}
+
+#[wasm_bindgen(js_class = "Bntx")]
+impl Bntx {
+    pub fn new(data: &[u8]) -> Result<Bntx, String> {
+        read_magic(data, 0x00, b"BNTX")?;
+        if read_u16(data, 0x0C)? != 0xFEFF {
+            return Err("big-endian BNTX files are not supported".to_string());
+        }
+        let name_offs = read_u32(data, 0x10)? as usize;
+        let name = if name_offs >= 2 { read_bin_str(data, name_offs - 2)? } else { String::new() };
+
+        read_magic(data, 0x20, b"NX  ")?;
+        let texture_count = read_u32(data, 0x24)? as usize;
+        let info_array_offs = read_ptr(data, 0x28)?;
+
+        // The count can't be trusted for the allocation; each entry takes 8 bytes of the file.
+        let max_texture_count = data.len().saturating_sub(info_array_offs) / 8;
+        let mut textures = Vec::with_capacity(texture_count.min(max_texture_count));
+        for i in 0..texture_count {
+            let brti_offs = read_ptr(data, ptr_array_entry(info_array_offs, i)?)?;
+            textures.push(Texture::parse(data, brti_offs)?);
+        }
+
+        Ok(Bntx { name, textures })
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_num_textures(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn get_texture(&self, index: usize) -> Option<Texture> {
+        self.textures.get(index).cloned()
+    }
+
+    pub fn get_texture_names(&self) -> Vec<String> {
+        self.textures.iter().map(|texture| texture.name.clone()).collect()
+    }
+
+    pub fn find_texture(&self, name: &str) -> Option<Texture> {
+        self.textures.iter().find(|texture| texture.name == name).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u16(buf: &mut [u8], offs: usize, v: u16) { LittleEndian::write_u16(&mut buf[offs..], v); }
+    fn put_u32(buf: &mut [u8], offs: usize, v: u32) { LittleEndian::write_u32(&mut buf[offs..], v); }
+    fn put_u64(buf: &mut [u8], offs: usize, v: u64) { LittleEndian::write_u64(&mut buf[offs..], v); }
+
+    struct TestTexture {
+        name: &'static str,
+        format: ChannelFormat,
+        type_format: TypeFormat,
+        dimension: ImageDimension,
+        width: usize,
+        height: usize,
+        mip_count: usize,
+        array_size: usize,
+        block_height_log2: usize,
+    }
+
+    impl TestTexture {
+        fn layout(&self) -> SurfaceLayout {
+            SurfaceLayout::new(self.format.get_compression_type().unwrap(), self.width, self.height, self.block_height_log2, self.mip_count, self.array_size)
+        }
+
+        fn linear(&self) -> Vec<u8> {
+            let seed = self.name.len();
+            (0..self.layout().linear_size()).map(|i| ((i * 13 + seed) % 251) as u8).collect()
+        }
+    }
+
+    // Lays the file out the way NintendoWare's converter does: the BNTX and NX headers, the
+    // BRTI pointer array, a _STR string pool, one BRTI per texture with its mip table, and a
+    // BRTD block holding each texture's swizzled surface aligned to 0x200. Returns the file
+    // and each texture's BRTI and mip table offsets.
+    fn build_bntx(file_name: &str, textures: &[TestTexture]) -> (Vec<u8>, Vec<(usize, usize)>) {
+        let info_array = 0x60;
+        let str_pool = (info_array + textures.len() * 8).next_multiple_of(0x10);
+        let mut data = vec![0u8; str_pool];
+        data[0x00..0x04].copy_from_slice(b"BNTX");
+        put_u32(&mut data, 0x08, 0x00040000);
+        put_u16(&mut data, 0x0C, 0xFEFF);
+        data[0x0E] = 0x0C;
+        data[0x0F] = 0x40;
+        put_u16(&mut data, 0x16, 0x20);
+        data[0x20..0x24].copy_from_slice(b"NX  ");
+        put_u32(&mut data, 0x24, textures.len() as u32);
+        put_u64(&mut data, 0x28, info_array as u64);
+
+        // _STR: the file name first, then the texture names. Each is a u16 length, the bytes
+        // and a NUL, padded to two bytes.
+        data.extend_from_slice(b"_STR");
+        data.resize(str_pool + 0x14, 0);
+        put_u32(&mut data, str_pool + 0x10, textures.len() as u32 + 1);
+        let mut name_offsets = Vec::new();
+        for name in std::iter::once(file_name).chain(textures.iter().map(|texture| texture.name)) {
+            name_offsets.push(data.len());
+            data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            data.extend_from_slice(name.as_bytes());
+            data.push(0);
+            data.resize(data.len().next_multiple_of(2), 0);
+        }
+        // The header points at the file name's bytes, not its length.
+        put_u32(&mut data, 0x10, name_offsets[0] as u32 + 2);
+
+        let mut offsets = Vec::new();
+        for (i, texture) in textures.iter().enumerate() {
+            let brti = data.len().next_multiple_of(0x10);
+            let mip_table = brti + 0xA0;
+            data.resize(mip_table + texture.mip_count * 8, 0);
+            put_u64(&mut data, info_array + i * 8, brti as u64);
+            data[brti..brti + 4].copy_from_slice(b"BRTI");
+            put_u32(&mut data, brti + 0x08, 0xA0);
+            data[brti + 0x10] = 0x01;
+            data[brti + 0x11] = texture.dimension as u8;
+            put_u16(&mut data, brti + 0x16, texture.mip_count as u16);
+            put_u32(&mut data, brti + 0x1C, ((texture.format as u32) << 8) | texture.type_format as u32);
+            put_u32(&mut data, brti + 0x24, texture.width as u32);
+            put_u32(&mut data, brti + 0x28, texture.height as u32);
+            put_u32(&mut data, brti + 0x2C, 1);
+            put_u32(&mut data, brti + 0x30, texture.array_size as u32);
+            put_u32(&mut data, brti + 0x34, texture.block_height_log2 as u32);
+            put_u32(&mut data, brti + 0x50, texture.layout().swizzled_size() as u32);
+            put_u32(&mut data, brti + 0x58, 0x05040302);
+            data[brti + 0x5C] = texture.dimension as u8;
+            put_u64(&mut data, brti + 0x60, name_offsets[i + 1] as u64);
+            put_u64(&mut data, brti + 0x70, mip_table as u64);
+            offsets.push((brti, mip_table));
+        }
+
+        let brtd = data.len().next_multiple_of(0x10);
+        data.resize(brtd + 0x10, 0);
+        data[brtd..brtd + 4].copy_from_slice(b"BRTD");
+        put_u64(&mut data, 0x30, brtd as u64);
+        for (texture, &(_, mip_table)) in textures.iter().zip(offsets.iter()) {
+            let layout = texture.layout();
+            let start = data.len().next_multiple_of(0x200);
+            data.resize(start, 0);
+            data.extend_from_slice(&tegra_texture::swizzle_surface(&texture.linear(), &layout).unwrap());
+            for (level, level_layout) in layout.levels.iter().enumerate() {
+                put_u64(&mut data, mip_table + level * 8, (start + level_layout.offset) as u64);
+            }
+        }
+        let size = data.len() - brtd;
+        put_u64(&mut data, brtd + 0x08, size as u64);
+        let file_size = data.len();
+        put_u32(&mut data, 0x1C, file_size as u32);
+        (data, offsets)
+    }
+
+    fn test_textures() -> Vec<TestTexture> {
+        vec![
+            TestTexture { name: "test", format: ChannelFormat::R8G8B8A8, type_format: TypeFormat::UnormSrgb, dimension: ImageDimension::Dim2D, width: 64, height: 32, mip_count: 1, array_size: 1, block_height_log2: 2 },
+            TestTexture { name: "Leaf_Alb", format: ChannelFormat::Bc1, type_format: TypeFormat::Unorm, dimension: ImageDimension::Dim2D, width: 200, height: 120, mip_count: 8, array_size: 1, block_height_log2: 4 },
+            TestTexture { name: "Sky_Cube", format: ChannelFormat::Bc7, type_format: TypeFormat::UnormSrgb, dimension: ImageDimension::DimCube, width: 64, height: 64, mip_count: 7, array_size: 6, block_height_log2: 3 },
+        ]
+    }
+
+    #[test]
+    fn test_parse() {
+        let textures = test_textures();
+        let (data, _) = build_bntx("Textures", &textures);
+        let bntx = Bntx::new(&data).unwrap();
+        assert_eq!(bntx.get_name(), "Textures");
+        assert_eq!(bntx.get_texture_names(), vec!["test", "Leaf_Alb", "Sky_Cube"]);
+
+        let texture = bntx.find_texture("test").unwrap();
+        assert!(texture.is_srgb());
+        assert_eq!(texture.get_compression_type(), Some(CompressionType::Rgba8));
+        assert_eq!(texture.deswizzle().unwrap(), textures[0].linear());
+
+        let texture = bntx.find_texture("Leaf_Alb").unwrap();
+        assert_eq!((texture.width, texture.height, texture.mip_count, texture.block_height_log2), (200, 120, 8, 4));
+        assert!(!texture.is_srgb() && !texture.is_cubemap());
+        assert_eq!(texture.deswizzle().unwrap(), textures[1].linear());
+
+        let texture = bntx.find_texture("Sky_Cube").unwrap();
+        assert!(texture.is_cubemap());
+        let layout = textures[2].layout();
+        let linear = textures[2].linear();
+        let (layer, level) = (5, 3);
+        let start = layout.linear_offset(layer, level);
+        let size = layout.levels[level].linear_size(layout.bytes_per_block());
+        assert_eq!(texture.deswizzle_level(layer, level).unwrap(), linear[start..start + size]);
+        assert_eq!(texture.deswizzle().unwrap(), linear);
+        assert!(texture.deswizzle_level(6, 0).is_err());
+        assert!(texture.deswizzle_level(0, 7).is_err());
+    }
+
+    #[test]
+    fn test_bad_mips() {
+        let textures = test_textures();
+        let (data, offsets) = build_bntx("Textures", &textures);
+        let (brti, mip_table) = offsets[1];
+
+        // A middle mip pointing past the end of the surface parses, but can't be deswizzled.
+        let mut bad = data.clone();
+        let offset = LittleEndian::read_u64(&bad[mip_table + 3 * 8..]);
+        put_u64(&mut bad, mip_table + 3 * 8, offset + 0x10000);
+        let texture = Bntx::new(&bad).unwrap().find_texture("Leaf_Alb").unwrap();
+        assert_eq!(texture.deswizzle(), Err("texture Leaf_Alb mip 3 runs past the end of its layer".to_string()));
+        assert!(texture.deswizzle_level(0, 0).is_err());
+
+        // So does one that would overflow.
+        put_u64(&mut bad, mip_table + 3 * 8, u64::MAX - 4);
+        let texture = Bntx::new(&bad).unwrap().find_texture("Leaf_Alb").unwrap();
+        assert_eq!(texture.deswizzle(), Err("texture Leaf_Alb mip 3 runs past the end of its layer".to_string()));
+
+        for mip_count in [0, 33, 0xFFFF] {
+            let mut bad = data.clone();
+            put_u16(&mut bad, brti + 0x16, mip_count);
+            assert!(Bntx::new(&bad).is_err());
+        }
+
+        // Mip tables that wrap around the address space.
+        let mut bad = data.clone();
+        put_u64(&mut bad, brti + 0x70, u64::MAX - 8);
+        assert!(Bntx::new(&bad).is_err());
+        put_u64(&mut bad, brti + 0x70, u64::MAX);
+        assert!(Bntx::new(&bad).is_err());
+
+        // More layers than the data holds.
+        let mut bad = data.clone();
+        put_u32(&mut bad, offsets[2].0 + 0x30, 600);
+        assert!(Bntx::new(&bad).unwrap().find_texture("Sky_Cube").unwrap().deswizzle().is_err());
+    }
+
+    #[test]
+    fn test_bad_header() {
+        let (data, _) = build_bntx("Textures", &test_textures());
+
+        // A huge texture count mustn't be allocated up front.
+        let mut bad = data.clone();
+        put_u32(&mut bad, 0x24, u32::MAX);
+        assert!(Bntx::new(&bad).is_err());
+
+        // Neither may a texture array near the end of the address space wrap around.
+        for info_array in [u64::MAX, u64::MAX - 8, data.len() as u64] {
+            let mut bad = data.clone();
+            put_u64(&mut bad, 0x28, info_array);
+            assert!(Bntx::new(&bad).is_err());
+        }
+
+        assert!(Bntx::new(&data[..0x30]).is_err());
+    }
+}
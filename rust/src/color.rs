@@ -0,0 +1,409 @@
+use std::sync::OnceLock;
+
+use wasm_bindgen::prelude::*;
+
+pub fn srgb_to_linear_f32(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb_f32(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        value.powf(1.0 / 2.4) * 1.055 - 0.055
+    }
+}
+
+fn srgb_to_linear_table() -> &'static [f32; 256] {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| std::array::from_fn(|i| srgb_to_linear_f32(i as f32 / 255.0)))
+}
+
+// The linear value halfway (in sRGB) between each pair of neighbouring u8 values, so the
+// encoded value of x is the number of thresholds at or below it.
+fn linear_to_srgb_thresholds() -> &'static [f32; 255] {
+    static TABLE: OnceLock<[f32; 255]> = OnceLock::new();
+    TABLE.get_or_init(|| std::array::from_fn(|i| srgb_to_linear_f32((i as f32 + 0.5) / 255.0)))
+}
+
+pub fn srgb_to_linear_u8(value: u8) -> f32 {
+    srgb_to_linear_table()[value as usize]
+}
+
+// Correctly rounded in sRGB space. NaN encodes as 0.
+pub fn linear_to_srgb_u8(value: f32) -> u8 {
+    if value.is_nan() {
+        return 0;
+    }
+    linear_to_srgb_thresholds().partition_point(|&t| t <= value) as u8
+}
+
+pub fn unorm8_to_f32(value: u8) -> f32 {
+    value as f32 / 255.0
+}
+
+pub fn f32_to_unorm8(value: f32) -> u8 {
+    if value.is_nan() {
+        return 0;
+    }
+    (value.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+}
+
+// a * b / 255, rounded.
+pub fn mul_unorm8(a: u8, b: u8) -> u8 {
+    let v = a as u32 * b as u32 + 128;
+    ((v + (v >> 8)) >> 8) as u8
+}
+
+// Premultiplies RGBA8 pixels in place, in the stored (not linear) space.
+pub fn premultiply_alpha_rgba8(pixels: &mut [u8]) {
+    for p in pixels.chunks_exact_mut(4) {
+        let a = p[3];
+        for c in &mut p[..3] {
+            *c = mul_unorm8(*c, a);
+        }
+    }
+}
+
+// The inverse of premultiply_alpha_rgba8, as near as the precision left allows. Fully
+// transparent pixels become transparent black.
+pub fn unpremultiply_alpha_rgba8(pixels: &mut [u8]) {
+    for p in pixels.chunks_exact_mut(4) {
+        let a = p[3] as u32;
+        for c in &mut p[..3] {
+            *c = (*c as u32 * 255 + a / 2).checked_div(a).map_or(0, |c| c.min(255) as u8);
+        }
+    }
+}
+
+pub fn premultiply_alpha_f32(pixels: &mut [f32]) {
+    for p in pixels.chunks_exact_mut(4) {
+        let a = p[3];
+        for c in &mut p[..3] {
+            *c *= a;
+        }
+    }
+}
+
+pub fn unpremultiply_alpha_f32(pixels: &mut [f32]) {
+    for p in pixels.chunks_exact_mut(4) {
+        let a = p[3];
+        for c in &mut p[..3] {
+            *c = if a > 0.0 { *c / a } else { 0.0 };
+        }
+    }
+}
+
+// IEEE 754 binary16, rounding to nearest even. NaN payloads survive a round trip.
+pub fn f32_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xFF) as i32;
+    let mantissa = bits & 0x7F_FFFF;
+
+    if exp == 0xFF {
+        let payload = (mantissa >> 13) as u16;
+        return match (mantissa, payload) {
+            (0, _) => sign | 0x7C00,
+            (_, 0) => sign | 0x7E00,
+            _ => sign | 0x7C00 | payload,
+        };
+    }
+
+    let exp = exp - 127 + 15;
+    if exp >= 0x1F {
+        return sign | 0x7C00;
+    }
+    let (value, shift) = if exp <= 0 {
+        // Subnormal, with the implicit bit made explicit.
+        if exp < -10 {
+            return sign;
+        }
+        (mantissa | 0x80_0000, (14 - exp) as u32)
+    } else {
+        ((exp as u32) << 23 | mantissa, 13)
+    };
+    let truncated = value >> shift;
+    let rem = value & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    let round_up = rem > halfway || (rem == halfway && truncated & 1 != 0);
+    // A carry out of the mantissa correctly bumps the exponent, up to infinity.
+    sign | (truncated + round_up as u32) as u16
+}
+
+pub fn half_to_f32(half: u16) -> f32 {
+    let sign = ((half & 0x8000) as u32) << 16;
+    let exp = ((half >> 10) & 0x1F) as u32;
+    let mantissa = (half & 0x3FF) as u32;
+    let bits = match exp {
+        0 if mantissa == 0 => sign,
+        0 => {
+            let shift = mantissa.leading_zeros() - 21;
+            sign | ((127 - 15 + 1 - shift) << 23) | ((mantissa << shift) & 0x3FF) << 13
+        }
+        0x1F => sign | 0x7F80_0000 | mantissa << 13,
+        _ => sign | ((exp + 127 - 15) << 23) | mantissa << 13,
+    };
+    f32::from_bits(bits)
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MipFilter {
+    Box,
+    // A Kaiser-windowed sinc, three destination pixels wide. Sharper than a box.
+    Kaiser,
+}
+
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-8 {
+        term *= (x * 0.5 / k) * (x * 0.5 / k);
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+const KAISER_WIDTH: f32 = 3.0;
+const KAISER_ALPHA: f32 = 4.0;
+
+fn kaiser_weight(x: f32) -> f32 {
+    if x.abs() >= KAISER_WIDTH {
+        return 0.0;
+    }
+    let sinc = if x == 0.0 { 1.0 } else { (x * std::f32::consts::PI).sin() / (x * std::f32::consts::PI) };
+    let t = x / KAISER_WIDTH;
+    sinc * bessel_i0(KAISER_ALPHA * (1.0 - t * t).sqrt()) / bessel_i0(KAISER_ALPHA)
+}
+
+// For each destination pixel, the source pixels it reads and their normalised weights.
+// Reads past the edges are clamped.
+fn filter_weights(src_len: usize, dst_len: usize, filter: MipFilter) -> Vec<Vec<(usize, f32)>> {
+    let scale = src_len as f32 / dst_len as f32;
+    let radius = match filter {
+        MipFilter::Box => scale * 0.5,
+        MipFilter::Kaiser => scale * KAISER_WIDTH,
+    };
+    (0..dst_len).map(|i| {
+        let center = (i as f32 + 0.5) * scale;
+        let mut weights: Vec<(usize, f32)> = Vec::new();
+        for j in (center - radius).floor() as i64..(center + radius).ceil() as i64 {
+            let weight = match filter {
+                MipFilter::Box => ((j + 1) as f32).min(center + radius) - (j as f32).max(center - radius),
+                MipFilter::Kaiser => kaiser_weight((j as f32 + 0.5 - center) / scale),
+            };
+            if weight == 0.0 {
+                continue;
+            }
+            let j = j.clamp(0, src_len as i64 - 1) as usize;
+            match weights.iter_mut().find(|(k, _)| *k == j) {
+                Some((_, w)) => *w += weight,
+                None => weights.push((j, weight)),
+            }
+        }
+        let total: f32 = weights.iter().map(|(_, w)| w).sum();
+        weights.iter_mut().for_each(|(_, w)| *w /= total);
+        weights
+    }).collect()
+}
+
+pub fn mip_size(size: usize) -> usize {
+    (size / 2).max(1)
+}
+
+// Halves an RGBA f32 image, to at least 1x1. Filter in linear light with premultiplied alpha.
+pub fn downsample_f32(src: &[f32], width: usize, height: usize, filter: MipFilter) -> (Vec<f32>, usize, usize) {
+    assert_eq!(src.len(), width * height * 4);
+    let (dst_width, dst_height) = (mip_size(width), mip_size(height));
+
+    let weights = filter_weights(width, dst_width, filter);
+    let mut horizontal = vec![0.0; dst_width * height * 4];
+    for y in 0..height {
+        for (x, taps) in weights.iter().enumerate() {
+            let dst = &mut horizontal[(y * dst_width + x) * 4..][..4];
+            for &(sx, w) in taps {
+                let src = &src[(y * width + sx) * 4..][..4];
+                for c in 0..4 {
+                    dst[c] += src[c] * w;
+                }
+            }
+        }
+    }
+
+    let weights = filter_weights(height, dst_height, filter);
+    let mut result = vec![0.0; dst_width * dst_height * 4];
+    for (y, taps) in weights.iter().enumerate() {
+        for &(sy, w) in taps {
+            let src = &horizontal[sy * dst_width * 4..][..dst_width * 4];
+            let dst = &mut result[y * dst_width * 4..][..dst_width * 4];
+            for (d, s) in dst.iter_mut().zip(src) {
+                *d += s * w;
+            }
+        }
+    }
+    (result, dst_width, dst_height)
+}
+
+// RGBA8 to linear RGBA with premultiplied alpha. Alpha is always linear.
+pub fn rgba8_to_linear_premultiplied(src: &[u8], srgb: bool) -> Vec<f32> {
+    let mut result: Vec<f32> = src.chunks_exact(4).flat_map(|p| {
+        let decode = |c: u8| if srgb { srgb_to_linear_u8(c) } else { unorm8_to_f32(c) };
+        [decode(p[0]), decode(p[1]), decode(p[2]), unorm8_to_f32(p[3])]
+    }).collect();
+    premultiply_alpha_f32(&mut result);
+    result
+}
+
+// The inverse of rgba8_to_linear_premultiplied, clamping anything a filter overshot.
+pub fn linear_premultiplied_to_rgba8(src: &[f32], srgb: bool) -> Vec<u8> {
+    let mut pixels = src.to_vec();
+    unpremultiply_alpha_f32(&mut pixels);
+    pixels.chunks_exact(4).flat_map(|p| {
+        let encode = |c: f32| if srgb { linear_to_srgb_u8(c) } else { f32_to_unorm8(c) };
+        [encode(p[0]), encode(p[1]), encode(p[2]), f32_to_unorm8(p[3])]
+    }).collect()
+}
+
+// Gamma-correct downsampling of an RGBA8 image to the next mip level.
+#[wasm_bindgen]
+pub fn downsample_rgba8(src: &[u8], width: usize, height: usize, filter: MipFilter, srgb: bool) -> Result<Vec<u8>, String> {
+    if width == 0 || height == 0 || src.len() != width * height * 4 {
+        return Err(format!("{} bytes isn't a {}x{} RGBA8 image", src.len(), width, height));
+    }
+    let linear = rgba8_to_linear_premultiplied(src, srgb);
+    let (result, _, _) = downsample_f32(&linear, width, height, filter);
+    Ok(linear_premultiplied_to_rgba8(&result, srgb))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_srgb_u8_exhaustive() {
+        for i in 0..=255u8 {
+            let linear = srgb_to_linear_u8(i);
+            assert_eq!(linear, srgb_to_linear_f32(i as f32 / 255.0));
+            assert_eq!(linear_to_srgb_u8(linear), i);
+
+            // Agrees with rounding the exact curve, away from the rounding boundaries.
+            for offset in [-0.4, -0.1, 0.0, 0.1, 0.4] {
+                let srgb = (i as f32 + offset) / 255.0;
+                if (0.0..=1.0).contains(&srgb) {
+                    assert_eq!(linear_to_srgb_u8(srgb_to_linear_f32(srgb)), i, "{} {}", i, offset);
+                }
+            }
+
+            if i < 255 {
+                let threshold = linear_to_srgb_thresholds()[i as usize];
+                assert!(threshold > linear && threshold < srgb_to_linear_u8(i + 1));
+                assert_eq!(linear_to_srgb_u8(threshold), i + 1);
+            }
+        }
+        assert_eq!(linear_to_srgb_u8(0.5), 188);
+        assert_eq!(linear_to_srgb_u8(-1.0), 0);
+        assert_eq!(linear_to_srgb_u8(2.0), 255);
+        assert_eq!(linear_to_srgb_u8(f32::NAN), 0);
+        assert!((linear_to_srgb_f32(srgb_to_linear_f32(0.25)) - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_unorm8_exhaustive() {
+        for i in 0..=255u8 {
+            assert_eq!(f32_to_unorm8(unorm8_to_f32(i)), i);
+            for a in 0..=255u8 {
+                let expected = (i as f32 * a as f32 / 255.0).round() as u8;
+                assert_eq!(mul_unorm8(i, a), expected);
+            }
+        }
+        assert_eq!(f32_to_unorm8(f32::NAN), 0);
+        assert_eq!(f32_to_unorm8(1.5), 255);
+    }
+
+    #[test]
+    fn test_premultiply_exhaustive() {
+        for a in 0..=255u8 {
+            let mut pixels: Vec<u8> = (0..=255u8).flat_map(|c| [c, 255 - c, c / 2, a]).collect();
+            let original = pixels.clone();
+            premultiply_alpha_rgba8(&mut pixels);
+            unpremultiply_alpha_rgba8(&mut pixels);
+            for (p, o) in pixels.chunks_exact(4).zip(original.chunks_exact(4)) {
+                assert_eq!(p[3], a);
+                // Premultiplying keeps about log2(a) bits of each color.
+                let tolerance = if a == 0 { 255 } else { (255 + a as u32) / (2 * a as u32) };
+                for c in 0..3 {
+                    assert!((p[c] as i32 - o[c] as i32).unsigned_abs() <= tolerance, "{:?} {:?}", p, o);
+                }
+            }
+        }
+
+        let mut pixels = [0.5, 1.0, 0.25, 0.5, 1.0, 1.0, 1.0, 0.0];
+        premultiply_alpha_f32(&mut pixels);
+        assert_eq!(pixels, [0.25, 0.5, 0.125, 0.5, 0.0, 0.0, 0.0, 0.0]);
+        unpremultiply_alpha_f32(&mut pixels);
+        assert_eq!(pixels[..4], [0.5, 1.0, 0.25, 0.5]);
+    }
+
+    #[test]
+    fn test_half_exhaustive() {
+        for half in 0..=u16::MAX {
+            assert_eq!(f32_to_half(half_to_f32(half)), half, "{:#06x}", half);
+        }
+        assert_eq!(f32_to_half(1.0), 0x3C00);
+        assert_eq!(f32_to_half(-2.0), 0xC000);
+        assert_eq!(f32_to_half(65504.0), 0x7BFF);
+        assert_eq!(f32_to_half(65520.0), 0x7C00);
+        assert_eq!(f32_to_half(1e10), 0x7C00);
+        assert_eq!(f32_to_half(f32::NEG_INFINITY), 0xFC00);
+        assert!(half_to_f32(f32_to_half(f32::NAN)).is_nan());
+        assert_eq!(half_to_f32(0x0001), 2.0f32.powi(-24));
+        assert_eq!(f32_to_half(2.0f32.powi(-25)), 0x0000);
+        assert_eq!(f32_to_half(2.0f32.powi(-25) * 1.5), 0x0001);
+        assert_eq!(f32_to_half(1e-10), 0x0000);
+        // Ties round to even.
+        assert_eq!(f32_to_half(1.0 + 2.0f32.powi(-11)), 0x3C00);
+        assert_eq!(f32_to_half(1.0 + 3.0 * 2.0f32.powi(-11)), 0x3C02);
+    }
+
+    #[test]
+    fn test_downsample() {
+        // A black and white checkerboard averages to middle grey in linear light.
+        let checker: Vec<u8> = (0..16).flat_map(|i| {
+            let v = if (i % 4 + i / 4) % 2 == 0 { 255 } else { 0 };
+            [v, v, v, 255]
+        }).collect();
+        let srgb = downsample_rgba8(&checker, 4, 4, MipFilter::Box, true).unwrap();
+        assert!(srgb.chunks_exact(4).all(|p| p == [188, 188, 188, 255]));
+        let linear = downsample_rgba8(&checker, 4, 4, MipFilter::Box, false).unwrap();
+        assert!(linear.chunks_exact(4).all(|p| p == [128, 128, 128, 255]));
+
+        // Transparent pixels don't bleed their color.
+        let cutout = [255, 0, 0, 255, 0, 255, 0, 0, 255, 0, 0, 255, 0, 255, 0, 0];
+        assert_eq!(downsample_rgba8(&cutout, 2, 2, MipFilter::Box, true).unwrap(), [255, 0, 0, 128]);
+
+        // Flat images stay flat, including odd sizes and the Kaiser filter's negative lobes.
+        for &(width, height) in &[(8, 8), (5, 3), (1, 7), (16, 2)] {
+            let flat: Vec<u8> = (0..width * height).flat_map(|_| [200, 100, 50, 180]).collect();
+            for filter in [MipFilter::Box, MipFilter::Kaiser] {
+                let result = downsample_rgba8(&flat, width, height, filter, true).unwrap();
+                assert_eq!(result.len(), mip_size(width) * mip_size(height) * 4);
+                assert!(result.chunks_exact(4).all(|p| p == [200, 100, 50, 180]), "{:?} {:?}", filter, result);
+            }
+        }
+
+        // A box filter over an odd width covers all three columns.
+        let row = [0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0];
+        let (result, width, height) = downsample_f32(&row, 3, 1, MipFilter::Box);
+        assert_eq!((width, height), (1, 1));
+        assert!((result[0] - 1.0 / 3.0).abs() < 1e-6);
+
+        assert!(downsample_rgba8(&checker, 4, 3, MipFilter::Box, true).is_err());
+    }
+}
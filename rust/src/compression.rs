@@ -1,55 +1,2076 @@
-use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::prelude::*;
+use crate::util::ByteReader;
+use crate::{bc_texture, etc_texture, yaz0};
 use std::convert::TryInto;
+use std::fmt;
+use std::io::Write;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecompressError {
+    // The input ended before the end of the compressed stream.
+    Truncated,
+    // The input is not a valid compressed stream.
+    Corrupt(String),
+    // The stream decoded fine, but not to the size the caller asked for.
+    SizeMismatch { expected: usize, actual: usize },
+}
+
+impl fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecompressError::Truncated => write!(f, "compressed data is truncated"),
+            DecompressError::Corrupt(msg) => write!(f, "compressed data is corrupt: {}", msg),
+            DecompressError::SizeMismatch { expected, actual } => write!(f, "expected {} decompressed bytes, got {}", expected, actual),
+        }
+    }
+}
+
+impl std::error::Error for DecompressError {}
+
+impl From<DecompressError> for JsValue {
+    fn from(err: DecompressError) -> Self {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
+pub(crate) fn corrupt<T>(msg: &str) -> Result<T, DecompressError> {
+    Err(DecompressError::Corrupt(msg.to_string()))
+}
 
 #[wasm_bindgen]
-pub fn lz4_decompress(src: &[u8], uncompressed_size: usize) -> Vec<u8> {
-    lz4_flex::decompress(src, uncompressed_size).unwrap()
+pub fn lz4_decompress(src: &[u8], uncompressed_size: usize) -> Result<Vec<u8>, DecompressError> {
+    use lz4_flex::block::DecompressError as Lz4Error;
+    lz4_flex::decompress(src, uncompressed_size).map_err(|err| match err {
+        Lz4Error::LiteralOutOfBounds | Lz4Error::ExpectedAnotherByte => DecompressError::Truncated,
+        // The block format has no end marker, so running out of input early looks like this.
+        Lz4Error::UncompressedSizeDiffers { expected, actual } if actual < expected => DecompressError::Truncated,
+        Lz4Error::UncompressedSizeDiffers { expected, actual } => DecompressError::SizeMismatch { expected, actual },
+        err => DecompressError::Corrupt(err.to_string()),
+    })
+}
+
+#[derive(Clone, Copy)]
+enum Lz4State {
+    Token,
+    Literals { left: usize, token: u8 },
+    Match { token: u8 },
+    Done,
+}
+
+// Streaming LZ4 block format decoder. The block format has no end marker, so the decompressed
+// size must be known up front. Long literal runs are copied out as they arrive.
+#[wasm_bindgen(js_name = "Lz4Decoder")]
+pub struct Lz4Decoder {
+    state: Lz4State,
+    input: Vec<u8>,
+    window: Vec<u8>,
+    uncompressed_size: usize,
+    total_out: usize,
+}
+
+const LZ4_WINDOW_SIZE: usize = 0x10000;
+
+// Reads the rest of a length whose token nibble is 15. Returns None if more input is needed.
+fn lz4_read_length(src: &[u8], pos: &mut usize, mut len: usize) -> Option<usize> {
+    if len == 0x0F {
+        loop {
+            let b = *src.get(*pos)?;
+            *pos += 1;
+            len += b as usize;
+            if b != 0xFF {
+                break;
+            }
+        }
+    }
+    Some(len)
+}
+
+#[wasm_bindgen(js_class = "Lz4Decoder")]
+impl Lz4Decoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new(uncompressed_size: usize) -> Self {
+        Self { state: Lz4State::Token, input: Vec::new(), window: Vec::new(), uncompressed_size, total_out: 0 }
+    }
+
+    // Returns whatever could be decoded with the data pushed so far.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<u8>, DecompressError> {
+        self.input.extend_from_slice(chunk);
+        let start = self.window.len();
+        let mut pos = 0;
+        loop {
+            let src = &self.input[pos..];
+            match self.state {
+                Lz4State::Token => {
+                    let token = match src.first() {
+                        Some(&token) => token,
+                        None => break,
+                    };
+                    let mut len = 1;
+                    let literal_len = match lz4_read_length(src, &mut len, (token >> 4) as usize) {
+                        Some(literal_len) => literal_len,
+                        None => break,
+                    };
+                    if self.total_out + literal_len > self.uncompressed_size {
+                        return corrupt("literals run past the end of the output");
+                    }
+                    pos += len;
+                    self.state = Lz4State::Literals { left: literal_len, token };
+                },
+                Lz4State::Literals { left, token } => {
+                    let n = left.min(src.len());
+                    self.window.extend_from_slice(&src[..n]);
+                    self.total_out += n;
+                    pos += n;
+                    if n < left {
+                        self.state = Lz4State::Literals { left: left - n, token };
+                        break;
+                    }
+                    // The last sequence is literals only.
+                    self.state = if self.total_out == self.uncompressed_size { Lz4State::Done } else { Lz4State::Match { token } };
+                },
+                Lz4State::Match { token } => {
+                    if src.len() < 2 {
+                        break;
+                    }
+                    let offset = u16::from_le_bytes([src[0], src[1]]) as usize;
+                    let mut len = 2;
+                    let match_len = match lz4_read_length(src, &mut len, (token & 0x0F) as usize) {
+                        Some(match_len) => match_len + 4,
+                        None => break,
+                    };
+                    if self.total_out + match_len > self.uncompressed_size {
+                        return corrupt("sequence runs past the end of the output");
+                    }
+                    if offset == 0 || offset > self.window.len() {
+                        return corrupt("match offset out of range");
+                    }
+                    let from = self.window.len() - offset;
+                    for i in 0..match_len {
+                        let b = self.window[from + i];
+                        self.window.push(b);
+                    }
+                    self.total_out += match_len;
+                    pos += len;
+                    self.state = Lz4State::Token;
+                },
+                Lz4State::Done => break,
+            }
+        }
+        self.input.drain(..pos);
+
+        let output = self.window[start..].to_vec();
+        if self.window.len() > LZ4_WINDOW_SIZE * 2 {
+            self.window.drain(..self.window.len() - LZ4_WINDOW_SIZE);
+        }
+        Ok(output)
+    }
+
+    pub fn finish(&mut self) -> Result<Vec<u8>, DecompressError> {
+        if !matches!(self.state, Lz4State::Done) {
+            return Err(DecompressError::Truncated);
+        }
+        if !self.input.is_empty() {
+            return corrupt("trailing data after the last sequence");
+        }
+        Ok(Vec::new())
+    }
 }
 
 #[wasm_bindgen]
 pub fn lzma_decompress(
-    mut src: &[u8],
+    src: &[u8],
     lc: u32,
     lp: u32,
     pb: u32,
     dict_size: u32,
-    unpacked_size: u64,
-) -> Vec<u8> {
-    let properties = lzma_rs::decompress::raw::LzmaProperties {
-        lc: lc,
-        lp: lp,
-        pb: pb,
+    unpacked_size: Option<u64>,
+) -> Result<Vec<u8>, DecompressError> {
+    let mut decoder = LzmaDecoder::new(lc, lp, pb, dict_size, unpacked_size)?;
+    let mut dst = decoder.push(src)?;
+    dst.extend(decoder.finish()?);
+    if let Some(unpacked_size) = unpacked_size {
+        if dst.len() as u64 != unpacked_size {
+            return Err(DecompressError::SizeMismatch { expected: unpacked_size as usize, actual: dst.len() });
+        }
+    }
+    Ok(dst)
+}
+
+// Raw LZMA decoder. Without an unpacked size, the stream must end with an end marker.
+#[wasm_bindgen(js_name = "LzmaDecoder")]
+pub struct LzmaDecoder {
+    stream: Option<lzma_rs::decompress::Stream<Vec<u8>>>,
+}
+
+fn lzma_error(err: lzma_rs::error::Error) -> DecompressError {
+    match err {
+        lzma_rs::error::Error::IoError(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => DecompressError::Truncated,
+        lzma_rs::error::Error::IoError(e) => DecompressError::Corrupt(e.to_string()),
+        lzma_rs::error::Error::HeaderTooShort(_) => DecompressError::Truncated,
+        lzma_rs::error::Error::LzmaError(msg) | lzma_rs::error::Error::XzError(msg) => DecompressError::Corrupt(msg),
+    }
+}
+
+#[wasm_bindgen(js_class = "LzmaDecoder")]
+impl LzmaDecoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new(lc: u32, lp: u32, pb: u32, dict_size: u32, unpacked_size: Option<u64>) -> Result<LzmaDecoder, DecompressError> {
+        if lc > 8 || lp > 4 || pb > 4 {
+            return corrupt("invalid LZMA properties");
+        }
+
+        // The stream decoder only understands .lzma files, so give it the header it expects.
+        let options = lzma_rs::decompress::Options {
+            unpacked_size: lzma_rs::decompress::UnpackedSize::ReadFromHeader,
+            memlimit: None,
+            allow_incomplete: false,
+        };
+        let mut stream = lzma_rs::decompress::Stream::new_with_options(&options, Vec::new());
+        let mut header = vec![((pb * 5 + lp) * 9 + lc) as u8];
+        header.extend_from_slice(&dict_size.to_le_bytes());
+        header.extend_from_slice(&unpacked_size.unwrap_or(u64::MAX).to_le_bytes());
+        stream.write_all(&header).map_err(|e| DecompressError::Corrupt(e.to_string()))?;
+        Ok(Self { stream: Some(stream) })
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<u8>, DecompressError> {
+        let stream = self.stream.as_mut().ok_or(DecompressError::Corrupt("decoder already finished".to_string()))?;
+        let mut chunk = chunk;
+        while !chunk.is_empty() {
+            match stream.write(chunk).map_err(|e| DecompressError::Corrupt(e.to_string()))? {
+                // The stream has ended; anything after it is ignored.
+                0 => break,
+                n => chunk = &chunk[n..],
+            }
+        }
+        Ok(std::mem::take(stream.get_output_mut().unwrap()))
+    }
+
+    pub fn finish(&mut self) -> Result<Vec<u8>, DecompressError> {
+        let stream = self.stream.take().ok_or(DecompressError::Corrupt("decoder already finished".to_string()))?;
+        stream.finish().map_err(lzma_error)
+    }
+}
+
+#[wasm_bindgen]
+pub fn deflate_decompress(src: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    let mut inflater = Inflater::new_zlib();
+    let mut dst = inflater.push(src)?;
+    dst.extend(inflater.finish()?);
+    Ok(dst)
+}
+
+#[wasm_bindgen]
+pub fn deflate_raw_decompress(src: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    let mut inflater = Inflater::new_raw();
+    let mut dst = inflater.push(src)?;
+    dst.extend(inflater.finish()?);
+    Ok(dst)
+}
+
+// InflateStream stops whenever its window fills up, so keep calling it until it neither reads
+// nor writes anything.
+fn inflate_update(stream: &mut inflate::InflateStream, mut data: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    let mut dst = Vec::new();
+    loop {
+        let (used, output) = stream.update(data).map_err(DecompressError::Corrupt)?;
+        if used == 0 && output.is_empty() {
+            return Ok(dst);
+        }
+        dst.extend_from_slice(output);
+        data = &data[used..];
+    }
+}
+
+const INFLATE_PROBE_SIZE: usize = 0x400;
+
+// InflateStream quietly swallows anything after the end of the stream, and doesn't say whether
+// it got there. A live stream can't take many 0xFF bytes without decoding something or failing
+// on a reserved block type, a bad stored length or a bad checksum, so one that takes them
+// silently has ended.
+fn inflate_finished(stream: &mut inflate::InflateStream) -> bool {
+    matches!(inflate_update(stream, &[0xFF; INFLATE_PROBE_SIZE]), Ok(output) if output.is_empty())
+}
+
+// Streaming deflate decoder, for either raw or zlib-wrapped data.
+#[wasm_bindgen(js_name = "Inflater")]
+pub struct Inflater {
+    stream: Option<inflate::InflateStream>,
+}
+
+#[wasm_bindgen(js_class = "Inflater")]
+impl Inflater {
+    pub fn new_raw() -> Self {
+        Self { stream: Some(inflate::InflateStream::new()) }
+    }
+
+    pub fn new_zlib() -> Self {
+        Self { stream: Some(inflate::InflateStream::from_zlib()) }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<u8>, DecompressError> {
+        let stream = self.stream.as_mut().ok_or(DecompressError::Corrupt("decoder already finished".to_string()))?;
+        inflate_update(stream, chunk)
+    }
+
+    pub fn finish(&mut self) -> Result<Vec<u8>, DecompressError> {
+        let mut stream = self.stream.take().ok_or(DecompressError::Corrupt("decoder already finished".to_string()))?;
+        if !inflate_finished(&mut stream) {
+            return Err(DecompressError::Truncated);
+        }
+        Ok(Vec::new())
+    }
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+pub(crate) fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut c = !crc;
+    for &b in data {
+        c = CRC32_TABLE[((c ^ b as u32) & 0xFF) as usize] ^ (c >> 8);
+    }
+    !c
+}
+
+const GZIP_FHCRC: u8 = 0x02;
+const GZIP_FEXTRA: u8 = 0x04;
+const GZIP_FNAME: u8 = 0x08;
+const GZIP_FCOMMENT: u8 = 0x10;
+
+// Decodes one gzip member onto dst, returning its size.
+fn gzip_decode_member(src: &[u8], dst: &mut Vec<u8>) -> Result<usize, DecompressError> {
+    let header = src.get(..10).ok_or(DecompressError::Truncated)?;
+    if header[0..2] != [0x1F, 0x8B] {
+        return corrupt("not a gzip stream");
+    }
+    if header[2] != 8 {
+        return corrupt("unsupported gzip compression method");
+    }
+    let flags = header[3];
+    if flags & 0xE0 != 0 {
+        return corrupt("reserved gzip flags are set");
+    }
+
+    let mut pos = 10;
+    if flags & GZIP_FEXTRA != 0 {
+        let xlen = src.get(pos..pos + 2).ok_or(DecompressError::Truncated)?;
+        pos += 2 + u16::from_le_bytes(xlen.try_into().unwrap()) as usize;
+    }
+    for flag in [GZIP_FNAME, GZIP_FCOMMENT] {
+        if flags & flag != 0 {
+            let rest = src.get(pos..).ok_or(DecompressError::Truncated)?;
+            pos += rest.iter().position(|&b| b == 0).ok_or(DecompressError::Truncated)? + 1;
+        }
+    }
+    if flags & GZIP_FHCRC != 0 {
+        let expected = src.get(pos..pos + 2).ok_or(DecompressError::Truncated)?;
+        if crc32(0, &src[..pos]) as u16 != u16::from_le_bytes(expected.try_into().unwrap()) {
+            return corrupt("gzip header CRC mismatch");
+        }
+        pos += 2;
+    }
+
+    let mut stream = inflate::InflateStream::new();
+    let data = inflate_update(&mut stream, src.get(pos..).ok_or(DecompressError::Truncated)?)?;
+    if !inflate_finished(&mut stream) {
+        return Err(DecompressError::Truncated);
+    }
+
+    // InflateStream doesn't say where the deflate data ended, so look for the trailer, which
+    // must be followed by the end of the input or another member. A trailer with only one of
+    // the CRC and size right is reported as a mismatch; one with neither hasn't arrived yet.
+    let (crc, size) = (crc32(0, &data).to_le_bytes(), (data.len() as u32).to_le_bytes());
+    let end = (pos + 8..=src.len()).find(|&end| {
+        let trailer = &src[end - 8..end];
+        (trailer[0..4] == crc || trailer[4..8] == size) && (end == src.len() || src[end..].starts_with(&[0x1F, 0x8B]))
+    }).ok_or(DecompressError::Truncated)?;
+    let trailer = &src[end - 8..end];
+    if trailer[0..4] != crc {
+        return corrupt("gzip CRC-32 mismatch");
+    }
+    // The size is stored modulo 2^32.
+    if trailer[4..8] != size {
+        let expected = u32::from_le_bytes(trailer[4..8].try_into().unwrap());
+        return Err(DecompressError::SizeMismatch { expected: expected as usize, actual: data.len() });
+    }
+    dst.extend_from_slice(&data);
+    Ok(end)
+}
+
+// Concatenated members are decoded one after the other, as gunzip does.
+#[wasm_bindgen]
+pub fn gzip_decompress(src: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    let mut dst = Vec::new();
+    let mut pos = 0;
+    loop {
+        pos += gzip_decode_member(&src[pos..], &mut dst)?;
+        if pos == src.len() {
+            return Ok(dst);
+        }
+    }
+}
+
+// Zstandard (RFC 8878), decoded with ruzstd.
+const ZSTD_MAGIC: u32 = 0xFD2FB528;
+
+// ruzstd reports running out of input as an I/O error somewhere down the error chain.
+fn zstd_error(err: ruzstd::frame_decoder::FrameDecoderError) -> DecompressError {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(&err);
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                return DecompressError::Truncated;
+            }
+        }
+        source = e.source();
+    }
+    DecompressError::Corrupt(err.to_string())
+}
+
+// A zstd dictionary: either a formatted one with an ID and entropy tables, or raw content.
+#[wasm_bindgen(js_name = "ZstdDictionary")]
+pub struct ZstdDictionary {
+    id: u32,
+    data: Vec<u8>,
+    formatted: bool,
+}
+
+#[wasm_bindgen(js_class = "ZstdDictionary")]
+impl ZstdDictionary {
+    pub fn new(data: &[u8]) -> Result<ZstdDictionary, DecompressError> {
+        let formatted = data.starts_with(&ruzstd::decoding::dictionary::MAGIC_NUM);
+        let id = if formatted {
+            ruzstd::decoding::dictionary::Dictionary::decode_dict(data)
+                .map_err(|err| DecompressError::Corrupt(err.to_string()))?.id
+        } else {
+            0
+        };
+        Ok(Self { id, data: data.to_vec(), formatted })
+    }
+
+    pub fn get_id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl ZstdDictionary {
+    fn to_ruzstd(&self) -> Result<ruzstd::decoding::dictionary::Dictionary, DecompressError> {
+        use ruzstd::decoding::{dictionary::Dictionary, scratch};
+        if self.formatted {
+            return Dictionary::decode_dict(&self.data).map_err(|err| DecompressError::Corrupt(err.to_string()));
+        }
+        Ok(Dictionary {
+            id: 0,
+            fse: scratch::FSEScratch::new(),
+            huf: scratch::HuffmanScratch::new(),
+            dict_content: self.data.clone(),
+            offset_hist: [1, 4, 8],
+        })
+    }
+}
+
+fn zstd_decompress_frames(mut src: &[u8], dict: Option<&ZstdDictionary>) -> Result<Vec<u8>, DecompressError> {
+    use ruzstd::frame::ReadFrameHeaderError;
+    use ruzstd::frame_decoder::{BlockDecodingStrategy, FrameDecoder, FrameDecoderError};
+
+    if src.is_empty() {
+        return Err(DecompressError::Truncated);
+    }
+    let mut decoder = FrameDecoder::new();
+    if let Some(dict) = dict {
+        decoder.add_dict(dict.to_ruzstd()?).map_err(zstd_error)?;
+    }
+
+    let mut dst = Vec::new();
+    while !src.is_empty() {
+        match decoder.reset(&mut src) {
+            Ok(()) => {},
+            Err(FrameDecoderError::ReadFrameHeaderError(ReadFrameHeaderError::SkipFrame { length, .. })) => {
+                src = src.get(length as usize..).ok_or(DecompressError::Truncated)?;
+                continue;
+            },
+            Err(err) => return Err(zstd_error(err)),
+        }
+        // Frames without a dictionary ID still use the one they were given, as zstd does.
+        if let Some(dict) = dict {
+            decoder.force_dict(dict.id).map_err(zstd_error)?;
+        }
+        decoder.decode_blocks(&mut src, BlockDecodingStrategy::All).map_err(zstd_error)?;
+        if !decoder.is_finished() {
+            return Err(DecompressError::Truncated);
+        }
+        // The checksum only covers what has been collected.
+        dst.extend(decoder.collect().unwrap_or_default());
+        if let Some(expected) = decoder.get_checksum_from_data() {
+            if decoder.get_calculated_checksum() != Some(expected) {
+                return corrupt("content checksum mismatch");
+            }
+        }
+    }
+    Ok(dst)
+}
+
+// Decodes a sequence of zstd frames. Skippable frames are ignored.
+#[wasm_bindgen]
+pub fn zstd_decompress(src: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    zstd_decompress_frames(src, None)
+}
+
+#[wasm_bindgen]
+pub fn zstd_decompress_with_dictionary(src: &[u8], dict: &ZstdDictionary) -> Result<Vec<u8>, DecompressError> {
+    zstd_decompress_frames(src, Some(dict))
+}
+
+// Hash chain match finder, shared by the deflate and LZMA encoders.
+struct MatchFinder<'a> {
+    data: &'a [u8],
+    window_size: usize,
+    max_len: usize,
+    max_chain: usize,
+    // Positions are stored plus one, so that zero means empty.
+    head: Vec<u32>,
+    prev: Vec<u32>,
+}
+
+const MATCH_HASH_BITS: u32 = 15;
+
+impl<'a> MatchFinder<'a> {
+    fn new(data: &'a [u8], window_size: usize, max_len: usize, max_chain: usize) -> Self {
+        Self {
+            data,
+            window_size,
+            max_len,
+            max_chain,
+            head: vec![0; 1 << MATCH_HASH_BITS],
+            prev: vec![0; window_size.min(data.len()).max(1)],
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let v = u32::from_le_bytes([self.data[pos], self.data[pos + 1], self.data[pos + 2], 0]);
+        (v.wrapping_mul(0x9E3779B1) >> (32 - MATCH_HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if pos + 3 > self.data.len() {
+            return;
+        }
+        let hash = self.hash(pos);
+        let prev_len = self.prev.len();
+        self.prev[pos % prev_len] = self.head[hash];
+        self.head[hash] = pos as u32 + 1;
+    }
+
+    // Returns the longest earlier match for pos as (length, distance). Call before insert(pos).
+    fn find(&self, pos: usize) -> Option<(usize, usize)> {
+        if pos + 3 > self.data.len() {
+            return None;
+        }
+        let max_len = self.max_len.min(self.data.len() - pos);
+        let (mut best_len, mut best_dist) = (0, 0);
+        let mut candidate = self.head[self.hash(pos)];
+        let mut chain = self.max_chain;
+        while candidate != 0 && chain > 0 {
+            let c = candidate as usize - 1;
+            if pos - c > self.window_size {
+                break;
+            }
+            if self.data[c + best_len] == self.data[pos + best_len] {
+                let len = self.data[c..c + max_len].iter().zip(&self.data[pos..pos + max_len]).take_while(|(a, b)| a == b).count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = pos - c;
+                    if len == max_len {
+                        break;
+                    }
+                }
+            }
+            let next = self.prev[c % self.prev.len()];
+            // The slot was reused by a newer position, so the chain ends here.
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+            chain -= 1;
+        }
+        if best_len >= 3 { Some((best_len, best_dist)) } else { None }
+    }
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new(out: Vec<u8>) -> Self {
+        Self { out, bits: 0, count: 0 }
+    }
+
+    fn write(&mut self, value: u32, n: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn align_to_byte(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+            self.bits = 0;
+            self.count = 0;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align_to_byte();
+        self.out
+    }
+}
+
+// Huffman code lengths for the given frequencies, limited to max_len bits.
+fn huffman_lengths(freqs: &[u32], max_len: u32) -> Vec<u8> {
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+
+    let mut lengths = vec![0u8; freqs.len()];
+    let mut symbols: Vec<usize> = (0..freqs.len()).filter(|&i| freqs[i] > 0).collect();
+    if symbols.len() < 2 {
+        for &symbol in &symbols {
+            lengths[symbol] = 1;
+        }
+        return lengths;
+    }
+
+    let n = symbols.len();
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = symbols.iter().enumerate().map(|(i, &s)| Reverse((freqs[s] as u64, i))).collect();
+    let mut parent = vec![0usize; n * 2 - 1];
+    let mut next = n;
+    while heap.len() > 1 {
+        let Reverse((wa, a)) = heap.pop().unwrap();
+        let Reverse((wb, b)) = heap.pop().unwrap();
+        parent[a] = next;
+        parent[b] = next;
+        heap.push(Reverse((wa + wb, next)));
+        next += 1;
+    }
+
+    // Parents are always created after their children, so walk down from the root.
+    let root = next - 1;
+    let mut depth = vec![0u32; next];
+    for i in (0..root).rev() {
+        depth[i] = depth[parent[i]] + 1;
+    }
+
+    // Clamp to max_len, then rebalance until the code is complete again.
+    let mut counts = vec![0u32; max_len as usize + 1];
+    for &d in &depth[..n] {
+        counts[d.min(max_len) as usize] += 1;
+    }
+    let mut total: u32 = (1..=max_len).map(|i| counts[i as usize] << (max_len - i)).sum();
+    while total > 1 << max_len {
+        counts[max_len as usize] -= 1;
+        for i in (1..max_len as usize).rev() {
+            if counts[i] > 0 {
+                counts[i] -= 1;
+                counts[i + 1] += 2;
+                break;
+            }
+        }
+        total -= 1;
+    }
+
+    // Most frequent symbols get the shortest codes.
+    symbols.sort_by_key(|&s| Reverse(freqs[s]));
+    let mut symbols = symbols.into_iter();
+    for (len, &count) in counts.iter().enumerate().skip(1) {
+        for _ in 0..count {
+            lengths[symbols.next().unwrap()] = len as u8;
+        }
+    }
+    lengths
+}
+
+// Canonical codes for the given lengths, bit-reversed for writing LSB-first.
+fn huffman_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut counts = [0u16; 16];
+    for &len in lengths {
+        counts[len as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut next_code = [0u32; 16];
+    for len in 1..16 {
+        next_code[len] = (next_code[len - 1] + counts[len - 1] as u32) << 1;
+    }
+    lengths.iter().map(|&len| {
+        if len == 0 {
+            return 0;
+        }
+        let code = next_code[len as usize];
+        next_code[len as usize] += 1;
+        (code.reverse_bits() >> (32 - len as u32)) as u16
+    }).collect()
+}
+
+const DEFLATE_WINDOW_SIZE: usize = 0x8000;
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn fixed_lengths() -> ([u8; 288], [u8; 30]) {
+    let mut lengths = [0u8; 288];
+    lengths[0..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..288].fill(8);
+    (lengths, [5; 30])
+}
+
+#[derive(Clone, Copy)]
+enum DeflateToken {
+    Literal(u8),
+    Match { len: u16, dist: u16 },
+}
+
+fn length_code(len: usize) -> usize {
+    LENGTH_BASE.iter().rposition(|&base| base as usize <= len).unwrap()
+}
+
+fn dist_code(dist: usize) -> usize {
+    DIST_BASE.iter().rposition(|&base| base as usize <= dist).unwrap()
+}
+
+// (max hash chain length, lazy matching) for levels 1-9.
+fn deflate_level_params(level: u32) -> (usize, bool) {
+    match level {
+        1 => (4, false),
+        2 => (8, false),
+        3 => (16, false),
+        4 => (16, true),
+        5 => (32, true),
+        6 => (128, true),
+        7 => (256, true),
+        8 => (1024, true),
+        _ => (4096, true),
+    }
+}
+
+fn deflate_tokens(src: &[u8], level: u32) -> Vec<DeflateToken> {
+    let (max_chain, lazy) = deflate_level_params(level);
+    let mut finder = MatchFinder::new(src, DEFLATE_WINDOW_SIZE, 258, max_chain);
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < src.len() {
+        let found = finder.find(pos);
+        finder.insert(pos);
+        match found {
+            // With lazy matching, a literal is emitted if the next position has a longer match.
+            Some((len, _)) if lazy && finder.find(pos + 1).is_some_and(|(next_len, _)| next_len > len) => {
+                tokens.push(DeflateToken::Literal(src[pos]));
+                pos += 1;
+            },
+            Some((len, dist)) => {
+                tokens.push(DeflateToken::Match { len: len as u16, dist: dist as u16 });
+                for p in pos + 1..pos + len {
+                    finder.insert(p);
+                }
+                pos += len;
+            },
+            None => {
+                tokens.push(DeflateToken::Literal(src[pos]));
+                pos += 1;
+            },
+        }
+    }
+    tokens
+}
+
+fn deflate_token_cost(tokens: &[DeflateToken], lit_lengths: &[u8], dist_lengths: &[u8]) -> usize {
+    tokens.iter().map(|token| match *token {
+        DeflateToken::Literal(b) => lit_lengths[b as usize] as usize,
+        DeflateToken::Match { len, dist } => {
+            let lc = length_code(len as usize);
+            let dc = dist_code(dist as usize);
+            (lit_lengths[257 + lc] + LENGTH_EXTRA[lc] + dist_lengths[dc] + DIST_EXTRA[dc]) as usize
+        },
+    }).sum::<usize>() + lit_lengths[256] as usize
+}
+
+fn write_deflate_tokens(w: &mut BitWriter, tokens: &[DeflateToken], lit_lengths: &[u8], dist_lengths: &[u8]) {
+    let lit_codes = huffman_codes(lit_lengths);
+    let dist_codes = huffman_codes(dist_lengths);
+    for token in tokens {
+        match *token {
+            DeflateToken::Literal(b) => w.write(lit_codes[b as usize] as u32, lit_lengths[b as usize] as u32),
+            DeflateToken::Match { len, dist } => {
+                let lc = length_code(len as usize);
+                w.write(lit_codes[257 + lc] as u32, lit_lengths[257 + lc] as u32);
+                w.write(len as u32 - LENGTH_BASE[lc] as u32, LENGTH_EXTRA[lc] as u32);
+                let dc = dist_code(dist as usize);
+                w.write(dist_codes[dc] as u32, dist_lengths[dc] as u32);
+                w.write(dist as u32 - DIST_BASE[dc] as u32, DIST_EXTRA[dc] as u32);
+            },
+        }
+    }
+    w.write(lit_codes[256] as u32, lit_lengths[256] as u32);
+}
+
+// Run-length encodes code lengths with the code length alphabet, as (symbol, extra bits).
+fn code_length_rle(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut rle = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let len = lengths[i];
+        let run = lengths[i..].iter().take_while(|&&l| l == len).count();
+        let mut left = run;
+        if len == 0 {
+            while left >= 11 {
+                let n = left.min(138);
+                rle.push((18, (n - 11) as u8));
+                left -= n;
+            }
+            if left >= 3 {
+                rle.push((17, (left - 3) as u8));
+                left = 0;
+            }
+        } else {
+            rle.push((len, 0));
+            left -= 1;
+            while left >= 3 {
+                let n = left.min(6);
+                rle.push((16, (n - 3) as u8));
+                left -= n;
+            }
+        }
+        rle.extend(std::iter::repeat_n((len, 0), left));
+        i += run;
+    }
+    rle
+}
+
+fn write_stored_blocks(w: &mut BitWriter, data: &[u8], is_final: bool) {
+    let mut chunks = data.chunks(0xFFFF).peekable();
+    if chunks.peek().is_none() {
+        w.write(is_final as u32, 1);
+        w.write(0, 2);
+        w.align_to_byte();
+        w.write(0, 16);
+        w.write(0xFFFF, 16);
+        return;
+    }
+    while let Some(chunk) = chunks.next() {
+        w.write((is_final && chunks.peek().is_none()) as u32, 1);
+        w.write(0, 2);
+        w.align_to_byte();
+        w.write(chunk.len() as u32, 16);
+        w.write(!chunk.len() as u32 & 0xFFFF, 16);
+        w.out.extend_from_slice(chunk);
+    }
+}
+
+// Writes the tokens for data as whichever of a stored, fixed or dynamic block is smallest.
+fn write_deflate_block(w: &mut BitWriter, tokens: &[DeflateToken], data: &[u8], is_final: bool) {
+    let mut lit_freqs = [0u32; 286];
+    let mut dist_freqs = [0u32; 30];
+    lit_freqs[256] = 1;
+    for token in tokens {
+        match *token {
+            DeflateToken::Literal(b) => lit_freqs[b as usize] += 1,
+            DeflateToken::Match { len, dist } => {
+                lit_freqs[257 + length_code(len as usize)] += 1;
+                dist_freqs[dist_code(dist as usize)] += 1;
+            },
+        }
+    }
+
+    let lit_lengths = huffman_lengths(&lit_freqs, 15);
+    let mut dist_lengths = huffman_lengths(&dist_freqs, 15);
+    if dist_lengths.iter().all(|&l| l == 0) {
+        dist_lengths[0] = 1;
+    }
+    let hlit = 257.max(lit_lengths.iter().rposition(|&l| l != 0).unwrap() + 1);
+    let hdist = dist_lengths.iter().rposition(|&l| l != 0).unwrap() + 1;
+
+    let mut all_lengths = lit_lengths[..hlit].to_vec();
+    all_lengths.extend_from_slice(&dist_lengths[..hdist]);
+    let rle = code_length_rle(&all_lengths);
+    let mut cl_freqs = [0u32; 19];
+    for &(symbol, _) in &rle {
+        cl_freqs[symbol as usize] += 1;
+    }
+    let cl_lengths = huffman_lengths(&cl_freqs, 7);
+    let hclen = 4.max(CODE_LENGTH_ORDER.iter().rposition(|&i| cl_lengths[i] != 0).unwrap() + 1);
+    let extra_bits = |symbol: u8| match symbol { 16 => 2, 17 => 3, 18 => 7, _ => 0 };
+
+    let dynamic_cost = 3 + 14 + hclen * 3
+        + rle.iter().map(|&(symbol, _)| cl_lengths[symbol as usize] as usize + extra_bits(symbol)).sum::<usize>()
+        + deflate_token_cost(tokens, &lit_lengths, &dist_lengths);
+    let (fixed_lit, fixed_dist) = fixed_lengths();
+    let fixed_cost = 3 + deflate_token_cost(tokens, &fixed_lit, &fixed_dist);
+    let stored_cost = (3 + 7 + 32) * (data.len() / 0xFFFF + 1) + data.len() * 8;
+
+    if stored_cost <= fixed_cost && stored_cost <= dynamic_cost {
+        write_stored_blocks(w, data, is_final);
+    } else if fixed_cost <= dynamic_cost {
+        w.write(is_final as u32, 1);
+        w.write(1, 2);
+        write_deflate_tokens(w, tokens, &fixed_lit, &fixed_dist);
+    } else {
+        w.write(is_final as u32, 1);
+        w.write(2, 2);
+        w.write(hlit as u32 - 257, 5);
+        w.write(hdist as u32 - 1, 5);
+        w.write(hclen as u32 - 4, 4);
+        for &i in &CODE_LENGTH_ORDER[..hclen] {
+            w.write(cl_lengths[i] as u32, 3);
+        }
+        let cl_codes = huffman_codes(&cl_lengths);
+        for &(symbol, extra) in &rle {
+            w.write(cl_codes[symbol as usize] as u32, cl_lengths[symbol as usize] as u32);
+            w.write(extra as u32, extra_bits(symbol) as u32);
+        }
+        write_deflate_tokens(w, tokens, &lit_lengths, &dist_lengths);
+    }
+}
+
+const DEFLATE_BLOCK_TOKENS: usize = 0x4000;
+
+fn deflate_raw_compress_into(out: Vec<u8>, src: &[u8], level: u32) -> Vec<u8> {
+    let mut w = BitWriter::new(out);
+    if level == 0 {
+        write_stored_blocks(&mut w, src, true);
+        return w.finish();
+    }
+
+    let tokens = deflate_tokens(src, level);
+    if tokens.is_empty() {
+        write_deflate_block(&mut w, &[], &[], true);
+    }
+    let mut pos = 0;
+    let num_blocks = tokens.len().div_ceil(DEFLATE_BLOCK_TOKENS);
+    for (i, block) in tokens.chunks(DEFLATE_BLOCK_TOKENS).enumerate() {
+        let size: usize = block.iter().map(|t| match *t { DeflateToken::Literal(_) => 1, DeflateToken::Match { len, .. } => len as usize }).sum();
+        write_deflate_block(&mut w, block, &src[pos..pos + size], i + 1 == num_blocks);
+        pos += size;
+    }
+    w.finish()
+}
+
+// level is 0 (stored) to 9 (best), as in zlib.
+#[wasm_bindgen]
+pub fn deflate_raw_compress(src: &[u8], level: u32) -> Vec<u8> {
+    deflate_raw_compress_into(Vec::new(), src, level.min(9))
+}
+
+fn adler32(mut adler: u32, data: &[u8]) -> u32 {
+    let (mut a, mut b) = (adler & 0xFFFF, adler >> 16);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    adler = (b << 16) | a;
+    adler
+}
+
+#[wasm_bindgen]
+pub fn deflate_compress(src: &[u8], level: u32) -> Vec<u8> {
+    let level = level.min(9);
+    let cmf = 0x78u32;
+    let mut flg = match level { 0 | 1 => 0, 2..=5 => 1, 6 => 2, _ => 3 } << 6;
+    flg += 31 - ((cmf << 8) | flg) % 31;
+    let mut dst = deflate_raw_compress_into(vec![cmf as u8, flg as u8], src, level);
+    dst.extend_from_slice(&adler32(1, src).to_be_bytes());
+    dst
+}
+
+#[wasm_bindgen]
+pub fn lz4_compress(src: &[u8]) -> Vec<u8> {
+    lz4_flex::compress(src)
+}
+
+fn xxh32(data: &[u8], seed: u32) -> u32 {
+    const P1: u32 = 2654435761;
+    const P2: u32 = 2246822519;
+    const P3: u32 = 3266489917;
+    const P4: u32 = 668265263;
+    const P5: u32 = 374761393;
+
+    let read = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+    let round = |acc: u32, input: u32| acc.wrapping_add(input.wrapping_mul(P2)).rotate_left(13).wrapping_mul(P1);
+
+    let mut i = 0;
+    let mut h = if data.len() >= 16 {
+        let mut v = [seed.wrapping_add(P1).wrapping_add(P2), seed.wrapping_add(P2), seed, seed.wrapping_sub(P1)];
+        while i + 16 <= data.len() {
+            for (lane, acc) in v.iter_mut().enumerate() {
+                *acc = round(*acc, read(i + lane * 4));
+            }
+            i += 16;
+        }
+        v[0].rotate_left(1).wrapping_add(v[1].rotate_left(7)).wrapping_add(v[2].rotate_left(12)).wrapping_add(v[3].rotate_left(18))
+    } else {
+        seed.wrapping_add(P5)
+    };
+
+    h = h.wrapping_add(data.len() as u32);
+    while i + 4 <= data.len() {
+        h = h.wrapping_add(read(i).wrapping_mul(P3)).rotate_left(17).wrapping_mul(P4);
+        i += 4;
+    }
+    for &b in &data[i..] {
+        h = h.wrapping_add((b as u32).wrapping_mul(P5)).rotate_left(11).wrapping_mul(P1);
+    }
+    h ^= h >> 15;
+    h = h.wrapping_mul(P2);
+    h ^= h >> 13;
+    h = h.wrapping_mul(P3);
+    h ^= h >> 16;
+    h
+}
+
+const LZ4_FRAME_MAGIC: u32 = 0x184D2204;
+const LZ4_FRAME_BLOCK_SIZE: usize = 0x10000;
+
+// LZ4 frame with independent 64KB blocks, the content size and a content checksum.
+#[wasm_bindgen]
+pub fn lz4_frame_compress(src: &[u8]) -> Vec<u8> {
+    let mut dst = LZ4_FRAME_MAGIC.to_le_bytes().to_vec();
+    let mut descriptor = vec![0x6C, 0x40];
+    descriptor.extend_from_slice(&(src.len() as u64).to_le_bytes());
+    dst.extend_from_slice(&descriptor);
+    dst.push((xxh32(&descriptor, 0) >> 8) as u8);
+
+    for chunk in src.chunks(LZ4_FRAME_BLOCK_SIZE) {
+        let compressed = lz4_flex::compress(chunk);
+        if compressed.len() < chunk.len() {
+            dst.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            dst.extend_from_slice(&compressed);
+        } else {
+            dst.extend_from_slice(&(chunk.len() as u32 | 0x80000000).to_le_bytes());
+            dst.extend_from_slice(chunk);
+        }
+    }
+    dst.extend_from_slice(&0u32.to_le_bytes());
+    dst.extend_from_slice(&xxh32(src, 0).to_le_bytes());
+    dst
+}
+
+// Decodes a sequence of LZ4 frames. Skippable frames are ignored.
+#[wasm_bindgen]
+pub fn lz4_frame_decompress(src: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    fn take<'a>(src: &mut &'a [u8], n: usize) -> Result<&'a [u8], DecompressError> {
+        if src.len() < n {
+            return Err(DecompressError::Truncated);
+        }
+        let (head, tail) = src.split_at(n);
+        *src = tail;
+        Ok(head)
+    }
+    fn take_u32(src: &mut &[u8]) -> Result<u32, DecompressError> {
+        Ok(u32::from_le_bytes(take(src, 4)?.try_into().unwrap()))
+    }
+
+    let mut src = src;
+    let mut dst = Vec::new();
+    while !src.is_empty() {
+        let magic = take_u32(&mut src)?;
+        if magic & 0xFFFFFFF0 == 0x184D2A50 {
+            let size = take_u32(&mut src)? as usize;
+            take(&mut src, size)?;
+            continue;
+        }
+        if magic != LZ4_FRAME_MAGIC {
+            return corrupt("bad LZ4 frame magic");
+        }
+
+        let descriptor = src;
+        let flg = take(&mut src, 1)?[0];
+        let bd = take(&mut src, 1)?[0];
+        if flg >> 6 != 1 {
+            return corrupt("unsupported LZ4 frame version");
+        }
+        if flg & 0x01 != 0 {
+            return corrupt("LZ4 dictionaries are not supported");
+        }
+        let independent = flg & 0x20 != 0;
+        let block_checksum = flg & 0x10 != 0;
+        let content_checksum = flg & 0x04 != 0;
+        let max_block_size = match (bd >> 4) & 0x07 {
+            n @ 4..=7 => 0x10000 << (2 * (n - 4)),
+            _ => return corrupt("bad LZ4 block size"),
+        };
+        let content_size = if flg & 0x08 != 0 { Some(u64::from_le_bytes(take(&mut src, 8)?.try_into().unwrap())) } else { None };
+        let descriptor_len = if content_size.is_some() { 10 } else { 2 };
+        let hc = take(&mut src, 1)?[0];
+        if (xxh32(&descriptor[..descriptor_len], 0) >> 8) as u8 != hc {
+            return corrupt("LZ4 frame header checksum mismatch");
+        }
+
+        let frame_start = dst.len();
+        let mut block = vec![0u8; max_block_size];
+        loop {
+            let size = take_u32(&mut src)?;
+            if size == 0 {
+                break;
+            }
+            let data = take(&mut src, (size & 0x7FFFFFFF) as usize)?;
+            if block_checksum && take_u32(&mut src)? != xxh32(data, 0) {
+                return corrupt("LZ4 block checksum mismatch");
+            }
+            if size & 0x80000000 != 0 {
+                dst.extend_from_slice(data);
+                continue;
+            }
+            let dict = if independent { &[][..] } else { &dst[frame_start.max(dst.len().saturating_sub(LZ4_WINDOW_SIZE))..] };
+            let len = lz4_flex::block::decompress_into_with_dict(data, &mut block, dict)
+                .map_err(|e| DecompressError::Corrupt(e.to_string()))?;
+            dst.extend_from_slice(&block[..len]);
+        }
+
+        let frame = &dst[frame_start..];
+        if content_checksum && take_u32(&mut src)? != xxh32(frame, 0) {
+            return corrupt("LZ4 content checksum mismatch");
+        }
+        if let Some(content_size) = content_size {
+            if frame.len() as u64 != content_size {
+                return Err(DecompressError::SizeMismatch { expected: content_size as usize, actual: frame.len() });
+            }
+        }
+    }
+    Ok(dst)
+}
+
+struct RangeEncoder {
+    low: u64,
+    range: u32,
+    cache: u8,
+    cache_size: u64,
+    out: Vec<u8>,
+}
+
+const LZMA_PROB_INIT: u16 = 0x400;
+
+impl RangeEncoder {
+    fn new() -> Self {
+        Self { low: 0, range: 0xFFFFFFFF, cache: 0, cache_size: 1, out: Vec::new() }
+    }
+
+    fn shift_low(&mut self) {
+        if (self.low as u32) < 0xFF000000 || (self.low >> 32) != 0 {
+            let carry = (self.low >> 32) as u8;
+            let mut temp = self.cache;
+            loop {
+                self.out.push(temp.wrapping_add(carry));
+                temp = 0xFF;
+                self.cache_size -= 1;
+                if self.cache_size == 0 {
+                    break;
+                }
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.cache_size += 1;
+        self.low = (self.low & 0x00FFFFFF) << 8;
+    }
+
+    fn normalize(&mut self) {
+        while self.range < 1 << 24 {
+            self.range <<= 8;
+            self.shift_low();
+        }
+    }
+
+    fn encode_bit(&mut self, prob: &mut u16, bit: u32) {
+        let bound = (self.range >> 11) * *prob as u32;
+        if bit == 0 {
+            self.range = bound;
+            *prob += (0x800 - *prob) >> 5;
+        } else {
+            self.low += bound as u64;
+            self.range -= bound;
+            *prob -= *prob >> 5;
+        }
+        self.normalize();
+    }
+
+    fn encode_direct(&mut self, value: u32, num_bits: u32) {
+        for i in (0..num_bits).rev() {
+            self.range >>= 1;
+            if (value >> i) & 1 != 0 {
+                self.low += self.range as u64;
+            }
+            self.normalize();
+        }
+    }
+
+    fn encode_tree(&mut self, probs: &mut [u16], num_bits: u32, symbol: u32) {
+        let mut m = 1;
+        for i in (0..num_bits).rev() {
+            let bit = (symbol >> i) & 1;
+            self.encode_bit(&mut probs[m], bit);
+            m = (m << 1) | bit as usize;
+        }
+    }
+
+    fn encode_reverse_tree(&mut self, probs: &mut [u16], num_bits: u32, symbol: u32) {
+        let mut m = 1;
+        for i in 0..num_bits {
+            let bit = (symbol >> i) & 1;
+            self.encode_bit(&mut probs[m], bit);
+            m = (m << 1) | bit as usize;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for _ in 0..5 {
+            self.shift_low();
+        }
+        self.out
+    }
+}
+
+struct LzmaLenEncoder {
+    choice: u16,
+    choice2: u16,
+    low: Vec<[u16; 8]>,
+    mid: Vec<[u16; 8]>,
+    high: [u16; 256],
+}
+
+impl LzmaLenEncoder {
+    fn new() -> Self {
+        Self {
+            choice: LZMA_PROB_INIT,
+            choice2: LZMA_PROB_INIT,
+            low: vec![[LZMA_PROB_INIT; 8]; 16],
+            mid: vec![[LZMA_PROB_INIT; 8]; 16],
+            high: [LZMA_PROB_INIT; 256],
+        }
+    }
+
+    // len is the match length minus two.
+    fn encode(&mut self, rc: &mut RangeEncoder, len: u32, pos_state: usize) {
+        if len < 8 {
+            rc.encode_bit(&mut self.choice, 0);
+            rc.encode_tree(&mut self.low[pos_state], 3, len);
+        } else if len < 16 {
+            rc.encode_bit(&mut self.choice, 1);
+            rc.encode_bit(&mut self.choice2, 0);
+            rc.encode_tree(&mut self.mid[pos_state], 3, len - 8);
+        } else {
+            rc.encode_bit(&mut self.choice, 1);
+            rc.encode_bit(&mut self.choice2, 1);
+            rc.encode_tree(&mut self.high, 8, len - 16);
+        }
+    }
+}
+
+const LZMA_MAX_MATCH_LEN: usize = 273;
+
+// Greedy LZMA encoder, using plain matches and rep0 matches. It doesn't do the optimal parsing
+// of the reference encoder, but is compatible with any LZMA decoder.
+struct LzmaEncoder {
+    lc: u32,
+    lp: u32,
+    pb: u32,
+    rc: RangeEncoder,
+    literal_probs: Vec<u16>,
+    is_match: [u16; 192],
+    is_rep: [u16; 12],
+    is_rep_g0: [u16; 12],
+    is_rep0_long: [u16; 192],
+    pos_slot: Vec<[u16; 64]>,
+    pos_special: [u16; 115],
+    align: [u16; 16],
+    len: LzmaLenEncoder,
+    rep_len: LzmaLenEncoder,
+    state: usize,
+    reps: [u32; 4],
+}
+
+impl LzmaEncoder {
+    fn new(lc: u32, lp: u32, pb: u32) -> Self {
+        Self {
+            lc,
+            lp,
+            pb,
+            rc: RangeEncoder::new(),
+            literal_probs: vec![LZMA_PROB_INIT; 0x300 << (lc + lp)],
+            is_match: [LZMA_PROB_INIT; 192],
+            is_rep: [LZMA_PROB_INIT; 12],
+            is_rep_g0: [LZMA_PROB_INIT; 12],
+            is_rep0_long: [LZMA_PROB_INIT; 192],
+            pos_slot: vec![[LZMA_PROB_INIT; 64]; 4],
+            pos_special: [LZMA_PROB_INIT; 115],
+            align: [LZMA_PROB_INIT; 16],
+            len: LzmaLenEncoder::new(),
+            rep_len: LzmaLenEncoder::new(),
+            state: 0,
+            reps: [0; 4],
+        }
+    }
+
+    fn encode_literal(&mut self, src: &[u8], pos: usize) {
+        let pos_state = pos & ((1 << self.pb) - 1);
+        self.rc.encode_bit(&mut self.is_match[(self.state << 4) + pos_state], 0);
+
+        let prev_byte = if pos > 0 { src[pos - 1] as usize } else { 0 };
+        let lit_state = ((pos & ((1 << self.lp) - 1)) << self.lc) + (prev_byte >> (8 - self.lc));
+        let probs = &mut self.literal_probs[0x300 * lit_state..][..0x300];
+        let byte = src[pos] as u32;
+
+        let mut symbol = 1usize;
+        let mut i = 8;
+        if self.state >= 7 {
+            let mut match_byte = src[pos - self.reps[0] as usize - 1] as u32;
+            while i > 0 {
+                i -= 1;
+                let match_bit = (match_byte >> 7) & 1;
+                match_byte <<= 1;
+                let bit = (byte >> i) & 1;
+                self.rc.encode_bit(&mut probs[((1 + match_bit as usize) << 8) + symbol], bit);
+                symbol = (symbol << 1) | bit as usize;
+                if match_bit != bit {
+                    break;
+                }
+            }
+        }
+        while i > 0 {
+            i -= 1;
+            let bit = (byte >> i) & 1;
+            self.rc.encode_bit(&mut probs[symbol], bit);
+            symbol = (symbol << 1) | bit as usize;
+        }
+
+        self.state = if self.state < 4 { 0 } else if self.state < 10 { self.state - 3 } else { self.state - 6 };
+    }
+
+    fn encode_distance(&mut self, dist: u32, len: u32) {
+        let len_state = len.min(3) as usize;
+        if dist < 4 {
+            self.rc.encode_tree(&mut self.pos_slot[len_state], 6, dist);
+            return;
+        }
+
+        let log2 = 31 - dist.leading_zeros();
+        let slot = 2 * log2 + ((dist >> (log2 - 1)) & 1);
+        self.rc.encode_tree(&mut self.pos_slot[len_state], 6, slot);
+        let num_direct_bits = (slot >> 1) - 1;
+        let base = (2 | (slot & 1)) << num_direct_bits;
+        let reduced = dist - base;
+        if slot < 14 {
+            let offset = (base - slot) as usize;
+            self.rc.encode_reverse_tree(&mut self.pos_special[offset..], num_direct_bits, reduced);
+        } else {
+            self.rc.encode_direct(reduced >> 4, num_direct_bits - 4);
+            self.rc.encode_reverse_tree(&mut self.align, 4, reduced & 0x0F);
+        }
+    }
+
+    // dist is zero-based, as in the decoder's rep registers.
+    fn encode_match(&mut self, pos: usize, len: usize, dist: u32) {
+        let pos_state = pos & ((1 << self.pb) - 1);
+        self.rc.encode_bit(&mut self.is_match[(self.state << 4) + pos_state], 1);
+        self.rc.encode_bit(&mut self.is_rep[self.state], 0);
+        self.len.encode(&mut self.rc, len as u32 - 2, pos_state);
+        self.encode_distance(dist, len as u32 - 2);
+        self.reps = [dist, self.reps[0], self.reps[1], self.reps[2]];
+        self.state = if self.state < 7 { 7 } else { 10 };
+    }
+
+    fn encode_rep0(&mut self, pos: usize, len: usize) {
+        let pos_state = pos & ((1 << self.pb) - 1);
+        self.rc.encode_bit(&mut self.is_match[(self.state << 4) + pos_state], 1);
+        self.rc.encode_bit(&mut self.is_rep[self.state], 1);
+        self.rc.encode_bit(&mut self.is_rep_g0[self.state], 0);
+        self.rc.encode_bit(&mut self.is_rep0_long[(self.state << 4) + pos_state], 1);
+        self.rep_len.encode(&mut self.rc, len as u32 - 2, pos_state);
+        self.state = if self.state < 7 { 8 } else { 11 };
+    }
+
+    fn encode(mut self, src: &[u8], dict_size: u32, end_marker: bool) -> Vec<u8> {
+        let mut finder = MatchFinder::new(src, dict_size as usize, LZMA_MAX_MATCH_LEN, 48);
+        let mut pos = 0;
+        while pos < src.len() {
+            let max_len = LZMA_MAX_MATCH_LEN.min(src.len() - pos);
+            let rep0 = self.reps[0] as usize + 1;
+            let rep_len = if pos >= rep0 {
+                src[pos - rep0..pos - rep0 + max_len].iter().zip(&src[pos..pos + max_len]).take_while(|(a, b)| a == b).count()
+            } else {
+                0
+            };
+
+            let found = finder.find(pos);
+            finder.insert(pos);
+            let len = match found {
+                // Repeating the last distance is cheap enough to prefer unless the match is clearly longer.
+                _ if rep_len >= 2 && found.is_none_or(|(len, _)| rep_len + 1 >= len) => {
+                    self.encode_rep0(pos, rep_len);
+                    rep_len
+                },
+                // Short matches far away cost more than the literals.
+                Some((len, dist)) if len > 3 || dist < 0x1000 => {
+                    self.encode_match(pos, len, dist as u32 - 1);
+                    len
+                },
+                _ => {
+                    self.encode_literal(src, pos);
+                    1
+                },
+            };
+            for p in pos + 1..pos + len {
+                finder.insert(p);
+            }
+            pos += len;
+        }
+
+        if end_marker {
+            let pos_state = pos & ((1 << self.pb) - 1);
+            self.rc.encode_bit(&mut self.is_match[(self.state << 4) + pos_state], 1);
+            self.rc.encode_bit(&mut self.is_rep[self.state], 0);
+            self.len.encode(&mut self.rc, 0, pos_state);
+            self.encode_distance(0xFFFFFFFF, 0);
+        }
+        self.rc.finish()
+    }
+}
+
+// Raw LZMA stream. Without an end marker, the decoder needs to know the uncompressed size.
+#[wasm_bindgen]
+pub fn lzma_compress(src: &[u8], lc: u32, lp: u32, pb: u32, dict_size: u32, end_marker: bool) -> Result<Vec<u8>, String> {
+    if lc > 8 || lp > 4 || pb > 4 {
+        return Err("invalid LZMA properties".to_string());
+    }
+    Ok(LzmaEncoder::new(lc, lp, pb).encode(src, dict_size.max(0x1000), end_marker))
+}
+
+// .lzma ("LZMA alone") file, with the default lc=3, lp=0, pb=2 properties.
+#[wasm_bindgen]
+pub fn lzma_alone_compress(src: &[u8]) -> Vec<u8> {
+    let dict_size = (src.len() as u32).clamp(0x1000, 0x4000000).next_power_of_two();
+    let mut dst = vec![(2 * 5) * 9 + 3];
+    dst.extend_from_slice(&dict_size.to_le_bytes());
+    dst.extend_from_slice(&(src.len() as u64).to_le_bytes());
+    dst.extend(LzmaEncoder::new(3, 0, 2).encode(src, dict_size, false));
+    dst
+}
+
+#[wasm_bindgen]
+pub fn lzma_alone_decompress(src: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    if src.len() < 13 {
+        return Err(DecompressError::Truncated);
+    }
+    let props = src[0] as u32;
+    if props >= 9 * 5 * 5 {
+        return corrupt("invalid LZMA properties");
+    }
+    let dict_size = u32::from_le_bytes(src[1..5].try_into().unwrap());
+    let unpacked_size = match u64::from_le_bytes(src[5..13].try_into().unwrap()) {
+        u64::MAX => None,
+        size => Some(size),
+    };
+    lzma_decompress(&src[13..], props % 9, (props / 9) % 5, props / 45, dict_size, unpacked_size)
+}
+
+// Nintendo "CX" formats from the DS and Wii SDKs. The header is a type byte followed by the
+// 24-bit little endian uncompressed size; if that is zero, a 32-bit size follows. Some Wii
+// titles put an extra "LZ77" magic in front of the header.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CxFormat {
+    Lz10 = 0x10,
+    Lz11 = 0x11,
+    Huffman4 = 0x24,
+    Huffman8 = 0x28,
+}
+
+struct CxHeader {
+    format: CxFormat,
+    size: usize,
+    data_offs: usize,
+}
+
+fn cx_header(src: &[u8]) -> Result<CxHeader, DecompressError> {
+    let offs = if src.starts_with(b"LZ77") { 4 } else { 0 };
+    let header = src.get(offs..offs + 4).ok_or(DecompressError::Truncated)?;
+    let format = match header[0] {
+        0x10 => CxFormat::Lz10,
+        0x11 => CxFormat::Lz11,
+        0x24 => CxFormat::Huffman4,
+        0x28 => CxFormat::Huffman8,
+        _ => return corrupt("unknown CX compression type"),
+    };
+    let size = u32::from_le_bytes(header.try_into().unwrap()) >> 8;
+    if size != 0 {
+        return Ok(CxHeader { format, size: size as usize, data_offs: offs + 4 });
+    }
+    let size = src.get(offs + 4..offs + 8).ok_or(DecompressError::Truncated)?;
+    Ok(CxHeader { format, size: u32::from_le_bytes(size.try_into().unwrap()) as usize, data_offs: offs + 8 })
+}
+
+// Both LZ variants: flag bytes MSB first, where a set bit is a back-reference. LZ10 references
+// are 2 bytes with a 4-bit length; LZ11 uses the top nibble to pick a 2, 3 or 4 byte encoding.
+fn cx_lz_decode(src: &[u8], size: usize, mut offs: usize, extended: bool) -> Result<Vec<u8>, DecompressError> {
+    // The header size can't be trusted for the allocation; see cx_detect for the ratios.
+    let max_ratio = if extended { 0x4000 } else { 9 };
+    let mut dst = Vec::with_capacity(size.min(src.len().saturating_mul(max_ratio)));
+    while dst.len() < size {
+        let flags = *src.get(offs).ok_or(DecompressError::Truncated)?;
+        offs += 1;
+        for bit in (0..8).rev() {
+            if dst.len() >= size {
+                break;
+            }
+            if flags & (1 << bit) == 0 {
+                dst.push(*src.get(offs).ok_or(DecompressError::Truncated)?);
+                offs += 1;
+                continue;
+            }
+
+            let b = src.get(offs..offs + 2).ok_or(DecompressError::Truncated)?;
+            let (len, token_size) = if !extended {
+                ((b[0] >> 4) as usize + 3, 2)
+            } else {
+                match b[0] >> 4 {
+                    0 => {
+                        let b = src.get(offs..offs + 3).ok_or(DecompressError::Truncated)?;
+                        ((((b[0] & 0x0F) as usize) << 4 | (b[1] >> 4) as usize) + 0x11, 3)
+                    },
+                    1 => {
+                        let b = src.get(offs..offs + 4).ok_or(DecompressError::Truncated)?;
+                        ((((b[0] & 0x0F) as usize) << 12 | (b[1] as usize) << 4 | (b[2] >> 4) as usize) + 0x111, 4)
+                    },
+                    n => (n as usize + 1, 2),
+                }
+            };
+            let b = &src[offs + token_size - 2..offs + token_size];
+            let dist = (((b[0] & 0x0F) as usize) << 8 | b[1] as usize) + 1;
+            offs += token_size;
+
+            if dist > dst.len() {
+                return corrupt("back-reference before start of output");
+            }
+            // Encoders are allowed to run the last match past the end.
+            let start = dst.len() - dist;
+            for i in 0..len.min(size - dst.len()) {
+                dst.push(dst[start + i]);
+            }
+        }
+    }
+    Ok(dst)
+}
+
+// The Huffman tree follows the header: a byte giving the tree size in 2-byte units minus one,
+// then the root node. Each node holds the offset to its pair of children in the low 6 bits,
+// with bits 7 and 6 marking whether child 0 and child 1 are leaves. The bitstream is a series
+// of little endian 32-bit words, read MSB first. 4-bit data fills the low nibble first.
+fn cx_huffman_decode(src: &[u8], size: usize, offs: usize, bits: u32) -> Result<Vec<u8>, DecompressError> {
+    let tree_size = (*src.get(offs).ok_or(DecompressError::Truncated)? as usize + 1) * 2;
+    let tree = src.get(offs..offs + tree_size).ok_or(DecompressError::Truncated)?;
+    let mut pos = offs + tree_size;
+
+    // Every input bit ends at most one symbol, so each byte decodes to at most `bits` bytes.
+    let mut dst = Vec::with_capacity(size.min(src.len().saturating_mul(bits as usize)));
+    let mut node = 1;
+    let mut value = 0u8;
+    let mut value_bits = 0;
+    while dst.len() < size {
+        let word = src.get(pos..pos + 4).ok_or(DecompressError::Truncated)?;
+        let word = u32::from_le_bytes(word.try_into().unwrap());
+        pos += 4;
+        for bit in (0..32).rev() {
+            let b = ((word >> bit) & 1) as usize;
+            let n = tree[node];
+            let child = (node & !1) + (n & 0x3F) as usize * 2 + 2 + b;
+            if child >= tree.len() {
+                return corrupt("Huffman node points outside the tree");
+            }
+            if n & (0x80 >> b) == 0 {
+                node = child;
+                continue;
+            }
+
+            node = 1;
+            if bits == 8 {
+                dst.push(tree[child]);
+            } else {
+                value |= (tree[child] & 0x0F) << value_bits;
+                value_bits += 4;
+                if value_bits < 8 {
+                    continue;
+                }
+                dst.push(value);
+                value = 0;
+                value_bits = 0;
+            }
+            if dst.len() >= size {
+                break;
+            }
+        }
+    }
+    Ok(dst)
+}
+
+// Returns the format of an LZ10/LZ11 file, or None if it doesn't look like one. The Huffman
+// formats have no usable signature, so callers that expect them go to cx_decompress directly.
+#[wasm_bindgen]
+pub fn cx_detect(src: &[u8]) -> Option<CxFormat> {
+    let header = cx_header(src).ok()?;
+    // The most a 2 byte LZ10 or 4 byte LZ11 reference can expand to, flag bytes included.
+    let max_ratio = match header.format {
+        CxFormat::Lz10 => 9,
+        CxFormat::Lz11 => 0x4000,
+        CxFormat::Huffman4 | CxFormat::Huffman8 => return None,
     };
-    let params =
-        lzma_rs::decompress::raw::LzmaParams::new(properties, dict_size, Some(unpacked_size));
-    let mut decoder = lzma_rs::decompress::raw::LzmaDecoder::new(params, None).unwrap();
-    let mut dst = Vec::<u8>::with_capacity(unpacked_size.try_into().unwrap());
-    decoder.decompress(&mut src, &mut dst).unwrap();
+    // Nothing has been output yet, so the first token has to be a literal.
+    let flags = *src.get(header.data_offs)?;
+    let payload = src.len() - header.data_offs;
+    if header.size > 0 && flags & 0x80 == 0 && header.size <= payload * max_ratio {
+        Some(header.format)
+    } else {
+        None
+    }
+}
+
+#[wasm_bindgen]
+pub fn cx_decompress(src: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    let header = cx_header(src)?;
+    match header.format {
+        CxFormat::Lz10 => cx_lz_decode(src, header.size, header.data_offs, false),
+        CxFormat::Lz11 => cx_lz_decode(src, header.size, header.data_offs, true),
+        CxFormat::Huffman4 => cx_huffman_decode(src, header.size, header.data_offs, 4),
+        CxFormat::Huffman8 => cx_huffman_decode(src, header.size, header.data_offs, 8),
+    }
+}
+
+fn cx_write_header(dst: &mut Vec<u8>, format: CxFormat, size: usize) {
+    if size > 0 && size <= 0xFFFFFF {
+        dst.extend_from_slice(&((size as u32) << 8 | format as u32).to_le_bytes());
+    } else {
+        dst.extend_from_slice(&[format as u8, 0, 0, 0]);
+        dst.extend_from_slice(&(size as u32).to_le_bytes());
+    }
+}
+
+const CX_LZ_WINDOW_SIZE: usize = 0x1000;
+
+fn cx_lz_encode(dst: &mut Vec<u8>, src: &[u8], extended: bool) {
+    let max_len = if extended { 0x10110 } else { 0x12 };
+    let mut finder = MatchFinder::new(src, CX_LZ_WINDOW_SIZE, max_len, 64);
+    let mut pos = 0;
+    let mut flags_offs = 0;
+    let mut flag_bit = 0;
+    while pos < src.len() {
+        if flag_bit == 0 {
+            flags_offs = dst.len();
+            dst.push(0);
+            flag_bit = 8;
+        }
+        flag_bit -= 1;
+
+        let Some((len, dist)) = finder.find(pos) else {
+            finder.insert(pos);
+            dst.push(src[pos]);
+            pos += 1;
+            continue;
+        };
+
+        dst[flags_offs] |= 1 << flag_bit;
+        let d = dist - 1;
+        if !extended {
+            dst.extend_from_slice(&[((len - 3) << 4 | d >> 8) as u8, d as u8]);
+        } else if len <= 0x10 {
+            dst.extend_from_slice(&[((len - 1) << 4 | d >> 8) as u8, d as u8]);
+        } else if len <= 0x110 {
+            let l = len - 0x11;
+            dst.extend_from_slice(&[(l >> 4) as u8, ((l & 0x0F) << 4 | d >> 8) as u8, d as u8]);
+        } else {
+            let l = len - 0x111;
+            dst.extend_from_slice(&[(0x10 | l >> 12) as u8, (l >> 4) as u8, ((l & 0x0F) << 4 | d >> 8) as u8, d as u8]);
+        }
+        for p in pos..pos + len {
+            finder.insert(p);
+        }
+        pos += len;
+    }
+}
+
+enum HuffmanNode {
+    Leaf(u8),
+    Internal(usize, usize),
+}
+
+// Builds the canonical tree for the given code lengths. nodes[0] is the root.
+fn cx_huffman_tree(lengths: &[u8]) -> Vec<HuffmanNode> {
+    let mut nodes = vec![HuffmanNode::Internal(0, 0)];
+    let mut symbols: Vec<usize> = (0..lengths.len()).filter(|&s| lengths[s] > 0).collect();
+    symbols.sort_by_key(|&s| (lengths[s], s));
+
+    let mut code = 0u32;
+    let mut prev_len = 0;
+    for &symbol in &symbols {
+        let len = lengths[symbol] as u32;
+        code <<= len - prev_len;
+        prev_len = len;
+
+        let mut node = 0;
+        for i in (0..len).rev() {
+            let bit = (code >> i) & 1;
+            let HuffmanNode::Internal(c0, c1) = nodes[node] else { unreachable!() };
+            let mut child = if bit == 0 { c0 } else { c1 };
+            if child == 0 {
+                nodes.push(if i == 0 { HuffmanNode::Leaf(symbol as u8) } else { HuffmanNode::Internal(0, 0) });
+                child = nodes.len() - 1;
+                if let HuffmanNode::Internal(c0, c1) = &mut nodes[node] {
+                    if bit == 0 { *c0 = child } else { *c1 = child }
+                }
+            }
+            node = child;
+        }
+        code += 1;
+    }
+    nodes
+}
+
+// Lays out the tree table, or returns None if some child pair ends up more than 63 pairs past
+// its parent. Children are placed depth first to keep the list of pending nodes short, except
+// when the oldest pending node is about to run out of range.
+fn cx_huffman_layout(nodes: &[HuffmanNode]) -> Option<Vec<u8>> {
+    let mut table = vec![0u8; 2];
+    // (node, table position)
+    let mut pending = vec![(0, 1)];
+    while !pending.is_empty() {
+        let pair = table.len() / 2;
+        let oldest = pending.iter().enumerate().min_by_key(|(_, &(_, pos))| pos).map(|(i, _)| i).unwrap();
+        let deadline = pending[oldest].1 / 2 + 64;
+        let index = if deadline - pair <= pending.len() { oldest } else { pending.len() - 1 };
+        let (node, pos) = pending.remove(index);
+        let offset = pair - pos / 2 - 1;
+        if offset > 0x3F {
+            return None;
+        }
+
+        let HuffmanNode::Internal(c0, c1) = nodes[node] else { unreachable!() };
+        table[pos] = offset as u8;
+        for (i, &child) in [c0, c1].iter().enumerate() {
+            match nodes[child] {
+                HuffmanNode::Leaf(symbol) => {
+                    table[pos] |= 0x80 >> i;
+                    table.push(symbol);
+                },
+                HuffmanNode::Internal(..) => {
+                    pending.push((child, table.len()));
+                    table.push(0);
+                },
+            }
+        }
+    }
+
+    // Pad to keep the bitstream word aligned.
+    table.resize(table.len().next_multiple_of(4), 0);
+    table[0] = (table.len() / 2 - 1) as u8;
+    Some(table)
+}
+
+fn cx_huffman_encode(dst: &mut Vec<u8>, src: &[u8], bits: u32) {
+    let symbols: Vec<u8> = if bits == 8 {
+        src.to_vec()
+    } else {
+        src.iter().flat_map(|&b| [b & 0x0F, b >> 4]).collect()
+    };
+
+    let mut freqs = vec![0u32; 1 << bits];
+    for &s in &symbols {
+        freqs[s as usize] += 1;
+    }
+    // The tree needs at least two leaves.
+    for s in 0..2 {
+        if freqs.iter().filter(|&&f| f > 0).count() < 2 && freqs[s] == 0 {
+            freqs[s] = 1;
+        }
+    }
+
+    // If the tree doesn't fit the 6-bit child offsets, retry with shorter codes. The flattest
+    // tree, a complete one, always fits.
+    let mut max_len = 15;
+    let (lengths, table) = loop {
+        let lengths = huffman_lengths(&freqs, max_len);
+        if let Some(table) = cx_huffman_layout(&cx_huffman_tree(&lengths)) {
+            break (lengths, table);
+        }
+        max_len -= 1;
+    };
+    dst.extend_from_slice(&table);
+
+    let codes = huffman_codes(&lengths);
+    let mut word = 0u32;
+    let mut word_bits = 0;
+    for &s in &symbols {
+        let len = lengths[s as usize] as u32;
+        // huffman_codes bit-reverses for LSB-first writers; this stream is MSB first.
+        let code = codes[s as usize].reverse_bits() as u32 >> (16 - len);
+        for i in (0..len).rev() {
+            word = word << 1 | (code >> i) & 1;
+            word_bits += 1;
+            if word_bits == 32 {
+                dst.extend_from_slice(&word.to_le_bytes());
+                word = 0;
+                word_bits = 0;
+            }
+        }
+    }
+    if word_bits > 0 {
+        dst.extend_from_slice(&(word << (32 - word_bits)).to_le_bytes());
+    }
+}
+
+#[wasm_bindgen]
+pub fn cx_compress(src: &[u8], format: CxFormat, lz77_header: bool) -> Vec<u8> {
+    let mut dst = Vec::new();
+    if lz77_header {
+        dst.extend_from_slice(b"LZ77");
+    }
+    cx_write_header(&mut dst, format, src.len());
+    match format {
+        CxFormat::Lz10 => cx_lz_encode(&mut dst, src, false),
+        CxFormat::Lz11 => cx_lz_encode(&mut dst, src, true),
+        CxFormat::Huffman4 => cx_huffman_encode(&mut dst, src, 4),
+        CxFormat::Huffman8 => cx_huffman_encode(&mut dst, src, 8),
+    }
+    dst.resize(dst.len().next_multiple_of(4), 0);
     dst
 }
 
 #[wasm_bindgen]
-pub fn deflate_decompress(src: &[u8]) -> Vec<u8> {
-    inflate::inflate_bytes_zlib(src).unwrap()
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionFormat {
+    None,
+    Yaz0,
+    Yay0,
+    Zlib,
+    Gzip,
+    LzmaAlone,
+    Lz4Frame,
+    Zstd,
+    Cx,
 }
 
+impl CompressionFormat {
+    // Formats without a magic number can be matched by uncompressed data by chance.
+    fn has_magic(self) -> bool {
+        !matches!(self, CompressionFormat::Zlib | CompressionFormat::LzmaAlone | CompressionFormat::Cx)
+    }
+}
+
+fn is_zlib_header(src: &[u8]) -> bool {
+    let (cmf, flg) = match src {
+        [cmf, flg, ..] => (*cmf as u32, *flg as u32),
+        _ => return false,
+    };
+    if cmf & 0x0F != 8 || cmf >> 4 > 7 || flg & 0x20 != 0 || ((cmf << 8) | flg) % 31 != 0 {
+        return false;
+    }
+    true
+}
+
+// Follows xz's checks: a known properties byte, a dictionary size of 2^n or 2^n + 2^(n-1),
+// and an unknown or plausible uncompressed size.
+fn is_lzma_alone_header(src: &[u8]) -> bool {
+    if src.len() < 13 || src[0] >= 9 * 5 * 5 {
+        return false;
+    }
+    let dict_size = u32::from_le_bytes(src[1..5].try_into().unwrap());
+    let unpacked_size = u64::from_le_bytes(src[5..13].try_into().unwrap());
+    let dict_ok = dict_size == u32::MAX || (dict_size != 0 && (dict_size >> dict_size.trailing_zeros()) <= 3);
+    dict_ok && (unpacked_size == u64::MAX || unpacked_size < (1 << 38))
+}
+
+#[wasm_bindgen]
+pub fn detect_compression(src: &[u8]) -> CompressionFormat {
+    let magic = src.get(0..4).map(|magic| u32::from_le_bytes(magic.try_into().unwrap()));
+    if src.starts_with(b"Yaz0") {
+        CompressionFormat::Yaz0
+    } else if src.starts_with(b"Yay0") {
+        CompressionFormat::Yay0
+    } else if src.starts_with(&[0x1F, 0x8B, 0x08]) {
+        CompressionFormat::Gzip
+    } else if magic == Some(ZSTD_MAGIC) {
+        CompressionFormat::Zstd
+    } else if magic == Some(LZ4_FRAME_MAGIC) {
+        CompressionFormat::Lz4Frame
+    } else if is_zlib_header(src) {
+        CompressionFormat::Zlib
+    } else if is_lzma_alone_header(src) {
+        CompressionFormat::LzmaAlone
+    } else if cx_detect(src).is_some() {
+        CompressionFormat::Cx
+    } else {
+        CompressionFormat::None
+    }
+}
+
+#[wasm_bindgen]
+pub struct AutoDecompressed {
+    format: CompressionFormat,
+    data: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl AutoDecompressed {
+    pub fn get_format(&self) -> CompressionFormat {
+        self.format
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+// Sniffs the format from the header and decompresses. Data that isn't compressed, or that
+// only looked compressed by chance, comes back as CompressionFormat::None with no data, and
+// the caller keeps using its own buffer. CX is only sniffed for LZ10/LZ11, see cx_detect.
+#[wasm_bindgen]
+pub fn auto_decompress(src: &[u8]) -> Result<AutoDecompressed, DecompressError> {
+    let format = detect_compression(src);
+    let result = match format {
+        CompressionFormat::None => Ok(Vec::new()),
+        CompressionFormat::Yaz0 => yaz0::yaz0dec(src),
+        CompressionFormat::Yay0 => yaz0::yay0dec(src),
+        CompressionFormat::Zlib => deflate_decompress(src),
+        CompressionFormat::Gzip => gzip_decompress(src),
+        CompressionFormat::LzmaAlone => lzma_alone_decompress(src),
+        CompressionFormat::Lz4Frame => lz4_frame_decompress(src),
+        CompressionFormat::Zstd => zstd_decompress(src),
+        CompressionFormat::Cx => cx_decompress(src),
+    };
+
+    match result {
+        Ok(data) => Ok(AutoDecompressed { format, data }),
+        Err(_) if !format.has_magic() => Ok(AutoDecompressed { format: CompressionFormat::None, data: Vec::new() }),
+        Err(err) => Err(err),
+    }
+}
+
+// crnlib's crn_format.
 #[wasm_bindgen]
-pub fn deflate_raw_decompress(src: &[u8]) -> Vec<u8> {
-    inflate::inflate_bytes(src).unwrap()
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrunchFormat {
+    Dxt1 = 0,
+    Dxt3 = 1,
+    Dxt5 = 2,
+    Dxt5CCxY = 3,
+    Dxt5xGxR = 4,
+    Dxt5xGBR = 5,
+    Dxt5AGBR = 6,
+    DxnXY = 7,
+    DxnYX = 8,
+    Dxt5A = 9,
+    Etc1 = 10,
+    Etc2 = 11,
+    Etc2A = 12,
+    Etc1S = 13,
+    Etc2AS = 14,
+}
+
+impl CrunchFormat {
+    fn from_u8(v: u8) -> Option<Self> {
+        use CrunchFormat::*;
+        [Dxt1, Dxt3, Dxt5, Dxt5CCxY, Dxt5xGxR, Dxt5xGBR, Dxt5AGBR, DxnXY, DxnYX, Dxt5A, Etc1, Etc2, Etc2A, Etc1S, Etc2AS]
+            .get(v as usize).copied()
+    }
+
+    fn bytes_per_block(self) -> usize {
+        match self {
+            CrunchFormat::Dxt1 | CrunchFormat::Dxt5A | CrunchFormat::Etc1 | CrunchFormat::Etc2 | CrunchFormat::Etc1S => 0x08,
+            _ => 0x10,
+        }
+    }
+}
+
+// The parts of crnlib's crn_header that describe the texture. Fields are big-endian.
+const CRUNCH_HEADER_MIN_SIZE: usize = 0x4A;
+
+#[derive(Debug)]
+struct CrunchHeader {
+    width: u32,
+    height: u32,
+    num_levels: u32,
+    num_faces: u32,
+    format: CrunchFormat,
+}
+
+impl CrunchHeader {
+    fn parse(data: &[u8]) -> Result<Self, String> {
+        if data.len() < CRUNCH_HEADER_MIN_SIZE || &data[0x00..0x02] != b"Hx" {
+            return Err("not a crunch file".to_string());
+        }
+
+        let mut header = ByteReader::be(data).at(0x0C)?;
+        let width = header.read_u16()? as u32;
+        let height = header.read_u16()? as u32;
+        let num_levels = header.read_u8()? as u32;
+        let num_faces = header.read_u8()? as u32;
+        let format = header.read_u8()?;
+        let format = CrunchFormat::from_u8(format)
+            .ok_or_else(|| format!("unknown crunch format {}", format))?;
+        Ok(Self { width, height, num_levels, num_faces, format })
+    }
+
+    fn level_width(&self, level_index: u32) -> u32 {
+        self.width.checked_shr(level_index).unwrap_or(0).max(1)
+    }
+
+    fn level_height(&self, level_index: u32) -> u32 {
+        self.height.checked_shr(level_index).unwrap_or(0).max(1)
+    }
+
+    // The size of one face of a level once transcoded.
+    fn level_size(&self, level_index: u32) -> usize {
+        let blocks_x = (self.level_width(level_index) as usize).div_ceil(4);
+        let blocks_y = (self.level_height(level_index) as usize).div_ceil(4);
+        blocks_x * blocks_y * self.format.bytes_per_block()
+    }
 }
 
 #[wasm_bindgen(js_name = "CrunchTexture")]
 pub struct CrunchTexture {
     handle: texture2ddecoder::CrunchHandle,
+    header: CrunchHeader,
 }
 
 #[wasm_bindgen(js_class = "CrunchTexture")]
 impl CrunchTexture {
     pub fn new(data: &[u8]) -> Result<Self, String> {
+        let header = CrunchHeader::parse(data)?;
         let handle = texture2ddecoder::CrunchHandle::new(data)
             .map_err(|err| format!("{:?}", err))?;
         Ok(Self {
             handle,
+            header,
         })
     }
 
@@ -57,8 +2078,499 @@ impl CrunchTexture {
         self.handle.get_num_levels()
     }
 
+    pub fn get_width(&self) -> u32 {
+        self.header.width
+    }
+
+    pub fn get_height(&self) -> u32 {
+        self.header.height
+    }
+
+    // 6 for cubemaps, 1 otherwise.
+    pub fn get_num_faces(&self) -> u32 {
+        self.header.num_faces
+    }
+
+    pub fn get_format(&self) -> CrunchFormat {
+        self.header.format
+    }
+
+    pub fn get_level_width(&self, level_index: u32) -> u32 {
+        self.header.level_width(level_index)
+    }
+
+    pub fn get_level_height(&self, level_index: u32) -> u32 {
+        self.header.level_height(level_index)
+    }
+
+    pub fn get_level_size(&self, level_index: u32) -> usize {
+        self.header.level_size(level_index)
+    }
+
     pub fn decode_level(&self, data: &[u8], level_index: u32) -> Result<Vec<u8>, String> {
         self.handle.unpack_level(data, level_index)
             .map_err(|err| err.into())
     }
+
+    // Returns the transcoded blocks of a single face of a level.
+    pub fn decode_level_face(&self, data: &[u8], level_index: u32, face_index: u32) -> Result<Vec<u8>, String> {
+        if level_index >= self.header.num_levels {
+            return Err(format!("level {} out of range ({} levels)", level_index, self.header.num_levels));
+        }
+        if face_index >= self.header.num_faces {
+            return Err(format!("face {} out of range ({} faces)", face_index, self.header.num_faces));
+        }
+
+        // Faces are unpacked back to back. Only the first face is guaranteed to be there,
+        // since the transcoder may stop after it.
+        let level = self.decode_level(data, level_index)?;
+        let face_size = self.header.level_size(level_index);
+        let start = face_index as usize * face_size;
+        level.get(start..start + face_size)
+            .map(|face| face.to_vec())
+            .ok_or_else(|| format!("level {} unpacked to {} bytes, missing face {}", level_index, level.len(), face_index))
+    }
+
+    // Decodes a single face of a level all the way to RGBA8, for when the GPU cannot
+    // sample the transcoded format. The swizzled DXT5 variants come back as plain RGBA.
+    pub fn decode_level_rgba(&self, data: &[u8], level_index: u32, face_index: u32) -> Result<Vec<u8>, String> {
+        let face = self.decode_level_face(data, level_index, face_index)?;
+        let w = self.header.level_width(level_index) as usize;
+        let h = self.header.level_height(level_index) as usize;
+        Ok(match self.header.format {
+            CrunchFormat::Dxt1 => bc_texture::decode_bc1(&face, w, h)?,
+            CrunchFormat::Dxt3 => bc_texture::decode_bc2(&face, w, h)?,
+            CrunchFormat::Dxt5 => bc_texture::decode_bc3(&face, w, h)?,
+            CrunchFormat::Dxt5CCxY | CrunchFormat::Dxt5xGxR | CrunchFormat::Dxt5xGBR | CrunchFormat::Dxt5AGBR => {
+                let mut dst = bc_texture::decode_bc3(&face, w, h)?;
+                crunch_unswizzle(self.header.format, &mut dst);
+                dst
+            },
+            CrunchFormat::DxnXY => bc_texture::decode_bc5(&face, w, h, false)?,
+            CrunchFormat::DxnYX => {
+                let mut dst = bc_texture::decode_bc5(&face, w, h, false)?;
+                for px in dst.chunks_exact_mut(4) {
+                    px.swap(0, 1);
+                }
+                dst
+            },
+            CrunchFormat::Dxt5A => bc_texture::decode_bc4(&face, w, h, false)?,
+            CrunchFormat::Etc1 | CrunchFormat::Etc1S => etc_texture::decode_etc1(&face, w, h)?,
+            CrunchFormat::Etc2 => etc_texture::decode_etc2_rgb(&face, w, h)?,
+            CrunchFormat::Etc2A | CrunchFormat::Etc2AS => etc_texture::decode_etc2_rgba(&face, w, h)?,
+        })
+    }
+}
+
+// Undoes the channel layouts of crnlib's swizzled DXT5 formats. CCxY is YCoCg, with Co and Cg
+// in red and green (biased by 128) and Y in alpha. xGxR and xGBR move red into alpha, and
+// AGBR swaps red and alpha. Alpha is opaque unless the format keeps it.
+fn crunch_unswizzle(format: CrunchFormat, rgba: &mut [u8]) {
+    let clamp = |v: i32| v.clamp(0, 255) as u8;
+    for px in rgba.chunks_exact_mut(4) {
+        let [r, g, b, a] = [px[0], px[1], px[2], px[3]];
+        let unswizzled = match format {
+            CrunchFormat::Dxt5CCxY => {
+                let (y, co, cg) = (a as i32, r as i32 - 128, g as i32 - 128);
+                [clamp(y + co - cg), clamp(y + cg), clamp(y - co - cg), 0xFF]
+            },
+            CrunchFormat::Dxt5xGxR => [a, g, 0x00, 0xFF],
+            CrunchFormat::Dxt5xGBR => [a, g, b, 0xFF],
+            CrunchFormat::Dxt5AGBR => [a, g, b, r],
+            _ => return,
+        };
+        px.copy_from_slice(&unswizzled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // zlib level 9 (a dynamic Huffman block) of test_data().
+    const ZLIB_DYNAMIC: [u8; 42] = [120, 218, 165, 202, 193, 17, 0, 48, 8, 2, 176, 89, 241, 80, 65, 246, 255, 119, 136, 230, 29, 104, 143, 168, 206, 133, 72, 169, 219, 44, 138, 156, 216, 208, 44, 254, 195, 3, 219, 220, 39, 212];
+    const ZLIB_FIXED: [u8; 20] = [120, 156, 243, 72, 205, 201, 201, 215, 81, 168, 202, 201, 76, 82, 4, 0, 27, 101, 4, 19];
+    const RAW_STORED: [u8; 11] = [1, 6, 0, 249, 255, 115, 116, 111, 114, 101, 100];
+    // gzip of "Hello, gzip!", with a file name.
+    const GZIP_NAMED: [u8; 35] = [31, 139, 8, 8, 207, 173, 213, 106, 0, 3, 104, 103, 0, 243, 72, 205, 201, 201, 215, 81, 72, 175, 202, 44, 80, 4, 0, 62, 61, 15, 16, 12, 0, 0, 0];
+    // zstd -19 of test_data(): Huffman literals with FSE-compressed weights.
+    const ZSTD_TEST_DATA: [u8; 47] = [40, 181, 47, 253, 36, 100, 21, 1, 0, 34, 66, 6, 10, 208, 231, 132, 132, 118, 170, 5, 0, 20, 30, 27, 225, 136, 42, 207, 97, 138, 32, 116, 183, 26, 176, 107, 184, 1, 0, 58, 145, 212, 19, 25, 157, 192, 183];
+    // zstd -19 of words(60), with FSE-compressed sequence tables.
+    const ZSTD_WORDS: [u8; 169] = [40, 181, 47, 253, 100, 116, 0, 221, 4, 0, 66, 198, 17, 16, 176, 235, 36, 73, 196, 28, 195, 145, 165, 106, 197, 36, 41, 72, 166, 42, 36, 156, 229, 114, 93, 155, 31, 144, 112, 150, 139, 14, 197, 76, 128, 36, 56, 145, 143, 22, 167, 47, 174, 85, 250, 102, 106, 7, 248, 246, 202, 108, 51, 129, 167, 185, 64, 37, 233, 15, 85, 154, 7, 124, 206, 197, 3, 109, 86, 193, 113, 116, 81, 10, 40, 168, 241, 82, 64, 19, 22, 151, 150, 107, 6, 32, 134, 24, 6, 211, 3, 32, 70, 224, 46, 72, 225, 24, 1, 5, 70, 43, 93, 32, 39, 35, 131, 88, 4, 167, 143, 90, 190, 170, 196, 153, 168, 233, 211, 68, 151, 182, 23, 84, 225, 18, 108, 5, 244, 192, 195, 126, 215, 117, 175, 94, 88, 138, 155, 118, 125, 20, 193, 184, 182, 239, 192, 147, 124, 98, 32, 29, 14, 175, 2, 233, 218, 244, 217];
+    // zstd -19 of words(100), using words(60) as a raw content dictionary.
+    const ZSTD_WORDS_DICT: [u8; 93] = [40, 181, 47, 253, 100, 127, 1, 125, 2, 0, 112, 115, 109, 97, 119, 115, 101, 121, 111, 115, 104, 109, 97, 114, 105, 27, 160, 208, 165, 3, 16, 28, 162, 152, 246, 153, 112, 160, 200, 214, 145, 131, 114, 31, 153, 149, 36, 124, 187, 204, 76, 206, 18, 115, 240, 56, 89, 6, 91, 84, 196, 239, 198, 177, 65, 176, 201, 249, 135, 207, 188, 196, 140, 243, 83, 200, 240, 120, 24, 154, 231, 6, 219, 16, 224, 238, 84, 66, 2, 107, 199, 107, 121];
+
+    fn test_data() -> Vec<u8> {
+        (0..100u32).map(|i| ((i * i * 7 + i / 3) % 11 + 97) as u8).collect()
+    }
+
+    fn words(count: usize) -> Vec<u8> {
+        const WORDS: [&str; 16] = ["mario", "luigi", "galaxy", "star", "comet", "bee", "boo", "spring", "yoshi", "peach", "bowser", "rosalina", "luma", "launch", "dome", "observatory"];
+        let mut state = 0x12345678u32;
+        let words: Vec<&str> = (0..count).map(|_| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            WORDS[(state >> 28) as usize]
+        }).collect();
+        words.join(" ").into_bytes()
+    }
+
+    fn inflate_chunked(mut inflater: Inflater, src: &[u8], chunk_size: usize) -> Result<Vec<u8>, DecompressError> {
+        let mut dst = Vec::new();
+        for chunk in src.chunks(chunk_size) {
+            dst.extend(inflater.push(chunk)?);
+        }
+        dst.extend(inflater.finish()?);
+        Ok(dst)
+    }
+
+    #[test]
+    fn test_inflate() {
+        assert_eq!(deflate_decompress(&ZLIB_DYNAMIC).unwrap(), test_data());
+        assert_eq!(deflate_decompress(&ZLIB_FIXED).unwrap(), b"Hello, zlib!");
+        assert_eq!(deflate_raw_decompress(&RAW_STORED).unwrap(), b"stored");
+        for chunk_size in [1, 3, 7] {
+            assert_eq!(inflate_chunked(Inflater::new_zlib(), &ZLIB_DYNAMIC, chunk_size).unwrap(), test_data());
+            assert_eq!(inflate_chunked(Inflater::new_raw(), &RAW_STORED, chunk_size).unwrap(), b"stored");
+        }
+    }
+
+    #[test]
+    fn test_inflate_errors() {
+        for len in 0..ZLIB_DYNAMIC.len() {
+            assert_eq!(deflate_decompress(&ZLIB_DYNAMIC[..len]), Err(DecompressError::Truncated));
+        }
+        for len in 0..RAW_STORED.len() {
+            assert_eq!(deflate_raw_decompress(&RAW_STORED[..len]), Err(DecompressError::Truncated));
+        }
+
+        // Bad header, bad checksum, reserved block type, and a stored block with a bad length.
+        let mut bad = ZLIB_FIXED;
+        bad[1] ^= 1;
+        assert!(matches!(deflate_decompress(&bad), Err(DecompressError::Corrupt(_))));
+        let mut bad = ZLIB_FIXED;
+        bad[19] ^= 1;
+        assert!(matches!(deflate_decompress(&bad), Err(DecompressError::Corrupt(_))));
+        assert!(matches!(deflate_raw_decompress(&[0x07]), Err(DecompressError::Corrupt(_))));
+        let mut bad = RAW_STORED;
+        bad[3] ^= 1;
+        assert!(matches!(deflate_raw_decompress(&bad), Err(DecompressError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_lz4() {
+        let data = test_data().repeat(20);
+        let compressed = lz4_flex::compress(&data);
+        assert_eq!(lz4_decompress(&compressed, data.len()).unwrap(), data);
+
+        let mut decoder = Lz4Decoder::new(data.len());
+        let mut dst = Vec::new();
+        for chunk in compressed.chunks(5) {
+            dst.extend(decoder.push(chunk).unwrap());
+        }
+        dst.extend(decoder.finish().unwrap());
+        assert_eq!(dst, data);
+
+        assert_eq!(lz4_decompress(&[0x00], 0).unwrap(), b"");
+        assert_eq!(lz4_decompress(&compressed[..compressed.len() - 1], data.len()), Err(DecompressError::Truncated));
+        assert!(matches!(lz4_decompress(&compressed, data.len() - 1), Err(DecompressError::Corrupt(_))));
+        // A match pointing before the start of the output.
+        assert!(matches!(lz4_decompress(&[0x10, b'a', 0x02, 0x00, 0x00], 10), Err(DecompressError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_lzma() {
+        let data = test_data().repeat(20);
+        let mut alone = Vec::new();
+        lzma_rs::lzma_compress(&mut &data[..], &mut alone).unwrap();
+
+        // .lzma header: properties byte, dictionary size, unpacked size.
+        let props = alone[0] as u32;
+        let (lc, lp, pb) = (props % 9, (props / 9) % 5, props / 45);
+        let dict_size = u32::from_le_bytes(alone[1..5].try_into().unwrap());
+        let raw = &alone[13..];
+
+        // lzma-rs writes an end marker, so this works both with and without a known size.
+        assert_eq!(lzma_decompress(raw, lc, lp, pb, dict_size, Some(data.len() as u64)).unwrap(), data);
+        assert_eq!(lzma_decompress(raw, lc, lp, pb, dict_size, None).unwrap(), data);
+
+        let mut decoder = LzmaDecoder::new(lc, lp, pb, dict_size, None).unwrap();
+        let mut dst = Vec::new();
+        for chunk in raw.chunks(16) {
+            dst.extend(decoder.push(chunk).unwrap());
+        }
+        dst.extend(decoder.finish().unwrap());
+        assert_eq!(dst, data);
+
+        assert_eq!(lzma_decompress(&raw[..raw.len() / 2], lc, lp, pb, dict_size, None), Err(DecompressError::Truncated));
+        assert!(lzma_decompress(raw, 9, lp, pb, dict_size, None).is_err());
+    }
+
+    // Mixed input: text with repeats, a long run, and incompressible noise.
+    fn round_trip_inputs() -> Vec<Vec<u8>> {
+        let mut state = 0x12345678u32;
+        let noise: Vec<u8> = (0..70000).map(|_| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 24) as u8
+        }).collect();
+        let text = b"Super Mario Galaxy, Super Mario Galaxy 2, and the Comet Observatory. ".repeat(500);
+        let mut mixed = text.clone();
+        mixed.extend_from_slice(&noise[..5000]);
+        mixed.extend(std::iter::repeat_n(0xAA, 100000));
+        mixed.extend_from_slice(&text);
+        vec![Vec::new(), b"a".to_vec(), test_data(), text, noise, mixed]
+    }
+
+    #[test]
+    fn test_deflate_round_trip() {
+        for data in round_trip_inputs() {
+            for level in 0..=9 {
+                // Decode with the inflate crate directly so the encoder isn't checked against itself.
+                let compressed = deflate_compress(&data, level);
+                assert_eq!(inflate::inflate_bytes_zlib(&compressed).unwrap(), data, "level {}", level);
+                let compressed = deflate_raw_compress(&data, level);
+                assert_eq!(inflate::inflate_bytes(&compressed).unwrap(), data, "level {}", level);
+                // Incompressible data falls back to stored blocks.
+                assert!(compressed.len() <= data.len() + 5 * (data.len() / DEFLATE_BLOCK_TOKENS + 1));
+            }
+        }
+
+        let text = b"Super Mario Galaxy ".repeat(1000);
+        assert!(deflate_compress(&text, 9).len() < 200);
+        assert!(deflate_compress(&text, 9).len() <= deflate_compress(&text, 1).len());
+    }
+
+    #[test]
+    fn test_streaming_large_blocks() {
+        // Long stored blocks, Huffman blocks and literal runs fed a few bytes at a time. Retrying
+        // a whole block or sequence on every push would make this quadratic.
+        let inputs = round_trip_inputs();
+        let (noise, mixed) = (&inputs[4], &inputs[5]);
+        for level in [0, 9] {
+            let compressed = deflate_compress(mixed, level);
+            assert_eq!(&inflate_chunked(Inflater::new_zlib(), &compressed, 16).unwrap(), mixed);
+        }
+
+        let compressed = lz4_flex::compress(noise);
+        let mut decoder = Lz4Decoder::new(noise.len());
+        let mut dst = Vec::new();
+        for chunk in compressed.chunks(16) {
+            dst.extend(decoder.push(chunk).unwrap());
+        }
+        dst.extend(decoder.finish().unwrap());
+        assert_eq!(&dst, noise);
+    }
+
+    #[test]
+    fn test_lz4_round_trip() {
+        assert_eq!(xxh32(b"", 0), 0x02CC5D05);
+        assert_eq!(xxh32(b"abc", 0), 0x32D153FF);
+
+        for data in round_trip_inputs() {
+            let compressed = lz4_compress(&data);
+            assert_eq!(lz4_decompress(&compressed, data.len()).unwrap(), data);
+            let frame = lz4_frame_compress(&data);
+            assert_eq!(lz4_frame_decompress(&frame).unwrap(), data);
+        }
+
+        let frame = lz4_frame_compress(&test_data());
+        assert_eq!(lz4_frame_decompress(&frame[..frame.len() - 1]), Err(DecompressError::Truncated));
+        let mut bad = frame.clone();
+        bad[frame.len() - 1] ^= 1;
+        assert!(matches!(lz4_frame_decompress(&bad), Err(DecompressError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_lzma_round_trip() {
+        for data in round_trip_inputs() {
+            let compressed = lzma_compress(&data, 3, 0, 2, 0x100000, true).unwrap();
+            assert_eq!(lzma_decompress(&compressed, 3, 0, 2, 0x100000, None).unwrap(), data);
+            let compressed = lzma_compress(&data, 0, 2, 0, 0x100000, false).unwrap();
+            assert_eq!(lzma_decompress(&compressed, 0, 2, 0, 0x100000, Some(data.len() as u64)).unwrap(), data);
+
+            let alone = lzma_alone_compress(&data);
+            assert_eq!(lzma_alone_decompress(&alone).unwrap(), data);
+            let mut decoded = Vec::new();
+            lzma_rs::lzma_decompress(&mut &alone[..], &mut decoded).unwrap();
+            assert_eq!(decoded, data);
+        }
+
+        let text = b"Super Mario Galaxy ".repeat(1000);
+        assert!(lzma_alone_compress(&text).len() < 200);
+        assert!(lzma_compress(&text, 9, 0, 2, 0x1000, true).is_err());
+    }
+
+    #[test]
+    fn test_cx() {
+        // LZ10: a literal, then a 9 byte match at distance 1.
+        let lz10 = [0x10, 0x0A, 0x00, 0x00, 0x40, b'a', 0x60, 0x00];
+        assert_eq!(cx_detect(&lz10), Some(CxFormat::Lz10));
+        assert_eq!(cx_decompress(&lz10).unwrap(), b"aaaaaaaaaa");
+        let mut wrapped = b"LZ77".to_vec();
+        wrapped.extend_from_slice(&lz10);
+        assert_eq!(cx_decompress(&wrapped).unwrap(), b"aaaaaaaaaa");
+
+        assert_eq!(cx_detect(b"Yaz0"), None);
+        // Text that happens to start with a CX type byte.
+        assert_eq!(cx_detect(b"$version 1.0\n"), None);
+        assert_eq!(cx_detect(b"(define x 1)"), None);
+        // A size no LZ10 stream this short could produce, and a first token that isn't a literal.
+        assert_eq!(cx_detect(&[0x10, 0x00, 0x10, 0x00, 0x40, b'a', 0x60, 0x00]), None);
+        assert_eq!(cx_detect(&[0x10, 0x0A, 0x00, 0x00, 0x80, 0x60, 0x00]), None);
+        assert_eq!(cx_decompress(&lz10[..7]), Err(DecompressError::Truncated));
+        assert!(matches!(cx_decompress(&[0x10, 0x0A, 0x00, 0x00, 0x80, 0x60, 0x00]), Err(DecompressError::Corrupt(_))));
+        // A huge header size on truncated input mustn't be allocated up front.
+        assert_eq!(cx_decompress(&[0x11, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x00]), Err(DecompressError::Truncated));
+        assert_eq!(cx_decompress(&[0x28, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x00]), Err(DecompressError::Truncated));
+
+        let mut inputs = round_trip_inputs();
+        // Flat and very skewed byte distributions, for the Huffman tree layout.
+        inputs.push((0..=255u8).cycle().take(4096).collect());
+        inputs.push((0..20000u32).map(|i| (i.wrapping_mul(2654435761).leading_zeros() * 7 + i % 3) as u8).collect());
+        inputs.push((0..=255u8).flat_map(|b| std::iter::repeat_n(b, 1 + (b as usize % 40) * (b as usize % 7))).collect());
+        for data in inputs {
+            for format in [CxFormat::Lz10, CxFormat::Lz11, CxFormat::Huffman4, CxFormat::Huffman8] {
+                for lz77_header in [false, true] {
+                    let compressed = cx_compress(&data, format, lz77_header);
+                    let is_lz = matches!(format, CxFormat::Lz10 | CxFormat::Lz11);
+                    assert_eq!(cx_detect(&compressed), if is_lz && !data.is_empty() { Some(format) } else { None });
+                    assert_eq!(cx_decompress(&compressed).unwrap(), data, "{:?}", format);
+                }
+            }
+        }
+
+        let text = b"Super Mario Galaxy ".repeat(1000);
+        assert!(cx_compress(&text, CxFormat::Lz11, false).len() < 100);
+        assert!(cx_compress(&text, CxFormat::Lz10, false).len() < text.len() / 7);
+        assert!(cx_compress(&text, CxFormat::Huffman8, false).len() < text.len() / 2);
+    }
+
+    #[test]
+    fn test_gzip() {
+        assert_eq!(crc32(0, b"123456789"), 0xCBF43926);
+        assert_eq!(gzip_decompress(&GZIP_NAMED).unwrap(), b"Hello, gzip!");
+
+        // A member with every optional header field, concatenated after the first.
+        let data = test_data();
+        let mut member = vec![0x1F, 0x8B, 8, GZIP_FHCRC | GZIP_FEXTRA | GZIP_FNAME | GZIP_FCOMMENT, 0, 0, 0, 0, 0, 255, 3, 0, 1, 2, 3];
+        member.extend_from_slice(b"name\0comment\0");
+        member.extend_from_slice(&(crc32(0, &member) as u16).to_le_bytes());
+        member.extend(deflate_raw_compress(&data, 6));
+        member.extend_from_slice(&crc32(0, &data).to_le_bytes());
+        member.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        assert_eq!(gzip_decompress(&member).unwrap(), data);
+        let mut concatenated = GZIP_NAMED.to_vec();
+        concatenated.extend_from_slice(&member);
+        assert_eq!(gzip_decompress(&concatenated).unwrap(), [&b"Hello, gzip!"[..], &data].concat());
+
+        for len in 0..GZIP_NAMED.len() {
+            assert_eq!(gzip_decompress(&GZIP_NAMED[..len]), Err(DecompressError::Truncated));
+        }
+        let mut bad = GZIP_NAMED;
+        bad[28] ^= 1;
+        assert!(matches!(gzip_decompress(&bad), Err(DecompressError::Corrupt(_))));
+        let mut bad = member.clone();
+        bad[12] ^= 1;
+        assert!(matches!(gzip_decompress(&bad), Err(DecompressError::Corrupt(_))));
+        let mut bad = GZIP_NAMED;
+        bad[31] ^= 1;
+        assert!(matches!(gzip_decompress(&bad), Err(DecompressError::SizeMismatch { .. })));
+    }
+
+    #[test]
+    fn test_zstd() {
+        assert_eq!(zstd_decompress(&ZSTD_TEST_DATA).unwrap(), test_data());
+        assert_eq!(zstd_decompress(&ZSTD_WORDS).unwrap(), words(60));
+        let dict = ZstdDictionary::new(&words(60)).unwrap();
+        assert_eq!(dict.get_id(), 0);
+        assert_eq!(zstd_decompress_with_dictionary(&ZSTD_WORDS_DICT, &dict).unwrap(), words(100));
+        assert!(matches!(zstd_decompress(&ZSTD_WORDS_DICT), Err(DecompressError::Corrupt(_))));
+
+        // Concatenated frames, with a skippable frame in between.
+        let mut frames = ZSTD_TEST_DATA.to_vec();
+        frames.extend_from_slice(&[0x5E, 0x2A, 0x4D, 0x18, 3, 0, 0, 0, 1, 2, 3]);
+        frames.extend_from_slice(&ZSTD_WORDS);
+        assert_eq!(zstd_decompress(&frames).unwrap(), [test_data(), words(60)].concat());
+
+        for len in 0..ZSTD_WORDS.len() {
+            assert_eq!(zstd_decompress(&ZSTD_WORDS[..len]), Err(DecompressError::Truncated));
+        }
+        let mut bad = ZSTD_WORDS;
+        bad[ZSTD_WORDS.len() - 1] ^= 1;
+        assert!(matches!(zstd_decompress(&bad), Err(DecompressError::Corrupt(_))));
+        assert!(matches!(zstd_decompress(b"Yaz0...."), Err(DecompressError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_crunch_header() {
+        // A 256x128 cubemap with 9 levels of ETC2A; the palettes and level offsets are zeroed.
+        let mut data = vec![0x00; CRUNCH_HEADER_MIN_SIZE + 8 * 4];
+        data[0x00..0x02].copy_from_slice(b"Hx");
+        data[0x0C..0x10].copy_from_slice(&[0x01, 0x00, 0x00, 0x80]);
+        data[0x10] = 9;
+        data[0x11] = 6;
+        data[0x12] = CrunchFormat::Etc2A as u8;
+        let header = CrunchHeader::parse(&data).unwrap();
+        assert_eq!((header.width, header.height, header.num_levels, header.num_faces), (256, 128, 9, 6));
+        assert_eq!(header.format, CrunchFormat::Etc2A);
+        assert_eq!((header.level_width(3), header.level_height(3)), (32, 16));
+        assert_eq!((header.level_width(8), header.level_height(8)), (1, 1));
+        assert_eq!(header.level_size(0), 64 * 32 * 0x10);
+        assert_eq!(header.level_size(8), 0x10);
+
+        data[0x12] = 15;
+        assert!(CrunchHeader::parse(&data).is_err());
+        assert!(CrunchHeader::parse(&data[..0x20]).is_err());
+    }
+
+    #[test]
+    fn test_crunch_unswizzle() {
+        // RGB (200, 100, 50) in YCoCg is Y 113, Co 75 and Cg -12, which loses a bit of green.
+        // Zero chroma is gray.
+        let mut rgba = [203, 116, 0, 113, 128, 128, 0, 77];
+        crunch_unswizzle(CrunchFormat::Dxt5CCxY, &mut rgba);
+        assert_eq!(rgba, [200, 101, 50, 255, 77, 77, 77, 255]);
+
+        let swizzled = [10, 20, 30, 40];
+        for (format, expected) in [
+            (CrunchFormat::Dxt5xGxR, [40, 20, 0, 255]),
+            (CrunchFormat::Dxt5xGBR, [40, 20, 30, 255]),
+            (CrunchFormat::Dxt5AGBR, [40, 20, 30, 10]),
+            (CrunchFormat::Dxt5, swizzled),
+        ] {
+            let mut rgba = swizzled;
+            crunch_unswizzle(format, &mut rgba);
+            assert_eq!(rgba, expected, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_auto_decompress() {
+        let data = test_data();
+        let check = |src: &[u8], format: CompressionFormat, expected: &[u8]| {
+            let result = auto_decompress(src).unwrap();
+            assert_eq!(result.get_format(), format);
+            assert_eq!(result.into_data(), expected);
+        };
+        check(&deflate_compress(&data, 6), CompressionFormat::Zlib, &data);
+        check(&GZIP_NAMED, CompressionFormat::Gzip, b"Hello, gzip!");
+        check(&lzma_alone_compress(&data), CompressionFormat::LzmaAlone, &data);
+        check(&lz4_frame_compress(&data), CompressionFormat::Lz4Frame, &data);
+        check(&ZSTD_TEST_DATA, CompressionFormat::Zstd, &data);
+        check(&cx_compress(&data, CxFormat::Lz11, false), CompressionFormat::Cx, &data);
+
+        let mut yaz0 = b"Yaz0\x00\x00\x00\x0C".to_vec();
+        yaz0.extend_from_slice(&[0x00; 8]);
+        yaz0.extend_from_slice(&[0xE0, b'a', b'b', b'c', 0x70, 0x02]);
+        check(&yaz0, CompressionFormat::Yaz0, b"abcabcabcabc");
+
+        // Uncompressed data, including data that only looks like a zlib header.
+        check(&data, CompressionFormat::None, &[]);
+        check(b"x\x9Cnot zlib", CompressionFormat::None, &[]);
+        assert_eq!(detect_compression(b"x\x9Cnot zlib"), CompressionFormat::Zlib);
+        // Text starting with a CX Huffman type byte isn't taken for Huffman data.
+        check(b"$include \"common.h\"", CompressionFormat::None, &[]);
+
+        // Formats with a magic number report errors.
+        assert!(auto_decompress(&ZSTD_TEST_DATA[..20]).is_err());
+    }
 }
@@ -0,0 +1,283 @@
+// Software decoders for the ETC1 / ETC2 / EAC block compressed formats, for use
+// where the GPU lacks support for them (e.g. desktop WebGL with crunched Unity textures).
+
+use crate::bc_texture::{decode_blocks, Block};
+use crate::util::OutOfBounds;
+use crate::util;
+
+const ETC1_MODIFIERS: [[i32; 4]; 8] = [
+    [ 2,   8,  -2,   -8],
+    [ 5,  17,  -5,  -17],
+    [ 9,  29,  -9,  -29],
+    [13,  42, -13,  -42],
+    [18,  60, -18,  -60],
+    [24,  80, -24,  -80],
+    [33, 106, -33, -106],
+    [47, 183, -47, -183],
+];
+
+const ETC2_DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6,  -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5,  -8, -13, 1, 4, 7, 12],
+    [-2, -4,  -6, -13, 1, 3, 5, 12],
+    [-3, -6,  -8, -12, 2, 5, 7, 11],
+    [-3, -7,  -9, -11, 2, 6, 8, 10],
+    [-4, -7,  -8, -11, 3, 6, 7, 10],
+    [-3, -5,  -8, -11, 2, 4, 7, 10],
+    [-2, -6,  -8, -10, 1, 5, 7,  9],
+    [-2, -5,  -8, -10, 1, 4, 7,  9],
+    [-2, -4,  -8, -10, 1, 3, 7,  9],
+    [-2, -5,  -7, -10, 1, 4, 6,  9],
+    [-3, -4,  -7, -10, 2, 3, 6,  9],
+    [-1, -2,  -3, -10, 0, 1, 2,  9],
+    [-4, -6,  -8,  -9, 3, 5, 7,  8],
+    [-3, -5,  -7,  -9, 2, 4, 6,  8],
+];
+
+fn clamp8(v: i32) -> u8 {
+    v.clamp(0, 255) as u8
+}
+
+fn expand(bits: u8, v: u8) -> i32 {
+    util::expand_n_to_8(bits, v) as i32
+}
+
+fn offset_rgb(c: [i32; 3], d: i32) -> [u8; 4] {
+    [clamp8(c[0] + d), clamp8(c[1] + d), clamp8(c[2] + d), 0xFF]
+}
+
+fn sign_extend3(v: u8) -> i32 {
+    (((v << 5) as i8) >> 5) as i32
+}
+
+// Pixels are stored column-major, with the high and low bits of each 2-bit index
+// in separate 16-bit halves.
+fn pixel_index(bits: u32, x: usize, y: usize) -> usize {
+    let i = x * 4 + y;
+    ((((bits >> (i + 16)) & 0x01) << 1) | ((bits >> i) & 0x01)) as usize
+}
+
+fn decode_paint_block(bits: u32, paint: &[[u8; 4]; 4], dst: &mut Block) {
+    for y in 0..4 {
+        for x in 0..4 {
+            dst[y * 4 + x] = paint[pixel_index(bits, x, y)];
+        }
+    }
+}
+
+fn decode_etc2_t_block(src: &[u8], bits: u32, dst: &mut Block) {
+    let c0 = [expand(4, ((src[0] >> 1) & 0x0C) | (src[0] & 0x03)), expand(4, src[1] >> 4), expand(4, src[1] & 0x0F)];
+    let c1 = [expand(4, src[2] >> 4), expand(4, src[2] & 0x0F), expand(4, src[3] >> 4)];
+    let d = ETC2_DISTANCES[(((src[3] >> 1) & 0x06) | (src[3] & 0x01)) as usize];
+    let paint = [offset_rgb(c0, 0), offset_rgb(c1, d), offset_rgb(c1, 0), offset_rgb(c1, -d)];
+    decode_paint_block(bits, &paint, dst);
+}
+
+fn decode_etc2_h_block(src: &[u8], bits: u32, dst: &mut Block) {
+    let r0 = (src[0] >> 3) & 0x0F;
+    let g0 = ((src[0] & 0x07) << 1) | ((src[1] >> 4) & 0x01);
+    let b0 = (src[1] & 0x08) | ((src[1] & 0x03) << 1) | (src[2] >> 7);
+    let r1 = (src[2] >> 3) & 0x0F;
+    let g1 = ((src[2] & 0x07) << 1) | (src[3] >> 7);
+    let b1 = (src[3] >> 3) & 0x0F;
+
+    // The low bit of the distance index is implied by the ordering of the two colors.
+    let key = |r: u8, g: u8, b: u8| ((r as u32) << 8) | ((g as u32) << 4) | (b as u32);
+    let order = (key(r0, g0, b0) >= key(r1, g1, b1)) as u8;
+    let d = ETC2_DISTANCES[((src[3] & 0x04) | ((src[3] & 0x01) << 1) | order) as usize];
+
+    let c0 = [expand(4, r0), expand(4, g0), expand(4, b0)];
+    let c1 = [expand(4, r1), expand(4, g1), expand(4, b1)];
+    let paint = [offset_rgb(c0, d), offset_rgb(c0, -d), offset_rgb(c1, d), offset_rgb(c1, -d)];
+    decode_paint_block(bits, &paint, dst);
+}
+
+fn decode_etc2_planar_block(src: &[u8], dst: &mut Block) {
+    let o = [
+        expand(6, (src[0] >> 1) & 0x3F),
+        expand(7, ((src[0] & 0x01) << 6) | ((src[1] >> 1) & 0x3F)),
+        expand(6, ((src[1] & 0x01) << 5) | (src[2] & 0x18) | ((src[2] & 0x03) << 1) | (src[3] >> 7)),
+    ];
+    let h = [
+        expand(6, ((src[3] >> 1) & 0x3E) | (src[3] & 0x01)),
+        expand(7, src[4] >> 1),
+        expand(6, ((src[4] & 0x01) << 5) | (src[5] >> 3)),
+    ];
+    let v = [
+        expand(6, ((src[5] & 0x07) << 3) | (src[6] >> 5)),
+        expand(7, ((src[6] & 0x1F) << 2) | (src[7] >> 6)),
+        expand(6, src[7] & 0x3F),
+    ];
+
+    for y in 0..4 {
+        for x in 0..4 {
+            let px = &mut dst[y * 4 + x];
+            for c in 0..3 {
+                px[c] = clamp8(((x as i32) * (h[c] - o[c]) + (y as i32) * (v[c] - o[c]) + 4 * o[c] + 2) >> 2);
+            }
+            px[3] = 0xFF;
+        }
+    }
+}
+
+// ETC2 reuses the differential encodings that overflow a channel in ETC1 for its
+// T, H and planar modes.
+fn decode_etc_color_block(src: &[u8], dst: &mut Block, is_etc2: bool) {
+    let bits = u32::from_be_bytes([src[4], src[5], src[6], src[7]]);
+    let is_differential = (src[3] & 0x02) != 0;
+    let is_flipped = (src[3] & 0x01) != 0;
+
+    let (c0, c1) = if is_differential {
+        let base = [(src[0] >> 3) as i32, (src[1] >> 3) as i32, (src[2] >> 3) as i32];
+        let delta = [sign_extend3(src[0]), sign_extend3(src[1]), sign_extend3(src[2])];
+        if is_etc2 {
+            let overflows = |c: usize| !(0..32).contains(&(base[c] + delta[c]));
+            if overflows(0) {
+                return decode_etc2_t_block(src, bits, dst);
+            } else if overflows(1) {
+                return decode_etc2_h_block(src, bits, dst);
+            } else if overflows(2) {
+                return decode_etc2_planar_block(src, dst);
+            }
+        }
+
+        let expand5 = |v: i32| expand(5, (v & 0x1F) as u8);
+        (
+            [expand5(base[0]), expand5(base[1]), expand5(base[2])],
+            [expand5(base[0] + delta[0]), expand5(base[1] + delta[1]), expand5(base[2] + delta[2])],
+        )
+    } else {
+        (
+            [expand(4, src[0] >> 4), expand(4, src[1] >> 4), expand(4, src[2] >> 4)],
+            [expand(4, src[0] & 0x0F), expand(4, src[1] & 0x0F), expand(4, src[2] & 0x0F)],
+        )
+    };
+
+    let modifiers = [&ETC1_MODIFIERS[(src[3] >> 5) as usize], &ETC1_MODIFIERS[((src[3] >> 2) & 0x07) as usize]];
+    for y in 0..4 {
+        for x in 0..4 {
+            // Two 2x4 subblocks side by side, or two 4x2 subblocks stacked when flipped.
+            let subblock = if is_flipped { y >= 2 } else { x >= 2 };
+            let (base, modifiers) = if subblock { (c1, modifiers[1]) } else { (c0, modifiers[0]) };
+            dst[y * 4 + x] = offset_rgb(base, modifiers[pixel_index(bits, x, y)]);
+        }
+    }
+}
+
+fn decode_eac_alpha_block(src: &[u8], dst: &mut Block) {
+    let base = src[0] as i32;
+    let multiplier = (src[1] >> 4) as i32;
+    let modifiers = &EAC_MODIFIERS[(src[1] & 0x0F) as usize];
+    let bits = u64::from_be_bytes([src[0], src[1], src[2], src[3], src[4], src[5], src[6], src[7]]);
+    for y in 0..4 {
+        for x in 0..4 {
+            let index = (bits >> (45 - (x * 4 + y) * 3)) & 0x07;
+            dst[y * 4 + x][3] = clamp8(base + modifiers[index as usize] * multiplier);
+        }
+    }
+}
+
+pub fn decode_etc1(src: &[u8], w: usize, h: usize) -> Result<Vec<u8>, OutOfBounds> {
+    decode_blocks(src, w, h, |src, dst| {
+        decode_etc_color_block(src.read_bytes(0x08)?, dst, false);
+        Ok(())
+    })
+}
+
+pub fn decode_etc2_rgb(src: &[u8], w: usize, h: usize) -> Result<Vec<u8>, OutOfBounds> {
+    decode_blocks(src, w, h, |src, dst| {
+        decode_etc_color_block(src.read_bytes(0x08)?, dst, true);
+        Ok(())
+    })
+}
+
+// ETC2 RGBA8: an EAC alpha block followed by an ETC2 color block.
+pub fn decode_etc2_rgba(src: &[u8], w: usize, h: usize) -> Result<Vec<u8>, OutOfBounds> {
+    decode_blocks(src, w, h, |src, dst| {
+        let alpha = src.read_bytes(0x08)?;
+        decode_etc_color_block(src.read_bytes(0x08)?, dst, true);
+        decode_eac_alpha_block(alpha, dst);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sets the differential bit and fills the given unused bits of an ETC2 T/H/planar
+    // block so that exactly the requested channel overflows.
+    fn etc2_block(bits: u64, free_bits: &[u32], channel: u32) -> [u8; 8] {
+        for combo in 0..(1u64 << free_bits.len()) {
+            let mut v = bits | (1 << 33);
+            for (i, &bit) in free_bits.iter().enumerate() {
+                v |= ((combo >> i) & 0x01) << bit;
+            }
+            let overflows = |c: u32| {
+                let shift = 59 - c * 8;
+                let sum = ((v >> shift) & 0x1F) as i32 + sign_extend3(((v >> (shift - 3)) & 0x07) as u8);
+                !(0..32).contains(&sum)
+            };
+            if overflows(channel) && (0..channel).all(|c| !overflows(c)) {
+                return v.to_be_bytes();
+            }
+        }
+        unreachable!();
+    }
+
+    #[test]
+    fn test_etc1_individual() {
+        // Colors 0x842 and 0xF00, tables 0 and 7, every pixel index 0.
+        let block = [0x8F, 0x40, 0x20, 0x1C, 0x00, 0x00, 0x00, 0x00];
+        let dst = decode_etc1(&block, 4, 4).unwrap();
+        assert_eq!(&dst[0x00..0x04], &[138, 70, 36, 0xFF]);
+        assert_eq!(&dst[0x0C..0x10], &[0xFF, 47, 47, 0xFF]);
+    }
+
+    #[test]
+    fn test_etc1_differential() {
+        // Base (16, 0, 31), delta (-1, 0, -2), flipped, tables 1 and 0, every pixel index 3.
+        let block = [0x87, 0x00, 0xFE, 0x23, 0xFF, 0xFF, 0xFF, 0xFF];
+        let dst = decode_etc1(&block, 4, 4).unwrap();
+        assert_eq!(&dst[0x00..0x04], &[115, 0, 238, 0xFF]);
+        assert_eq!(&dst[0x20..0x24], &[115, 0, 231, 0xFF]);
+        assert_eq!(decode_etc2_rgb(&block, 4, 4).unwrap(), dst);
+    }
+
+    #[test]
+    fn test_etc2_t_mode() {
+        // Colors 0xF00 and 0x888, distance 3; pixel (0, 0) uses index 1 and the rest index 0.
+        let bits = (0x03 << 59) | (0x03 << 56) | (0x08 << 44) | (0x08 << 40) | (0x08 << 36) | 0x01;
+        let block = etc2_block(bits, &[63, 62, 61, 58], 0);
+        let dst = decode_etc2_rgb(&block, 4, 4).unwrap();
+        assert_eq!(&dst[0x00..0x04], &[139, 139, 139, 0xFF]);
+        assert_eq!(&dst[0x04..0x08], &[0xFF, 0x00, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn test_etc2_planar() {
+        // Red ramps up horizontally and green vertically.
+        let bits = (0x1F << 34) | (0x01 << 32) | (0x7F << 6);
+        let block = etc2_block(bits, &[63, 55, 47, 46, 45, 42], 2);
+        let dst = decode_etc2_rgb(&block, 4, 4).unwrap();
+        let row: Vec<u8> = dst[0x00..0x10].chunks(4).map(|px| px[0]).collect();
+        assert_eq!(row, [0, 64, 128, 191]);
+        let column: Vec<u8> = dst.chunks(0x10).map(|row| row[1]).collect();
+        assert_eq!(column, [0, 64, 128, 191]);
+    }
+
+    #[test]
+    fn test_eac_alpha() {
+        // Base 128, multiplier 1, table 0, every pixel index 4 (+2).
+        let mut block = [0x00; 16];
+        block[0] = 128;
+        block[1] = 0x10;
+        let indices = (0..16).fold(0u64, |bits, _| (bits << 3) | 0x04);
+        block[2..8].copy_from_slice(&indices.to_be_bytes()[2..8]);
+        let dst = decode_etc2_rgba(&block, 4, 4).unwrap();
+        assert!(dst.chunks(4).all(|px| px[3] == 130));
+    }
+}
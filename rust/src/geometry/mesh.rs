@@ -0,0 +1,750 @@
+use std::collections::{HashMap, VecDeque};
+
+use nalgebra_glm::{make_vec2, make_vec3, Vec2, Vec3};
+use wasm_bindgen::prelude::*;
+
+// Mesh processing over flat buffers shared by every backend: positions and normals are packed
+// xyz, UVs are packed uv, and each three indices make a triangle. Remap tables give the new
+// index of every old vertex, or UNUSED for vertices that were dropped; apply them with
+// remap_vertices and remap_indices.
+
+pub const UNUSED: u32 = u32::MAX;
+
+fn vertex_count(data: &[f32], components: usize) -> Result<usize, String> {
+    if components == 0 || !data.len().is_multiple_of(components) {
+        return Err(format!("vertex data length {} isn't a multiple of {}", data.len(), components));
+    }
+    Ok(data.len() / components)
+}
+
+fn check_indices(indices: &[u32], vertex_count: usize) -> Result<(), String> {
+    if !indices.len().is_multiple_of(3) {
+        return Err(format!("index count {} isn't a multiple of 3", indices.len()));
+    }
+    match indices.iter().find(|&&i| i as usize >= vertex_count) {
+        Some(i) => Err(format!("index {} out of range for {} vertices", i, vertex_count)),
+        None => Ok(()),
+    }
+}
+
+fn position(positions: &[f32], i: u32) -> Vec3 {
+    let i = i as usize * 3;
+    make_vec3(&positions[i..i + 3])
+}
+
+fn triangle_positions(positions: &[f32], tri: &[u32]) -> [Vec3; 3] {
+    [position(positions, tri[0]), position(positions, tri[1]), position(positions, tri[2])]
+}
+
+fn normalize_or_zero(v: &Vec3) -> Vec3 {
+    let len = v.norm();
+    if len > 0.0 { v / len } else { Vec3::zeros() }
+}
+
+// The interior angle at `a`.
+fn corner_angle(a: &Vec3, b: &Vec3, c: &Vec3) -> f32 {
+    let (u, v) = (b - a, c - a);
+    let len = u.norm() * v.norm();
+    if len == 0.0 {
+        return 0.0;
+    }
+    (u.dot(&v) / len).clamp(-1.0, 1.0).acos()
+}
+
+fn flatten(vectors: &[Vec3]) -> Vec<f32> {
+    vectors.iter().flat_map(|v| v.iter().copied()).collect()
+}
+
+// Angle-weighted vertex normals, which don't depend on how faces were triangulated.
+// Vertices with no area around them get a zero normal.
+#[wasm_bindgen]
+pub fn compute_smooth_normals(positions: &[f32], indices: &[u32]) -> Result<Vec<f32>, String> {
+    let count = vertex_count(positions, 3)?;
+    check_indices(indices, count)?;
+    let mut normals = vec![Vec3::zeros(); count];
+    for tri in indices.chunks_exact(3) {
+        let p = triangle_positions(positions, tri);
+        let normal = normalize_or_zero(&(p[1] - p[0]).cross(&(p[2] - p[0])));
+        for corner in 0..3 {
+            let angle = corner_angle(&p[corner], &p[(corner + 1) % 3], &p[(corner + 2) % 3]);
+            normals[tri[corner] as usize] += normal * angle;
+        }
+    }
+    let normals: Vec<Vec3> = normals.iter().map(normalize_or_zero).collect();
+    Ok(flatten(&normals))
+}
+
+// One normal per index, for the mesh unindexed by unindex_vertices.
+#[wasm_bindgen]
+pub fn compute_flat_normals(positions: &[f32], indices: &[u32]) -> Result<Vec<f32>, String> {
+    check_indices(indices, vertex_count(positions, 3)?)?;
+    let mut normals = Vec::with_capacity(indices.len());
+    for tri in indices.chunks_exact(3) {
+        let p = triangle_positions(positions, tri);
+        let normal = normalize_or_zero(&(p[1] - p[0]).cross(&(p[2] - p[0])));
+        normals.extend_from_slice(&[normal; 3]);
+    }
+    Ok(flatten(&normals))
+}
+
+// Expands vertex data to one vertex per index.
+#[wasm_bindgen]
+pub fn unindex_vertices(data: &[f32], components: usize, indices: &[u32]) -> Result<Vec<f32>, String> {
+    check_indices(indices, vertex_count(data, components)?)?;
+    let mut result = Vec::with_capacity(indices.len() * components);
+    for &i in indices {
+        let i = i as usize * components;
+        result.extend_from_slice(&data[i..i + components]);
+    }
+    Ok(result)
+}
+
+// Some unit vector perpendicular to `n`.
+fn any_perpendicular(n: &Vec3) -> Vec3 {
+    let axis = if n.x.abs() < 0.9 { Vec3::x() } else { Vec3::y() };
+    (axis - n * n.dot(&axis)).normalize()
+}
+
+// Per-vertex tangents as xyz, with the bitangent's handedness in w so that the bitangent is
+// w * cross(normal, tangent). As in MikkTSpace, each triangle's tangent and bitangent are
+// normalised, weighted by the corner angle and projected into the vertex normal's tangent
+// plane. Unlike MikkTSpace, vertices aren't split where the handedness flips, so mirrored
+// UV seams need their own vertices.
+#[wasm_bindgen]
+pub fn compute_tangents(positions: &[f32], normals: &[f32], uvs: &[f32], indices: &[u32]) -> Result<Vec<f32>, String> {
+    let count = vertex_count(positions, 3)?;
+    if vertex_count(normals, 3)? != count || vertex_count(uvs, 2)? != count {
+        return Err(format!("normals and UVs must have {} vertices like the positions", count));
+    }
+    check_indices(indices, count)?;
+
+    let normal = |i: usize| make_vec3(&normals[i * 3..i * 3 + 3]);
+    let uv = |i: u32| make_vec2(&uvs[i as usize * 2..i as usize * 2 + 2]);
+    let mut tangents = vec![Vec3::zeros(); count];
+    let mut bitangents = vec![Vec3::zeros(); count];
+    for tri in indices.chunks_exact(3) {
+        let p = triangle_positions(positions, tri);
+        let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+        let (d1, d2): (Vec2, Vec2) = (uv(tri[1]) - uv(tri[0]), uv(tri[2]) - uv(tri[0]));
+        let det = d1.x * d2.y - d2.x * d1.y;
+        if det == 0.0 {
+            continue;
+        }
+        let tangent = normalize_or_zero(&((e1 * d2.y - e2 * d1.y) / det));
+        let bitangent = normalize_or_zero(&((e2 * d1.x - e1 * d2.x) / det));
+        for corner in 0..3 {
+            let i = tri[corner] as usize;
+            let n = normal(i);
+            let angle = corner_angle(&p[corner], &p[(corner + 1) % 3], &p[(corner + 2) % 3]);
+            tangents[i] += normalize_or_zero(&(tangent - n * n.dot(&tangent))) * angle;
+            bitangents[i] += normalize_or_zero(&(bitangent - n * n.dot(&bitangent))) * angle;
+        }
+    }
+
+    let mut result = Vec::with_capacity(count * 4);
+    for i in 0..count {
+        let n = normal(i);
+        let mut t = normalize_or_zero(&(tangents[i] - n * n.dot(&tangents[i])));
+        if t == Vec3::zeros() {
+            t = any_perpendicular(&n);
+        }
+        let w = if n.cross(&t).dot(&bitangents[i]) < 0.0 { -1.0 } else { 1.0 };
+        result.extend_from_slice(&[t.x, t.y, t.z, w]);
+    }
+    Ok(result)
+}
+
+// Merges each vertex into the first earlier vertex whose components are all within
+// `epsilon`, or only into exact copies with an epsilon of zero. Vertices are `stride` floats
+// starting with the position. Returns the remap and the welded vertex count.
+pub fn weld_vertices(vertices: &[f32], stride: usize, epsilon: f32) -> Result<(Vec<u32>, usize), String> {
+    if stride < 3 {
+        return Err(format!("vertex stride {} has no room for a position", stride));
+    }
+    let count = vertex_count(vertices, stride)?;
+    let vertex = |i: usize| &vertices[i * stride..(i + 1) * stride];
+    let mut remap = Vec::with_capacity(count);
+
+    if epsilon <= 0.0 {
+        let mut unique: HashMap<Vec<u32>, u32> = HashMap::new();
+        for i in 0..count {
+            // Adding zero turns -0.0 into 0.0.
+            let key: Vec<u32> = vertex(i).iter().map(|x| (x + 0.0).to_bits()).collect();
+            let next = unique.len() as u32;
+            remap.push(*unique.entry(key).or_insert(next));
+        }
+        return Ok((remap, unique.len()));
+    }
+
+    // Welded vertices are bucketed by position in cells of size epsilon, so any match is in
+    // a neighbouring cell.
+    let cell = |v: &[f32]| [0, 1, 2].map(|axis| (v[axis] / epsilon).floor() as i32);
+    let mut grid: HashMap<[i32; 3], Vec<usize>> = HashMap::new();
+    let mut welded = 0;
+    for i in 0..count {
+        let v = vertex(i);
+        let [x, y, z] = cell(v);
+        let mut found = None;
+        'search: for key in (-1..=1).flat_map(|dx| (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| [x + dx, y + dy, z + dz]))) {
+            for &j in grid.get(&key).into_iter().flatten() {
+                if vertex(j).iter().zip(v).all(|(a, b)| (a - b).abs() <= epsilon) {
+                    found = Some(remap[j]);
+                    break 'search;
+                }
+            }
+        }
+        match found {
+            Some(index) => remap.push(index),
+            None => {
+                grid.entry([x, y, z]).or_default().push(i);
+                remap.push(welded);
+                welded += 1;
+            }
+        }
+    }
+    Ok((remap, welded as usize))
+}
+
+pub fn remap_vertices(data: &[f32], components: usize, remap: &[u32], new_count: usize) -> Result<Vec<f32>, String> {
+    if vertex_count(data, components)? != remap.len() {
+        return Err(format!("remap has {} entries for {} vertices", remap.len(), data.len() / components));
+    }
+    let mut result = vec![0.0; new_count * components];
+    for (old, &new) in remap.iter().enumerate() {
+        if new == UNUSED {
+            continue;
+        }
+        let new = new as usize;
+        if new >= new_count {
+            return Err(format!("remapped vertex {} out of range for {} vertices", new, new_count));
+        }
+        result[new * components..(new + 1) * components].copy_from_slice(&data[old * components..(old + 1) * components]);
+    }
+    Ok(result)
+}
+
+pub fn remap_indices(indices: &[u32], remap: &[u32]) -> Result<Vec<u32>, String> {
+    indices.iter().map(|&i| match remap.get(i as usize) {
+        Some(&new) if new != UNUSED => Ok(new),
+        _ => Err(format!("index {} has no remapped vertex", i)),
+    }).collect()
+}
+
+const VERTEX_CACHE_SIZE: usize = 32;
+
+// Forsyth's "Linear-Speed Vertex Cache Optimisation" scoring, with its usual constants.
+fn vertex_cache_score(cache_position: Option<usize>, remaining_triangles: usize) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        None => 0.0,
+        Some(position) if position < 3 => 0.75,
+        Some(position) => (1.0 - (position - 3) as f32 / (VERTEX_CACHE_SIZE - 3) as f32).powf(1.5),
+    };
+    cache_score + 2.0 / (remaining_triangles as f32).sqrt()
+}
+
+// Reorders triangles for the post-transform vertex cache.
+#[wasm_bindgen]
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Result<Vec<u32>, String> {
+    check_indices(indices, vertex_count)?;
+    let triangle_count = indices.len() / 3;
+    let triangle = |t: usize| &indices[t * 3..t * 3 + 3];
+
+    // The triangles not yet emitted around each vertex. Only vertices the indices use need a
+    // slot, however large vertex_count is.
+    let used_vertex_count = indices.iter().max().map_or(0, |&i| i as usize + 1);
+    let mut vertex_triangles = vec![Vec::new(); used_vertex_count];
+    for t in 0..triangle_count {
+        for &v in triangle(t) {
+            vertex_triangles[v as usize].push(t);
+        }
+    }
+    let mut scores: Vec<f32> = vertex_triangles.iter().map(|t| vertex_cache_score(None, t.len())).collect();
+    let triangle_score = |t: usize, scores: &[f32]| triangle(t).iter().map(|&v| scores[v as usize]).sum::<f32>();
+
+    let mut result = Vec::with_capacity(indices.len());
+    let mut emitted = vec![false; triangle_count];
+    let mut next_unemitted = 0;
+    let mut cache: Vec<u32> = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+    let mut best = (0..triangle_count).max_by(|&a, &b| triangle_score(a, &scores).total_cmp(&triangle_score(b, &scores)));
+    while let Some(t) = best {
+        emitted[t] = true;
+        result.extend_from_slice(triangle(t));
+        for &v in triangle(t) {
+            let triangles = &mut vertex_triangles[v as usize];
+            if let Some(i) = triangles.iter().position(|&other| other == t) {
+                triangles.swap_remove(i);
+            }
+        }
+
+        // The triangle's vertices move to the front of the LRU cache. Scores are updated for
+        // everything that was in it, including vertices that just fell out.
+        let mut new_cache: Vec<u32> = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+        for &v in triangle(t).iter().chain(&cache) {
+            if !new_cache.contains(&v) {
+                new_cache.push(v);
+            }
+        }
+        for (i, &v) in new_cache.iter().enumerate() {
+            let position = if i < VERTEX_CACHE_SIZE { Some(i) } else { None };
+            scores[v as usize] = vertex_cache_score(position, vertex_triangles[v as usize].len());
+        }
+        new_cache.truncate(VERTEX_CACHE_SIZE);
+        cache = new_cache;
+
+        best = None;
+        let mut best_score = f32::NEG_INFINITY;
+        for &v in &cache {
+            for &other in &vertex_triangles[v as usize] {
+                let score = triangle_score(other, &scores);
+                if score > best_score {
+                    best = Some(other);
+                    best_score = score;
+                }
+            }
+        }
+        if best.is_none() {
+            while next_unemitted < triangle_count && emitted[next_unemitted] {
+                next_unemitted += 1;
+            }
+            if next_unemitted < triangle_count {
+                best = Some(next_unemitted);
+            }
+        }
+    }
+    Ok(result)
+}
+
+// Renumbers vertices in the order the index buffer first uses them, which keeps vertex
+// fetches local. Unused vertices are dropped.
+pub fn optimize_vertex_fetch(indices: &[u32], vertex_count: usize) -> Result<(Vec<u32>, usize), String> {
+    check_indices(indices, vertex_count)?;
+    let mut remap = vec![UNUSED; vertex_count];
+    let mut next = 0;
+    for &i in indices {
+        if remap[i as usize] == UNUSED {
+            remap[i as usize] = next;
+            next += 1;
+        }
+    }
+    Ok((remap, next as usize))
+}
+
+// The average number of vertices transformed per triangle (ACMR) with a FIFO cache.
+pub fn vertex_cache_miss_ratio(indices: &[u32], cache_size: usize) -> f32 {
+    let mut cache = VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+    for i in indices {
+        if cache.contains(i) {
+            continue;
+        }
+        misses += 1;
+        if cache.len() == cache_size {
+            cache.pop_front();
+        }
+        if cache_size > 0 {
+            cache.push_back(*i);
+        }
+    }
+    misses as f32 / (indices.len() / 3).max(1) as f32
+}
+
+// A sum of plane quadrics (Garland & Heckbert), as the upper triangle of the 4x4 matrix,
+// weighted by area. The total weight turns an error back into a squared distance.
+#[derive(Clone, Copy, Default)]
+struct Quadric {
+    m: [f64; 10],
+    weight: f64,
+}
+
+impl Quadric {
+    fn from_plane(normal: &Vec3, d: f32, weight: f32) -> Quadric {
+        let [a, b, c, d, w] = [normal.x, normal.y, normal.z, d, weight].map(f64::from);
+        let m = [a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|x| x * w);
+        Quadric { m, weight: w }
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (a, b) in self.m.iter_mut().zip(&other.m) {
+            *a += b;
+        }
+        self.weight += other.weight;
+    }
+
+    fn squared_distance(&self, p: &Vec3) -> f64 {
+        let [x, y, z] = [p.x, p.y, p.z].map(f64::from);
+        let m = &self.m;
+        let error = x * x * m[0] + 2.0 * x * y * m[1] + 2.0 * x * z * m[2] + 2.0 * x * m[3]
+            + y * y * m[4] + 2.0 * y * z * m[5] + 2.0 * y * m[6]
+            + z * z * m[7] + 2.0 * z * m[8]
+            + m[9];
+        error.max(0.0) / self.weight.max(f64::MIN_POSITIVE)
+    }
+}
+
+// How strongly borders resist moving, relative to the surface.
+const BORDER_WEIGHT: f32 = 10.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VertexKind {
+    Interior,
+    // On exactly two border edges, and only collapses along them.
+    Border,
+    // On a non-manifold edge or where borders meet.
+    Locked,
+}
+
+fn undirected(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+fn triangle_edges(tri: &[u32]) -> [(u32, u32); 3] {
+    [(tri[0], tri[1]), (tri[1], tri[2]), (tri[2], tri[0])]
+}
+
+// The number of triangles on each edge, and the kind of each vertex.
+fn classify_vertices(indices: &[u32], vertex_count: usize) -> (HashMap<(u32, u32), u32>, Vec<VertexKind>) {
+    let mut edge_triangles = HashMap::new();
+    for tri in indices.chunks_exact(3) {
+        for (a, b) in triangle_edges(tri) {
+            *edge_triangles.entry(undirected(a, b)).or_insert(0) += 1;
+        }
+    }
+
+    let mut kinds = vec![VertexKind::Interior; vertex_count];
+    let mut border_edges = vec![0; vertex_count];
+    for (&(a, b), &count) in &edge_triangles {
+        match count {
+            1 => {
+                border_edges[a as usize] += 1;
+                border_edges[b as usize] += 1;
+            }
+            2 => {}
+            _ => {
+                kinds[a as usize] = VertexKind::Locked;
+                kinds[b as usize] = VertexKind::Locked;
+            }
+        }
+    }
+    for (kind, &count) in kinds.iter_mut().zip(&border_edges) {
+        if *kind == VertexKind::Interior && count > 0 {
+            *kind = if count == 2 { VertexKind::Border } else { VertexKind::Locked };
+        }
+    }
+    (edge_triangles, kinds)
+}
+
+// Collapses edges, cheapest first, until the mesh has at most `target_index_count` indices
+// or any further collapse would move the surface by more than `max_error`. Vertices only
+// collapse onto their neighbours, so the result indexes the same vertex buffer. Borders,
+// including attribute seams where positions were duplicated, only collapse along themselves.
+#[wasm_bindgen]
+pub fn simplify_mesh(positions: &[f32], indices: &[u32], target_index_count: usize, max_error: f32) -> Result<Vec<u32>, String> {
+    let count = vertex_count(positions, 3)?;
+    check_indices(indices, count)?;
+    let point = |i: u32| position(positions, i);
+    let mut indices: Vec<u32> = indices.chunks_exact(3)
+        .filter(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0])
+        .flatten().copied().collect();
+
+    let (edge_triangles, _) = classify_vertices(&indices, count);
+    let mut quadrics = vec![Quadric::default(); count];
+    for tri in indices.chunks_exact(3) {
+        let p = triangle_positions(positions, tri);
+        let cross = (p[1] - p[0]).cross(&(p[2] - p[0]));
+        let normal = normalize_or_zero(&cross);
+        let quadric = Quadric::from_plane(&normal, -normal.dot(&p[0]), cross.norm() * 0.5);
+        for &v in tri {
+            quadrics[v as usize].add(&quadric);
+        }
+
+        // A plane through each border edge, perpendicular to the triangle, keeps the
+        // outline in place.
+        for (a, b) in triangle_edges(tri) {
+            if edge_triangles[&undirected(a, b)] == 1 {
+                let edge = point(b) - point(a);
+                let border_normal = normalize_or_zero(&edge.cross(&normal));
+                let quadric = Quadric::from_plane(&border_normal, -border_normal.dot(&point(a)), edge.norm_squared() * BORDER_WEIGHT);
+                quadrics[a as usize].add(&quadric);
+                quadrics[b as usize].add(&quadric);
+            }
+        }
+    }
+
+    let target_triangles = target_index_count / 3;
+    let max_error = f64::from(max_error).powi(2);
+    while indices.len() / 3 > target_triangles {
+        let (edge_triangles, kinds) = classify_vertices(&indices, count);
+        let mut vertex_triangles = vec![Vec::new(); count];
+        for (t, tri) in indices.chunks_exact(3).enumerate() {
+            for &v in tri {
+                vertex_triangles[v as usize].push(t);
+            }
+        }
+
+        let mut candidates = Vec::new();
+        for tri in indices.chunks_exact(3) {
+            for (a, b) in triangle_edges(tri) {
+                for (u, v) in [(a, b), (b, a)] {
+                    let allowed = match kinds[u as usize] {
+                        VertexKind::Interior => true,
+                        VertexKind::Border => edge_triangles[&undirected(u, v)] == 1,
+                        VertexKind::Locked => false,
+                    };
+                    if allowed {
+                        let mut quadric = quadrics[u as usize];
+                        quadric.add(&quadrics[v as usize]);
+                        candidates.push((quadric.squared_distance(&point(v)), u, v));
+                    }
+                }
+            }
+        }
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        // Each collapse locks the triangles around it for the rest of the pass, so the
+        // remaining candidates stay valid.
+        let mut remap: Vec<u32> = (0..count as u32).collect();
+        let mut locked = vec![false; count];
+        let mut triangles = indices.len() / 3;
+        for &(error, u, v) in &candidates {
+            if error > max_error || triangles <= target_triangles {
+                break;
+            }
+            let (ui, vi) = (u as usize, v as usize);
+            if locked[ui] || locked[vi] {
+                continue;
+            }
+
+            // Triangles that keep u must not flip or degenerate when it moves.
+            let flips = vertex_triangles[ui].iter().any(|&t| {
+                let tri = &indices[t * 3..t * 3 + 3];
+                if tri.contains(&v) {
+                    return false;
+                }
+                let p = triangle_positions(positions, tri);
+                let moved = tri.iter().map(|&w| if w == u { point(v) } else { point(w) }).collect::<Vec<_>>();
+                let old_normal = (p[1] - p[0]).cross(&(p[2] - p[0]));
+                let new_normal = (moved[1] - moved[0]).cross(&(moved[2] - moved[0]));
+                new_normal.dot(&old_normal) <= 0.25 * old_normal.norm() * new_normal.norm()
+            });
+            if flips {
+                continue;
+            }
+
+            remap[ui] = v;
+            let quadric = quadrics[ui];
+            quadrics[vi].add(&quadric);
+            for &t in &vertex_triangles[ui] {
+                let tri = &indices[t * 3..t * 3 + 3];
+                if tri.contains(&v) {
+                    triangles -= 1;
+                }
+                for &w in tri {
+                    locked[w as usize] = true;
+                }
+            }
+        }
+
+        let before = indices.len();
+        indices = indices.chunks_exact(3)
+            .map(|t| [remap[t[0] as usize], remap[t[1] as usize], remap[t[2] as usize]])
+            .filter(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0])
+            .flatten().collect();
+        if indices.len() == before {
+            break;
+        }
+    }
+    Ok(indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An n by n grid of quads over the unit square, with z from `height`.
+    fn grid(n: u32, height: impl Fn(f32, f32) -> f32) -> (Vec<f32>, Vec<u32>) {
+        let mut positions = Vec::new();
+        for y in 0..=n {
+            for x in 0..=n {
+                let (x, y) = (x as f32 / n as f32, y as f32 / n as f32);
+                positions.extend_from_slice(&[x, y, height(x, y)]);
+            }
+        }
+        let mut indices = Vec::new();
+        for y in 0..n {
+            for x in 0..n {
+                let i = y * (n + 1) + x;
+                indices.extend_from_slice(&[i, i + 1, i + n + 2, i, i + n + 2, i + n + 1]);
+            }
+        }
+        (positions, indices)
+    }
+
+    fn cube() -> (Vec<f32>, Vec<u32>) {
+        let mut positions = Vec::new();
+        for i in 0..8 {
+            positions.extend_from_slice(&[(i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32]);
+        }
+        let indices = vec![
+            0, 2, 3, 0, 3, 1, 4, 5, 7, 4, 7, 6, // -z, +z
+            0, 1, 5, 0, 5, 4, 2, 6, 7, 2, 7, 3, // -y, +y
+            0, 4, 6, 0, 6, 2, 1, 3, 7, 1, 7, 5, // -x, +x
+        ];
+        (positions, indices)
+    }
+
+    fn area(positions: &[f32], indices: &[u32]) -> f32 {
+        indices.chunks_exact(3).map(|tri| {
+            let p = triangle_positions(positions, tri);
+            (p[1] - p[0]).cross(&(p[2] - p[0])).norm() * 0.5
+        }).sum()
+    }
+
+    #[test]
+    fn test_normals() {
+        let (positions, indices) = cube();
+        let normals = compute_smooth_normals(&positions, &indices).unwrap();
+        for (p, n) in positions.chunks_exact(3).zip(normals.chunks_exact(3)) {
+            let expected = (make_vec3(p) - Vec3::from_element(0.5)).normalize();
+            assert!((make_vec3(n) - expected).norm() < 1e-6);
+        }
+
+        let flat = compute_flat_normals(&positions, &indices).unwrap();
+        let corners = unindex_vertices(&positions, 3, &indices).unwrap();
+        assert_eq!(flat.len(), corners.len());
+        for (p, n) in corners.chunks_exact(3).zip(flat.chunks_exact(3)) {
+            let n = make_vec3(n);
+            assert!((n.amax() - 1.0).abs() < 1e-6);
+            // The cube's faces are at 0 or 1, in the direction of the normal.
+            assert!((make_vec3(p).dot(&n) - n.max().max(0.0)).abs() < 1e-6);
+        }
+
+        assert!(compute_smooth_normals(&positions, &[0, 1, 8]).is_err());
+        assert!(compute_smooth_normals(&positions, &[0, 1]).is_err());
+    }
+
+    #[test]
+    fn test_tangents() {
+        let (positions, indices) = grid(4, |_, _| 0.0);
+        let normals = compute_smooth_normals(&positions, &indices).unwrap();
+        let uvs: Vec<f32> = positions.chunks_exact(3).flat_map(|p| [p[0], p[1]]).collect();
+        let tangents = compute_tangents(&positions, &normals, &uvs, &indices).unwrap();
+        for t in tangents.chunks_exact(4) {
+            assert!((t[0] - 1.0).abs() < 1e-6 && t[1].abs() < 1e-6 && t[2].abs() < 1e-6 && t[3] == 1.0);
+        }
+
+        // Mirroring U flips the tangent and the handedness.
+        let mirrored: Vec<f32> = uvs.chunks_exact(2).flat_map(|uv| [-uv[0], uv[1]]).collect();
+        let tangents = compute_tangents(&positions, &normals, &mirrored, &indices).unwrap();
+        for t in tangents.chunks_exact(4) {
+            assert!((t[0] + 1.0).abs() < 1e-6 && t[3] == -1.0);
+        }
+
+        // Tangents stay perpendicular to curved normals.
+        let (positions, indices) = grid(8, |x, y| (x * 3.0).sin() * y);
+        let normals = compute_smooth_normals(&positions, &indices).unwrap();
+        // The UVs are still the smaller grid's.
+        assert!(compute_tangents(&positions, &normals, &uvs, &indices).is_err());
+        let uvs: Vec<f32> = positions.chunks_exact(3).flat_map(|p| [p[0], p[1]]).collect();
+        let tangents = compute_tangents(&positions, &normals, &uvs, &indices).unwrap();
+        for (n, t) in normals.chunks_exact(3).zip(tangents.chunks_exact(4)) {
+            assert!(make_vec3(n).dot(&make_vec3(&t[..3])).abs() < 1e-5);
+            assert!((make_vec3(&t[..3]).norm() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_weld() {
+        let (positions, indices) = cube();
+        let corners = unindex_vertices(&positions, 3, &indices).unwrap();
+        let (remap, count) = weld_vertices(&corners, 3, 0.0).unwrap();
+        assert_eq!(count, 8);
+        let welded = remap_vertices(&corners, 3, &remap, count).unwrap();
+        let welded_indices = remap_indices(&(0..36).collect::<Vec<u32>>(), &remap).unwrap();
+        assert_eq!(unindex_vertices(&welded, 3, &welded_indices).unwrap(), corners);
+
+        // Flat normals keep the faces apart.
+        let normals = compute_flat_normals(&positions, &indices).unwrap();
+        let interleaved: Vec<f32> = corners.chunks_exact(3).zip(normals.chunks_exact(3)).flat_map(|(p, n)| p.iter().chain(n).copied()).collect();
+        assert_eq!(weld_vertices(&interleaved, 6, 0.0).unwrap().1, 24);
+
+        let jittered: Vec<f32> = corners.iter().enumerate().map(|(i, x)| x + (i % 7) as f32 * 1e-5).collect();
+        assert!(weld_vertices(&jittered, 3, 0.0).unwrap().1 > 8);
+        assert_eq!(weld_vertices(&jittered, 3, 1e-4).unwrap().1, 8);
+        assert!(weld_vertices(&corners, 2, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_optimize_indices() {
+        let (positions, indices) = grid(32, |_, _| 0.0);
+        let vertex_count = positions.len() / 3;
+
+        // Shuffle the triangles.
+        let mut triangles: Vec<&[u32]> = indices.chunks_exact(3).collect();
+        let mut seed = 7u32;
+        for i in (1..triangles.len()).rev() {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            triangles.swap(i, (seed >> 8) as usize % (i + 1));
+        }
+        let shuffled: Vec<u32> = triangles.concat();
+
+        let optimized = optimize_vertex_cache(&shuffled, vertex_count).unwrap();
+        let before = vertex_cache_miss_ratio(&shuffled, 32);
+        let after = vertex_cache_miss_ratio(&optimized, 32);
+        assert!(before > 1.5 && after < 0.8, "{} {}", before, after);
+        let mut sorted_before: Vec<&[u32]> = shuffled.chunks_exact(3).collect();
+        let mut sorted_after: Vec<&[u32]> = optimized.chunks_exact(3).collect();
+        sorted_before.sort();
+        sorted_after.sort();
+        assert_eq!(sorted_before, sorted_after);
+
+        // A huge vertex count only costs what the indices use; a short one is rejected.
+        assert_eq!(optimize_vertex_cache(&[0, 1, 2], usize::MAX).unwrap(), [0, 1, 2]);
+        assert!(optimize_vertex_cache(&[0, 1, 2], 2).is_err());
+
+        // Drop a vertex, then renumber the rest by first use.
+        let unused = optimized[0];
+        let without: Vec<u32> = optimized.chunks_exact(3).filter(|t| !t.contains(&unused)).flatten().copied().collect();
+        let (remap, count) = optimize_vertex_fetch(&without, vertex_count).unwrap();
+        let mut used = without.clone();
+        used.sort_unstable();
+        used.dedup();
+        assert_eq!(count, used.len());
+        assert_eq!(remap[unused as usize], UNUSED);
+        let fetched = remap_indices(&without, &remap).unwrap();
+        let mut next = 0;
+        for &i in &fetched {
+            assert!(i <= next);
+            next = next.max(i + 1);
+        }
+        let new_positions = remap_vertices(&positions, 3, &remap, count).unwrap();
+        assert_eq!(unindex_vertices(&new_positions, 3, &fetched).unwrap(), unindex_vertices(&positions, 3, &without).unwrap());
+    }
+
+    #[test]
+    fn test_simplify() {
+        // A flat grid reduces to a handful of triangles covering the same square.
+        let (positions, indices) = grid(16, |_, _| 0.0);
+        let simplified = simplify_mesh(&positions, &indices, 0, 1e-4).unwrap();
+        assert!(simplified.len() <= 3 * 8, "{}", simplified.len());
+        assert!((area(&positions, &simplified) - 1.0).abs() < 1e-4);
+        let normals = compute_flat_normals(&positions, &simplified).unwrap();
+        assert!(normals.chunks_exact(3).all(|n| n[2] > 0.999));
+
+        // A curved surface only simplifies as far as the error allows.
+        let (positions, indices) = grid(16, |x, y| (x * 6.0).sin() * (y * 4.0).cos() * 0.2);
+        let tight = simplify_mesh(&positions, &indices, 0, 1e-5).unwrap();
+        let loose = simplify_mesh(&positions, &indices, 0, 1e-2).unwrap();
+        let target = simplify_mesh(&positions, &indices, 150, 1.0).unwrap();
+        assert!(tight.len() > indices.len() * 3 / 4, "{}", tight.len());
+        assert!(loose.len() < tight.len() / 2, "{} {}", loose.len(), tight.len());
+        assert!(target.len() <= 150 && target.len() > 100, "{}", target.len());
+        check_indices(&target, positions.len() / 3).unwrap();
+
+        // Every vertex of a closed cube is a corner.
+        let (positions, indices) = cube();
+        assert_eq!(simplify_mesh(&positions, &indices, 0, 1e-3).unwrap().len(), indices.len());
+    }
+}
@@ -0,0 +1,1967 @@
+use core::f32;
+use std::collections::HashMap;
+
+use nalgebra_glm::{make_mat4, make_vec3, triangle_normal, vec2, vec4, Mat3, Mat4, Vec3, Vec2, Vec4};
+use wasm_bindgen::prelude::*;
+
+pub mod mesh;
+
+#[derive(Default, Debug, Clone)]
+pub struct Plane {
+    pub d: f32,
+    pub normal: Vec3,
+}
+
+impl Plane {
+    pub fn new(normal: Vec3, d: f32) -> Plane {
+        Plane { normal, d }
+    }
+
+    pub fn normalized(&self) -> Plane {
+        let mag = self.normal.magnitude();
+        Plane::new(self.normal / mag, self.d / mag)
+    }
+
+    pub fn negate(&mut self) {
+        self.normal.neg_mut();
+        self.d *= -1.0;
+    }
+
+    pub fn set_tri(&mut self, p0: &Vec3, p1: &Vec3, p2: &Vec3) {
+        self.normal = triangle_normal(p0, p1, p2);
+        self.d = -self.normal.dot(&p0);
+    }
+
+    pub fn distance(&self, p: &Vec3) -> f32 {
+        self.normal.dot(p) + self.d
+    }
+
+    pub fn intersect_line(&self, p: &Vec3, dir: &Vec3) -> f32 {
+        -(self.normal.dot(p) + self.d) / self.normal.dot(dir)
+    }
+
+    pub fn transform(&mut self, inv_transpose_mat: &Mat4) {
+        let transformed =
+            inv_transpose_mat * vec4(self.normal.x, self.normal.y, self.normal.z, self.d);
+        self.normal.x = transformed.x;
+        self.normal.y = transformed.y;
+        self.normal.z = transformed.z;
+        self.d = transformed.w;
+    }
+
+    pub fn major_axis(&self) -> Axis {
+        let mut max_axis = f32::NEG_INFINITY;
+        let mut axis = 3;
+        for i in 0..3 {
+            if self.normal[i].abs() > max_axis {
+                max_axis = self.normal[i].abs();
+                axis = i as u8;
+            }
+        }
+        match axis {
+            0 => Axis::X,
+            1 => Axis::Y,
+            2 => Axis::Z,
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AABB {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Default for AABB {
+    fn default() -> Self {
+        Self {
+            min: Vec3::from_element(f32::INFINITY),
+            max: Vec3::from_element(f32::NEG_INFINITY),
+        }
+    }
+}
+
+impl AABB {
+    pub fn from_f32(min_x: f32, min_y: f32, min_z: f32, max_x: f32, max_y: f32, max_z: f32) -> Self {
+        AABB {
+            min: Vec3::new(min_x, min_y, min_z),
+            max: Vec3::new(max_x, max_y, max_z),
+        }
+    }
+
+    pub fn from_slice(slice: &[f32]) -> Self {
+        assert_eq!(slice.iter().count(), 6);
+        AABB {
+            min: make_vec3(&slice[0..3]),
+            max: make_vec3(&slice[3..6]),
+        }
+    }
+
+    pub fn get_closest_point_along_direction(&self, dir: &Vec3) -> Vec3 {
+        let x = if dir.x >= 0.0 { self.max.x } else { self.min.x };
+        let y = if dir.y >= 0.0 { self.max.y } else { self.min.y };
+        let z = if dir.z >= 0.0 { self.max.z } else { self.min.z };
+        Vec3::new(x, y, z)
+    }
+
+    pub fn transform(&mut self, mat: &Mat4) {
+        // Transforming Axis-Aligned Bounding Boxes from Graphics Gems.
+        let min = self.min.clone();
+        let max = self.max.clone();
+
+        // Translation can be applied directly
+        self.min.x = mat[12];
+        self.min.y = mat[13];
+        self.min.z = mat[14];
+        self.max.copy_from(&self.min);
+
+        for i in 0..3 {
+            for j in 0..3 {
+                let a = mat[i * 4 + j] * min[i];
+                let b = mat[i * 4 + j] * max[i];
+                self.min[j] += a.min(b);
+                self.max[j] += a.max(b);
+            }
+        }
+    }
+
+    pub fn union_point(&mut self, p: &Vec3) {
+        self.min.x = self.min.x.min(p.x);
+        self.min.y = self.min.y.min(p.y);
+        self.min.z = self.min.z.min(p.z);
+        self.max.x = self.max.x.max(p.x);
+        self.max.y = self.max.y.max(p.y);
+        self.max.z = self.max.z.max(p.z);
+    }
+
+    pub fn union_aabb(&mut self, other: &AABB) {
+        self.union_point(&other.min);
+        self.union_point(&other.max);
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn set_from_points(&mut self, points: &[Vec3]) {
+        self.min = Vec3::from_element(f32::INFINITY);
+        self.max = Vec3::from_element(f32::NEG_INFINITY);
+
+        for p in points {
+            self.union_point(&p);
+        }
+    }
+
+    pub fn contains_point(&self, p: &Vec3) -> bool {
+        !(p.x < self.min.x
+            || p.x > self.max.x
+            || p.y < self.min.y
+            || p.y > self.max.y
+            || p.z < self.min.z
+            || p.z > self.max.z)
+    }
+
+    pub fn contains_point_ignore_max_z(&self, p: &Vec3) -> bool {
+        !(p.x < self.min.x
+            || p.x > self.max.x
+            || p.y < self.min.y
+            || p.y > self.max.y
+            || p.z < self.min.z)
+    }
+}
+
+// The largest factor a matrix scales any direction by, assuming no shear.
+fn max_scale(mat: &Mat4) -> f32 {
+    let m = mat.fixed_view::<3, 3>(0, 0);
+    m.column(0).norm().max(m.column(1).norm()).max(m.column(2).norm())
+}
+
+fn transform_point(mat: &Mat4, p: &Vec3) -> Vec3 {
+    (mat * vec4(p.x, p.y, p.z, 1.0)).xyz()
+}
+
+// The centroid and principal axes of a point set: the eigenvectors of its covariance
+// matrix, largest variance first.
+fn principal_axes(points: &[Vec3]) -> (Vec3, Mat3) {
+    let centroid = points.iter().sum::<Vec3>() / points.len() as f32;
+    let mut covariance = Mat3::zeros();
+    for p in points {
+        let d = p - centroid;
+        covariance += d * d.transpose();
+    }
+
+    let eigen = covariance.symmetric_eigen();
+    let mut order = [0, 1, 2];
+    order.sort_by(|&i, &j| eigen.eigenvalues[j].total_cmp(&eigen.eigenvalues[i]));
+    let mut axes = Mat3::zeros();
+    for (dst, &src) in order.iter().enumerate() {
+        axes.set_column(dst, &eigen.eigenvectors.column(src));
+    }
+    (centroid, axes)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn new(center: Vec3, radius: f32) -> Sphere {
+        Sphere { center, radius }
+    }
+
+    // Ritter's bounding sphere; within a few percent of the smallest one.
+    pub fn from_points(points: &[Vec3]) -> Sphere {
+        let first = match points.first() {
+            Some(p) => p,
+            None => return Sphere::new(Vec3::zeros(), 0.0),
+        };
+        let farthest_from = |p: &Vec3| points.iter().max_by(|a, b| (*a - p).norm_squared().total_cmp(&(*b - p).norm_squared())).unwrap();
+        let a = farthest_from(first);
+        let b = farthest_from(a);
+        let mut sphere = Sphere::new((a + b) * 0.5, (b - a).norm() * 0.5);
+        for p in points {
+            let dist = (p - sphere.center).norm();
+            if dist > sphere.radius {
+                let radius = (sphere.radius + dist) * 0.5;
+                sphere.center += (p - sphere.center) * ((radius - sphere.radius) / dist);
+                sphere.radius = radius;
+            }
+        }
+        sphere
+    }
+
+    pub fn transform(&mut self, mat: &Mat4) {
+        self.center = transform_point(mat, &self.center);
+        self.radius *= max_scale(mat);
+    }
+
+    pub fn contains_point(&self, p: &Vec3) -> bool {
+        (p - self.center).norm_squared() <= self.radius * self.radius
+    }
+
+    pub fn intersects_sphere(&self, other: &Sphere) -> bool {
+        let radius = self.radius + other.radius;
+        (other.center - self.center).norm_squared() <= radius * radius
+    }
+
+    pub fn intersects_obb(&self, obb: &OBB) -> bool {
+        obb.intersects_sphere(self)
+    }
+
+    pub fn intersects_capsule(&self, capsule: &Capsule) -> bool {
+        capsule.intersects_sphere(self)
+    }
+}
+
+// Oriented bounding box. The columns of `axes` are orthonormal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OBB {
+    pub center: Vec3,
+    pub axes: Mat3,
+    pub half_extents: Vec3,
+}
+
+impl OBB {
+    pub fn from_aabb(aabb: &AABB) -> OBB {
+        OBB {
+            center: aabb.center(),
+            axes: Mat3::identity(),
+            half_extents: (aabb.max - aabb.min) * 0.5,
+        }
+    }
+
+    // Fits the box to the principal axes of the points. Not the smallest box, but close for
+    // elongated shapes.
+    pub fn from_points(points: &[Vec3]) -> OBB {
+        if points.is_empty() {
+            return OBB::from_aabb(&AABB::from_f32(0.0, 0.0, 0.0, 0.0, 0.0, 0.0));
+        }
+
+        let (_, axes) = principal_axes(points);
+        let mut local = AABB::default();
+        for p in points {
+            local.union_point(&(axes.transpose() * p));
+        }
+        OBB {
+            center: axes * local.center(),
+            axes,
+            half_extents: (local.max - local.min) * 0.5,
+        }
+    }
+
+    // Unlike AABB::transform, the box stays tight under rotation. Shear isn't supported.
+    pub fn transform(&mut self, mat: &Mat4) {
+        let m = mat.fixed_view::<3, 3>(0, 0);
+        self.center = transform_point(mat, &self.center);
+        for i in 0..3 {
+            let axis = m * self.axes.column(i);
+            let scale = axis.norm();
+            if scale > 0.0 {
+                self.axes.set_column(i, &(axis / scale));
+            }
+            self.half_extents[i] *= scale;
+        }
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let mut corners = [self.center; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            for axis in 0..3 {
+                let sign = if (i >> axis) & 1 != 0 { 1.0 } else { -1.0 };
+                *corner += self.axes.column(axis) * (self.half_extents[axis] * sign);
+            }
+        }
+        corners
+    }
+
+    pub fn to_aabb(&self) -> AABB {
+        let mut aabb = AABB::default();
+        aabb.set_from_points(&self.corners());
+        aabb
+    }
+
+    pub fn closest_point(&self, p: &Vec3) -> Vec3 {
+        let d = p - self.center;
+        let mut result = self.center;
+        for i in 0..3 {
+            let axis = self.axes.column(i);
+            result += axis * d.dot(&axis).clamp(-self.half_extents[i], self.half_extents[i]);
+        }
+        result
+    }
+
+    pub fn contains_point(&self, p: &Vec3) -> bool {
+        let d = p - self.center;
+        (0..3).all(|i| d.dot(&self.axes.column(i)).abs() <= self.half_extents[i])
+    }
+
+    // The extent of the box along a direction, from its center.
+    pub fn projected_radius(&self, dir: &Vec3) -> f32 {
+        (0..3).map(|i| dir.dot(&self.axes.column(i)).abs() * self.half_extents[i]).sum()
+    }
+
+    // Separating axis test over the 15 candidate axes (Gottschalk).
+    pub fn intersects_obb(&self, other: &OBB) -> bool {
+        let r = self.axes.transpose() * other.axes;
+        // Padding keeps near-parallel edges from producing bogus cross product axes.
+        let abs_r = r.abs().add_scalar(1e-6);
+        let t = self.axes.transpose() * (other.center - self.center);
+        let (a, b) = (&self.half_extents, &other.half_extents);
+
+        for i in 0..3 {
+            if t[i].abs() > a[i] + b.dot(&abs_r.row(i).transpose()) {
+                return false;
+            }
+            if t.dot(&r.column(i)).abs() > a.dot(&abs_r.column(i)) + b[i] {
+                return false;
+            }
+        }
+
+        for i in 0..3 {
+            let (i1, i2) = ((i + 1) % 3, (i + 2) % 3);
+            for j in 0..3 {
+                let (j1, j2) = ((j + 1) % 3, (j + 2) % 3);
+                let ra = a[i1] * abs_r[(i2, j)] + a[i2] * abs_r[(i1, j)];
+                let rb = b[j1] * abs_r[(i, j2)] + b[j2] * abs_r[(i, j1)];
+                if (t[i2] * r[(i1, j)] - t[i1] * r[(i2, j)]).abs() > ra + rb {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        sphere.contains_point(&self.closest_point(&sphere.center))
+    }
+
+    pub fn intersects_capsule(&self, capsule: &Capsule) -> bool {
+        capsule.intersects_obb(self)
+    }
+
+    // Squared distance from the segment a-b to the box. The distance is piecewise quadratic
+    // along the segment, with pieces split where a coordinate crosses a face of the box.
+    pub fn segment_distance_squared(&self, a: &Vec3, b: &Vec3) -> f32 {
+        let p = self.axes.transpose() * (a - self.center);
+        let d = self.axes.transpose() * (b - a);
+        let e = &self.half_extents;
+
+        let mut ts = vec![0.0, 1.0];
+        for i in 0..3 {
+            if d[i] != 0.0 {
+                ts.extend([(-e[i] - p[i]) / d[i], (e[i] - p[i]) / d[i]].iter().filter(|t| (0.0..=1.0).contains(*t)));
+            }
+        }
+        ts.sort_by(f32::total_cmp);
+
+        let dist_squared = |t: f32| (0..3).map(|i| {
+            let x = p[i] + d[i] * t;
+            let excess = (x.abs() - e[i]).max(0.0);
+            excess * excess
+        }).sum::<f32>();
+
+        let mut best = f32::INFINITY;
+        for w in ts.windows(2) {
+            let (t0, t1) = (w[0], w[1]);
+            // Within a piece, each clamped coordinate contributes (p + d t - bound)^2.
+            let mid = p + d * ((t0 + t1) * 0.5);
+            let (mut num, mut den) = (0.0, 0.0);
+            for i in 0..3 {
+                if mid[i].abs() > e[i] {
+                    let bound = e[i].copysign(mid[i]);
+                    num -= d[i] * (p[i] - bound);
+                    den += d[i] * d[i];
+                }
+            }
+            let t = if den > 0.0 { (num / den).clamp(t0, t1) } else { t0 };
+            best = best.min(dist_squared(t)).min(dist_squared(t0)).min(dist_squared(t1));
+        }
+        best
+    }
+}
+
+// A line segment swept by a sphere.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capsule {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub radius: f32,
+}
+
+impl Capsule {
+    pub fn new(a: Vec3, b: Vec3, radius: f32) -> Capsule {
+        Capsule { a, b, radius }
+    }
+
+    // Runs the segment along the principal axis of the points, then makes it as short as it
+    // can be while still reaching every point.
+    pub fn from_points(points: &[Vec3]) -> Capsule {
+        if points.is_empty() {
+            return Capsule::new(Vec3::zeros(), Vec3::zeros(), 0.0);
+        }
+
+        let (centroid, axes) = principal_axes(points);
+        let axis: Vec3 = axes.column(0).into_owned();
+
+        let projected: Vec<(f32, f32)> = points.iter().map(|p| {
+            let d = p - centroid;
+            let t = d.dot(&axis);
+            (t, (d - axis * t).norm_squared())
+        }).collect();
+        let radius_squared = projected.iter().map(|&(_, perp)| perp).fold(0.0, f32::max);
+        let reach = |perp: f32| (radius_squared - perp).max(0.0).sqrt();
+        let t0 = projected.iter().map(|&(t, perp)| t + reach(perp)).fold(f32::INFINITY, f32::min);
+        let t1 = projected.iter().map(|&(t, perp)| t - reach(perp)).fold(f32::NEG_INFINITY, f32::max);
+        // If the ends cross, any point between them reaches everything.
+        let (t0, t1) = if t0 > t1 { let mid = (t0 + t1) * 0.5; (mid, mid) } else { (t0, t1) };
+        Capsule::new(centroid + axis * t0, centroid + axis * t1, radius_squared.sqrt())
+    }
+
+    pub fn transform(&mut self, mat: &Mat4) {
+        self.a = transform_point(mat, &self.a);
+        self.b = transform_point(mat, &self.b);
+        self.radius *= max_scale(mat);
+    }
+
+    pub fn closest_point_on_segment(&self, p: &Vec3) -> Vec3 {
+        let ab = self.b - self.a;
+        let len_squared = ab.norm_squared();
+        if len_squared == 0.0 {
+            return self.a;
+        }
+        self.a + ab * ((p - self.a).dot(&ab) / len_squared).clamp(0.0, 1.0)
+    }
+
+    pub fn contains_point(&self, p: &Vec3) -> bool {
+        (p - self.closest_point_on_segment(p)).norm_squared() <= self.radius * self.radius
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        let radius = self.radius + sphere.radius;
+        (sphere.center - self.closest_point_on_segment(&sphere.center)).norm_squared() <= radius * radius
+    }
+
+    pub fn intersects_capsule(&self, other: &Capsule) -> bool {
+        let radius = self.radius + other.radius;
+        segment_distance_squared(&self.a, &self.b, &other.a, &other.b) <= radius * radius
+    }
+
+    pub fn intersects_obb(&self, obb: &OBB) -> bool {
+        obb.segment_distance_squared(&self.a, &self.b) <= self.radius * self.radius
+    }
+}
+
+// Squared distance between segments p1-q1 and p2-q2 (Ericson, Real-Time Collision Detection 5.1.9).
+pub fn segment_distance_squared(p1: &Vec3, q1: &Vec3, p2: &Vec3, q2: &Vec3) -> f32 {
+    let d1 = q1 - p1;
+    let d2 = q2 - p2;
+    let r = p1 - p2;
+    let a = d1.norm_squared();
+    let e = d2.norm_squared();
+    let f = d2.dot(&r);
+
+    let (s, t) = if a == 0.0 && e == 0.0 {
+        (0.0, 0.0)
+    } else if a == 0.0 {
+        (0.0, (f / e).clamp(0.0, 1.0))
+    } else {
+        let c = d1.dot(&r);
+        if e == 0.0 {
+            ((-c / a).clamp(0.0, 1.0), 0.0)
+        } else {
+            let b = d1.dot(&d2);
+            let denom = a * e - b * b;
+            let mut s = if denom != 0.0 { ((b * f - c * e) / denom).clamp(0.0, 1.0) } else { 0.0 };
+            let mut t = (b * s + f) / e;
+            if t < 0.0 {
+                t = 0.0;
+                s = (-c / a).clamp(0.0, 1.0);
+            } else if t > 1.0 {
+                t = 1.0;
+                s = ((b - c) / a).clamp(0.0, 1.0);
+            }
+            (s, t)
+        }
+    };
+
+    ((p1 + d1 * s) - (p2 + d2 * t)).norm_squared()
+}
+
+#[wasm_bindgen(js_name = "IntersectionState")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntersectionState {
+    Inside,
+    Outside,
+    Intersection,
+}
+
+// Where the near plane sits in clip space: -1 for GL, 0 for WebGPU and reversed-Z.
+#[wasm_bindgen(js_name = "ClipSpaceNearZ")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClipSpaceNearZ {
+    NegativeOne,
+    Zero,
+}
+
+// can be used as a Frustum
+#[wasm_bindgen(js_name = "ConvexHull")]
+#[derive(Debug, Clone)]
+pub struct ConvexHull {
+    pub(crate) planes: Vec<Plane>,
+    // Only hulls built from points have a mesh. Faces are wound counter-clockwise seen from
+    // outside.
+    pub(crate) vertices: Vec<Vec3>,
+    pub(crate) faces: Vec<[u32; 3]>,
+}
+
+impl ConvexHull {
+    pub fn contains_point(&self, p: &Vec3) -> bool {
+        for plane in &self.planes {
+            if plane.distance(p) < 0.0 {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    pub fn intersect_aabb(&self, aabb: &AABB) -> IntersectionState {
+        let mut result = IntersectionState::Inside;
+        for plane in &self.planes {
+            let nearest = aabb.get_closest_point_along_direction(&plane.normal);
+            if plane.distance(&nearest) < 0.0 {
+                return IntersectionState::Outside;
+            }
+
+            let farthest = aabb.get_closest_point_along_direction(&-plane.normal);
+            if plane.distance(&farthest) < 0.0 {
+                result = IntersectionState::Intersection;
+            }
+        }
+        result
+    }
+
+    pub fn intersect_sphere(&self, center: &Vec3, radius: f32) -> IntersectionState {
+        let mut result = IntersectionState::Inside;
+        for plane in &self.planes {
+            let dist = plane.distance(center);
+            if dist < -radius {
+                return IntersectionState::Outside;
+            } else if dist < radius {
+                result = IntersectionState::Intersection;
+            }
+        }
+        return result;
+    }
+
+    pub fn intersect_obb(&self, obb: &OBB) -> IntersectionState {
+        let mut result = IntersectionState::Inside;
+        for plane in &self.planes {
+            let dist = plane.distance(&obb.center);
+            let radius = obb.projected_radius(&plane.normal);
+            if dist < -radius {
+                return IntersectionState::Outside;
+            } else if dist < radius {
+                result = IntersectionState::Intersection;
+            }
+        }
+        result
+    }
+
+    pub fn intersect_capsule(&self, capsule: &Capsule) -> IntersectionState {
+        let mut result = IntersectionState::Inside;
+        for plane in &self.planes {
+            let dist_a = plane.distance(&capsule.a);
+            let dist_b = plane.distance(&capsule.b);
+            if dist_a.max(dist_b) < -capsule.radius {
+                return IntersectionState::Outside;
+            } else if dist_a.min(dist_b) < capsule.radius {
+                result = IntersectionState::Intersection;
+            }
+        }
+        result
+    }
+
+    pub fn contains_obb(&self, obb: &OBB) -> bool {
+        self.intersect_obb(obb) != IntersectionState::Outside
+    }
+
+    pub fn contains_capsule(&self, capsule: &Capsule) -> bool {
+        self.intersect_capsule(capsule) != IntersectionState::Outside
+    }
+
+    pub fn contains_aabb(&self, aabb: &AABB) -> bool {
+        match self.intersect_aabb(aabb) {
+            IntersectionState::Outside => false,
+            _ => true,
+        }
+    }
+
+    pub fn contains_sphere(&self, center: &Vec3, radius: f32) -> bool {
+        match self.intersect_sphere(center, radius) {
+            IntersectionState::Outside => false,
+            _ => true,
+        }
+    }
+
+    pub fn transform(&mut self, mat: &Mat4) {
+        let mut inv_transpose_mat = mat.try_inverse().unwrap();
+        inv_transpose_mat.transpose_mut();
+        for plane in &mut self.planes {
+            plane.transform(&inv_transpose_mat);
+        }
+        for v in &mut self.vertices {
+            *v = transform_point(mat, v);
+        }
+        if mat.determinant() < 0.0 {
+            for face in &mut self.faces {
+                face.swap(1, 2);
+            }
+        }
+    }
+
+    // Extracts the frustum planes of a view-projection matrix, in the order left, bottom, right,
+    // top, near, far (Gribb & Hartmann). Reversed-Z swaps the near and far planes, which
+    // leaves the hull the same. The far plane of an infinite projection is degenerate and
+    // is left out.
+    pub fn set_view_projection(&mut self, m: &Mat4, near_z: ClipSpaceNearZ) {
+        let rows: Vec<Vec4> = (0..4).map(|i| m.row(i).transpose()).collect();
+        let near = match near_z {
+            ClipSpaceNearZ::NegativeOne => rows[3] + rows[2],
+            ClipSpaceNearZ::Zero => rows[2],
+        };
+
+        self.clear();
+        for v in &[rows[3] + rows[0], rows[3] + rows[1], rows[3] - rows[0], rows[3] - rows[1], near, rows[3] - rows[2]] {
+            let normal = v.xyz();
+            if normal.norm() <= v.w.abs() * 1e-6 {
+                continue;
+            }
+            self.planes.push(Plane::new(normal, v.w).normalized());
+        }
+    }
+
+    pub fn from_view_projection(m: &Mat4, near_z: ClipSpaceNearZ) -> ConvexHull {
+        let mut hull = ConvexHull::new();
+        hull.set_view_projection(m, near_z);
+        hull
+    }
+
+    // The volume seen from `eye` through a convex polygon: a plane through the eye and each
+    // edge, plus the polygon's own plane facing away from the eye. Returns None for a
+    // degenerate polygon, or if the eye is in the polygon's plane.
+    pub fn from_portal(eye: &Vec3, polygon: &[Vec3]) -> Option<ConvexHull> {
+        if polygon.len() < 3 {
+            return None;
+        }
+
+        // Newell's method, which copes with collinear vertices.
+        let mut normal = Vec3::zeros();
+        for (i, a) in polygon.iter().enumerate() {
+            normal += a.cross(&polygon[(i + 1) % polygon.len()]);
+        }
+        if normal.norm() == 0.0 {
+            return None;
+        }
+
+        let centroid = polygon.iter().sum::<Vec3>() / polygon.len() as f32;
+        let mut polygon_plane = Plane::new(normal, -normal.dot(&centroid)).normalized();
+        let eye_dist = polygon_plane.distance(eye);
+        if eye_dist.abs() <= plane_epsilon(eye) {
+            return None;
+        }
+        if eye_dist > 0.0 {
+            polygon_plane.negate();
+        }
+
+        let mut planes = Vec::with_capacity(polygon.len() + 1);
+        for (i, a) in polygon.iter().enumerate() {
+            let (ea, eb) = (a - eye, polygon[(i + 1) % polygon.len()] - eye);
+            let normal = ea.cross(&eb);
+            // Skip duplicate vertices.
+            if normal.norm() <= f32::EPSILON * ea.norm() * eb.norm() {
+                continue;
+            }
+
+            let mut plane = Plane::new(normal, -normal.dot(eye)).normalized();
+            if plane.distance(&centroid) < 0.0 {
+                plane.negate();
+            }
+            planes.push(plane);
+        }
+        planes.push(polygon_plane);
+        Some(ConvexHull { planes, ..ConvexHull::new() })
+    }
+
+    // Clips a convex polygon to the inside of the hull.
+    pub fn clip_polygon(&self, polygon: &[Vec3]) -> Vec<Vec3> {
+        let mut result = polygon.to_vec();
+        for plane in &self.planes {
+            if result.is_empty() {
+                break;
+            }
+            result = clip_polygon_to_plane(&result, plane);
+        }
+        result
+    }
+
+    // Narrows this frustum to what can be seen through a convex portal or mirror polygon.
+    // The polygon is clipped to the hull first, and the hull's planes that don't pass through
+    // the eye (near and far) are kept. Returns None if none of the polygon is visible.
+    pub fn clip_to_portal(&self, eye: &Vec3, polygon: &[Vec3]) -> Option<ConvexHull> {
+        let clipped = self.clip_polygon(polygon);
+        let mut result = ConvexHull::from_portal(eye, &clipped)?;
+        let epsilon = plane_epsilon(eye);
+        result.planes.extend(self.planes.iter().filter(|plane| plane.distance(eye).abs() > epsilon).cloned());
+        Some(result)
+    }
+
+    // Quickhull (Barber, Dobkin & Huhdanpaa). Points within a small tolerance of the hull
+    // count as on it, and faces that come out coplanar share one plane. Input with no volume
+    // gives a flat hull: a plane facing each way plus one per edge.
+    pub fn from_points(points: &[Vec3]) -> Result<ConvexHull, String> {
+        if points.iter().any(|p| !p.iter().all(|x| x.is_finite())) {
+            return Err("convex hull points must be finite".to_string());
+        }
+        let mut quickhull = QuickHull::new(points)?;
+        let (faces, planes) = match quickhull.initial_simplex() {
+            Some(simplex) => {
+                quickhull.build(simplex)?;
+                quickhull.mesh()
+            }
+            None => quickhull.flat_mesh()?,
+        };
+
+        // Keep the hull's vertices only.
+        let mut remap = HashMap::new();
+        let mut hull = ConvexHull { planes, ..ConvexHull::new() };
+        for face in &faces {
+            let mut face_out = [0; 3];
+            for (out, &i) in face_out.iter_mut().zip(face) {
+                *out = *remap.entry(i).or_insert_with(|| {
+                    hull.vertices.push(points[i]);
+                    hull.vertices.len() as u32 - 1
+                });
+            }
+            hull.faces.push(face_out);
+        }
+        Ok(hull)
+    }
+}
+
+// The error in a plane distance computed around `p`.
+fn plane_epsilon(p: &Vec3) -> f32 {
+    1e-5 * p.amax().max(1.0)
+}
+
+struct HullFace {
+    vertices: [usize; 3],
+    // Facing outwards.
+    plane: Plane,
+    // Points outside this face and no face before it.
+    outside: Vec<usize>,
+    alive: bool,
+}
+
+struct QuickHull<'a> {
+    points: &'a [Vec3],
+    epsilon: f32,
+    faces: Vec<HullFace>,
+    // Each directed edge of the hull maps to the face it winds around, so an edge's
+    // neighbour is found by reversing it.
+    edges: HashMap<(usize, usize), usize>,
+}
+
+impl<'a> QuickHull<'a> {
+    fn new(points: &'a [Vec3]) -> Result<Self, String> {
+        if points.len() < 3 {
+            return Err(format!("convex hull needs at least 3 points, got {}", points.len()));
+        }
+        // The usual tolerance for round-off in a plane distance over the point set's extent.
+        let max_abs = points.iter().fold(Vec3::zeros(), |acc, p| acc.zip_map(p, |a, b| a.max(b.abs())));
+        let epsilon = 3.0 * f32::EPSILON * (max_abs.x + max_abs.y + max_abs.z);
+        Ok(QuickHull { points, epsilon, faces: Vec::new(), edges: HashMap::new() })
+    }
+
+    fn farthest<I: Iterator<Item = usize>>(&self, candidates: I, distance: impl Fn(&Vec3) -> f32) -> (usize, f32) {
+        candidates
+            .map(|i| (i, distance(&self.points[i])))
+            .fold((0, f32::NEG_INFINITY), |best, x| if x.1 > best.1 { x } else { best })
+    }
+
+    // A tetrahedron of extreme points with (a, b, c) facing away from d, or None if the
+    // points are coplanar or collinear.
+    fn initial_simplex(&self) -> Option<[usize; 4]> {
+        let n = self.points.len();
+        let mut extremes = Vec::with_capacity(6);
+        for axis in 0..3 {
+            extremes.push(self.farthest(0..n, |p| -p[axis]).0);
+            extremes.push(self.farthest(0..n, |p| p[axis]).0);
+        }
+        let mut pair = (extremes[0], extremes[1]);
+        for &i in &extremes {
+            for &j in &extremes {
+                if self.points[i].metric_distance(&self.points[j]) > self.points[pair.0].metric_distance(&self.points[pair.1]) {
+                    pair = (i, j);
+                }
+            }
+        }
+
+        let (a, b) = pair;
+        let (pa, pb) = (self.points[a], self.points[b]);
+        let (c, area) = self.farthest(0..n, |p| (p - pa).cross(&(p - pb)).norm());
+        let line_distance = area / pa.metric_distance(&pb);
+        if line_distance.is_nan() || line_distance <= self.epsilon {
+            return None;
+        }
+        let mut plane = Plane::default();
+        plane.set_tri(&pa, &pb, &self.points[c]);
+        let (d, dist) = self.farthest(0..n, |p| plane.distance(p).abs());
+        if dist <= self.epsilon {
+            return None;
+        }
+        if plane.distance(&self.points[d]) > 0.0 {
+            Some([a, c, b, d])
+        } else {
+            Some([a, b, c, d])
+        }
+    }
+
+    fn add_face(&mut self, a: usize, b: usize, c: usize) -> usize {
+        let mut plane = Plane::default();
+        plane.set_tri(&self.points[a], &self.points[b], &self.points[c]);
+        let face = self.faces.len();
+        for &edge in &[(a, b), (b, c), (c, a)] {
+            self.edges.insert(edge, face);
+        }
+        self.faces.push(HullFace { vertices: [a, b, c], plane, outside: Vec::new(), alive: true });
+        face
+    }
+
+    fn assign_outside(&mut self, candidates: &[usize], faces: &[usize]) {
+        for &i in candidates {
+            let p = &self.points[i];
+            let face = faces.iter().copied().find(|&f| self.faces[f].plane.distance(p) > self.epsilon);
+            if let Some(face) = face {
+                self.faces[face].outside.push(i);
+            }
+        }
+    }
+
+    fn build(&mut self, simplex: [usize; 4]) -> Result<(), String> {
+        let [a, b, c, d] = simplex;
+        let mut pending = vec![
+            self.add_face(a, b, c),
+            self.add_face(a, d, b),
+            self.add_face(b, d, c),
+            self.add_face(c, d, a),
+        ];
+        let candidates: Vec<usize> = (0..self.points.len()).filter(|i| !simplex.contains(i)).collect();
+        self.assign_outside(&candidates, &pending);
+
+        while let Some(face) = pending.pop() {
+            if !self.faces[face].alive || self.faces[face].outside.is_empty() {
+                continue;
+            }
+            let plane = self.faces[face].plane.clone();
+            let (eye, _) = self.farthest(self.faces[face].outside.iter().copied(), |p| plane.distance(p));
+            let eye_point = self.points[eye];
+
+            // Flood fill the faces the eye can see. Their boundary is the horizon.
+            let mut visible = vec![face];
+            let mut horizon = Vec::new();
+            self.faces[face].alive = false;
+            let mut i = 0;
+            while i < visible.len() {
+                let [a, b, c] = self.faces[visible[i]].vertices;
+                i += 1;
+                for &(u, v) in &[(a, b), (b, c), (c, a)] {
+                    // Only missing if rounding left the mesh inconsistent.
+                    let neighbour = *self.edges.get(&(v, u))
+                        .ok_or_else(|| "convex hull points are too degenerate to build a hull".to_string())?;
+                    if !self.faces[neighbour].alive {
+                        continue;
+                    }
+                    if self.faces[neighbour].plane.distance(&eye_point) > self.epsilon {
+                        self.faces[neighbour].alive = false;
+                        visible.push(neighbour);
+                    } else {
+                        horizon.push((u, v));
+                    }
+                }
+            }
+
+            let mut orphans = Vec::new();
+            for &f in &visible {
+                let [a, b, c] = self.faces[f].vertices;
+                for edge in &[(a, b), (b, c), (c, a)] {
+                    self.edges.remove(edge);
+                }
+                orphans.extend(self.faces[f].outside.drain(..).filter(|&i| i != eye));
+            }
+            let new_faces: Vec<usize> = horizon.iter().map(|&(u, v)| self.add_face(u, v, eye)).collect();
+            self.assign_outside(&orphans, &new_faces);
+            pending.extend(new_faces);
+        }
+        Ok(())
+    }
+
+    // The live faces, and an inward-facing plane for each set of coplanar faces.
+    fn mesh(&self) -> (Vec<[usize; 3]>, Vec<Plane>) {
+        let mut faces = Vec::new();
+        let mut planes: Vec<Plane> = Vec::new();
+        for face in self.faces.iter().filter(|f| f.alive) {
+            faces.push(face.vertices);
+            let mut plane = face.plane.clone();
+            plane.negate();
+            let coplanar = planes.iter().any(|other| {
+                other.normal.dot(&plane.normal) > 0.0
+                    && face.vertices.iter().all(|&i| other.distance(&self.points[i]).abs() <= self.epsilon)
+            });
+            if !coplanar {
+                planes.push(plane);
+            }
+        }
+        (faces, planes)
+    }
+
+    // The 2D hull of coplanar points, as a polygon with faces on both sides.
+    fn flat_mesh(&self) -> Result<(Vec<[usize; 3]>, Vec<Plane>), String> {
+        let n = self.points.len();
+        let a = self.points[0];
+        let (b, _) = self.farthest(0..n, |p| p.metric_distance(&a));
+        let pb = self.points[b];
+        let (c, area) = self.farthest(0..n, |p| (p - a).cross(&(p - pb)).norm());
+        let line_distance = area / pb.metric_distance(&a);
+        if line_distance.is_nan() || line_distance <= self.epsilon {
+            return Err("convex hull points are collinear".to_string());
+        }
+        let u = (pb - a).normalize();
+        let normal = u.cross(&(self.points[c] - a)).normalize();
+        let v = normal.cross(&u);
+
+        // Andrew's monotone chain, counter-clockwise around the normal.
+        let mut sorted: Vec<(usize, Vec2)> = (0..n).map(|i| {
+            let p = self.points[i] - a;
+            (i, vec2(p.dot(&u), p.dot(&v)))
+        }).collect();
+        sorted.sort_by(|(_, p), (_, q)| p.x.total_cmp(&q.x).then(p.y.total_cmp(&q.y)));
+        let mut chain: Vec<(usize, Vec2)> = Vec::with_capacity(n + 1);
+        for pass in 0..2 {
+            let start = chain.len();
+            for &(i, p) in &sorted {
+                while chain.len() >= start + 2 {
+                    let (o, q) = (chain[chain.len() - 2].1, chain[chain.len() - 1].1);
+                    // Drops points on the outline's edges too.
+                    if (q - o).perp(&(p - o)) > self.epsilon * (q - o).norm() {
+                        break;
+                    }
+                    chain.pop();
+                }
+                chain.push((i, p));
+            }
+            chain.pop();
+            if pass == 0 {
+                sorted.reverse();
+            }
+        }
+        let outline: Vec<usize> = chain.iter().map(|&(i, _)| i).collect();
+        if outline.len() < 3 {
+            return Err("convex hull points are collinear".to_string());
+        }
+
+        let mut faces = Vec::new();
+        for i in 1..outline.len() - 1 {
+            faces.push([outline[0], outline[i], outline[i + 1]]);
+            faces.push([outline[0], outline[i + 1], outline[i]]);
+        }
+        let mut planes = vec![Plane::new(-normal, normal.dot(&a)), Plane::new(normal, -normal.dot(&a))];
+        for (i, &j) in outline.iter().enumerate() {
+            let (pa, pb) = (self.points[j], self.points[outline[(i + 1) % outline.len()]]);
+            let edge_normal = normal.cross(&(pb - pa));
+            planes.push(Plane::new(edge_normal, -edge_normal.dot(&pa)).normalized());
+        }
+        Ok((faces, planes))
+    }
+}
+
+#[wasm_bindgen(js_class = "ConvexHull")]
+impl ConvexHull {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        ConvexHull { planes: Vec::new(), vertices: Vec::new(), faces: Vec::new() }
+    }
+
+    pub fn copy(&mut self) -> Self {
+        return self.clone()
+    }
+
+    pub fn clear(&mut self) {
+        self.planes.clear();
+        self.vertices.clear();
+        self.faces.clear();
+    }
+
+    pub fn push_plane(&mut self, x: f32, y: f32, z: f32, d: f32) {
+        // Normalize the plane equation so that sphere tests work.
+        let plane = Plane::new(Vec3::new(x, y, z), d);
+        self.planes.push(plane.normalized());
+        // The mesh no longer describes the hull.
+        self.vertices.clear();
+        self.faces.clear();
+    }
+
+    // The points are packed xyz.
+    pub fn js_from_points(points_slice: &[f32]) -> Result<ConvexHull, String> {
+        let points: Vec<Vec3> = points_slice.chunks_exact(3).map(make_vec3).collect();
+        ConvexHull::from_points(&points)
+    }
+
+    pub fn get_num_planes(&self) -> usize {
+        self.planes.len()
+    }
+
+    pub fn get_vertices(&self) -> Vec<f32> {
+        self.vertices.iter().flat_map(|v| v.iter().copied()).collect()
+    }
+
+    pub fn get_faces(&self) -> Vec<u32> {
+        self.faces.iter().flatten().copied().collect()
+    }
+
+    pub fn js_intersect_aabb(&mut self, min_x: f32, min_y: f32, min_z: f32, max_x: f32, max_y: f32, max_z: f32) -> IntersectionState {
+        let aabb = AABB::from_f32(min_x, min_y, min_z, max_x, max_y, max_z);
+        self.intersect_aabb(&aabb)
+    }
+
+    pub fn js_contains_aabb(&mut self, min_x: f32, min_y: f32, min_z: f32, max_x: f32, max_y: f32, max_z: f32) -> bool {
+        let aabb = AABB::from_f32(min_x, min_y, min_z, max_x, max_y, max_z);
+        self.contains_aabb(&aabb)
+    }
+
+    // Tests a model-space AABB under a model matrix as an OBB, which stays tight when the
+    // model is rotated.
+    pub fn js_intersect_transformed_aabb(&self, mat_slice: &[f32], aabb_slice: &[f32]) -> IntersectionState {
+        assert_eq!(mat_slice.len(), 16);
+        let mut obb = OBB::from_aabb(&AABB::from_slice(aabb_slice));
+        obb.transform(&make_mat4(mat_slice));
+        self.intersect_obb(&obb)
+    }
+
+    pub fn js_intersect_capsule(&self, a_slice: &[f32], b_slice: &[f32], radius: f32) -> IntersectionState {
+        assert_eq!(a_slice.len(), 3);
+        assert_eq!(b_slice.len(), 3);
+        self.intersect_capsule(&Capsule::new(make_vec3(a_slice), make_vec3(b_slice), radius))
+    }
+
+    pub fn js_contains_point(&mut self, pt_slice: &[f32]) -> bool {
+        assert_eq!(pt_slice.iter().count(), 3);
+        let pt = make_vec3(pt_slice);
+        self.contains_point(&pt)
+    }
+
+    pub fn js_intersect_sphere(&mut self, x: f32, y: f32, z: f32, radius: f32) -> IntersectionState {
+        let center = Vec3::new(x, y, z);
+        self.intersect_sphere(&center, radius)
+    }
+
+    pub fn js_contains_sphere(&mut self, x: f32, y: f32, z: f32, radius: f32) -> bool {
+        let center = Vec3::new(x, y, z);
+        self.contains_sphere(&center, radius)
+    }
+
+    pub fn js_transform(&mut self, mat_slice: &[f32]) {
+        assert_eq!(mat_slice.iter().count(), 16);
+        let mat = make_mat4(mat_slice);
+        self.transform(&mat);
+    }
+
+    pub fn js_set_view_projection(&mut self, mat_slice: &[f32], near_z: ClipSpaceNearZ) {
+        assert_eq!(mat_slice.len(), 16);
+        self.set_view_projection(&make_mat4(mat_slice), near_z);
+    }
+
+    // The polygon is packed xyz.
+    pub fn js_clip_to_portal(&self, eye_slice: &[f32], polygon_slice: &[f32]) -> Option<ConvexHull> {
+        assert_eq!(eye_slice.len(), 3);
+        let polygon: Vec<Vec3> = polygon_slice.chunks_exact(3).map(make_vec3).collect();
+        self.clip_to_portal(&make_vec3(eye_slice), &polygon)
+    }
+
+    pub fn debug_str(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub dir: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, dir: Vec3) -> Ray {
+        Ray { origin, dir }
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.dir * t
+    }
+
+    // Möller–Trumbore, for either winding. Returns the distance along the ray and the
+    // barycentric coordinates of p1 and p2.
+    pub fn intersect_triangle(&self, p0: &Vec3, p1: &Vec3, p2: &Vec3) -> Option<(f32, f32, f32)> {
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let pvec = self.dir.cross(&e2);
+        let det = e1.dot(&pvec);
+        if det == 0.0 {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let tvec = self.origin - p0;
+        let u = tvec.dot(&pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let qvec = tvec.cross(&e1);
+        let v = self.dir.dot(&qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = e2.dot(&qvec) * inv_det;
+        if t < 0.0 {
+            return None;
+        }
+        Some((t, u, v))
+    }
+
+    // Slab test. Returns the entry and exit distances; the entry distance is negative if
+    // the ray starts inside the box.
+    pub fn intersect_aabb(&self, aabb: &AABB) -> Option<(f32, f32)> {
+        let mut t_min = f32::NEG_INFINITY;
+        let mut t_max = f32::INFINITY;
+        for i in 0..3 {
+            let inv_dir = 1.0 / self.dir[i];
+            let t0 = (aabb.min[i] - self.origin[i]) * inv_dir;
+            let t1 = (aabb.max[i] - self.origin[i]) * inv_dir;
+            // NaNs from rays in the plane of a slab are dropped by min/max.
+            t_min = t_min.max(t0.min(t1));
+            t_max = t_max.min(t0.max(t1));
+        }
+
+        if t_max < t_min.max(0.0) {
+            return None;
+        }
+        Some((t_min, t_max))
+    }
+
+    // Returns the distance to the first hit in front of the origin.
+    pub fn intersect_sphere(&self, center: &Vec3, radius: f32) -> Option<f32> {
+        let oc = self.origin - center;
+        let a = self.dir.dot(&self.dir);
+        let half_b = oc.dot(&self.dir);
+        let c = oc.dot(&oc) - radius * radius;
+        let discriminant = half_b * half_b - a * c;
+        if a == 0.0 || discriminant < 0.0 {
+            return None;
+        }
+
+        let sqrt_d = discriminant.sqrt();
+        let near = (-half_b - sqrt_d) / a;
+        let far = (-half_b + sqrt_d) / a;
+        if near >= 0.0 {
+            Some(near)
+        } else if far >= 0.0 {
+            Some(far)
+        } else {
+            None
+        }
+    }
+}
+
+const BVH_LEAF_SIZE: usize = 4;
+
+#[derive(Debug, Clone)]
+struct BvhEntry {
+    id: u32,
+    aabb: AABB,
+}
+
+// Nodes are laid out depth first, so a node's left child directly follows it and every
+// subtree covers a contiguous range of entries. Leaves have no right child.
+#[derive(Debug, Clone)]
+struct BvhNode {
+    aabb: AABB,
+    start: usize,
+    count: usize,
+    right: Option<usize>,
+}
+
+#[wasm_bindgen(js_name = "Bvh")]
+#[derive(Debug, Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    entries: Vec<BvhEntry>,
+    entry_index: HashMap<u32, usize>,
+}
+
+impl Bvh {
+    pub fn build(&mut self, entries: impl IntoIterator<Item = (u32, AABB)>) -> Result<(), String> {
+        self.entries = entries.into_iter().map(|(id, aabb)| BvhEntry { id, aabb }).collect();
+        self.nodes.clear();
+        if !self.entries.is_empty() {
+            Bvh::build_node(&mut self.nodes, &mut self.entries, 0);
+        }
+
+        self.entry_index.clear();
+        for (i, entry) in self.entries.iter().enumerate() {
+            if self.entry_index.insert(entry.id, i).is_some() {
+                let id = entry.id;
+                *self = Bvh::default();
+                return Err(format!("duplicate BVH entry {}", id));
+            }
+        }
+        Ok(())
+    }
+
+    fn build_node(nodes: &mut Vec<BvhNode>, entries: &mut [BvhEntry], start: usize) -> usize {
+        let index = nodes.len();
+        let mut aabb = AABB::default();
+        for entry in entries.iter() {
+            aabb.union_aabb(&entry.aabb);
+        }
+        nodes.push(BvhNode { aabb, start, count: entries.len(), right: None });
+        if entries.len() <= BVH_LEAF_SIZE {
+            return index;
+        }
+
+        // Split at the median centroid, along the axis the centroids are most spread out on.
+        let mut centroid_bounds = AABB::default();
+        for entry in entries.iter() {
+            centroid_bounds.union_point(&entry.aabb.center());
+        }
+        let axis = (centroid_bounds.max - centroid_bounds.min).imax();
+        let mid = entries.len() / 2;
+        entries.select_nth_unstable_by(mid, |a, b| a.aabb.center()[axis].total_cmp(&b.aabb.center()[axis]));
+
+        let (left, right) = entries.split_at_mut(mid);
+        Bvh::build_node(nodes, left, start);
+        nodes[index].right = Some(Bvh::build_node(nodes, right, start + mid));
+        index
+    }
+
+    pub fn set_aabb(&mut self, id: u32, aabb: AABB) -> Result<(), String> {
+        let index = *self.entry_index.get(&id).ok_or_else(|| format!("no BVH entry {}", id))?;
+        self.entries[index].aabb = aabb;
+        Ok(())
+    }
+
+    // Recomputes node bounds after entries moved. The tree shape is kept, so culling gets
+    // slower if entries move far from where they were built.
+    pub fn refit(&mut self) {
+        for i in (0..self.nodes.len()).rev() {
+            let mut aabb = AABB::default();
+            let node = &self.nodes[i];
+            if let Some(right) = node.right {
+                aabb.union_aabb(&self.nodes[i + 1].aabb);
+                aabb.union_aabb(&self.nodes[right].aabb);
+            } else {
+                for entry in &self.entries[node.start..node.start + node.count] {
+                    aabb.union_aabb(&entry.aabb);
+                }
+            }
+            self.nodes[i].aabb = aabb;
+        }
+    }
+
+    // Appends the IDs of all entries that are at least partially inside the hull. Subtrees
+    // entirely inside are taken without testing their entries.
+    pub fn cull(&self, hull: &ConvexHull, dst: &mut Vec<u32>) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let entries = &self.entries[node.start..node.start + node.count];
+            match hull.intersect_aabb(&node.aabb) {
+                IntersectionState::Outside => {},
+                IntersectionState::Inside => dst.extend(entries.iter().map(|entry| entry.id)),
+                IntersectionState::Intersection => match node.right {
+                    Some(right) => {
+                        stack.push(right);
+                        stack.push(index + 1);
+                    },
+                    None => dst.extend(entries.iter().filter(|entry| hull.contains_aabb(&entry.aabb)).map(|entry| entry.id)),
+                },
+            }
+        }
+    }
+
+    // Finds the closest entry along the ray, visiting nearer subtrees first and skipping
+    // those beyond the closest hit so far. `hit` returns the distance to an entry, if hit.
+    pub fn raycast(&self, ray: &Ray, mut hit: impl FnMut(u32) -> Option<f32>) -> Option<(u32, f32)> {
+        let mut closest: Option<(u32, f32)> = None;
+        let mut stack = Vec::new();
+        if let Some((t, _)) = self.nodes.first().and_then(|root| ray.intersect_aabb(&root.aabb)) {
+            stack.push((0, t));
+        }
+
+        while let Some((index, t_enter)) = stack.pop() {
+            if closest.is_some_and(|(_, t)| t_enter > t) {
+                continue;
+            }
+
+            let node = &self.nodes[index];
+            if let Some(right) = node.right {
+                let left = ray.intersect_aabb(&self.nodes[index + 1].aabb).map(|(t, _)| (index + 1, t));
+                let right = ray.intersect_aabb(&self.nodes[right].aabb).map(|(t, _)| (right, t));
+                match (left, right) {
+                    (Some(a), Some(b)) => {
+                        let (near, far) = if a.1 <= b.1 { (a, b) } else { (b, a) };
+                        stack.push(far);
+                        stack.push(near);
+                    },
+                    (Some(child), None) | (None, Some(child)) => stack.push(child),
+                    (None, None) => {},
+                }
+            } else {
+                for entry in &self.entries[node.start..node.start + node.count] {
+                    if let Some(t) = hit(entry.id) {
+                        if closest.is_none_or(|(_, closest_t)| t < closest_t) {
+                            closest = Some((entry.id, t));
+                        }
+                    }
+                }
+            }
+        }
+        closest
+    }
+}
+
+fn aabbs_from_slice<'a>(ids: &'a [u32], aabbs: &'a [f32]) -> Result<impl Iterator<Item = (u32, AABB)> + 'a, String> {
+    if aabbs.len() != ids.len() * 6 {
+        return Err(format!("expected {} AABB floats for {} IDs, got {}", ids.len() * 6, ids.len(), aabbs.len()));
+    }
+    Ok(ids.iter().copied().zip(aabbs.chunks_exact(6).map(AABB::from_slice)))
+}
+
+#[wasm_bindgen(js_class = "Bvh")]
+impl Bvh {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Bvh::default()
+    }
+
+    pub fn get_num_entries(&self) -> usize {
+        self.entries.len()
+    }
+
+    // AABBs are packed as min xyz, max xyz; one per ID.
+    pub fn js_build(&mut self, ids: &[u32], aabbs: &[f32]) -> Result<(), String> {
+        let entries = aabbs_from_slice(ids, aabbs)?;
+        self.build(entries)
+    }
+
+    // Updates the AABBs of the given entries, then refits.
+    pub fn js_refit(&mut self, ids: &[u32], aabbs: &[f32]) -> Result<(), String> {
+        for (id, aabb) in aabbs_from_slice(ids, aabbs)? {
+            self.set_aabb(id, aabb)?;
+        }
+        self.refit();
+        Ok(())
+    }
+
+    pub fn js_cull(&self, hull: &ConvexHull) -> Vec<u32> {
+        let mut dst = Vec::new();
+        self.cull(hull, &mut dst);
+        dst
+    }
+}
+
+#[wasm_bindgen(js_name = "RayHit")]
+#[derive(Debug, Clone)]
+pub struct RayHit {
+    t: f32,
+    triangle_index: u32,
+    barycentrics: Vec3,
+    normal: Vec3,
+}
+
+#[wasm_bindgen(js_class = "RayHit")]
+impl RayHit {
+    pub fn get_distance(&self) -> f32 {
+        self.t
+    }
+
+    pub fn get_triangle_index(&self) -> u32 {
+        self.triangle_index
+    }
+
+    pub fn get_barycentrics(&self) -> Vec<f32> {
+        self.barycentrics.as_slice().to_vec()
+    }
+
+    pub fn get_normal(&self) -> Vec<f32> {
+        self.normal.as_slice().to_vec()
+    }
+}
+
+// An indexed triangle mesh with a BVH over its triangles, for picking.
+#[wasm_bindgen(js_name = "TriangleMesh")]
+#[derive(Debug)]
+pub struct TriangleMesh {
+    positions: Vec<Vec3>,
+    indices: Vec<u32>,
+    bvh: Bvh,
+}
+
+impl TriangleMesh {
+    fn triangle(&self, triangle_index: u32) -> [&Vec3; 3] {
+        let i = triangle_index as usize * 3;
+        [
+            &self.positions[self.indices[i] as usize],
+            &self.positions[self.indices[i + 1] as usize],
+            &self.positions[self.indices[i + 2] as usize],
+        ]
+    }
+
+    // Returns the closest hit. The normal follows the triangle's winding, regardless of
+    // which side was hit.
+    pub fn raycast(&self, ray: &Ray) -> Option<RayHit> {
+        let (triangle_index, t) = self.bvh.raycast(ray, |triangle_index| {
+            let [p0, p1, p2] = self.triangle(triangle_index);
+            ray.intersect_triangle(p0, p1, p2).map(|(t, _, _)| t)
+        })?;
+
+        let [p0, p1, p2] = self.triangle(triangle_index);
+        let (_, u, v) = ray.intersect_triangle(p0, p1, p2)?;
+        Some(RayHit {
+            t,
+            triangle_index,
+            barycentrics: Vec3::new(1.0 - u - v, u, v),
+            normal: triangle_normal(p0, p1, p2),
+        })
+    }
+}
+
+#[wasm_bindgen(js_class = "TriangleMesh")]
+impl TriangleMesh {
+    // Positions are packed xyz; every three indices make a triangle.
+    pub fn new(positions: &[f32], indices: &[u32]) -> Result<TriangleMesh, String> {
+        if !positions.len().is_multiple_of(3) || !indices.len().is_multiple_of(3) {
+            return Err("positions and indices must come in threes".to_string());
+        }
+        let positions: Vec<Vec3> = positions.chunks_exact(3).map(make_vec3).collect();
+        if let Some(&index) = indices.iter().find(|&&index| index as usize >= positions.len()) {
+            return Err(format!("index {} out of range ({} vertices)", index, positions.len()));
+        }
+
+        let mut bvh = Bvh::new();
+        bvh.build(indices.chunks_exact(3).enumerate().map(|(i, triangle)| {
+            let mut aabb = AABB::default();
+            for &index in triangle {
+                aabb.union_point(&positions[index as usize]);
+            }
+            (i as u32, aabb)
+        }))?;
+
+        Ok(TriangleMesh { positions, indices: indices.to_vec(), bvh })
+    }
+
+    pub fn get_num_triangles(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn js_raycast(&self, origin_slice: &[f32], dir_slice: &[f32]) -> Option<RayHit> {
+        assert_eq!(origin_slice.len(), 3);
+        assert_eq!(dir_slice.len(), 3);
+        self.raycast(&Ray::new(make_vec3(origin_slice), make_vec3(dir_slice)))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+pub fn project_vec3_to_vec2(v: &Vec3, axis: Axis) -> Vec2 {
+    match axis {
+        Axis::X => vec2(v[1], v[2]),
+        Axis::Y => vec2(v[2], v[0]),
+        Axis::Z => vec2(v[0], v[1]),
+    }
+}
+
+// Sutherland–Hodgman: the part of a convex polygon on the positive side of the plane.
+pub fn clip_polygon_to_plane(polygon: &[Vec3], plane: &Plane) -> Vec<Vec3> {
+    let mut result = Vec::with_capacity(polygon.len() + 1);
+    for (i, a) in polygon.iter().enumerate() {
+        let b = &polygon[(i + 1) % polygon.len()];
+        let dist_a = plane.distance(a);
+        let dist_b = plane.distance(b);
+        if dist_a >= 0.0 {
+            result.push(*a);
+        }
+        if (dist_a >= 0.0) != (dist_b >= 0.0) {
+            let t = dist_a / (dist_a - dist_b);
+            result.push(a + (b - a) * t);
+        }
+    }
+    result
+}
+
+pub fn point_inside_polygon(point: &Vec2, polygon_vertices: &[Vec2]) -> bool {
+    let mut sign = None;
+    let num_verts = polygon_vertices.len();
+    for i in 0..num_verts {
+        let a = &polygon_vertices[i];
+        let b = &polygon_vertices[(i + 1) % num_verts];
+        let x = (b - a).perp(&(point - a));
+        match sign {
+            Some(is_positive) => {
+                if is_positive != x.is_sign_positive() {
+                    return false;
+                }
+            }
+            None => sign = Some(x.is_sign_positive()),
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The box from -10 to 10 on every axis.
+    fn box_hull() -> ConvexHull {
+        let mut hull = ConvexHull::new();
+        for axis in 0..3 {
+            let mut normal = [0.0; 3];
+            normal[axis] = 1.0;
+            hull.push_plane(normal[0], normal[1], normal[2], 10.0);
+            hull.push_plane(-normal[0], -normal[1], -normal[2], 10.0);
+        }
+        hull
+    }
+
+    fn brute_force_cull(hull: &ConvexHull, entries: &[(u32, AABB)]) -> Vec<u32> {
+        let mut ids: Vec<u32> = entries.iter().filter(|(_, aabb)| hull.contains_aabb(aabb)).map(|(id, _)| *id).collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    fn bvh_cull(bvh: &Bvh, hull: &ConvexHull) -> Vec<u32> {
+        let mut ids = bvh.js_cull(hull);
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn test_bvh_cull_and_refit() {
+        let mut entries: Vec<(u32, AABB)> = (0..1000).map(|i| {
+            let p = Vec3::new((i % 10) as f32, ((i / 10) % 10) as f32, (i / 100) as f32) * 4.0 - Vec3::from_element(18.0);
+            (i * 3, AABB { min: p, max: p + Vec3::from_element(1.0) })
+        }).collect();
+        let mut bvh = Bvh::new();
+        bvh.build(entries.clone()).unwrap();
+        assert_eq!(bvh.get_num_entries(), 1000);
+
+        let hull = box_hull();
+        let visible = bvh_cull(&bvh, &hull);
+        assert!(!visible.is_empty() && visible.len() < 1000);
+        assert_eq!(visible, brute_force_cull(&hull, &entries));
+
+        // Move every other entry far away, and one back in.
+        for (i, (id, aabb)) in entries.iter_mut().enumerate() {
+            if i % 2 == 0 {
+                aabb.min.x += 100.0;
+                aabb.max.x += 100.0;
+                bvh.set_aabb(*id, aabb.clone()).unwrap();
+            }
+        }
+        entries[0].1 = AABB::from_f32(0.0, 0.0, 0.0, 1.0, 1.0, 1.0);
+        bvh.set_aabb(entries[0].0, entries[0].1.clone()).unwrap();
+        bvh.refit();
+        assert_eq!(bvh_cull(&bvh, &hull), brute_force_cull(&hull, &entries));
+
+        assert!(bvh.set_aabb(1, AABB::default()).is_err());
+        assert!(bvh.js_build(&[1, 1], &[0.0; 12]).is_err());
+        assert!(bvh.js_cull(&hull).is_empty());
+    }
+
+    #[test]
+    fn test_ray_primitives() {
+        let ray = Ray::new(Vec3::new(0.25, 0.25, 5.0), Vec3::new(0.0, 0.0, -2.0));
+        let (p0, p1, p2) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(ray.intersect_triangle(&p0, &p1, &p2), Some((2.5, 0.25, 0.25)));
+        assert_eq!(ray.intersect_triangle(&p0, &p2, &p1), Some((2.5, 0.25, 0.25)));
+        assert_eq!(Ray::new(Vec3::new(1.0, 1.0, 5.0), ray.dir).intersect_triangle(&p0, &p1, &p2), None);
+        assert_eq!(Ray::new(ray.origin, -ray.dir).intersect_triangle(&p0, &p1, &p2), None);
+
+        let aabb = AABB::from_f32(-1.0, -1.0, -1.0, 1.0, 1.0, 1.0);
+        assert_eq!(ray.intersect_aabb(&aabb), Some((2.0, 3.0)));
+        assert_eq!(Ray::new(Vec3::zeros(), ray.dir).intersect_aabb(&aabb), Some((-0.5, 0.5)));
+        assert_eq!(Ray::new(Vec3::new(2.0, 0.0, 5.0), ray.dir).intersect_aabb(&aabb), None);
+        assert_eq!(Ray::new(ray.origin, -ray.dir).intersect_aabb(&aabb), None);
+
+        let center = Vec3::new(0.25, 0.25, 0.0);
+        assert_eq!(ray.intersect_sphere(&center, 1.0), Some(2.0));
+        assert_eq!(Ray::new(center, ray.dir).intersect_sphere(&center, 1.0), Some(0.5));
+        assert_eq!(Ray::new(ray.origin, -ray.dir).intersect_sphere(&center, 1.0), None);
+    }
+
+    #[test]
+    fn test_triangle_mesh_raycast() {
+        // A bumpy 32x32 heightfield.
+        let n = 32;
+        let height = |x: usize, z: usize| ((x * 7 + z * 13) % 5) as f32 * 0.5;
+        let mut positions = Vec::new();
+        for z in 0..=n {
+            for x in 0..=n {
+                positions.extend_from_slice(&[x as f32, height(x, z), z as f32]);
+            }
+        }
+        let mut indices = Vec::new();
+        for z in 0..n {
+            for x in 0..n {
+                let i = (z * (n + 1) + x) as u32;
+                let row = (n + 1) as u32;
+                indices.extend_from_slice(&[i, i + row, i + 1, i + 1, i + row, i + row + 1]);
+            }
+        }
+        let mesh = TriangleMesh::new(&positions, &indices).unwrap();
+        assert_eq!(mesh.get_num_triangles(), n * n * 2);
+
+        for i in 0..200 {
+            let origin = Vec3::new((i % 17) as f32 * 2.1 - 1.0, 10.0, (i / 17) as f32 * 3.3 - 2.0);
+            let ray = Ray::new(origin, Vec3::new(0.3, -1.0, (i % 5) as f32 * 0.1));
+            let brute_force = (0..mesh.get_num_triangles() as u32)
+                .filter_map(|t| {
+                    let [p0, p1, p2] = mesh.triangle(t);
+                    ray.intersect_triangle(p0, p1, p2).map(|(d, _, _)| d)
+                })
+                .min_by(f32::total_cmp);
+            let hit = mesh.raycast(&ray);
+            assert_eq!(hit.as_ref().map(|hit| hit.t), brute_force);
+
+            if let Some(hit) = hit {
+                let [p0, p1, p2] = mesh.triangle(hit.triangle_index);
+                let point = p0 * hit.barycentrics.x + p1 * hit.barycentrics.y + p2 * hit.barycentrics.z;
+                assert!((point - ray.at(hit.t)).norm() < 1e-4);
+                assert!(hit.normal.y > 0.0 && (hit.normal.norm() - 1.0).abs() < 1e-5);
+            }
+        }
+
+        let hit = mesh.js_raycast(&[0.25, 10.0, 0.5], &[0.0, -1.0, 0.0]).unwrap();
+        assert_eq!(hit.get_triangle_index(), 0);
+        assert!(TriangleMesh::new(&positions, &[0, 1, 100000]).is_err());
+        assert!(TriangleMesh::new(&positions, &[0, 1]).is_err());
+    }
+
+    #[test]
+    fn test_view_projection_planes() {
+        let inside = Vec3::new(0.0, 0.0, -50.0);
+        let outside = [Vec3::new(0.0, 0.0, -0.05), Vec3::new(0.0, 0.0, -200.0), Vec3::new(100.0, 0.0, -50.0), Vec3::new(0.0, 0.0, 50.0)];
+
+        let gl = ConvexHull::from_view_projection(&nalgebra_glm::perspective_rh_no(1.0, 1.0, 0.1, 100.0), ClipSpaceNearZ::NegativeOne);
+        let webgpu = ConvexHull::from_view_projection(&nalgebra_glm::perspective_rh_zo(1.0, 1.0, 0.1, 100.0), ClipSpaceNearZ::Zero);
+        for hull in &[gl, webgpu] {
+            assert_eq!(hull.planes.len(), 6);
+            assert!(hull.contains_point(&inside));
+            assert!(outside.iter().all(|p| !hull.contains_point(p)));
+        }
+
+        // Reversed-Z with an infinite far plane.
+        let f = 1.0 / 0.5f32.tan();
+        let reversed = Mat4::new(
+            f, 0.0, 0.0, 0.0,
+            0.0, f, 0.0, 0.0,
+            0.0, 0.0, 0.0, 0.1,
+            0.0, 0.0, -1.0, 0.0,
+        );
+        let hull = ConvexHull::from_view_projection(&reversed, ClipSpaceNearZ::Zero);
+        assert_eq!(hull.planes.len(), 5);
+        assert!(hull.contains_point(&inside) && hull.contains_point(&Vec3::new(0.0, 0.0, -1.0e6)));
+        assert!(!hull.contains_point(&outside[0]) && !hull.contains_point(&outside[2]));
+
+        // Reusing a hull built from points drops its old mesh along with its planes.
+        let mut hull = ConvexHull::from_points(&[Vec3::zeros(), Vec3::x(), Vec3::y(), Vec3::z()]).unwrap();
+        hull.set_view_projection(&reversed, ClipSpaceNearZ::Zero);
+        assert_eq!(hull.planes.len(), 5);
+        assert!(hull.get_vertices().is_empty() && hull.get_faces().is_empty());
+    }
+
+    #[test]
+    fn test_clip_polygon() {
+        let square = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)];
+        let clipped = clip_polygon_to_plane(&square, &Plane::new(Vec3::new(1.0, 0.0, 0.0), -0.5));
+        assert_eq!(clipped, [Vec3::new(0.5, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.5, 1.0, 0.0)]);
+        assert!(clip_polygon_to_plane(&square, &Plane::new(Vec3::new(1.0, 0.0, 0.0), -2.0)).is_empty());
+        assert_eq!(clip_polygon_to_plane(&square, &Plane::new(Vec3::new(1.0, 0.0, 0.0), 2.0)), square);
+    }
+
+    #[test]
+    fn test_portal_frustum() {
+        let eye = Vec3::zeros();
+        let frustum = ConvexHull::from_view_projection(&nalgebra_glm::perspective_rh_zo(1.0, 1.0, 0.1, 100.0), ClipSpaceNearZ::Zero);
+
+        // A doorway at z = -5 that sticks out to the right of the view.
+        let portal = [Vec3::new(-1.0, -1.0, -5.0), Vec3::new(20.0, -1.0, -5.0), Vec3::new(20.0, 1.0, -5.0), Vec3::new(-1.0, 1.0, -5.0)];
+        let hull = frustum.clip_to_portal(&eye, &portal).unwrap();
+        assert!(hull.contains_point(&Vec3::new(0.0, 0.0, -10.0)));
+        // In front of the portal, above it, beyond the view's right edge, beyond the far plane.
+        assert!(!hull.contains_point(&Vec3::new(0.0, 0.0, -3.0)));
+        assert!(!hull.contains_point(&Vec3::new(0.0, 5.0, -10.0)));
+        assert!(!hull.contains_point(&Vec3::new(8.0, 0.0, -10.0)));
+        assert!(!hull.contains_point(&Vec3::new(0.0, 0.0, -150.0)));
+        // The view frustum's side planes pass through the eye, so only near and far are kept.
+        assert_eq!(hull.planes.len(), 4 + 1 + 2);
+
+        // Behind the eye, and seen edge-on.
+        let behind: Vec<Vec3> = portal.iter().map(|p| Vec3::new(p.x, p.y, 5.0)).collect();
+        assert!(frustum.clip_to_portal(&eye, &behind).is_none());
+        let edge_on: Vec<Vec3> = portal.iter().map(|p| Vec3::new(p.x, 0.0, p.y * 5.0)).collect();
+        assert!(ConvexHull::from_portal(&eye, &edge_on).is_none());
+
+        let js_hull = frustum.js_clip_to_portal(eye.as_slice(), &portal.iter().flat_map(|p| p.iter().copied()).collect::<Vec<f32>>()).unwrap();
+        assert_eq!(js_hull.planes.len(), hull.planes.len());
+    }
+
+    fn rotation_y(angle: f32) -> Mat4 {
+        nalgebra_glm::rotation(angle, &Vec3::new(0.0, 1.0, 0.0))
+    }
+
+    #[test]
+    fn test_obb() {
+        // A long thin box rotated 45 degrees, against the half-space (x + z) / sqrt(2) >= 1,
+        // which misses the box but not its world AABB.
+        let local = AABB::from_f32(-5.0, -0.1, -0.1, 5.0, 0.1, 0.1);
+        let mat = rotation_y(f32::consts::FRAC_PI_4);
+        let mut hull = ConvexHull::new();
+        hull.push_plane(1.0, 0.0, 1.0, -f32::consts::SQRT_2);
+
+        let mut aabb = local.clone();
+        aabb.transform(&mat);
+        assert_eq!(hull.intersect_aabb(&aabb), IntersectionState::Intersection);
+        let mut obb = OBB::from_aabb(&local);
+        obb.transform(&mat);
+        assert_eq!(hull.intersect_obb(&obb), IntersectionState::Outside);
+        assert_eq!(hull.js_intersect_transformed_aabb(mat.as_slice(), &[-5.0, -0.1, -0.1, 5.0, 0.1, 0.1]), IntersectionState::Outside);
+        hull.clear();
+        hull.push_plane(1.0, 0.0, 1.0, 1.0);
+        assert_eq!(hull.intersect_obb(&obb), IntersectionState::Inside);
+
+        // Fitting to the corners recovers the box.
+        let fitted = OBB::from_points(&obb.corners());
+        assert!((fitted.center - obb.center).norm() < 1e-4);
+        let mut extents: Vec<f32> = fitted.half_extents.iter().copied().collect();
+        extents.sort_by(f32::total_cmp);
+        assert!((extents[0] - 0.1).abs() < 1e-4 && (extents[1] - 0.1).abs() < 1e-4 && (extents[2] - 5.0).abs() < 1e-4);
+        assert!(obb.corners().iter().all(|p| fitted.contains_point(&(p * 0.999))));
+
+        // Crossed thin boxes, then lifted apart along Y, which is a face axis of both.
+        let mut crossed = OBB::from_aabb(&local);
+        crossed.transform(&rotation_y(-f32::consts::FRAC_PI_4));
+        assert!(obb.intersects_obb(&crossed));
+        crossed.center.y = 0.3;
+        assert!(!obb.intersects_obb(&crossed));
+
+        // Rods along X and Z, each spun 45 degrees about its length so no face faces Y. Lifted
+        // apart, only the edge-edge axis X x Z separates them; each reaches 0.1 * sqrt(2) along it.
+        let mut rod_x = OBB::from_aabb(&local);
+        rod_x.transform(&nalgebra_glm::rotation(f32::consts::FRAC_PI_4, &Vec3::x()));
+        let mut rod_z = OBB::from_aabb(&AABB::from_f32(-0.1, -0.1, -5.0, 0.1, 0.1, 5.0));
+        rod_z.transform(&nalgebra_glm::rotation(f32::consts::FRAC_PI_4, &Vec3::z()));
+        rod_z.center.y = 0.25;
+        assert!(rod_x.intersects_obb(&rod_z) && rod_z.intersects_obb(&rod_x));
+        rod_z.center.y = 0.3;
+        assert!(!rod_x.intersects_obb(&rod_z) && !rod_z.intersects_obb(&rod_x));
+
+        assert!(obb.intersects_sphere(&Sphere::new(Vec3::new(3.0, 0.0, -3.0), 0.2)));
+        assert!(!obb.intersects_sphere(&Sphere::new(Vec3::new(3.0, 0.0, 3.0), 0.2)));
+        assert!(obb.to_aabb().contains_point(&Vec3::new(3.5, 0.0, -3.5)));
+    }
+
+    #[test]
+    fn test_sphere_and_capsule() {
+        let points: Vec<Vec3> = (0..100).map(|i| {
+            let t = i as f32 * 0.37;
+            Vec3::new(t.sin() * 0.5 + i as f32 * 0.1, t.cos() * 0.5, (t * 1.7).sin() * 0.3)
+        }).collect();
+
+        let sphere = Sphere::from_points(&points);
+        assert!(points.iter().all(|p| (p - sphere.center).norm() <= sphere.radius * 1.0001));
+
+        let capsule = Capsule::from_points(&points);
+        assert!(points.iter().all(|p| (p - capsule.closest_point_on_segment(p)).norm() <= capsule.radius * 1.0001));
+        assert!(capsule.radius < 1.0 && (capsule.b - capsule.a).norm() > 5.0);
+
+        // Skew segments passing 1 unit apart.
+        let a = Capsule::new(Vec3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.6);
+        let b = Capsule::new(Vec3::new(0.0, 1.0, -1.0), Vec3::new(0.0, 1.0, 1.0), 0.6);
+        assert!((segment_distance_squared(&a.a, &a.b, &b.a, &b.b) - 1.0).abs() < 1e-6);
+        assert!(a.intersects_capsule(&b));
+        assert!(!a.intersects_capsule(&Capsule::new(b.a, b.b, 0.3)));
+        assert!(a.intersects_sphere(&Sphere::new(Vec3::new(1.5, 0.0, 0.0), 0.6)));
+        assert!(!a.intersects_sphere(&Sphere::new(Vec3::new(1.5, 1.5, 0.0), 0.6)));
+
+        // A diagonal capsule passing by the corner of a unit box.
+        let obb = OBB::from_aabb(&AABB::from_f32(-1.0, -1.0, -1.0, 1.0, 1.0, 1.0));
+        let corner = Capsule::new(Vec3::new(3.0, 1.0, 0.0), Vec3::new(1.0, 3.0, 0.0), 1.5);
+        assert!((obb.segment_distance_squared(&corner.a, &corner.b) - 2.0).abs() < 1e-5);
+        assert!(corner.intersects_obb(&obb));
+        assert!(!Capsule::new(corner.a, corner.b, 1.4).intersects_obb(&obb));
+        assert!(obb.segment_distance_squared(&Vec3::new(-3.0, 0.5, 0.0), &Vec3::new(3.0, 0.5, 0.0)) == 0.0);
+
+        let mut hull = ConvexHull::new();
+        hull.push_plane(0.0, 1.0, 0.0, 0.0);
+        assert_eq!(hull.intersect_capsule(&a), IntersectionState::Intersection);
+        assert_eq!(hull.intersect_capsule(&b), IntersectionState::Inside);
+        assert_eq!(hull.js_intersect_capsule(&[0.0, -1.0, 0.0], &[1.0, -2.0, 0.0], 0.5), IntersectionState::Outside);
+
+        let mut moved = a.clone();
+        moved.transform(&nalgebra_glm::scaling(&Vec3::new(2.0, 2.0, 2.0)));
+        assert_eq!((moved.b, moved.radius), (Vec3::new(2.0, 0.0, 0.0), 1.2));
+        let mut moved = sphere.clone();
+        moved.transform(&nalgebra_glm::translation(&Vec3::new(0.0, 10.0, 0.0)));
+        assert!(!moved.intersects_sphere(&sphere) && moved.intersects_sphere(&Sphere::new(sphere.center, 10.0)));
+    }
+
+    // Checks the hull encloses every point and its mesh is a closed, convex surface.
+    fn check_point_hull(hull: &ConvexHull, points: &[Vec3]) {
+        for p in points {
+            assert!(hull.planes.iter().all(|plane| plane.distance(p) > -1e-4), "{:?} is outside", p);
+        }
+        let mut edges = HashMap::new();
+        for face in &hull.faces {
+            let [a, b, c] = face.map(|i| hull.vertices[i as usize]);
+            let normal = (b - a).cross(&(c - a));
+            assert!(hull.vertices.iter().all(|v| normal.dot(&(v - a)) < 1e-3));
+            for (u, v) in [(face[0], face[1]), (face[1], face[2]), (face[2], face[0])] {
+                assert!(edges.insert((u, v), ()).is_none());
+            }
+        }
+        assert!(edges.keys().all(|&(u, v)| edges.contains_key(&(v, u))));
+    }
+
+    #[test]
+    fn test_quickhull_box() {
+        // A grid over the surface of a box, with its interior and some duplicates.
+        let mut points = Vec::new();
+        for x in 0..5 {
+            for y in 0..5 {
+                for z in 0..5 {
+                    points.push(Vec3::new(x as f32 - 2.0, y as f32 * 0.5 - 1.0, z as f32 * 2.0 + 3.0));
+                }
+            }
+        }
+        points.extend_from_slice(&points.clone()[..20]);
+
+        let hull = ConvexHull::from_points(&points).unwrap();
+        check_point_hull(&hull, &points);
+        assert_eq!(hull.planes.len(), 6);
+        for plane in &hull.planes {
+            assert!((plane.normal.amax() - 1.0).abs() < 1e-6);
+        }
+        assert!(hull.contains_point(&Vec3::new(0.0, 0.0, 7.0)));
+        assert!(!hull.contains_point(&Vec3::new(0.0, 0.0, 11.5)));
+        assert_eq!(hull.intersect_aabb(&AABB::from_f32(1.0, 0.0, 5.0, 3.0, 2.0, 6.0)), IntersectionState::Intersection);
+
+        let mut moved = hull.clone();
+        moved.transform(&nalgebra_glm::scaling(&Vec3::new(-1.0, 1.0, 1.0)));
+        check_point_hull(&moved, &points.iter().map(|p| Vec3::new(-p.x, p.y, p.z)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_quickhull_sphere() {
+        let mut seed = 1u32;
+        let mut random = || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        let points: Vec<Vec3> = (0..500).map(|_| Vec3::new(random(), random(), random()).normalize() * 50.0).collect();
+        let hull = ConvexHull::from_points(&points).unwrap();
+        check_point_hull(&hull, &points);
+        // Every point is on the sphere, so all of them are hull vertices.
+        assert_eq!(hull.vertices.len(), points.len());
+        assert_eq!(hull.faces.len(), 2 * points.len() - 4);
+
+        let js_hull = ConvexHull::js_from_points(&hull.get_vertices()).unwrap();
+        assert_eq!(js_hull.get_faces().len(), hull.faces.len() * 3);
+    }
+
+    #[test]
+    fn test_quickhull_flat() {
+        // A square in the plane x + y = 2, with points inside and along its edges.
+        let mut points = Vec::new();
+        for i in 0..5 {
+            for j in 0..5 {
+                let (s, t) = (i as f32 - 2.0, j as f32 - 2.0);
+                points.push(Vec3::new(1.0 + s, 1.0 - s, t));
+            }
+        }
+        let hull = ConvexHull::from_points(&points).unwrap();
+        assert_eq!(hull.vertices.len(), 4);
+        assert_eq!(hull.faces.len(), 4);
+        assert_eq!(hull.planes.len(), 6);
+        assert_eq!(hull.intersect_aabb(&AABB::from_f32(0.5, 0.5, -1.0, 1.5, 1.5, 1.0)), IntersectionState::Intersection);
+        assert_eq!(hull.intersect_aabb(&AABB::from_f32(0.5, 0.5, 2.5, 1.5, 1.5, 3.0)), IntersectionState::Outside);
+        assert_eq!(hull.intersect_aabb(&AABB::from_f32(2.0, 2.0, -1.0, 3.0, 3.0, 1.0)), IntersectionState::Outside);
+
+        assert!(ConvexHull::from_points(&points[..5]).is_err());
+        assert!(ConvexHull::from_points(&[Vec3::zeros(); 8]).is_err());
+        assert!(ConvexHull::from_points(&points[..2]).is_err());
+        assert!(ConvexHull::from_points(&[Vec3::new(f32::NAN, 0.0, 0.0), points[0], points[1]]).is_err());
+    }
+}
@@ -1,62 +1,1083 @@
-
-use wasm_bindgen::prelude::wasm_bindgen;
-use web_sys::console;
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+use std::collections::HashMap;
+use std::convert::TryInto;
 use std::error::Error;
 
-fn show_error(place: &str, error: impl Error, location: Option<naga::SourceLocation>) {
-    console::log_2(&place.into(), &error.to_string().into());
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub stage: String,
+    // Which step of compilation failed: "stage", "include", "parse", "validation", "target", "wgsl", "spirv" or "glsl".
+    pub place: String,
+    pub message: String,
+    // The file the error is in, when compiled with includes.
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub span_text: Option<String>,
+    pub chain: Vec<String>,
+}
+
+impl Diagnostic {
+    fn simple(stage: &str, place: &str, message: String) -> Self {
+        Diagnostic {
+            stage: stage.to_string(),
+            place: place.to_string(),
+            message,
+            file: None,
+            line: None,
+            column: None,
+            span_text: None,
+            chain: Vec::new(),
+        }
+    }
+
+    fn new(stage: &str, place: &str, error: &dyn Error, source: &str, location: Option<naga::SourceLocation>) -> Self {
+        let mut chain = Vec::new();
+        let mut e = error.source();
+        while let Some(inner) = e {
+            chain.push(inner.to_string());
+            e = inner.source();
+        }
+
+        let span_text = location.and_then(|loc| {
+            let start = loc.offset as usize;
+            source.get(start..start + loc.length as usize).map(|s| s.to_string())
+        });
 
-    if let Some(loc) = location {
-        console::log_3(&"At line".into(), &loc.line_number.into(), &loc.line_position.into());
+        Diagnostic {
+            stage: stage.to_string(),
+            place: place.to_string(),
+            message: error.to_string(),
+            file: None,
+            line: location.map(|loc| loc.line_number),
+            column: location.map(|loc| loc.line_position),
+            span_text,
+            chain,
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}: {}", self.stage, self.place, self.message)?;
+        if let Some(file) = &self.file {
+            write!(f, " (in {})", file)?;
+        }
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, " (at line {}:{})", line, column)?;
+        }
+        for inner in &self.chain {
+            write!(f, "\n  {}", inner)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_stage(stage: &str) -> Option<naga::ShaderStage> {
+    match stage {
+        "vertex" => Some(naga::ShaderStage::Vertex),
+        "fragment" => Some(naga::ShaderStage::Fragment),
+        "compute" => Some(naga::ShaderStage::Compute),
+        _ => None,
+    }
+}
+
+// Source with all #include directives expanded, remembering where each line came from.
+pub struct ExpandedSource {
+    pub text: String,
+    files: Vec<String>,
+    // (file index, 1-based line number) for each line of text.
+    lines: Vec<(usize, u32)>,
+}
+
+impl ExpandedSource {
+    fn remap(&self, diagnostic: &mut Diagnostic) {
+        if let Some(&(file, line)) = diagnostic.line.and_then(|line| self.lines.get((line as usize).wrapping_sub(1))) {
+            diagnostic.file = Some(self.files[file].clone());
+            diagnostic.line = Some(line);
+        }
+    }
+}
+
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+fn parse_include(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start().strip_prefix("include")?.trim();
+    let (open, close) = match rest.chars().next()? {
+        '"' => ('"', '"'),
+        '<' => ('<', '>'),
+        _ => return None,
     };
+    let rest = rest.strip_prefix(open)?;
+    Some(&rest[..rest.find(close)?])
+}
+
+fn is_pragma_once(line: &str) -> bool {
+    let mut words = line.trim_start().trim_start_matches('#').split_whitespace();
+    line.trim_start().starts_with('#') && words.next() == Some("pragma") && words.next() == Some("once")
+}
 
-    let mut e = error.source();
-    while let Some(source) = e {
-        console::log_1(&source.to_string().into());
-        e = source.source();
+// Splits a preprocessor line into its directive name and the rest of the line.
+fn parse_directive(line: &str) -> Option<(&str, &str)> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start();
+    let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+    Some((&rest[..end], rest[end..].trim()))
+}
+
+fn first_identifier(text: &str) -> &str {
+    let end = text.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(text.len());
+    &text[..end]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ExprToken<'a> {
+    Number(i64),
+    Ident(&'a str),
+    Op(&'a str),
+}
+
+fn tokenize_expr(expr: &str) -> Option<Vec<ExprToken<'_>>> {
+    const OPS: [&str; 22] = ["&&", "||", "==", "!=", "<=", ">=", "<<", ">>", "<", ">", "!", "(", ")", "+", "-", "*", "/", "%", "~", "^", "&", "|"];
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+    while !rest.is_empty() && !rest.starts_with("//") {
+        let c = rest.chars().next()?;
+        let len = if c.is_ascii_digit() {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            let digits = rest[..len].trim_end_matches(['u', 'U']);
+            // A leading zero means octal, as in C.
+            let value = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+                Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+                None if digits.len() > 1 && digits.starts_with('0') => i64::from_str_radix(&digits[1..], 8).ok()?,
+                None => digits.parse().ok()?,
+            };
+            tokens.push(ExprToken::Number(value));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let ident = first_identifier(rest);
+            tokens.push(ExprToken::Ident(ident));
+            ident.len()
+        } else {
+            let op = OPS.iter().find(|op| rest.starts_with(*op))?;
+            tokens.push(ExprToken::Op(op));
+            op.len()
+        };
+        rest = rest[len..].trim_start();
     }
+    Some(tokens)
 }
 
-#[wasm_bindgen]
-pub fn glsl_compile(source: &str, stage: &str, validation_enabled: bool) -> String {
-    let naga_stage = match stage {
-        "vertex" => Ok(naga::ShaderStage::Vertex),
-        "fragment" => Ok(naga::ShaderStage::Fragment),
-        "compute" => Ok(naga::ShaderStage::Compute),
-        _ => Err("unknown shader stage")
-    }.unwrap();
+// Evaluates #if expressions well enough to tell which branches are disabled. Returns None
+// for anything it doesn't understand, such as function-like or non-integer macros, and for
+// overflow and division by zero, which naga's preprocessor (pp-rs) rejects.
+struct ExprParser<'a, 'b> {
+    tokens: &'b [ExprToken<'a>],
+    pos: usize,
+    defines: &'b naga::FastHashMap<String, String>,
+}
 
-    let mut parser = naga::front::glsl::Frontend::default();
-    let module = match parser.parse(&naga::front::glsl::Options {
-        stage: naga_stage,
-        defines: Default::default(),
-    }, &source) {
-        Ok(v) => v,
-        Err(errors) => {
-            for e in errors.errors {
-                let location = e.location(source);
-                show_error(&"glsl::parse_str", e, location);
+impl ExprParser<'_, '_> {
+    fn eat(&mut self, op: &str) -> bool {
+        if self.tokens.get(self.pos) == Some(&ExprToken::Op(op)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Option<i64> {
+        const LEVELS: [&[&str]; 10] = [&["||"], &["&&"], &["|"], &["^"], &["&"], &["==", "!="], &["<=", ">=", "<", ">"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(op) = LEVELS[level].iter().find(|op| self.tokens.get(self.pos) == Some(&ExprToken::Op(op))) {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = match *op {
+                "||" => (lhs != 0 || rhs != 0) as i64,
+                "&&" => (lhs != 0 && rhs != 0) as i64,
+                "==" => (lhs == rhs) as i64,
+                "!=" => (lhs != rhs) as i64,
+                "<=" => (lhs <= rhs) as i64,
+                ">=" => (lhs >= rhs) as i64,
+                "<" => (lhs < rhs) as i64,
+                ">" => (lhs > rhs) as i64,
+                "|" => lhs | rhs,
+                "^" => lhs ^ rhs,
+                "&" => lhs & rhs,
+                "<<" => lhs.checked_shl(rhs.try_into().ok()?)?,
+                ">>" => lhs.checked_shr(rhs.try_into().ok()?)?,
+                "+" => lhs.checked_add(rhs)?,
+                "-" => lhs.checked_sub(rhs)?,
+                "*" => lhs.checked_mul(rhs)?,
+                "/" => lhs.checked_div(rhs)?,
+                _ => lhs.checked_rem(rhs)?,
+            };
+        }
+        Some(lhs)
+    }
+
+    fn unary(&mut self) -> Option<i64> {
+        if self.eat("!") {
+            Some((self.unary()? == 0) as i64)
+        } else if self.eat("-") {
+            self.unary()?.checked_neg()
+        } else if self.eat("~") {
+            Some(!self.unary()?)
+        } else if self.eat("+") {
+            self.unary()
+        } else if self.eat("(") {
+            let value = self.binary(0)?;
+            self.eat(")").then_some(value)
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Option<i64> {
+        let token = self.tokens.get(self.pos)?.clone();
+        self.pos += 1;
+        match token {
+            ExprToken::Number(value) => Some(value),
+            ExprToken::Ident("defined") => {
+                let paren = self.eat("(");
+                let name = match self.tokens.get(self.pos)? {
+                    ExprToken::Ident(name) => *name,
+                    _ => return None,
+                };
+                self.pos += 1;
+                if paren && !self.eat(")") {
+                    return None;
+                }
+                Some(self.defines.contains_key(name) as i64)
+            },
+            ExprToken::Ident(name) => match self.defines.get(name) {
+                Some(value) => match tokenize_expr(value)?.as_slice() {
+                    [ExprToken::Number(value)] => Some(*value),
+                    _ => None,
+                },
+                None => Some(0),
+            },
+            ExprToken::Op(_) => None,
+        }
+    }
+}
+
+fn eval_condition(expr: &str, defines: &naga::FastHashMap<String, String>) -> Option<bool> {
+    let tokens = tokenize_expr(expr)?;
+    let mut parser = ExprParser { tokens: &tokens, pos: 0, defines };
+    let value = parser.binary(0)?;
+    (parser.pos == tokens.len()).then_some(value != 0)
+}
+
+struct Conditional {
+    parent_active: bool,
+    active: bool,
+    taken: bool,
+}
+
+struct IncludeExpander<'a> {
+    stage: &'a str,
+    resolver: &'a mut dyn FnMut(&str) -> Option<String>,
+    out: ExpandedSource,
+    stack: Vec<String>,
+    once: Vec<String>,
+    defines: naga::FastHashMap<String, String>,
+    conditionals: Vec<Conditional>,
+}
+
+impl IncludeExpander<'_> {
+    fn active(&self) -> bool {
+        self.conditionals.last().is_none_or(|c| c.active)
+    }
+
+    // Tracks #if nesting so includes in disabled branches are never resolved. A condition
+    // that can't be evaluated keeps every branch of its block active and leaves the choice
+    // to the real preprocessor.
+    fn track_conditional(&mut self, directive: &str, rest: &str) {
+        match directive {
+            "if" | "ifdef" | "ifndef" => {
+                let condition = match directive {
+                    "ifdef" => Some(self.defines.contains_key(first_identifier(rest))),
+                    "ifndef" => Some(!self.defines.contains_key(first_identifier(rest))),
+                    _ => eval_condition(rest, &self.defines),
+                };
+                let parent_active = self.active();
+                self.conditionals.push(Conditional {
+                    parent_active,
+                    active: parent_active && condition.unwrap_or(true),
+                    taken: condition == Some(true),
+                });
+            },
+            "elif" => {
+                let condition = eval_condition(rest, &self.defines);
+                if let Some(c) = self.conditionals.last_mut() {
+                    c.active = c.parent_active && !c.taken && condition.unwrap_or(true);
+                    c.taken |= condition == Some(true);
+                }
+            },
+            "else" => {
+                if let Some(c) = self.conditionals.last_mut() {
+                    c.active = c.parent_active && !c.taken;
+                    c.taken = true;
+                }
+            },
+            "endif" => {
+                self.conditionals.pop();
+            },
+            "define" if self.active() => {
+                let name = first_identifier(rest);
+                self.defines.insert(name.to_string(), rest[name.len()..].trim().to_string());
+            },
+            "undef" if self.active() => {
+                self.defines.remove(first_identifier(rest));
+            },
+            _ => {},
+        }
+    }
+
+    fn expand(&mut self, name: &str, source: &str) -> Result<(), Vec<Diagnostic>> {
+        let file_index = self.out.files.len();
+        self.out.files.push(name.to_string());
+        self.stack.push(name.to_string());
+
+        for (i, line) in source.lines().enumerate() {
+            if let Some((directive, rest)) = parse_directive(line) {
+                self.track_conditional(directive, rest);
             }
 
-            panic!();
-        },
+            if !self.active() && (is_pragma_once(line) || parse_include(line).is_some()) {
+                continue;
+            }
+
+            if is_pragma_once(line) {
+                self.once.push(name.to_string());
+                continue;
+            }
+
+            if let Some(include) = parse_include(line) {
+                if self.once.iter().any(|f| f == include) {
+                    continue;
+                }
+                if self.stack.iter().any(|f| f == include) || self.stack.len() >= MAX_INCLUDE_DEPTH {
+                    let mut d = Diagnostic::simple(self.stage, "include", format!("recursive include of {:?}", include));
+                    d.file = Some(name.to_string());
+                    d.line = Some(i as u32 + 1);
+                    return Err(vec![d]);
+                }
+                let include_source = (self.resolver)(include).ok_or_else(|| {
+                    let mut d = Diagnostic::simple(self.stage, "include", format!("could not resolve include {:?}", include));
+                    d.file = Some(name.to_string());
+                    d.line = Some(i as u32 + 1);
+                    d.span_text = Some(line.to_string());
+                    vec![d]
+                })?;
+                self.expand(include, &include_source)?;
+                continue;
+            }
+
+            self.out.text.push_str(line);
+            self.out.text.push('\n');
+            self.out.lines.push((file_index, i as u32 + 1));
+        }
+
+        self.stack.pop();
+        Ok(())
+    }
+}
+
+// Includes inside #if branches that are disabled by the given defines are skipped.
+pub fn expand_includes(source: &str, stage: &str, defines: &naga::FastHashMap<String, String>, resolver: &mut dyn FnMut(&str) -> Option<String>) -> Result<ExpandedSource, Vec<Diagnostic>> {
+    let mut expander = IncludeExpander {
+        stage,
+        resolver,
+        out: ExpandedSource { text: String::new(), files: Vec::new(), lines: Vec::new() },
+        stack: Vec::new(),
+        once: Vec::new(),
+        defines: defines.clone(),
+        conditionals: Vec::new(),
     };
+    expander.expand("<source>", source)?;
+    Ok(expander.out)
+}
+
+fn parse_and_validate(source: &str, stage: &str, defines: &naga::FastHashMap<String, String>, validation_enabled: bool) -> Result<(naga::Module, naga::valid::ModuleInfo), Vec<Diagnostic>> {
+    let naga_stage = parse_stage(stage)
+        .ok_or_else(|| vec![Diagnostic::simple(stage, "stage", format!("unknown shader stage {:?}", stage))])?;
+
+    let mut parser = naga::front::glsl::Frontend::default();
+    let module = parser.parse(&naga::front::glsl::Options {
+        stage: naga_stage,
+        defines: defines.clone(),
+    }, source).map_err(|errors| {
+        errors.errors.iter()
+            .map(|e| Diagnostic::new(stage, "parse", e, source, e.location(source)))
+            .collect::<Vec<_>>()
+    })?;
 
     let validation_flags = if validation_enabled { naga::valid::ValidationFlags::all() } else { naga::valid::ValidationFlags::empty() };
-    let info = match naga::valid::Validator::new(validation_flags, naga::valid::Capabilities::all()).validate(&module) {
-        Ok(v) => v,
-        Err(e) => {
-            show_error(&"validator", e, None);
-            panic!();
+    let info = naga::valid::Validator::new(validation_flags, naga::valid::Capabilities::all()).validate(&module)
+        .map_err(|e| vec![Diagnostic::new(stage, "validation", e.as_inner(), source, e.location(source))])?;
+
+    Ok((module, info))
+}
+
+fn write_wgsl(module: &naga::Module, info: &naga::valid::ModuleInfo, stage: &str) -> Result<String, Vec<Diagnostic>> {
+    let writer_flags = naga::back::wgsl::WriterFlags::all();
+    naga::back::wgsl::write_string(module, info, writer_flags)
+        .map_err(|e| vec![Diagnostic::new(stage, "wgsl", &e, "", None)])
+}
+
+fn write_spirv(module: &naga::Module, info: &naga::valid::ModuleInfo, stage: &str) -> Result<Vec<u32>, Vec<Diagnostic>> {
+    let options = naga::back::spv::Options {
+        flags: naga::back::spv::WriterFlags::empty(),
+        ..Default::default()
+    };
+    naga::back::spv::write_vec(module, info, &options, None)
+        .map_err(|e| vec![Diagnostic::new(stage, "spirv", &e, "", None)])
+}
+
+fn write_glsl_es(module: &naga::Module, info: &naga::valid::ModuleInfo, stage: &str) -> Result<String, Vec<Diagnostic>> {
+    let entry_point = module.entry_points.first()
+        .ok_or_else(|| vec![Diagnostic::simple(stage, "glsl", "module has no entry point".to_string())])?;
+
+    // Re-emit without coordinate space adjustment, so the output can be diffed against the input.
+    let options = naga::back::glsl::Options {
+        version: naga::back::glsl::Version::Embedded { version: 300, is_webgl: true },
+        writer_flags: naga::back::glsl::WriterFlags::empty(),
+        ..Default::default()
+    };
+    let pipeline_options = naga::back::glsl::PipelineOptions {
+        shader_stage: entry_point.stage,
+        entry_point: entry_point.name.clone(),
+        multiview: None,
+    };
+
+    let mut out = String::new();
+    naga::back::glsl::Writer::new(&mut out, module, info, &options, &pipeline_options, Default::default())
+        .and_then(|mut writer| writer.write())
+        .map_err(|e| vec![Diagnostic::new(stage, "glsl", &e, "", None)])?;
+    Ok(out)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputTarget {
+    Wgsl,
+    SpirV,
+    GlslEs,
+}
+
+impl OutputTarget {
+    pub fn parse(target: &str) -> Option<OutputTarget> {
+        match target {
+            "wgsl" => Some(OutputTarget::Wgsl),
+            "spirv" => Some(OutputTarget::SpirV),
+            "glsl-es" => Some(OutputTarget::GlslEs),
+            _ => None,
         }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ShaderOutput {
+    Text(String),
+    Binary(Vec<u32>),
+}
+
+pub fn compile_to_target(source: &str, stage: &str, defines: &naga::FastHashMap<String, String>, validation_enabled: bool, target: OutputTarget) -> Result<ShaderOutput, Vec<Diagnostic>> {
+    let (module, info) = parse_and_validate(source, stage, defines, validation_enabled)?;
+    match target {
+        OutputTarget::Wgsl => write_wgsl(&module, &info, stage).map(ShaderOutput::Text),
+        OutputTarget::SpirV => write_spirv(&module, &info, stage).map(ShaderOutput::Binary),
+        OutputTarget::GlslEs => write_glsl_es(&module, &info, stage).map(ShaderOutput::Text),
+    }
+}
+
+pub fn compile_with_defines(source: &str, stage: &str, defines: &naga::FastHashMap<String, String>, validation_enabled: bool) -> Result<String, Vec<Diagnostic>> {
+    let (module, info) = parse_and_validate(source, stage, defines, validation_enabled)?;
+    write_wgsl(&module, &info, stage)
+}
+
+pub fn compile_with_reflection(source: &str, stage: &str, defines: &naga::FastHashMap<String, String>, validation_enabled: bool) -> Result<CompiledShader, Vec<Diagnostic>> {
+    let (module, info) = parse_and_validate(source, stage, defines, validation_enabled)?;
+    Ok(CompiledShader {
+        wgsl: write_wgsl(&module, &info, stage)?,
+        reflection: reflect(&module),
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UniformMember {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+    pub ty: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BufferBinding {
+    pub name: String,
+    pub group: u32,
+    pub binding: u32,
+    pub size: u32,
+    pub members: Vec<UniformMember>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TextureBinding {
+    pub name: String,
+    pub group: u32,
+    pub binding: u32,
+    // WebGPU view dimension: "1d", "2d", "2d-array", "cube", "cube-array" or "3d".
+    pub dimension: String,
+    // WebGPU sample type: "float", "sint", "uint" or "depth".
+    pub sample_type: String,
+    pub multisampled: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SamplerBinding {
+    pub name: String,
+    pub group: u32,
+    pub binding: u32,
+    pub comparison: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageVariable {
+    pub name: String,
+    pub location: u32,
+    pub ty: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ShaderReflection {
+    pub uniform_buffers: Vec<BufferBinding>,
+    pub storage_buffers: Vec<BufferBinding>,
+    pub textures: Vec<TextureBinding>,
+    pub samplers: Vec<SamplerBinding>,
+    pub vertex_inputs: Vec<StageVariable>,
+    pub fragment_outputs: Vec<StageVariable>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledShader {
+    pub wgsl: String,
+    pub reflection: ShaderReflection,
+}
+
+fn scalar_name(scalar: naga::Scalar) -> String {
+    match scalar.kind {
+        naga::ScalarKind::Sint => format!("i{}", scalar.width * 8),
+        naga::ScalarKind::Uint => format!("u{}", scalar.width * 8),
+        naga::ScalarKind::Float => format!("f{}", scalar.width * 8),
+        naga::ScalarKind::Bool => "bool".to_string(),
+        naga::ScalarKind::AbstractInt => "abstract-int".to_string(),
+        naga::ScalarKind::AbstractFloat => "abstract-float".to_string(),
+    }
+}
+
+fn type_name(module: &naga::Module, ty: naga::Handle<naga::Type>) -> String {
+    let ty = &module.types[ty];
+    match ty.inner {
+        naga::TypeInner::Scalar(scalar) => scalar_name(scalar),
+        naga::TypeInner::Vector { size, scalar } => format!("vec{}<{}>", size as u8, scalar_name(scalar)),
+        naga::TypeInner::Matrix { columns, rows, scalar } => format!("mat{}x{}<{}>", columns as u8, rows as u8, scalar_name(scalar)),
+        naga::TypeInner::Array { base, size: naga::ArraySize::Constant(n), .. } => format!("array<{}, {}>", type_name(module, base), n),
+        naga::TypeInner::Array { base, size: naga::ArraySize::Dynamic, .. } => format!("array<{}>", type_name(module, base)),
+        _ => ty.name.clone().unwrap_or_else(|| format!("{:?}", ty.inner)),
+    }
+}
+
+fn reflect_buffer(module: &naga::Module, var: &naga::GlobalVariable, binding: &naga::ResourceBinding) -> BufferBinding {
+    let ty = &module.types[var.ty];
+    let members = match &ty.inner {
+        naga::TypeInner::Struct { members, .. } => members.iter().map(|member| UniformMember {
+            name: member.name.clone().unwrap_or_default(),
+            offset: member.offset,
+            size: module.types[member.ty].inner.size(module.to_ctx()),
+            ty: type_name(module, member.ty),
+        }).collect(),
+        _ => Vec::new(),
     };
 
-    let writer_flags = naga::back::wgsl::WriterFlags::all();
-    match naga::back::wgsl::write_string(&module, &info, writer_flags) {
-        Ok(v) => v,
-        Err(e) => {
-            show_error(&"wgsl::write_string", e, None);
-            panic!();
+    BufferBinding {
+        name: var.name.clone().or_else(|| ty.name.clone()).unwrap_or_default(),
+        group: binding.group,
+        binding: binding.binding,
+        size: ty.inner.size(module.to_ctx()),
+        members,
+    }
+}
+
+fn reflect_stage_variables(module: &naga::Module, name: Option<&String>, ty: naga::Handle<naga::Type>, binding: Option<&naga::Binding>, out: &mut Vec<StageVariable>) {
+    match binding {
+        Some(naga::Binding::Location { location, .. }) => out.push(StageVariable {
+            name: name.cloned().unwrap_or_default(),
+            location: *location,
+            ty: type_name(module, ty),
+        }),
+        Some(naga::Binding::BuiltIn(_)) => {},
+        None => {
+            // Structs of IO variables.
+            if let naga::TypeInner::Struct { members, .. } = &module.types[ty].inner {
+                for member in members {
+                    reflect_stage_variables(module, member.name.as_ref(), member.ty, member.binding.as_ref(), out);
+                }
+            }
+        },
+    }
+}
+
+pub fn reflect(module: &naga::Module) -> ShaderReflection {
+    let mut reflection = ShaderReflection::default();
+
+    for (_, var) in module.global_variables.iter() {
+        let binding = match &var.binding {
+            Some(binding) => binding,
+            None => continue,
+        };
+        let name = var.name.clone().unwrap_or_default();
+
+        match var.space {
+            naga::AddressSpace::Uniform => reflection.uniform_buffers.push(reflect_buffer(module, var, binding)),
+            naga::AddressSpace::Storage { .. } => reflection.storage_buffers.push(reflect_buffer(module, var, binding)),
+            naga::AddressSpace::Handle => match module.types[var.ty].inner {
+                naga::TypeInner::Image { dim, arrayed, class } => {
+                    let dimension = match (dim, arrayed) {
+                        (naga::ImageDimension::D1, _) => "1d",
+                        (naga::ImageDimension::D2, false) => "2d",
+                        (naga::ImageDimension::D2, true) => "2d-array",
+                        (naga::ImageDimension::Cube, false) => "cube",
+                        (naga::ImageDimension::Cube, true) => "cube-array",
+                        (naga::ImageDimension::D3, _) => "3d",
+                    };
+                    let (sample_type, multisampled) = match class {
+                        naga::ImageClass::Sampled { kind: naga::ScalarKind::Sint, multi } => ("sint", multi),
+                        naga::ImageClass::Sampled { kind: naga::ScalarKind::Uint, multi } => ("uint", multi),
+                        naga::ImageClass::Sampled { multi, .. } => ("float", multi),
+                        naga::ImageClass::Depth { multi } => ("depth", multi),
+                        naga::ImageClass::Storage { .. } => ("storage", false),
+                    };
+                    reflection.textures.push(TextureBinding {
+                        name,
+                        group: binding.group,
+                        binding: binding.binding,
+                        dimension: dimension.to_string(),
+                        sample_type: sample_type.to_string(),
+                        multisampled,
+                    });
+                },
+                naga::TypeInner::Sampler { comparison } => reflection.samplers.push(SamplerBinding {
+                    name,
+                    group: binding.group,
+                    binding: binding.binding,
+                    comparison,
+                }),
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+
+    for entry_point in module.entry_points.iter() {
+        let function = &entry_point.function;
+        match entry_point.stage {
+            naga::ShaderStage::Vertex => {
+                for arg in function.arguments.iter() {
+                    reflect_stage_variables(module, arg.name.as_ref(), arg.ty, arg.binding.as_ref(), &mut reflection.vertex_inputs);
+                }
+            },
+            naga::ShaderStage::Fragment => {
+                if let Some(result) = &function.result {
+                    reflect_stage_variables(module, None, result.ty, result.binding.as_ref(), &mut reflection.fragment_outputs);
+                }
+            },
+            naga::ShaderStage::Compute => {},
+        }
+    }
+
+    reflection.uniform_buffers.sort_by_key(|b| (b.group, b.binding));
+    reflection.storage_buffers.sort_by_key(|b| (b.group, b.binding));
+    reflection.textures.sort_by_key(|b| (b.group, b.binding));
+    reflection.samplers.sort_by_key(|b| (b.group, b.binding));
+    reflection.vertex_inputs.sort_by_key(|v| v.location);
+    reflection.fragment_outputs.sort_by_key(|v| v.location);
+    reflection
+}
+
+pub fn compile(source: &str, stage: &str, validation_enabled: bool) -> Result<String, Vec<Diagnostic>> {
+    compile_with_defines(source, stage, &Default::default(), validation_enabled)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    source: String,
+    stage: String,
+    // Sorted, so the key doesn't depend on map iteration order.
+    defines: Vec<(String, String)>,
+    validation_enabled: bool,
+}
+
+impl CacheKey {
+    fn new(source: &str, stage: &str, defines: &naga::FastHashMap<String, String>, validation_enabled: bool) -> Self {
+        let mut defines: Vec<(String, String)> = defines.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        defines.sort();
+        CacheKey { source: source.to_string(), stage: stage.to_string(), defines, validation_enabled }
+    }
+}
+
+// Compiles shader permutations from one source using #defines and #includes, caching
+// the resulting WGSL by (source with includes expanded, stage, defines, validation).
+#[derive(Default)]
+pub struct ShaderCompiler {
+    pub validation_enabled: bool,
+    files: HashMap<String, String>,
+    cache: HashMap<CacheKey, CompiledShader>,
+}
+
+impl ShaderCompiler {
+    pub fn add_file(&mut self, name: &str, source: &str) {
+        self.files.insert(name.to_string(), source.to_string());
+        // Anything that included the old version can't be looked up again.
+        self.cache.clear();
+    }
+
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+
+    pub fn cache_size(&self) -> usize {
+        self.cache.len()
+    }
+
+    // Includes are looked up in the virtual file map first, then passed to the fallback resolver.
+    pub fn compile_with_reflection(&mut self, source: &str, stage: &str, defines: &naga::FastHashMap<String, String>, fallback: &mut dyn FnMut(&str) -> Option<String>) -> Result<&CompiledShader, Vec<Diagnostic>> {
+        // The fallback can return something different each time, so the key is the expanded
+        // source rather than the source itself.
+        let files = &self.files;
+        let mut resolver = |name: &str| files.get(name).cloned().or_else(|| fallback(name));
+        let expanded = expand_includes(source, stage, defines, &mut resolver)?;
+        let key = CacheKey::new(&expanded.text, stage, defines, self.validation_enabled);
+        if !self.cache.contains_key(&key) {
+            let compiled = compile_with_reflection(&expanded.text, stage, defines, self.validation_enabled)
+                .map_err(|mut diagnostics| {
+                    diagnostics.iter_mut().for_each(|d| expanded.remap(d));
+                    diagnostics
+                })?;
+            self.cache.insert(key.clone(), compiled);
         }
+        Ok(&self.cache[&key])
+    }
+
+    pub fn compile(&mut self, source: &str, stage: &str, defines: &naga::FastHashMap<String, String>, fallback: &mut dyn FnMut(&str) -> Option<String>) -> Result<String, Vec<Diagnostic>> {
+        self.compile_with_reflection(source, stage, defines, fallback).map(|compiled| compiled.wgsl.clone())
+    }
+}
+
+fn diagnostics_to_js(diagnostics: Vec<Diagnostic>) -> JsValue {
+    serde_wasm_bindgen::to_value(&diagnostics).unwrap()
+}
+
+// Undefined or null means no defines.
+fn defines_from_js(defines: JsValue) -> Result<naga::FastHashMap<String, String>, JsValue> {
+    if defines.is_undefined() || defines.is_null() {
+        return Ok(Default::default());
+    }
+    let defines: HashMap<String, String> = serde_wasm_bindgen::from_value(defines)?;
+    Ok(defines.into_iter().collect())
+}
+
+// On failure, throws an array of diagnostics to JS.
+#[wasm_bindgen]
+pub fn glsl_compile(source: &str, stage: &str, validation_enabled: bool) -> Result<String, JsValue> {
+    compile(source, stage, validation_enabled).map_err(diagnostics_to_js)
+}
+
+// target is one of "wgsl", "spirv" or "glsl-es", and defines is as for JsShaderCompiler.compile.
+// Returns a string for text targets, and a Uint32Array of SPIR-V words for "spirv". On
+// failure, throws an array of diagnostics.
+#[wasm_bindgen]
+pub fn glsl_compile_target(source: &str, stage: &str, target: &str, defines: JsValue, validation_enabled: bool) -> Result<JsValue, JsValue> {
+    let output_target = OutputTarget::parse(target)
+        .ok_or_else(|| diagnostics_to_js(vec![Diagnostic::simple(stage, "target", format!("unknown output target {:?}", target))]))?;
+    let defines = defines_from_js(defines)?;
+    match compile_to_target(source, stage, &defines, validation_enabled, output_target).map_err(diagnostics_to_js)? {
+        ShaderOutput::Text(text) => Ok(JsValue::from_str(&text)),
+        ShaderOutput::Binary(words) => Ok(js_sys::Uint32Array::from(&words[..]).into()),
+    }
+}
+
+// Returns the resource bindings and stage inputs/outputs of a shader, for building bind
+// group layouts. On failure, throws an array of diagnostics.
+#[wasm_bindgen]
+pub fn glsl_reflect(source: &str, stage: &str) -> Result<JsValue, JsValue> {
+    let compiled = compile_with_reflection(source, stage, &Default::default(), false).map_err(diagnostics_to_js)?;
+    Ok(serde_wasm_bindgen::to_value(&compiled.reflection)?)
+}
+
+#[wasm_bindgen(js_name = "GlslCompiler")]
+pub struct JsShaderCompiler {
+    compiler: ShaderCompiler,
+    include_callback: Option<js_sys::Function>,
+}
+
+#[wasm_bindgen(js_class = "GlslCompiler")]
+impl JsShaderCompiler {
+    #[wasm_bindgen(constructor)]
+    pub fn new(validation_enabled: bool) -> Self {
+        JsShaderCompiler {
+            compiler: ShaderCompiler { validation_enabled, ..Default::default() },
+            include_callback: None,
+        }
+    }
+
+    pub fn add_file(&mut self, name: &str, source: &str) {
+        self.compiler.add_file(name, source);
+    }
+
+    // Called with the include name for files not added with add_file(); should return
+    // the file's source, or null if it does not exist.
+    pub fn set_include_callback(&mut self, callback: js_sys::Function) {
+        self.include_callback = Some(callback);
+        self.compiler.clear_cache();
+    }
+
+    pub fn clear_cache(&mut self) {
+        self.compiler.clear_cache();
+    }
+
+    pub fn cache_size(&self) -> usize {
+        self.compiler.cache_size()
+    }
+
+    // defines is an object mapping define names to string values, or undefined.
+    // On failure, throws an array of diagnostics.
+    pub fn compile(&mut self, source: &str, stage: &str, defines: JsValue) -> Result<String, JsValue> {
+        Ok(self.compile_internal(source, stage, defines)?.wgsl.clone())
+    }
+
+    pub fn reflect(&mut self, source: &str, stage: &str, defines: JsValue) -> Result<JsValue, JsValue> {
+        let reflection = &self.compile_internal(source, stage, defines)?.reflection;
+        Ok(serde_wasm_bindgen::to_value(reflection)?)
+    }
+}
+
+impl JsShaderCompiler {
+    fn compile_internal(&mut self, source: &str, stage: &str, defines: JsValue) -> Result<&CompiledShader, JsValue> {
+        let defines = defines_from_js(defines)?;
+
+        let callback = self.include_callback.as_ref();
+        let mut fallback = |name: &str| {
+            callback?.call1(&JsValue::NULL, &JsValue::from_str(name)).ok()?.as_string()
+        };
+        self.compiler.compile_with_reflection(source, stage, &defines, &mut fallback).map_err(diagnostics_to_js)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile() {
+        let source = "#version 450\nlayout(location = 0) out vec4 o_Color;\nvoid main() { o_Color = vec4(1.0); }\n";
+        assert!(compile(source, "fragment", true).is_ok());
+    }
+
+    #[test]
+    fn test_output_targets() {
+        let source = "#version 450\nlayout(location = 0) in vec2 v_TexCoord;\nlayout(location = 0) out vec4 o_Color;\nvoid main() { o_Color = vec4(v_TexCoord, 0.0, 1.0); }\n";
+        let defines = Default::default();
+
+        match compile_to_target(source, "fragment", &defines, true, OutputTarget::SpirV).unwrap() {
+            ShaderOutput::Binary(words) => assert_eq!(words[0], 0x07230203),
+            ShaderOutput::Text(_) => panic!("expected SPIR-V binary"),
+        }
+
+        match compile_to_target(source, "fragment", &defines, true, OutputTarget::GlslEs).unwrap() {
+            ShaderOutput::Text(glsl) => assert!(glsl.starts_with("#version 300 es")),
+            ShaderOutput::Binary(_) => panic!("expected GLSL text"),
+        }
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let source = "#version 450\nvoid main() {\n    float x = ;\n}\n";
+        let diagnostics = compile(source, "fragment", true).unwrap_err();
+        assert_eq!(diagnostics[0].place, "parse");
+        assert_eq!(diagnostics[0].line, Some(3));
+    }
+
+    #[test]
+    fn test_defines_and_includes() {
+        let mut compiler = ShaderCompiler::default();
+        compiler.add_file("common.glsl", "#pragma once\nvec4 get_color() { return vec4(COLOR); }\n");
+        let source = "#version 450\n#include \"common.glsl\"\n#include \"common.glsl\"\nlayout(location = 0) out vec4 o_Color;\nvoid main() { o_Color = get_color(); }\n";
+
+        let mut defines = naga::FastHashMap::default();
+        defines.insert("COLOR".to_string(), "0.5".to_string());
+        let wgsl = compiler.compile(source, "fragment", &defines, &mut |_| None).unwrap();
+        assert!(wgsl.contains("0.5"));
+        compiler.compile(source, "fragment", &defines, &mut |_| None).unwrap();
+        assert_eq!(compiler.cache_size(), 1);
+
+        // Errors inside includes are reported against the included file.
+        compiler.add_file("broken.glsl", "void broken() {\n    float x = ;\n}\n");
+        let source = "#version 450\n#include <broken.glsl>\nvoid main() {}\n";
+        let diagnostics = compiler.compile(source, "fragment", &defines, &mut |_| None).unwrap_err();
+        assert_eq!(diagnostics[0].file.as_deref(), Some("broken.glsl"));
+        assert_eq!(diagnostics[0].line, Some(2));
+
+        let diagnostics = compiler.compile("#include \"missing.glsl\"\n", "fragment", &defines, &mut |_| None).unwrap_err();
+        assert_eq!(diagnostics[0].place, "include");
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let mut compiler = ShaderCompiler::default();
+        let source = "#version 450\nlayout(location = 0) out vec4 o_Color;\nvoid main() { o_Color = vec4(1.0); }\n";
+        let defines = naga::FastHashMap::default();
+        compiler.compile(source, "fragment", &defines, &mut |_| None).unwrap();
+        compiler.compile(source, "fragment", &defines, &mut |_| None).unwrap();
+        assert_eq!(compiler.cache_size(), 1);
+
+        // Toggling validation must not return a module compiled under the other setting.
+        compiler.validation_enabled = true;
+        compiler.compile(source, "fragment", &defines, &mut |_| None).unwrap();
+        assert_eq!(compiler.cache_size(), 2);
+
+        // Includes from the fallback resolver are part of the key too.
+        let source = "#version 450\n#include \"color.glsl\"\nlayout(location = 0) out vec4 o_Color;\nvoid main() { o_Color = COLOR; }\n";
+        let wgsl = compiler.compile(source, "fragment", &defines, &mut |_| Some("#define COLOR vec4(0.25)".to_string())).unwrap();
+        assert!(wgsl.contains("0.25"));
+        let wgsl = compiler.compile(source, "fragment", &defines, &mut |_| Some("#define COLOR vec4(0.75)".to_string())).unwrap();
+        assert!(wgsl.contains("0.75"));
+        compiler.compile(source, "fragment", &defines, &mut |_| Some("#define COLOR vec4(0.25)".to_string())).unwrap();
+        assert_eq!(compiler.cache_size(), 4);
+    }
+
+    #[test]
+    fn test_conditional_includes() {
+        let mut compiler = ShaderCompiler::default();
+        compiler.add_file("color.glsl", "vec4 get_color() { return vec4(0.25); }\n");
+        let source = "#version 450
+#if defined(USE_MISSING) && MISSING_VERSION >= 2
+#include \"missing.glsl\"
+#elif USE_COLOR
+#include \"color.glsl\"
+#else
+#define NO_COLOR
+vec4 get_color() { return vec4(1.0); }
+#endif
+#ifndef NO_COLOR
+#include \"missing.glsl\"
+#endif
+layout(location = 0) out vec4 o_Color;
+void main() { o_Color = get_color(); }
+";
+        let mut resolved = Vec::new();
+        let mut fallback = |name: &str| { resolved.push(name.to_string()); None };
+        let wgsl = compiler.compile(source, "fragment", &Default::default(), &mut fallback).unwrap();
+        assert!(wgsl.contains("1f"));
+        assert!(resolved.is_empty());
+
+        let mut defines = naga::FastHashMap::default();
+        defines.insert("USE_COLOR".to_string(), "1".to_string());
+        defines.insert("NO_COLOR".to_string(), String::new());
+        let wgsl = compiler.compile(source, "fragment", &defines, &mut |_| None).unwrap();
+        assert!(wgsl.contains("0.25"));
+
+        // Conditions that can't be evaluated leave the include to be resolved.
+        defines.insert("USE_MISSING".to_string(), String::new());
+        defines.insert("MISSING_VERSION".to_string(), "VERSION_TWO".to_string());
+        let diagnostics = compiler.compile(source, "fragment", &defines, &mut |_| None).unwrap_err();
+        assert_eq!((diagnostics[0].place.as_str(), diagnostics[0].line), ("include", Some(3)));
+
+        assert_eq!(eval_condition("!defined(A) || (B + 1) * 2 == 6 // comment", &defines), Some(true));
+        assert_eq!(eval_condition("0x10 > 15u && !UNDEFINED", &defines), Some(true));
+        assert_eq!(eval_condition("FOO(1)", &defines), None);
+    }
+
+    // Which branch naga's own preprocessor takes for an #if, or None if it rejects the condition.
+    fn naga_condition(expr: &str, defines: &naga::FastHashMap<String, String>) -> Option<bool> {
+        let source = format!("#version 450
+layout(location = 0) out vec4 o_Color;
+void main() {{
+#if {}
+    o_Color = vec4(0.25);
+#else
+    o_Color = vec4(0.75);
+#endif
+}}
+", expr);
+        let wgsl = compile_with_defines(&source, "fragment", defines, false).ok()?;
+        Some(wgsl.contains("0.25"))
+    }
+
+    #[test]
+    fn test_eval_condition_matches_naga() {
+        let mut defines = naga::FastHashMap::default();
+        defines.insert("ONE".to_string(), "1".to_string());
+        defines.insert("TWO".to_string(), "0x2".to_string());
+        defines.insert("EMPTY".to_string(), String::new());
+
+        let exprs = [
+            // defined, with and without parentheses.
+            "defined ONE", "defined UNDEFINED", "!defined ONE || defined EMPTY", "defined(TWO) && defined EMPTY",
+            // Unknown identifiers are zero.
+            "UNDEFINED", "!UNDEFINED", "UNDEFINED == 0", "ONE + UNDEFINED * 3 == 1",
+            // Overflow and division by zero are errors, not wrapped.
+            "0x7FFFFFFFFFFFFFFF + 1 > 0", "-0x7FFFFFFFFFFFFFFF - 2 < 0", "0x4000000000000000 * 2 != 0", "ONE / 0", "TWO % UNDEFINED",
+            "1 << 64", "0x7FFFFFFFFFFFFFFF + 0 > 0", "-0x7FFFFFFFFFFFFFFF - 1 < 0",
+            // Octal, bitwise operators and their precedence.
+            "010 == 8", "(1 << 3 | ONE) ^ TWO == 11", "~0 == -1", "TWO >> 1 & 1", "1 | 2 == 2", "6 & 3 ^ 1",
+            "3 * TWO + ONE == 7", "15u >= 0xF", "-ONE < +ONE",
+        ];
+        for expr in exprs {
+            assert_eq!(eval_condition(expr, &defines), naga_condition(expr, &defines), "{}", expr);
+        }
+
+        // Things naga rejects that the evaluator can't tell either.
+        for expr in ["EMPTY", "defined", "(ONE", "ONE ONE"] {
+            assert_eq!((eval_condition(expr, &defines), naga_condition(expr, &defines)), (None, None), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_reflect() {
+        let source = "#version 450
+layout(std140, set = 0, binding = 0) uniform ub_SceneParams {
+    mat4 u_Projection;
+    vec4 u_Misc;
+};
+layout(set = 1, binding = 0) uniform texture2D u_Texture;
+layout(set = 1, binding = 1) uniform sampler u_Sampler;
+layout(location = 0) in vec3 a_Position;
+layout(location = 1) in vec2 a_TexCoord;
+layout(location = 0) out vec2 v_TexCoord;
+void main() {
+    v_TexCoord = a_TexCoord + textureLod(sampler2D(u_Texture, u_Sampler), a_TexCoord, 0.0).xy;
+    gl_Position = u_Projection * vec4(a_Position, 1.0) + u_Misc;
+}
+";
+        let compiled = compile_with_reflection(source, "vertex", &Default::default(), true).unwrap();
+        let reflection = compiled.reflection;
+
+        let ub = &reflection.uniform_buffers[0];
+        assert_eq!((ub.group, ub.binding, ub.size), (0, 0, 80));
+        assert_eq!(ub.members[1].name, "u_Misc");
+        assert_eq!((ub.members[1].offset, ub.members[1].size), (64, 16));
+        assert_eq!(ub.members[0].ty, "mat4x4<f32>");
+
+        assert_eq!(reflection.textures[0].dimension, "2d");
+        assert_eq!(reflection.textures[0].sample_type, "float");
+        assert_eq!(reflection.samplers[0].binding, 1);
+
+        let inputs: Vec<(u32, &str)> = reflection.vertex_inputs.iter().map(|v| (v.location, v.ty.as_str())).collect();
+        assert_eq!(inputs, vec![(0, "vec3<f32>"), (1, "vec2<f32>")]);
     }
 }
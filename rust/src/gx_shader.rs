@@ -0,0 +1,2249 @@
+use naga::{BinaryOperator as Bo, Expression as E, Handle, MathFunction as Mf, Span, Statement, TypeInner as Ti};
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+// Generates WGSL for GX materials by building naga IR directly, rather than going through
+// GLSL text. The bindings and uniform block layouts match GX_Program in gx_material.ts, so
+// the same uniform buffer filling code works for both.
+
+// GX enum values, from gx_enum.ts.
+mod cc {
+    pub const CPREV: u32 = 0; pub const APREV: u32 = 1; pub const C0: u32 = 2; pub const A0: u32 = 3;
+    pub const C1: u32 = 4; pub const A1: u32 = 5; pub const C2: u32 = 6; pub const A2: u32 = 7;
+    pub const TEXC: u32 = 8; pub const TEXA: u32 = 9; pub const RASC: u32 = 10; pub const RASA: u32 = 11;
+    pub const ONE: u32 = 12; pub const HALF: u32 = 13; pub const KONST: u32 = 14; pub const ZERO: u32 = 15;
+}
+
+mod ca {
+    pub const APREV: u32 = 0; pub const A0: u32 = 1; pub const A1: u32 = 2; pub const A2: u32 = 3;
+    pub const TEXA: u32 = 4; pub const RASA: u32 = 5; pub const KONST: u32 = 6; pub const ZERO: u32 = 7;
+}
+
+mod tev_op {
+    pub const ADD: u32 = 0; pub const SUB: u32 = 1;
+    pub const COMP_R8_GT: u32 = 8; pub const COMP_R8_EQ: u32 = 9;
+    pub const COMP_GR16_GT: u32 = 10; pub const COMP_GR16_EQ: u32 = 11;
+    pub const COMP_BGR24_GT: u32 = 12; pub const COMP_BGR24_EQ: u32 = 13;
+    pub const COMP_RGB8_GT: u32 = 14; pub const COMP_RGB8_EQ: u32 = 15;
+}
+
+mod tev_bias {
+    pub const ADDHALF: u32 = 1; pub const SUBHALF: u32 = 2;
+}
+
+mod tev_scale {
+    pub const SCALE_2: u32 = 1; pub const SCALE_4: u32 = 2; pub const DIVIDE_2: u32 = 3;
+}
+
+mod ras_channel {
+    pub const COLOR0A0: u32 = 0; pub const COLOR1A1: u32 = 1; pub const ALPHA_BUMP: u32 = 5;
+    pub const ALPHA_BUMP_N: u32 = 6; pub const COLOR_ZERO: u32 = 7;
+}
+
+mod ind_tex {
+    // IndTexFormat
+    pub const FORMAT_8: u32 = 0; pub const FORMAT_5: u32 = 1; pub const FORMAT_4: u32 = 2; pub const FORMAT_3: u32 = 3;
+    // IndTexAlphaSel
+    pub const ALPHA_S: u32 = 1; pub const ALPHA_T: u32 = 2; pub const ALPHA_U: u32 = 3;
+    // IndTexMtxID
+    pub const MTX_OFF: u32 = 0; pub const MTX_0: u32 = 1; pub const MTX_2: u32 = 3;
+    // IndTexWrap
+    pub const WRAP_OFF: u32 = 0; pub const WRAP_0: u32 = 6;
+}
+
+mod compare {
+    pub const NEVER: u32 = 0; pub const LESS: u32 = 1; pub const EQUAL: u32 = 2; pub const LEQUAL: u32 = 3;
+    pub const GREATER: u32 = 4; pub const NEQUAL: u32 = 5; pub const GEQUAL: u32 = 6; pub const ALWAYS: u32 = 7;
+}
+
+mod alpha_op {
+    pub const AND: u32 = 0; pub const OR: u32 = 1; pub const XOR: u32 = 2; pub const XNOR: u32 = 3;
+}
+
+mod fog_type {
+    pub const NONE: u32 = 0x00; pub const LIN: u32 = 0x02; pub const EXP: u32 = 0x04; pub const EXP2: u32 = 0x05;
+    pub const REVEXP: u32 = 0x06; pub const REVEXP2: u32 = 0x07;
+}
+
+mod color_src {
+    pub const VTX: u32 = 1;
+}
+
+mod diffuse_fn {
+    pub const NONE: u32 = 0; pub const SIGN: u32 = 1; pub const CLAMP: u32 = 2;
+}
+
+mod atten_fn {
+    pub const SPEC: u32 = 0; pub const SPOT: u32 = 1; pub const NONE: u32 = 2;
+}
+
+mod tex_gen {
+    // TexGenType
+    pub const MTX3X4: u32 = 0; pub const MTX2X4: u32 = 1; pub const SRTG: u32 = 10;
+    // TexGenSrc
+    pub const SRC_POS: u32 = 0; pub const SRC_NRM: u32 = 1; pub const SRC_BINRM: u32 = 2; pub const SRC_TANGENT: u32 = 3;
+    pub const SRC_TEX0: u32 = 4; pub const SRC_TEX7: u32 = 11; pub const SRC_TEXCOORD0: u32 = 12; pub const SRC_TEXCOORD6: u32 = 18;
+    pub const SRC_COLOR0: u32 = 19; pub const SRC_COLOR1: u32 = 20;
+    // TexGenMatrix
+    pub const PNMTX0: u32 = 0; pub const TEXMTX0: u32 = 30; pub const IDENTITY: u32 = 60;
+    // PostTexGenMatrix
+    pub const PTTEXMTX0: u32 = 64; pub const PTIDENTITY: u32 = 125;
+}
+
+const TEXMAP_NULL: u32 = 0xFF;
+const TEXCOORD_NULL: u32 = 0xFF;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ColorChannelControl {
+    pub lighting_enabled: bool,
+    pub mat_color_source: u32,
+    pub amb_color_source: u32,
+    pub lit_mask: u32,
+    pub diffuse_function: u32,
+    pub attenuation_function: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LightChannelControl {
+    pub color_channel: ColorChannelControl,
+    pub alpha_channel: ColorChannelControl,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TexGen {
+    #[serde(rename = "type")]
+    pub type_: u32,
+    pub source: u32,
+    pub matrix: u32,
+    pub normalize: bool,
+    pub post_matrix: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndTexStage {
+    pub tex_coord_id: u32,
+    pub texture: u32,
+    pub scale_s: u32,
+    pub scale_t: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TevStage {
+    pub color_in_a: u32,
+    pub color_in_b: u32,
+    pub color_in_c: u32,
+    pub color_in_d: u32,
+    pub color_op: u32,
+    pub color_bias: u32,
+    pub color_scale: u32,
+    pub color_clamp: bool,
+    pub color_reg_id: u32,
+
+    pub alpha_in_a: u32,
+    pub alpha_in_b: u32,
+    pub alpha_in_c: u32,
+    pub alpha_in_d: u32,
+    pub alpha_op: u32,
+    pub alpha_bias: u32,
+    pub alpha_scale: u32,
+    pub alpha_clamp: bool,
+    pub alpha_reg_id: u32,
+
+    pub tex_coord_id: u32,
+    pub tex_map: u32,
+    pub channel_id: u32,
+
+    pub konst_color_sel: u32,
+    pub konst_alpha_sel: u32,
+
+    #[serde(default)]
+    pub ras_swap_table: Option<[u32; 4]>,
+    #[serde(default)]
+    pub tex_swap_table: Option<[u32; 4]>,
+
+    pub ind_tex_stage: u32,
+    pub ind_tex_format: u32,
+    pub ind_tex_bias_sel: u32,
+    pub ind_tex_alpha_sel: u32,
+    pub ind_tex_matrix: u32,
+    pub ind_tex_wrap_s: u32,
+    pub ind_tex_wrap_t: u32,
+    pub ind_tex_add_prev: bool,
+    pub ind_tex_use_orig_lod: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlphaTest {
+    pub op: u32,
+    pub compare_a: u32,
+    pub reference_a: f32,
+    pub compare_b: u32,
+    pub reference_b: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RopInfo {
+    pub fog_type: u32,
+    #[serde(default)]
+    pub dst_alpha: Option<f32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GxMaterial {
+    #[serde(default)]
+    pub name: String,
+    pub light_channels: Vec<LightChannelControl>,
+    pub tex_gens: Vec<TexGen>,
+    pub tev_stages: Vec<TevStage>,
+    pub ind_tex_stages: Vec<IndTexStage>,
+    pub alpha_test: AlphaTest,
+    pub rop_info: RopInfo,
+
+    // Optional parameters, with the same defaults as gx_material.ts.
+    #[serde(default)]
+    pub use_pn_mtx_idx: Option<bool>,
+    #[serde(default)]
+    pub use_tex_mtx_idx: Option<Vec<bool>>,
+    #[serde(default)]
+    pub has_post_tex_mtx_block: Option<bool>,
+    #[serde(default)]
+    pub has_lights_block: Option<bool>,
+    #[serde(default)]
+    pub has_fog_block: Option<bool>,
+    #[serde(default)]
+    pub has_dynamic_alpha_test: Option<bool>,
+}
+
+impl GxMaterial {
+    fn use_pn_mtx_idx(&self) -> bool { self.use_pn_mtx_idx.unwrap_or(true) }
+    fn has_post_tex_mtx_block(&self) -> bool { self.has_post_tex_mtx_block.unwrap_or(true) }
+    fn has_lights_block(&self) -> bool { self.has_lights_block.unwrap_or(true) }
+    fn has_fog_block(&self) -> bool { self.has_fog_block.unwrap_or(false) }
+    fn has_dynamic_alpha_test(&self) -> bool { self.has_dynamic_alpha_test.unwrap_or(false) }
+
+    fn use_tex_mtx_idx(&self, i: usize) -> bool {
+        self.use_tex_mtx_idx.as_ref().is_some_and(|v| v.get(i).copied().unwrap_or(false))
+    }
+}
+
+// Vertex attribute locations, in the order of vtxAttributeGenDefs.
+const ATTR_POS: u32 = 0;
+const ATTR_TEX0123MTXIDX: u32 = 1;
+const ATTR_TEX4567MTXIDX: u32 = 2;
+const ATTR_NRM: u32 = 3;
+const ATTR_BINRM: u32 = 4;
+const ATTR_TANGENT: u32 = 5;
+const ATTR_CLR0: u32 = 6;
+const ATTR_TEX01: u32 = 8;
+const ATTR_COUNT: usize = 12;
+
+// Binding numbers as assigned by GfxShaderCompiler: each texture takes two bindings
+// (texture, sampler), and uniform buffers follow.
+const TEXTURE_COUNT: u32 = 8;
+const UB_SCENE_PARAMS: u32 = TEXTURE_COUNT * 2;
+
+#[derive(Clone, Copy)]
+struct Types {
+    vec2: Handle<naga::Type>,
+    vec3: Handle<naga::Type>,
+    vec4: Handle<naga::Type>,
+}
+
+impl Types {
+    fn vec(&self, size: naga::VectorSize) -> Handle<naga::Type> {
+        match size {
+            naga::VectorSize::Bi => self.vec2,
+            naga::VectorSize::Tri => self.vec3,
+            naga::VectorSize::Quad => self.vec4,
+        }
+    }
+}
+
+// Member indices into ub_MaterialParams. The optional blocks shift later members.
+struct MaterialLayout {
+    color_mat_reg: u32,
+    color_amb_reg: u32,
+    konst_color: u32,
+    color: u32,
+    tex_mtx: u32,
+    texture_sizes: u32,
+    texture_biases: u32,
+    ind_tex_mtx: u32,
+    post_tex_mtx: Option<u32>,
+    light_params: Option<u32>,
+    fog_block: Option<u32>,
+    dynamic_alpha_params: Option<u32>,
+}
+
+struct Globals {
+    scene_params: Handle<naga::GlobalVariable>,
+    material_params: Handle<naga::GlobalVariable>,
+    draw_params: Handle<naga::GlobalVariable>,
+    textures: Vec<(Handle<naga::GlobalVariable>, Handle<naga::GlobalVariable>)>,
+    layout: MaterialLayout,
+    vertex_output: Handle<naga::Type>,
+    fragment_output: Handle<naga::Type>,
+}
+
+fn add_type(module: &mut naga::Module, name: Option<&str>, inner: Ti) -> Handle<naga::Type> {
+    module.types.insert(naga::Type { name: name.map(|s| s.to_string()), inner }, Span::UNDEFINED)
+}
+
+fn type_size_align(module: &naga::Module, ty: Handle<naga::Type>) -> (u32, u32) {
+    match module.types[ty].inner {
+        Ti::Scalar(scalar) => (scalar.width as u32, scalar.width as u32),
+        Ti::Vector { size, scalar } => {
+            let size = size as u32 * scalar.width as u32;
+            (size, if size == 8 { 8 } else { 16 })
+        },
+        Ti::Struct { span, .. } => (span, 16),
+        Ti::Array { size: naga::ArraySize::Constant(count), stride, .. } => (count.get() * stride, 16),
+        _ => unreachable!(),
+    }
+}
+
+fn add_struct(module: &mut naga::Module, name: &str, members: &[(&str, Handle<naga::Type>, Option<naga::Binding>)]) -> Handle<naga::Type> {
+    let mut offset = 0;
+    let mut struct_members = Vec::new();
+    for (member_name, ty, binding) in members.iter() {
+        let (size, align) = type_size_align(module, *ty);
+        offset = (offset + align - 1) & !(align - 1);
+        struct_members.push(naga::StructMember { name: Some(member_name.to_string()), ty: *ty, binding: binding.clone(), offset });
+        offset += size;
+    }
+    let span = (offset + 15) & !15;
+    add_type(module, Some(name), Ti::Struct { members: struct_members, span })
+}
+
+fn add_array(module: &mut naga::Module, base: Handle<naga::Type>, count: u32) -> Handle<naga::Type> {
+    let stride = type_size_align(module, base).0;
+    let size = naga::ArraySize::Constant(std::num::NonZeroU32::new(count).unwrap());
+    add_type(module, None, Ti::Array { base, size, stride })
+}
+
+fn add_uniform(module: &mut naga::Module, name: &str, ty: Handle<naga::Type>, binding: u32) -> Handle<naga::GlobalVariable> {
+    module.global_variables.append(naga::GlobalVariable {
+        name: Some(name.to_string()),
+        space: naga::AddressSpace::Uniform,
+        binding: Some(naga::ResourceBinding { group: 0, binding }),
+        ty,
+        init: None,
+    }, Span::UNDEFINED)
+}
+
+fn varying(location: u32) -> Option<naga::Binding> {
+    Some(naga::Binding::Location {
+        location,
+        second_blend_source: false,
+        interpolation: Some(naga::Interpolation::Perspective),
+        sampling: Some(naga::Sampling::Center),
+    })
+}
+
+fn location(location: u32) -> Option<naga::Binding> {
+    Some(naga::Binding::Location { location, second_blend_source: false, interpolation: None, sampling: None })
+}
+
+fn tex_coord_is_2d(tex_gen: &TexGen) -> bool {
+    tex_gen.type_ == tex_gen::MTX2X4 || tex_gen.type_ == tex_gen::SRTG
+}
+
+fn create_module(material: &GxMaterial) -> (naga::Module, Types, Globals) {
+    let mut module = naga::Module::default();
+
+    let scalar = naga::Scalar::F32;
+    let types = Types {
+        vec2: add_type(&mut module, None, Ti::Vector { size: naga::VectorSize::Bi, scalar }),
+        vec3: add_type(&mut module, None, Ti::Vector { size: naga::VectorSize::Tri, scalar }),
+        vec4: add_type(&mut module, None, Ti::Vector { size: naga::VectorSize::Quad, scalar }),
+    };
+    let vec4 = types.vec4;
+
+    let mat2x4 = add_struct(&mut module, "Mat2x4", &[("mx", vec4, None), ("my", vec4, None)]);
+    let mat3x4 = add_struct(&mut module, "Mat3x4", &[("mx", vec4, None), ("my", vec4, None), ("mz", vec4, None)]);
+    let mat4x4 = add_struct(&mut module, "Mat4x4", &[("mx", vec4, None), ("my", vec4, None), ("mz", vec4, None), ("mw", vec4, None)]);
+
+    let scene_params_ty = add_struct(&mut module, "ub_SceneParams", &[("u_Projection", mat4x4, None), ("u_Misc0", vec4, None)]);
+
+    let vec4_array2 = add_array(&mut module, vec4, 2);
+    let vec4_array3 = add_array(&mut module, vec4, 3);
+    let vec4_array4 = add_array(&mut module, vec4, 4);
+    let mut members = vec![
+        ("u_ColorMatReg", vec4_array2, None),
+        ("u_ColorAmbReg", vec4_array2, None),
+        ("u_KonstColor", vec4_array4, None),
+        ("u_Color", vec4_array4, None),
+        ("u_TexMtx", add_array(&mut module, mat3x4, 10), None),
+        ("u_TextureSizes", vec4_array4, None),
+        ("u_TextureBiases", vec4_array2, None),
+        ("u_IndTexMtx", add_array(&mut module, mat2x4, 3), None),
+    ];
+    let optional_member = |members: &mut Vec<(&str, Handle<naga::Type>, Option<naga::Binding>)>, enabled: bool, name, ty| {
+        if enabled {
+            members.push((name, ty, None));
+            Some(members.len() as u32 - 1)
+        } else {
+            None
+        }
+    };
+    let post_tex_mtx_ty = add_array(&mut module, mat3x4, 20);
+    let post_tex_mtx = optional_member(&mut members, material.has_post_tex_mtx_block(), "u_PostTexMtx", post_tex_mtx_ty);
+    let light = add_struct(&mut module, "Light", &[("Color", vec4, None), ("Position", vec4, None), ("Direction", vec4, None), ("DistAtten", vec4, None), ("CosAtten", vec4, None)]);
+    let light_array = add_array(&mut module, light, 8);
+    let light_params = optional_member(&mut members, material.has_lights_block(), "u_LightParams", light_array);
+    let fog_block_ty = add_struct(&mut module, "FogBlock", &[("Param", vec4, None), ("AdjTable", vec4_array3, None), ("Color", vec4, None)]);
+    let fog_block = optional_member(&mut members, material.has_fog_block(), "u_FogBlock", fog_block_ty);
+    let dynamic_alpha_params = optional_member(&mut members, material.has_dynamic_alpha_test(), "u_DynamicAlphaParams", vec4);
+    let material_params_ty = add_struct(&mut module, "ub_MaterialParams", &members);
+
+    let pos_mtx_count = if material.use_pn_mtx_idx() { 10 } else { 1 };
+    let pos_mtx_array = add_array(&mut module, mat3x4, pos_mtx_count);
+    let draw_params_ty = add_struct(&mut module, "ub_DrawParams", &[("u_PosMtx", pos_mtx_array, None)]);
+
+    let texture_ty = add_type(&mut module, None, Ti::Image {
+        dim: naga::ImageDimension::D2,
+        arrayed: false,
+        class: naga::ImageClass::Sampled { kind: naga::ScalarKind::Float, multi: false },
+    });
+    let sampler_ty = add_type(&mut module, None, Ti::Sampler { comparison: false });
+    let textures = (0..TEXTURE_COUNT).map(|i| {
+        let mut add_handle = |name: String, ty, binding| module.global_variables.append(naga::GlobalVariable {
+            name: Some(name),
+            space: naga::AddressSpace::Handle,
+            binding: Some(naga::ResourceBinding { group: 0, binding }),
+            ty,
+            init: None,
+        }, Span::UNDEFINED);
+        let texture = add_handle(format!("T_u_Texture{}", i), texture_ty, i * 2);
+        let sampler = add_handle(format!("S_u_Texture{}", i), sampler_ty, i * 2 + 1);
+        (texture, sampler)
+    }).collect();
+
+    let scene_params = add_uniform(&mut module, "_ub_SceneParams", scene_params_ty, UB_SCENE_PARAMS);
+    let material_params = add_uniform(&mut module, "_ub_MaterialParams", material_params_ty, UB_SCENE_PARAMS + 1);
+    let draw_params = add_uniform(&mut module, "_ub_DrawParams", draw_params_ty, UB_SCENE_PARAMS + 2);
+
+    // Varyings are in the same order as the GLSL version.
+    let tex_coord_names: Vec<String> = (0..material.tex_gens.len()).map(|i| format!("v_TexCoord{}", i)).collect();
+    let mut io_members = vec![
+        ("gl_Position", vec4, Some(naga::Binding::BuiltIn(naga::BuiltIn::Position { invariant: false }))),
+        ("v_Position", types.vec3, varying(0)),
+        ("v_Color0", vec4, varying(1)),
+        ("v_Color1", vec4, varying(2)),
+    ];
+    for (i, tex_gen) in material.tex_gens.iter().enumerate() {
+        let ty = if tex_coord_is_2d(tex_gen) { types.vec2 } else { types.vec3 };
+        io_members.push((&tex_coord_names[i], ty, varying(3 + i as u32)));
+    }
+    let vertex_output = add_struct(&mut module, "VertexOutput", &io_members);
+    let fragment_output = add_struct(&mut module, "FragmentOutput", &[("o_OutColor0", vec4, location(0)), ("o_OutColor1", vec4, location(1))]);
+
+    let layout = MaterialLayout {
+        color_mat_reg: 0,
+        color_amb_reg: 1,
+        konst_color: 2,
+        color: 3,
+        tex_mtx: 4,
+        texture_sizes: 5,
+        texture_biases: 6,
+        ind_tex_mtx: 7,
+        post_tex_mtx,
+        light_params,
+        fog_block,
+        dynamic_alpha_params,
+    };
+
+    let globals = Globals { scene_params, material_params, draw_params, textures, layout, vertex_output, fragment_output };
+    (module, types, globals)
+}
+
+fn swizzle_component(c: char) -> naga::SwizzleComponent {
+    match c {
+        'x' | 'r' => naga::SwizzleComponent::X,
+        'y' | 'g' => naga::SwizzleComponent::Y,
+        'z' | 'b' => naga::SwizzleComponent::Z,
+        'w' | 'a' => naga::SwizzleComponent::W,
+        _ => unreachable!(),
+    }
+}
+
+fn vector_size(n: usize) -> naga::VectorSize {
+    match n {
+        2 => naga::VectorSize::Bi,
+        3 => naga::VectorSize::Tri,
+        4 => naga::VectorSize::Quad,
+        _ => unreachable!(),
+    }
+}
+
+// Builds a naga function, emitting expressions into the current block as they are added.
+struct FunctionBuilder {
+    types: Types,
+    function: naga::Function,
+    blocks: Vec<naga::Block>,
+    emit_start: Option<usize>,
+}
+
+impl FunctionBuilder {
+    fn new(types: Types, name: &str) -> Self {
+        FunctionBuilder {
+            types,
+            function: naga::Function { name: Some(name.to_string()), ..Default::default() },
+            blocks: vec![naga::Block::new()],
+            emit_start: None,
+        }
+    }
+
+    fn expr(&mut self, expr: E) -> Handle<E> {
+        if expr.needs_pre_emit() {
+            self.flush();
+        } else if self.emit_start.is_none() {
+            self.emit_start = Some(self.function.expressions.len());
+        }
+        self.function.expressions.append(expr, Span::UNDEFINED)
+    }
+
+    fn flush(&mut self) {
+        if let Some(start) = self.emit_start.take() {
+            let range = self.function.expressions.range_from(start);
+            self.blocks.last_mut().unwrap().push(Statement::Emit(range), Span::UNDEFINED);
+        }
+    }
+
+    fn push(&mut self, statement: Statement) {
+        self.flush();
+        self.blocks.last_mut().unwrap().push(statement, Span::UNDEFINED);
+    }
+
+    fn name(&mut self, name: &str, expr: Handle<E>) -> Handle<E> {
+        if !self.function.expressions[expr].needs_pre_emit() {
+            self.function.named_expressions.insert(expr, name.to_string());
+        }
+        expr
+    }
+
+    fn finish(mut self) -> naga::Function {
+        self.flush();
+        self.function.body = self.blocks.pop().unwrap();
+        self.function
+    }
+
+    fn f32(&mut self, v: f32) -> Handle<E> {
+        self.expr(E::Literal(naga::Literal::F32(v)))
+    }
+
+    fn u32(&mut self, v: u32) -> Handle<E> {
+        self.expr(E::Literal(naga::Literal::U32(v)))
+    }
+
+    fn i32(&mut self, v: i32) -> Handle<E> {
+        self.expr(E::Literal(naga::Literal::I32(v)))
+    }
+
+    fn bool(&mut self, v: bool) -> Handle<E> {
+        self.expr(E::Literal(naga::Literal::Bool(v)))
+    }
+
+    fn compose(&mut self, ty: Handle<naga::Type>, components: Vec<Handle<E>>) -> Handle<E> {
+        self.expr(E::Compose { ty, components })
+    }
+
+    fn vec2(&mut self, components: Vec<Handle<E>>) -> Handle<E> {
+        let ty = self.types.vec2;
+        self.compose(ty, components)
+    }
+
+    fn vec3(&mut self, components: Vec<Handle<E>>) -> Handle<E> {
+        let ty = self.types.vec3;
+        self.compose(ty, components)
+    }
+
+    fn vec4(&mut self, components: Vec<Handle<E>>) -> Handle<E> {
+        let ty = self.types.vec4;
+        self.compose(ty, components)
+    }
+
+    fn splat(&mut self, size: naga::VectorSize, value: Handle<E>) -> Handle<E> {
+        self.expr(E::Splat { size, value })
+    }
+
+    fn splat_f32(&mut self, size: naga::VectorSize, v: f32) -> Handle<E> {
+        let value = self.f32(v);
+        self.splat(size, value)
+    }
+
+    fn zero(&mut self, size: naga::VectorSize) -> Handle<E> {
+        let ty = self.types.vec(size);
+        self.expr(E::ZeroValue(ty))
+    }
+
+    fn binary(&mut self, op: Bo, left: Handle<E>, right: Handle<E>) -> Handle<E> {
+        self.expr(E::Binary { op, left, right })
+    }
+
+    fn add(&mut self, left: Handle<E>, right: Handle<E>) -> Handle<E> { self.binary(Bo::Add, left, right) }
+    fn sub(&mut self, left: Handle<E>, right: Handle<E>) -> Handle<E> { self.binary(Bo::Subtract, left, right) }
+    fn mul(&mut self, left: Handle<E>, right: Handle<E>) -> Handle<E> { self.binary(Bo::Multiply, left, right) }
+    fn div(&mut self, left: Handle<E>, right: Handle<E>) -> Handle<E> { self.binary(Bo::Divide, left, right) }
+
+    fn mul_f32(&mut self, left: Handle<E>, v: f32) -> Handle<E> {
+        let right = self.f32(v);
+        self.mul(left, right)
+    }
+
+    fn unary(&mut self, op: naga::UnaryOperator, expr: Handle<E>) -> Handle<E> {
+        self.expr(E::Unary { op, expr })
+    }
+
+    fn math(&mut self, fun: Mf, args: &[Handle<E>]) -> Handle<E> {
+        self.expr(E::Math { fun, arg: args[0], arg1: args.get(1).copied(), arg2: args.get(2).copied(), arg3: None })
+    }
+
+    fn select(&mut self, condition: Handle<E>, accept: Handle<E>, reject: Handle<E>) -> Handle<E> {
+        self.expr(E::Select { condition, accept, reject })
+    }
+
+    fn cast(&mut self, expr: Handle<E>, kind: naga::ScalarKind) -> Handle<E> {
+        self.expr(E::As { expr, kind, convert: Some(4) })
+    }
+
+    // Accepts xyzw or rgba component names.
+    fn swizzle(&mut self, vector: Handle<E>, components: &str) -> Handle<E> {
+        let chars: Vec<char> = components.chars().collect();
+        if chars.len() == 1 {
+            return self.index(vector, swizzle_component(chars[0]) as u32);
+        }
+        let mut pattern = [naga::SwizzleComponent::X; 4];
+        for (i, c) in chars.iter().enumerate() {
+            pattern[i] = swizzle_component(*c);
+        }
+        self.expr(E::Swizzle { size: vector_size(chars.len()), vector, pattern })
+    }
+
+    fn index(&mut self, base: Handle<E>, index: u32) -> Handle<E> {
+        self.expr(E::AccessIndex { base, index })
+    }
+
+    fn access(&mut self, base: Handle<E>, index: Handle<E>) -> Handle<E> {
+        self.expr(E::Access { base, index })
+    }
+
+    fn load(&mut self, pointer: Handle<E>) -> Handle<E> {
+        self.expr(E::Load { pointer })
+    }
+
+    fn global(&mut self, var: Handle<naga::GlobalVariable>) -> Handle<E> {
+        self.expr(E::GlobalVariable(var))
+    }
+
+    fn argument(&mut self, index: u32) -> Handle<E> {
+        self.expr(E::FunctionArgument(index))
+    }
+
+    fn local(&mut self, name: &str, ty: Handle<naga::Type>) -> Handle<E> {
+        let var = self.function.local_variables.append(naga::LocalVariable { name: Some(name.to_string()), ty, init: None }, Span::UNDEFINED);
+        self.expr(E::LocalVariable(var))
+    }
+
+    fn store(&mut self, pointer: Handle<E>, value: Handle<E>) {
+        self.push(Statement::Store { pointer, value });
+    }
+
+    fn if_then(&mut self, condition: Handle<E>, accept: impl FnOnce(&mut Self)) {
+        self.flush();
+        self.blocks.push(naga::Block::new());
+        accept(self);
+        self.flush();
+        let accept = self.blocks.pop().unwrap();
+        self.push(Statement::If { condition, accept, reject: naga::Block::new() });
+    }
+
+    fn saturate(&mut self, v: Handle<E>) -> Handle<E> {
+        self.math(Mf::Saturate, &[v])
+    }
+
+    fn dot(&mut self, a: Handle<E>, b: Handle<E>) -> Handle<E> {
+        self.math(Mf::Dot, &[a, b])
+    }
+
+    fn max_f32(&mut self, v: Handle<E>, min: f32) -> Handle<E> {
+        let min = self.f32(min);
+        self.math(Mf::Max, &[min, v])
+    }
+
+    // GLSL mod(): x - y * floor(x / y).
+    fn modulo_f32(&mut self, x: Handle<E>, y: f32) -> Handle<E> {
+        let scaled = self.mul_f32(x, 1.0 / y);
+        let floor = self.math(Mf::Floor, &[scaled]);
+        let wrapped = self.mul_f32(floor, y);
+        self.sub(x, wrapped)
+    }
+
+    // float(int(v * 255.0) & mask) / 255.0, componentwise.
+    fn mask_8bit(&mut self, v: Handle<E>, mask: i32, size: Option<naga::VectorSize>) -> Handle<E> {
+        let scaled = self.mul_f32(v, 255.0);
+        let int = self.cast(scaled, naga::ScalarKind::Sint);
+        let mut mask = self.i32(mask);
+        if let Some(size) = size {
+            mask = self.splat(size, mask);
+        }
+        let masked = self.binary(Bo::And, int, mask);
+        let float = self.cast(masked, naga::ScalarKind::Float);
+        self.mul_f32(float, 1.0 / 255.0)
+    }
+
+    // Row-major matrix structs (Mat2x4, Mat3x4, Mat4x4) times a vec4.
+    fn mul_matrix(&mut self, matrix: Handle<E>, rows: u32, v: Handle<E>) -> Handle<E> {
+        let components = (0..rows).map(|i| {
+            let row_ptr = self.index(matrix, i);
+            let row = self.load(row_ptr);
+            self.dot(row, v)
+        }).collect();
+        let ty = self.types.vec(vector_size(rows as usize));
+        self.compose(ty, components)
+    }
+}
+
+fn material_member(b: &mut FunctionBuilder, g: &Globals, member: u32) -> Handle<E> {
+    let material = b.global(g.material_params);
+    b.index(material, member)
+}
+
+fn material_vec4(b: &mut FunctionBuilder, g: &Globals, member: u32, index: u32) -> Handle<E> {
+    let array = material_member(b, g, member);
+    let ptr = b.index(array, index);
+    b.load(ptr)
+}
+
+fn tev_overflow(b: &mut FunctionBuilder, v: Handle<E>) -> Handle<E> {
+    b.mask_8bit(v, 255, Some(naga::VectorSize::Quad))
+}
+
+fn ind_tex_scale(scale: u32) -> Result<f32, String> {
+    if scale > 8 {
+        return Err(format!("bad indirect texture scale {}", scale));
+    }
+    Ok(1.0 / (1 << scale) as f32)
+}
+
+struct FragmentGenerator<'a> {
+    material: &'a GxMaterial,
+    g: &'a Globals,
+    b: FunctionBuilder,
+    input: Handle<E>,
+    registers: [Handle<E>; 4],
+    tex_coord: Handle<E>,
+    ind_tex_coords: Vec<Option<Handle<E>>>,
+}
+
+// Per-stage cache of the texture and rasterized color, which are used by several inputs.
+#[derive(Default)]
+struct StageInputs {
+    tex: Option<Handle<E>>,
+    ras: Option<Handle<E>>,
+}
+
+impl<'a> FragmentGenerator<'a> {
+    fn read_tex_coord(&mut self, index: usize) -> Handle<E> {
+        let varying = self.b.index(self.input, 4 + index as u32);
+        if tex_coord_is_2d(&self.material.tex_gens[index]) {
+            varying
+        } else {
+            let xy = self.b.swizzle(varying, "xy");
+            let z = self.b.swizzle(varying, "z");
+            let z = self.b.splat(naga::VectorSize::Bi, z);
+            self.b.div(xy, z)
+        }
+    }
+
+    fn texture_scale(&mut self, tex_map: u32) -> Handle<E> {
+        let sizes = material_vec4(&mut self.b, self.g, self.g.layout.texture_sizes, tex_map / 2);
+        if tex_map & 1 == 0 { self.b.swizzle(sizes, "xy") } else { self.b.swizzle(sizes, "zw") }
+    }
+
+    fn texture_lod_bias(&mut self, tex_map: u32) -> Handle<E> {
+        let scene = self.b.global(self.g.scene_params);
+        let misc_ptr = self.b.index(scene, 1);
+        let misc = self.b.load(misc_ptr);
+        let scene_bias = self.b.index(misc, 0);
+        let biases = material_vec4(&mut self.b, self.g, self.g.layout.texture_biases, tex_map / 4);
+        let bias = self.b.index(biases, tex_map % 4);
+        self.b.add(scene_bias, bias)
+    }
+
+    fn sample(&mut self, tex_map: u32, coordinate: Handle<E>) -> Result<Handle<E>, String> {
+        let (texture, sampler) = *self.g.textures.get(tex_map as usize)
+            .ok_or_else(|| format!("bad texture map {}", tex_map))?;
+        let bias = self.texture_lod_bias(tex_map);
+        let image = self.b.global(texture);
+        let sampler = self.b.global(sampler);
+        Ok(self.b.expr(E::ImageSample {
+            image,
+            sampler,
+            gather: None,
+            coordinate,
+            array_index: None,
+            offset: None,
+            level: naga::SampleLevel::Bias(bias),
+            depth_ref: None,
+        }))
+    }
+
+    fn generate_ind_tex_stages(&mut self) -> Result<(), String> {
+        for (i, stage) in self.material.ind_tex_stages.iter().enumerate() {
+            if stage.tex_coord_id as usize >= self.material.tex_gens.len() {
+                self.ind_tex_coords.push(None);
+                continue;
+            }
+
+            let mut coord = self.read_tex_coord(stage.tex_coord_id as usize);
+            if stage.scale_s != 0 || stage.scale_t != 0 {
+                let s = self.b.f32(ind_tex_scale(stage.scale_s)?);
+                let t = self.b.f32(ind_tex_scale(stage.scale_t)?);
+                let scale = self.b.vec2(vec![s, t]);
+                coord = self.b.mul(coord, scale);
+            }
+            let color = self.sample(stage.texture, coord)?;
+            let abg = self.b.swizzle(color, "abg");
+            let ind_tex_coord = self.b.mul_f32(abg, 255.0);
+            self.ind_tex_coords.push(Some(self.b.name(&format!("t_IndTexCoord{}", i), ind_tex_coord)));
+        }
+        Ok(())
+    }
+
+    fn ind_tex_coord(&mut self, stage: &TevStage) -> Handle<E> {
+        match self.ind_tex_coords.get(stage.ind_tex_stage as usize) {
+            Some(Some(coord)) => *coord,
+            _ => self.b.zero(naga::VectorSize::Tri),
+        }
+    }
+
+    fn alpha_bump_sel(&mut self, stage: &TevStage) -> Result<Handle<E>, String> {
+        let coord = self.ind_tex_coord(stage);
+        let channel = match stage.ind_tex_alpha_sel {
+            ind_tex::ALPHA_S => self.b.index(coord, 0),
+            ind_tex::ALPHA_T => self.b.index(coord, 1),
+            ind_tex::ALPHA_U => self.b.index(coord, 2),
+            sel => return Err(format!("bad indirect alpha select {}", sel)),
+        };
+        let mask = match stage.ind_tex_format {
+            ind_tex::FORMAT_8 | ind_tex::FORMAT_3 => 0xF8,
+            ind_tex::FORMAT_5 => 0xE0,
+            ind_tex::FORMAT_4 => 0xF0,
+            format => return Err(format!("bad indirect texture format {}", format)),
+        };
+        Ok(self.b.mask_8bit(channel, mask, None))
+    }
+
+    fn ras(&mut self, stage: &TevStage, inputs: &mut StageInputs) -> Result<Handle<E>, String> {
+        if let Some(ras) = inputs.ras {
+            return Ok(ras);
+        }
+        let ras = match stage.channel_id {
+            ras_channel::COLOR0A0 => self.b.index(self.input, 2),
+            ras_channel::COLOR1A1 => self.b.index(self.input, 3),
+            ras_channel::ALPHA_BUMP => {
+                let sel = self.alpha_bump_sel(stage)?;
+                self.b.splat(naga::VectorSize::Quad, sel)
+            },
+            ras_channel::ALPHA_BUMP_N => {
+                let sel = self.alpha_bump_sel(stage)?;
+                let sel = self.b.mul_f32(sel, 255.0 / 248.0);
+                self.b.splat(naga::VectorSize::Quad, sel)
+            },
+            ras_channel::COLOR_ZERO => self.b.zero(naga::VectorSize::Quad),
+            channel => return Err(format!("bad rasterized color channel {}", channel)),
+        };
+        inputs.ras = Some(ras);
+        Ok(ras)
+    }
+
+    fn uses_simple_coords(stage: &TevStage) -> bool {
+        stage.ind_tex_matrix == ind_tex::MTX_OFF && !stage.ind_tex_add_prev
+    }
+
+    fn tex(&mut self, stage: &TevStage, inputs: &mut StageInputs) -> Result<Handle<E>, String> {
+        if let Some(tex) = inputs.tex {
+            return Ok(tex);
+        }
+        let tex = if stage.tex_map == TEXMAP_NULL {
+            self.b.splat_f32(naga::VectorSize::Quad, 1.0)
+        } else {
+            let mut coord = self.b.load(self.tex_coord);
+            if !Self::uses_simple_coords(stage) {
+                let scale = self.texture_scale(stage.tex_map);
+                coord = self.b.div(coord, scale);
+            }
+            self.sample(stage.tex_map, coord)?
+        };
+        inputs.tex = Some(tex);
+        Ok(tex)
+    }
+
+    fn konst(&mut self, index: u32) -> Handle<E> {
+        material_vec4(&mut self.b, self.g, self.g.layout.konst_color, index)
+    }
+
+    fn konst_color_sel(&mut self, sel: u32) -> Result<Handle<E>, String> {
+        match sel {
+            0x00..=0x07 => Ok(self.b.splat_f32(naga::VectorSize::Tri, (8 - sel) as f32 / 8.0)),
+            0x0C..=0x0F => {
+                let k = self.konst(sel - 0x0C);
+                Ok(self.b.swizzle(k, "rgb"))
+            },
+            0x10..=0x1F => {
+                let k = self.konst((sel - 0x10) & 0x03);
+                let c = self.b.index(k, (sel - 0x10) >> 2);
+                Ok(self.b.splat(naga::VectorSize::Tri, c))
+            },
+            _ => Err(format!("bad konst color select {}", sel)),
+        }
+    }
+
+    fn konst_alpha_sel(&mut self, sel: u32) -> Result<Handle<E>, String> {
+        match sel {
+            0x00..=0x07 => Ok(self.b.f32((8 - sel) as f32 / 8.0)),
+            0x10..=0x1F => {
+                let k = self.konst((sel - 0x10) & 0x03);
+                Ok(self.b.index(k, (sel - 0x10) >> 2))
+            },
+            _ => Err(format!("bad konst alpha select {}", sel)),
+        }
+    }
+
+    fn swap(table: Option<[u32; 4]>, channel: u32) -> char {
+        let channel = table.map_or(channel, |t| t[channel as usize]);
+        ['r', 'g', 'b', 'a'][channel as usize & 0x03]
+    }
+
+    fn register(&mut self, index: u32) -> Handle<E> {
+        let ptr = self.registers[index as usize & 0x03];
+        self.b.load(ptr)
+    }
+
+    fn color_in(&mut self, stage: &TevStage, inputs: &mut StageInputs, color_in: u32) -> Result<Handle<E>, String> {
+        let swizzle_rgb = |table: Option<[u32; 4]>| -> String { (0..3).map(|c| Self::swap(table, c)).collect() };
+        let swizzle_aaa = |table: Option<[u32; 4]>| -> String { [Self::swap(table, 3); 3].iter().collect() };
+
+        Ok(match color_in {
+            cc::CPREV | cc::C0 | cc::C1 | cc::C2 => {
+                let reg = self.register((color_in - cc::CPREV) / 2);
+                self.b.swizzle(reg, "rgb")
+            },
+            cc::APREV | cc::A0 | cc::A1 | cc::A2 => {
+                let reg = self.register((color_in - cc::APREV) / 2);
+                self.b.swizzle(reg, "aaa")
+            },
+            cc::TEXC | cc::TEXA => {
+                let tex = self.tex(stage, inputs)?;
+                let pattern = if color_in == cc::TEXC { swizzle_rgb(stage.tex_swap_table) } else { swizzle_aaa(stage.tex_swap_table) };
+                self.b.swizzle(tex, &pattern)
+            },
+            cc::RASC | cc::RASA => {
+                let ras = self.ras(stage, inputs)?;
+                let pattern = if color_in == cc::RASC { swizzle_rgb(stage.ras_swap_table) } else { swizzle_aaa(stage.ras_swap_table) };
+                let swizzled = self.b.swizzle(ras, &pattern);
+                self.b.saturate(swizzled)
+            },
+            cc::ONE => self.b.splat_f32(naga::VectorSize::Tri, 1.0),
+            cc::HALF => self.b.splat_f32(naga::VectorSize::Tri, 0.5),
+            cc::KONST => self.konst_color_sel(stage.konst_color_sel)?,
+            cc::ZERO => self.b.zero(naga::VectorSize::Tri),
+            _ => return Err(format!("bad color input {}", color_in)),
+        })
+    }
+
+    fn alpha_in(&mut self, stage: &TevStage, inputs: &mut StageInputs, alpha_in: u32) -> Result<Handle<E>, String> {
+        Ok(match alpha_in {
+            ca::APREV | ca::A0 | ca::A1 | ca::A2 => {
+                let reg = self.register(alpha_in - ca::APREV);
+                self.b.index(reg, 3)
+            },
+            ca::TEXA => {
+                let tex = self.tex(stage, inputs)?;
+                let pattern = Self::swap(stage.tex_swap_table, 3).to_string();
+                self.b.swizzle(tex, &pattern)
+            },
+            ca::RASA => {
+                let ras = self.ras(stage, inputs)?;
+                let pattern = Self::swap(stage.ras_swap_table, 3).to_string();
+                let a = self.b.swizzle(ras, &pattern);
+                self.b.saturate(a)
+            },
+            ca::KONST => self.konst_alpha_sel(stage.konst_alpha_sel)?,
+            ca::ZERO => self.b.f32(0.0),
+            _ => return Err(format!("bad alpha input {}", alpha_in)),
+        })
+    }
+
+    // inputs are the A, B, C and D values, either all vec3 (color) or all f32 (alpha).
+    // tev holds the full vec4 A and B values, for the packed comparisons.
+    #[allow(clippy::too_many_arguments)]
+    fn tev_op(&mut self, op: u32, bias: u32, scale: u32, clamp: bool, inputs: [Handle<E>; 4], tev: [Handle<E>; 2], size: Option<naga::VectorSize>) -> Result<Handle<E>, String> {
+        let [a, b, c, d] = inputs;
+        let zero = match size {
+            Some(size) => self.b.zero(size),
+            None => self.b.f32(0.0),
+        };
+
+        let value = match op {
+            tev_op::ADD | tev_op::SUB => {
+                let mut v = self.b.math(Mf::Mix, &[a, b, c]);
+                if op == tev_op::SUB {
+                    v = self.b.unary(naga::UnaryOperator::Negate, v);
+                }
+                v = self.b.add(v, d);
+
+                let bias = match bias {
+                    tev_bias::ADDHALF => Some(0.5),
+                    tev_bias::SUBHALF => Some(-0.5),
+                    _ => None,
+                };
+                if let Some(bias) = bias {
+                    let bias = match size {
+                        Some(size) => self.b.splat_f32(size, bias),
+                        None => self.b.f32(bias),
+                    };
+                    v = self.b.add(v, bias);
+                }
+
+                match scale {
+                    tev_scale::SCALE_2 => self.b.mul_f32(v, 2.0),
+                    tev_scale::SCALE_4 => self.b.mul_f32(v, 4.0),
+                    tev_scale::DIVIDE_2 => self.b.mul_f32(v, 0.5),
+                    _ => v,
+                }
+            },
+            tev_op::COMP_R8_GT | tev_op::COMP_R8_EQ | tev_op::COMP_GR16_GT | tev_op::COMP_GR16_EQ | tev_op::COMP_BGR24_GT | tev_op::COMP_BGR24_EQ => {
+                let pack = |gen: &mut Self, v: Handle<E>| -> Handle<E> {
+                    match op {
+                        tev_op::COMP_R8_GT | tev_op::COMP_R8_EQ => gen.b.index(v, 0),
+                        tev_op::COMP_GR16_GT | tev_op::COMP_GR16_EQ => {
+                            let rg = gen.b.swizzle(v, "rg");
+                            let one = gen.b.f32(1.0);
+                            let k = gen.b.f32(256.0);
+                            let weights = gen.b.vec2(vec![one, k]);
+                            gen.b.dot(rg, weights)
+                        },
+                        _ => {
+                            let rgb = gen.b.swizzle(v, "rgb");
+                            let one = gen.b.f32(1.0);
+                            let k = gen.b.f32(256.0);
+                            let k2 = gen.b.f32(256.0 * 256.0);
+                            let weights = gen.b.vec3(vec![one, k, k2]);
+                            gen.b.dot(rgb, weights)
+                        },
+                    }
+                };
+                let pa = pack(self, tev[0]);
+                let pb = pack(self, tev[1]);
+                let cmp = if op & 1 == 0 { Bo::Greater } else { Bo::Equal };
+                let cond = self.b.binary(cmp, pa, pb);
+                let v = self.b.select(cond, c, zero);
+                self.b.add(v, d)
+            },
+            tev_op::COMP_RGB8_GT | tev_op::COMP_RGB8_EQ => {
+                let cmp = if op == tev_op::COMP_RGB8_GT { Bo::Greater } else { Bo::Equal };
+                let cond = self.b.binary(cmp, a, b);
+                let v = self.b.select(cond, c, zero);
+                self.b.add(v, d)
+            },
+            _ => return Err(format!("bad TEV op {}", op)),
+        };
+
+        if clamp {
+            Ok(self.b.saturate(value))
+        } else {
+            let (min, max) = match size {
+                Some(size) => (self.b.splat_f32(size, -4.0), self.b.splat_f32(size, 4.0)),
+                None => (self.b.f32(-4.0), self.b.f32(4.0)),
+            };
+            Ok(self.b.math(Mf::Clamp, &[value, min, max]))
+        }
+    }
+
+    fn tex_coord_wrap(&mut self, coord: Handle<E>, wrap: u32) -> Result<Handle<E>, String> {
+        Ok(match wrap {
+            ind_tex::WRAP_OFF => coord,
+            ind_tex::WRAP_0 => self.b.f32(0.0),
+            1..=5 => self.b.modulo_f32(coord, (256 >> (wrap - 1)) as f32),
+            _ => return Err(format!("bad indirect wrap {}", wrap)),
+        })
+    }
+
+    fn tev_base_tex_coord(&mut self, stage: &TevStage) -> Result<Option<Handle<E>>, String> {
+        if stage.tex_coord_id == TEXCOORD_NULL || stage.tex_map == TEXMAP_NULL {
+            return Ok(None);
+        }
+        if self.material.tex_gens.is_empty() {
+            return Ok(Some(self.b.zero(naga::VectorSize::Bi)));
+        }
+
+        let tex_gen_id = std::cmp::min(stage.tex_coord_id as usize, self.material.tex_gens.len() - 1);
+        let mut coord = self.read_tex_coord(tex_gen_id);
+        if !Self::uses_simple_coords(stage) {
+            let scale = self.texture_scale(stage.tex_map);
+            coord = self.b.mul(coord, scale);
+        }
+
+        if stage.ind_tex_wrap_s == ind_tex::WRAP_OFF && stage.ind_tex_wrap_t == ind_tex::WRAP_OFF {
+            return Ok(Some(coord));
+        }
+        let s = self.b.index(coord, 0);
+        let s = self.tex_coord_wrap(s, stage.ind_tex_wrap_s)?;
+        let t = self.b.index(coord, 1);
+        let t = self.tex_coord_wrap(t, stage.ind_tex_wrap_t)?;
+        Ok(Some(self.b.vec2(vec![s, t])))
+    }
+
+    fn tev_indirect_tex_coord(&mut self, stage: &TevStage) -> Result<Option<Handle<E>>, String> {
+        if stage.ind_tex_matrix == ind_tex::MTX_OFF || stage.ind_tex_stage as usize >= self.material.ind_tex_stages.len() {
+            return Ok(None);
+        }
+        if stage.ind_tex_format != ind_tex::FORMAT_8 {
+            return Err(format!("unsupported indirect texture format {}", stage.ind_tex_format));
+        }
+
+        let mut coord = self.ind_tex_coord(stage);
+        if stage.ind_tex_bias_sel != 0 {
+            let bias = if stage.ind_tex_format == ind_tex::FORMAT_8 { -128.0 } else { 1.0 };
+            let components = (0..3).map(|i| {
+                let v = if stage.ind_tex_bias_sel & (1 << i) != 0 { bias } else { 0.0 };
+                self.b.f32(v)
+            }).collect();
+            let bias = self.b.vec3(components);
+            coord = self.b.add(coord, bias);
+        }
+
+        match stage.ind_tex_matrix {
+            ind_tex::MTX_0..=ind_tex::MTX_2 => {
+                let ind_tex_mtx = material_member(&mut self.b, self.g, self.g.layout.ind_tex_mtx);
+                let matrix = self.b.index(ind_tex_mtx, stage.ind_tex_matrix - ind_tex::MTX_0);
+                let zero = self.b.f32(0.0);
+                let coord = self.b.vec4(vec![coord, zero]);
+                Ok(Some(self.b.mul_matrix(matrix, 2, coord)))
+            },
+            matrix => Err(format!("unsupported indirect matrix {}", matrix)),
+        }
+    }
+
+    fn generate_tev_tex_coord(&mut self, stage: &TevStage) -> Result<(), String> {
+        let base = self.tev_base_tex_coord(stage)?;
+        let indirect = self.tev_indirect_tex_coord(stage)?;
+        let coord = match (base, indirect) {
+            (Some(base), Some(indirect)) => self.b.add(base, indirect),
+            (Some(coord), None) | (None, Some(coord)) => coord,
+            (None, None) => return Ok(()),
+        };
+
+        if stage.ind_tex_add_prev {
+            let prev = self.b.load(self.tex_coord);
+            let coord = self.b.add(prev, coord);
+            self.b.store(self.tex_coord, coord);
+        } else {
+            self.b.store(self.tex_coord, coord);
+        }
+        Ok(())
+    }
+
+    fn generate_tev_stage(&mut self, index: usize, stage: &TevStage) -> Result<(), String> {
+        self.generate_tev_tex_coord(stage)?;
+
+        let mut inputs = StageInputs::default();
+        let color_ins = [stage.color_in_a, stage.color_in_b, stage.color_in_c, stage.color_in_d];
+        let alpha_ins = [stage.alpha_in_a, stage.alpha_in_b, stage.alpha_in_c, stage.alpha_in_d];
+        let mut tev = Vec::new();
+        for (i, (color_in, alpha_in)) in color_ins.iter().zip(alpha_ins.iter()).enumerate() {
+            let color = self.color_in(stage, &mut inputs, *color_in)?;
+            let alpha = self.alpha_in(stage, &mut inputs, *alpha_in)?;
+            let mut v = self.b.vec4(vec![color, alpha]);
+            // D is not subject to overflow.
+            if i < 3 {
+                v = tev_overflow(&mut self.b, v);
+            }
+            tev.push(self.b.name(&format!("t_Tev{}{}", ['A', 'B', 'C', 'D'][i], index), v));
+        }
+
+        let rgb: Vec<Handle<E>> = tev.iter().map(|v| self.b.swizzle(*v, "rgb")).collect();
+        let color = self.tev_op(stage.color_op, stage.color_bias, stage.color_scale, stage.color_clamp,
+            [rgb[0], rgb[1], rgb[2], rgb[3]], [tev[0], tev[1]], Some(naga::VectorSize::Tri))?;
+        let reg = self.register(stage.color_reg_id);
+        let a = self.b.index(reg, 3);
+        let value = self.b.vec4(vec![color, a]);
+        self.b.store(self.registers[stage.color_reg_id as usize & 0x03], value);
+
+        let alpha: Vec<Handle<E>> = tev.iter().map(|v| self.b.index(*v, 3)).collect();
+        let alpha = self.tev_op(stage.alpha_op, stage.alpha_bias, stage.alpha_scale, stage.alpha_clamp,
+            [alpha[0], alpha[1], alpha[2], alpha[3]], [tev[0], tev[1]], None)?;
+        let reg = self.register(stage.alpha_reg_id);
+        let rgb = self.b.swizzle(reg, "rgb");
+        let value = self.b.vec4(vec![rgb, alpha]);
+        self.b.store(self.registers[stage.alpha_reg_id as usize & 0x03], value);
+        Ok(())
+    }
+
+    fn alpha_test_compare(&mut self, compare: u32, alpha: Handle<E>, reference: Handle<E>) -> Result<Handle<E>, String> {
+        let op = match compare {
+            compare::NEVER => return Ok(self.b.bool(false)),
+            compare::ALWAYS => return Ok(self.b.bool(true)),
+            compare::LESS => Bo::Less,
+            compare::EQUAL => Bo::Equal,
+            compare::LEQUAL => Bo::LessEqual,
+            compare::GREATER => Bo::Greater,
+            compare::NEQUAL => Bo::NotEqual,
+            compare::GEQUAL => Bo::GreaterEqual,
+            _ => return Err(format!("bad alpha compare {}", compare)),
+        };
+        Ok(self.b.binary(op, alpha, reference))
+    }
+
+    fn generate_alpha_test(&mut self, pixel: Handle<E>) -> Result<(), String> {
+        let alpha_test = &self.material.alpha_test;
+
+        // Don't even emit an alpha test if we don't need it, so the driver can keep early Z.
+        if alpha_test.op == alpha_op::OR && (alpha_test.compare_a == compare::ALWAYS || alpha_test.compare_b == compare::ALWAYS) {
+            return Ok(());
+        }
+
+        let (reference_a, reference_b) = match self.g.layout.dynamic_alpha_params {
+            Some(member) if self.material.has_dynamic_alpha_test() => {
+                let params = material_member(&mut self.b, self.g, member);
+                let params = self.b.load(params);
+                (self.b.index(params, 0), self.b.index(params, 1))
+            },
+            _ => (self.b.f32(alpha_test.reference_a), self.b.f32(alpha_test.reference_b)),
+        };
+
+        let alpha = self.b.index(pixel, 3);
+        let a = self.alpha_test_compare(alpha_test.compare_a, alpha, reference_a)?;
+        let b = self.alpha_test_compare(alpha_test.compare_b, alpha, reference_b)?;
+        let op = match alpha_test.op {
+            alpha_op::AND => Bo::LogicalAnd,
+            alpha_op::OR => Bo::LogicalOr,
+            alpha_op::XOR => Bo::NotEqual,
+            alpha_op::XNOR => Bo::Equal,
+            op => return Err(format!("bad alpha op {}", op)),
+        };
+        let pass = self.b.binary(op, a, b);
+        let fail = self.b.unary(naga::UnaryOperator::LogicalNot, pass);
+        self.b.if_then(fail, |b| b.push(Statement::Kill));
+        Ok(())
+    }
+
+    fn generate_fog(&mut self, pixel: Handle<E>) -> Result<Handle<E>, String> {
+        let fog_type = self.material.rop_info.fog_type;
+        if fog_type == fog_type::NONE {
+            return Ok(pixel);
+        }
+        let fog_block = self.g.layout.fog_block.ok_or("fog requires hasFogBlock")?;
+
+        let fog_block = material_member(&mut self.b, self.g, fog_block);
+        let param_ptr = self.b.index(fog_block, 0);
+        let param = self.b.load(param_ptr);
+        let (a, b, c) = (self.b.index(param, 0), self.b.index(param, 1), self.b.index(param, 2));
+
+        // Depth is reversed.
+        let frag_coord = self.b.index(self.input, 0);
+        let frag_z = self.b.index(frag_coord, 2);
+        let one = self.b.f32(1.0);
+        let z = self.b.sub(one, frag_z);
+
+        // Projection and orthographic fog can be switched at runtime, based on B.
+        let zero = self.b.f32(0.0);
+        let is_projection = self.b.binary(Bo::NotEqual, b, zero);
+        let b_minus_z = self.b.sub(b, z);
+        let persp = self.b.div(a, b_minus_z);
+        let ortho = self.b.mul(a, z);
+        let base = self.b.select(is_projection, persp, ortho);
+        let base = self.b.name("t_FogBase", base);
+        let fog_z = self.b.sub(base, c);
+        let fog_z = self.b.saturate(fog_z);
+
+        let exp_fog = |gen: &mut Self, v: Handle<E>| -> Handle<E> {
+            let scaled = gen.b.mul_f32(v, -8.0);
+            let exp = gen.b.math(Mf::Exp2, &[scaled]);
+            let one = gen.b.f32(1.0);
+            gen.b.sub(one, exp)
+        };
+        let fog = match fog_type & 0x07 {
+            fog_type::LIN => fog_z,
+            fog_type::EXP => exp_fog(self, fog_z),
+            fog_type::EXP2 => {
+                let sq = self.b.mul(fog_z, fog_z);
+                exp_fog(self, sq)
+            },
+            fog_type::REVEXP => {
+                let inv = self.b.sub(one, fog_z);
+                exp_fog(self, inv)
+            },
+            fog_type::REVEXP2 => {
+                let inv = self.b.sub(one, fog_z);
+                let sq = self.b.mul(inv, inv);
+                exp_fog(self, sq)
+            },
+            _ => return Err(format!("bad fog type {}", fog_type)),
+        };
+        let fog = self.b.name("t_Fog", fog);
+
+        let fog_color_ptr = self.b.index(fog_block, 2);
+        let fog_color = self.b.load(fog_color_ptr);
+        let fog_rgb = self.b.swizzle(fog_color, "rgb");
+        let rgb = self.b.swizzle(pixel, "rgb");
+        let fog = self.b.splat(naga::VectorSize::Tri, fog);
+        let rgb = self.b.math(Mf::Mix, &[rgb, fog_rgb, fog]);
+        let a = self.b.index(pixel, 3);
+        Ok(self.b.vec4(vec![rgb, a]))
+    }
+
+    fn generate(mut self) -> Result<naga::Function, String> {
+        let names = ["t_ColorPrev", "t_Color0", "t_Color1", "t_Color2"];
+        let vec4 = self.b.types.vec4;
+        for (i, name) in names.iter().enumerate() {
+            self.registers[i] = self.b.local(name, vec4);
+        }
+        for i in 0..4 {
+            let color = material_vec4(&mut self.b, self.g, self.g.layout.color, i);
+            self.b.store(self.registers[i as usize], color);
+        }
+
+        self.generate_ind_tex_stages()?;
+
+        let vec2 = self.b.types.vec2;
+        self.tex_coord = self.b.local("t_TexCoord", vec2);
+        let zero = self.b.zero(naga::VectorSize::Bi);
+        self.b.store(self.tex_coord, zero);
+
+        let material = self.material;
+        for (i, stage) in material.tev_stages.iter().enumerate() {
+            self.generate_tev_stage(i, stage)?;
+        }
+
+        // The output of the last stage is what goes to the pixel, regardless of its destination register.
+        let last = material.tev_stages.last().ok_or("material has no TEV stages")?;
+        let color = self.register(last.color_reg_id);
+        let rgb = self.b.swizzle(color, "rgb");
+        let alpha = self.register(last.alpha_reg_id);
+        let a = self.b.index(alpha, 3);
+        let output = self.b.vec4(vec![rgb, a]);
+        let pixel = tev_overflow(&mut self.b, output);
+        let mut pixel = self.b.name("t_PixelOut", pixel);
+
+        self.generate_alpha_test(pixel)?;
+        pixel = self.generate_fog(pixel)?;
+
+        if let Some(dst_alpha) = material.rop_info.dst_alpha {
+            let rgb = self.b.swizzle(pixel, "rgb");
+            let a = self.b.f32(dst_alpha);
+            pixel = self.b.vec4(vec![rgb, a]);
+        }
+
+        let zero = self.b.zero(naga::VectorSize::Quad);
+        let output = self.b.compose(self.g.fragment_output, vec![pixel, zero]);
+        self.b.push(Statement::Return { value: Some(output) });
+        Ok(self.b.finish())
+    }
+}
+
+fn generate_fragment(material: &GxMaterial, types: Types, g: &Globals) -> Result<naga::EntryPoint, String> {
+    let mut b = FunctionBuilder::new(types, "fs_main");
+    b.function.arguments.push(naga::FunctionArgument { name: Some("input".to_string()), ty: g.vertex_output, binding: None });
+    b.function.result = Some(naga::FunctionResult { ty: g.fragment_output, binding: None });
+    let input = b.argument(0);
+    let placeholder = input;
+
+    let generator = FragmentGenerator {
+        material,
+        g,
+        b,
+        input,
+        registers: [placeholder; 4],
+        tex_coord: placeholder,
+        ind_tex_coords: Vec::new(),
+    };
+
+    Ok(naga::EntryPoint {
+        name: "fs_main".to_string(),
+        stage: naga::ShaderStage::Fragment,
+        early_depth_test: None,
+        workgroup_size: [0; 3],
+        function: generator.generate()?,
+    })
+}
+
+struct VertexGenerator<'a> {
+    material: &'a GxMaterial,
+    g: &'a Globals,
+    b: FunctionBuilder,
+    attributes: [Option<Handle<E>>; ATTR_COUNT],
+    position: Handle<E>,
+    normal: Option<Handle<E>>,
+}
+
+impl<'a> VertexGenerator<'a> {
+    fn attribute(&self, location: u32) -> Handle<E> {
+        self.attributes[location as usize].unwrap()
+    }
+
+    fn pos_mtx(&mut self, index: Option<Handle<E>>, constant_index: u32) -> Handle<E> {
+        let draw_params = self.b.global(self.g.draw_params);
+        let pos_mtx = self.b.index(draw_params, 0);
+        match index {
+            Some(index) => self.b.access(pos_mtx, index),
+            None => self.b.index(pos_mtx, constant_index),
+        }
+    }
+
+    fn tex_mtx(&mut self, member: u32, index: Option<Handle<E>>, constant_index: u32) -> Handle<E> {
+        let array = material_member(&mut self.b, self.g, member);
+        match index {
+            Some(index) => self.b.access(array, index),
+            None => self.b.index(array, constant_index),
+        }
+    }
+
+    // MulNormalMatrix: removes the squared scale of the matrix before transforming.
+    fn mul_normal_matrix(&mut self, matrix: Handle<E>, v: Handle<E>) -> Handle<E> {
+        let rows: Vec<Handle<E>> = (0..3).map(|i| {
+            let ptr = self.b.index(matrix, i);
+            self.b.load(ptr)
+        }).collect();
+        let sq_scale = (0..3).map(|column| {
+            let components = rows.iter().map(|row| self.b.index(*row, column)).collect();
+            let column = self.b.vec3(components);
+            self.b.dot(column, column)
+        }).collect();
+        let sq_scale = self.b.vec3(sq_scale);
+        let scaled = self.b.div(v, sq_scale);
+        let components = rows.iter().map(|row| {
+            let xyz = self.b.swizzle(*row, "xyz");
+            self.b.dot(xyz, scaled)
+        }).collect();
+        let result = self.b.vec3(components);
+        self.b.math(Mf::Normalize, &[result])
+    }
+
+    fn pn_mtx(&mut self) -> Handle<E> {
+        if self.material.use_pn_mtx_idx() {
+            let position = self.attribute(ATTR_POS);
+            let w = self.b.index(position, 3);
+            let index = self.b.cast(w, naga::ScalarKind::Uint);
+            self.pos_mtx(Some(index), 0)
+        } else {
+            self.pos_mtx(None, 0)
+        }
+    }
+
+    fn uses_normal_channel(c: &ColorChannelControl) -> bool {
+        c.lighting_enabled && c.lit_mask != 0 && (c.diffuse_function != diffuse_fn::NONE || c.attenuation_function == atten_fn::SPEC)
+    }
+
+    fn uses_vertex_color(c: &ColorChannelControl) -> bool {
+        c.mat_color_source == color_src::VTX || c.amb_color_source == color_src::VTX
+    }
+
+    fn apply_attenuation(&mut self, coeff: Handle<E>, value: Handle<E>) -> Handle<E> {
+        let one = self.b.f32(1.0);
+        let sq = self.b.mul(value, value);
+        let v = self.b.vec3(vec![one, value, sq]);
+        self.b.dot(coeff, v)
+    }
+
+    fn color_source(&mut self, source: u32, register: u32, i: u32) -> Handle<E> {
+        if source == color_src::VTX {
+            self.attribute(ATTR_CLR0 + i)
+        } else {
+            material_vec4(&mut self.b, self.g, register, i)
+        }
+    }
+
+    fn generate_color_channel(&mut self, chan: &ColorChannelControl, i: u32) -> Result<Handle<E>, String> {
+        let mat = self.color_source(chan.mat_color_source, self.g.layout.color_mat_reg, i);
+        if !chan.lighting_enabled {
+            return Ok(mat);
+        }
+
+        let mut accum = self.color_source(chan.amb_color_source, self.g.layout.color_amb_reg, i);
+        if chan.lit_mask != 0 {
+            let light_params = self.g.layout.light_params.ok_or("lighting requires hasLightsBlock")?;
+            for j in 0..8 {
+                if chan.lit_mask & (1 << j) == 0 {
+                    continue;
+                }
+
+                let lights = material_member(&mut self.b, self.g, light_params);
+                let light = self.b.index(lights, j);
+                let field = |b: &mut FunctionBuilder, index: u32, swizzle: &str| {
+                    let ptr = b.index(light, index);
+                    let v = b.load(ptr);
+                    b.swizzle(v, swizzle)
+                };
+                let color = field(&mut self.b, 0, "xyzw");
+                let position = field(&mut self.b, 1, "xyz");
+                let direction = field(&mut self.b, 2, "xyz");
+                let dist_atten = field(&mut self.b, 3, "xyz");
+                let cos_atten = field(&mut self.b, 4, "xyz");
+
+                let delta = self.b.sub(position, self.position);
+                let dist2 = self.b.dot(delta, delta);
+                let dist = self.b.math(Mf::Sqrt, &[dist2]);
+                let dist_splat = self.b.splat(naga::VectorSize::Tri, dist);
+                let delta_dir = self.b.div(delta, dist_splat);
+
+                let attenuation = match chan.attenuation_function {
+                    atten_fn::NONE => self.b.f32(1.0),
+                    atten_fn::SPOT => {
+                        let attn = self.b.dot(delta_dir, direction);
+                        let attn = self.b.max_f32(attn, 0.0);
+                        let cos_attn = self.apply_attenuation(cos_atten, attn);
+                        let cos_attn = self.b.max_f32(cos_attn, 0.0);
+                        let dist_atten = if chan.diffuse_function != diffuse_fn::NONE {
+                            self.b.math(Mf::Normalize, &[dist_atten])
+                        } else {
+                            dist_atten
+                        };
+                        let one = self.b.f32(1.0);
+                        let dists = self.b.vec3(vec![one, dist, dist2]);
+                        let dist_attn = self.b.dot(dist_atten, dists);
+                        let attn = self.b.div(cos_attn, dist_attn);
+                        self.b.max_f32(attn, 0.0)
+                    },
+                    atten_fn::SPEC => {
+                        let normal = self.normal.unwrap();
+                        let n_dot_l = self.b.dot(normal, delta_dir);
+                        let zero = self.b.f32(0.0);
+                        let facing = self.b.binary(Bo::GreaterEqual, n_dot_l, zero);
+                        let n_dot_h = self.b.dot(normal, direction);
+                        let n_dot_h = self.b.max_f32(n_dot_h, 0.0);
+                        let attn = self.b.select(facing, n_dot_h, zero);
+                        let cos_attn = self.apply_attenuation(cos_atten, attn);
+                        let dist_attn = self.apply_attenuation(dist_atten, attn);
+                        let dist_attn = self.b.max_f32(dist_attn, 0.0);
+                        let attn = self.b.div(cos_attn, dist_attn);
+                        self.b.max_f32(attn, 0.0)
+                    },
+                    f => return Err(format!("bad attenuation function {}", f)),
+                };
+
+                let diffuse_function = if chan.attenuation_function == atten_fn::NONE { diffuse_fn::NONE } else { chan.diffuse_function };
+                let diffuse = match diffuse_function {
+                    diffuse_fn::NONE => self.b.f32(1.0),
+                    diffuse_fn::SIGN | diffuse_fn::CLAMP => {
+                        let normal = self.normal.unwrap();
+                        let n_dot_l = self.b.dot(normal, delta_dir);
+                        if diffuse_function == diffuse_fn::CLAMP { self.b.max_f32(n_dot_l, 0.0) } else { n_dot_l }
+                    },
+                    f => return Err(format!("bad diffuse function {}", f)),
+                };
+
+                let scale = self.b.mul(diffuse, attenuation);
+                let light_color = self.b.mul(color, scale);
+                accum = self.b.add(accum, light_color);
+            }
+        }
+
+        let accum = self.b.saturate(accum);
+        Ok(self.b.mul(mat, accum))
+    }
+
+    fn generate_light_channel(&mut self, channel: &LightChannelControl, i: u32) -> Result<Handle<E>, String> {
+        let color = self.generate_color_channel(&channel.color_channel, i)?;
+        let color = if channel.color_channel == channel.alpha_channel {
+            color
+        } else {
+            let alpha = self.generate_color_channel(&channel.alpha_channel, i)?;
+            let rgb = self.b.swizzle(color, "rgb");
+            let a = self.b.index(alpha, 3);
+            self.b.vec4(vec![rgb, a])
+        };
+        Ok(self.b.name(&format!("t_Color{}", i), color))
+    }
+
+    fn tex_gen_source(&mut self, tex_gen: &TexGen, colors: &[Handle<E>], tex_coords: &[Handle<E>]) -> Result<Handle<E>, String> {
+        let vec4_of = |b: &mut FunctionBuilder, xyz: Handle<E>| {
+            let one = b.f32(1.0);
+            b.vec4(vec![xyz, one])
+        };
+        Ok(match tex_gen.source {
+            tex_gen::SRC_POS => {
+                let position = self.attribute(ATTR_POS);
+                let xyz = self.b.swizzle(position, "xyz");
+                vec4_of(&mut self.b, xyz)
+            },
+            tex_gen::SRC_NRM => {
+                let attr = self.attribute(ATTR_NRM);
+                vec4_of(&mut self.b, attr)
+            },
+            tex_gen::SRC_BINRM => {
+                let attr = self.attribute(ATTR_BINRM);
+                vec4_of(&mut self.b, attr)
+            },
+            tex_gen::SRC_TANGENT => {
+                let attr = self.attribute(ATTR_TANGENT);
+                vec4_of(&mut self.b, attr)
+            },
+            tex_gen::SRC_TEX0..=tex_gen::SRC_TEX7 => {
+                let n = tex_gen.source - tex_gen::SRC_TEX0;
+                let attr = self.attribute(ATTR_TEX01 + n / 2);
+                let xy = self.b.swizzle(attr, if n & 1 == 0 { "xy" } else { "zw" });
+                let one = self.b.f32(1.0);
+                self.b.vec4(vec![xy, one, one])
+            },
+            tex_gen::SRC_TEXCOORD0..=tex_gen::SRC_TEXCOORD6 => {
+                let n = (tex_gen.source - tex_gen::SRC_TEXCOORD0) as usize;
+                let coord = *tex_coords.get(n).ok_or_else(|| format!("texgen source TEXCOORD{} is not generated yet", n))?;
+                vec4_of(&mut self.b, coord)
+            },
+            tex_gen::SRC_COLOR0 => colors[0],
+            tex_gen::SRC_COLOR1 => colors[1],
+            source => return Err(format!("bad texgen source {}", source)),
+        })
+    }
+
+    fn tex_gen_matrix_mult(&mut self, i: usize, tex_gen: &TexGen, src: Handle<E>) -> Result<Handle<E>, String> {
+        if self.material.use_tex_mtx_idx(i) {
+            // Per-vertex matrix index: 20 is identity, 10 and up are texture matrices.
+            let attr = self.attribute(if i < 4 { ATTR_TEX0123MTXIDX } else { ATTR_TEX4567MTXIDX });
+            let index = self.b.index(attr, i as u32 % 4);
+            let index = self.b.mul_f32(index, 256.0);
+            let index = self.b.cast(index, naga::ScalarKind::Uint);
+
+            let pos_mtx = self.pos_mtx(Some(index), 0);
+            let pos = self.b.mul_matrix(pos_mtx, 3, src);
+            let ten = self.b.u32(10);
+            let tex_index = self.b.sub(index, ten);
+            let tex_mtx = self.tex_mtx(self.g.layout.tex_mtx, Some(tex_index), 0);
+            let tex = self.b.mul_matrix(tex_mtx, 3, src);
+            let is_tex = self.b.binary(Bo::GreaterEqual, index, ten);
+            let v = self.b.select(is_tex, tex, pos);
+            let twenty = self.b.u32(20);
+            let is_identity = self.b.binary(Bo::Equal, index, twenty);
+            let xyz = self.b.swizzle(src, "xyz");
+            return Ok(self.b.select(is_identity, xyz, v));
+        }
+
+        match tex_gen.matrix {
+            tex_gen::IDENTITY => Ok(self.b.swizzle(src, "xyz")),
+            m if (tex_gen::TEXMTX0..tex_gen::IDENTITY).contains(&m) => {
+                let tex_mtx = self.tex_mtx(self.g.layout.tex_mtx, None, (m - tex_gen::TEXMTX0) / 3);
+                Ok(self.b.mul_matrix(tex_mtx, 3, src))
+            },
+            m if m < tex_gen::TEXMTX0 => {
+                let index = (m - tex_gen::PNMTX0) / 3;
+                if index > 0 && !self.material.use_pn_mtx_idx() {
+                    return Err(format!("texgen matrix PNMTX{} requires usePnMtxIdx", index));
+                }
+                let pos_mtx = self.pos_mtx(None, index);
+                Ok(self.b.mul_matrix(pos_mtx, 3, src))
+            },
+            m => Err(format!("bad texgen matrix {}", m)),
+        }
+    }
+
+    fn generate_tex_gen(&mut self, i: usize, tex_gen: &TexGen, colors: &[Handle<E>], tex_coords: &[Handle<E>]) -> Result<Handle<E>, String> {
+        let src = self.tex_gen_source(tex_gen, colors, tex_coords)?;
+        let mut coord = match tex_gen.type_ {
+            tex_gen::SRTG | tex_gen::MTX2X4 => {
+                let v = if tex_gen.type_ == tex_gen::SRTG { src } else { self.tex_gen_matrix_mult(i, tex_gen, src)? };
+                let xy = self.b.swizzle(v, "xy");
+                let one = self.b.f32(1.0);
+                self.b.vec3(vec![xy, one])
+            },
+            tex_gen::MTX3X4 => self.tex_gen_matrix_mult(i, tex_gen, src)?,
+            t => return Err(format!("unsupported texgen type {}", t)),
+        };
+
+        if tex_gen.normalize {
+            coord = self.b.math(Mf::Normalize, &[coord]);
+        }
+
+        if tex_gen.post_matrix != tex_gen::PTIDENTITY {
+            let post_tex_mtx = self.g.layout.post_tex_mtx.ok_or("post texgen matrices require hasPostTexMtxBlock")?;
+            if tex_gen.post_matrix < tex_gen::PTTEXMTX0 {
+                return Err(format!("bad post texgen matrix {}", tex_gen.post_matrix));
+            }
+            let matrix = self.tex_mtx(post_tex_mtx, None, (tex_gen.post_matrix - tex_gen::PTTEXMTX0) / 3);
+            let one = self.b.f32(1.0);
+            let v = self.b.vec4(vec![coord, one]);
+            coord = self.b.mul_matrix(matrix, 3, v);
+        }
+
+        Ok(self.b.name(&format!("t_TexCoord{}", i), coord))
+    }
+
+    fn generate(mut self) -> Result<naga::Function, String> {
+        let material = self.material;
+
+        let pn_mtx = self.pn_mtx();
+        let position = self.attribute(ATTR_POS);
+        let xyz = self.b.swizzle(position, "xyz");
+        let one = self.b.f32(1.0);
+        let position = self.b.vec4(vec![xyz, one]);
+        let position = self.b.mul_matrix(pn_mtx, 3, position);
+        self.position = self.b.name("t_Position", position);
+
+        if let Some(normal) = self.attributes[ATTR_NRM as usize] {
+            let normal = self.mul_normal_matrix(pn_mtx, normal);
+            self.normal = Some(self.b.name("t_Normal", normal));
+        }
+
+        let mut colors = Vec::new();
+        for i in 0..2 {
+            let color = match material.light_channels.get(i) {
+                Some(channel) => self.generate_light_channel(channel, i as u32)?,
+                None => self.b.zero(naga::VectorSize::Quad),
+            };
+            colors.push(color);
+        }
+
+        let mut tex_coords = Vec::new();
+        for (i, tex_gen) in material.tex_gens.iter().enumerate() {
+            let coord = self.generate_tex_gen(i, tex_gen, &colors, &tex_coords)?;
+            tex_coords.push(coord);
+        }
+
+        let scene_params = self.b.global(self.g.scene_params);
+        let projection = self.b.index(scene_params, 0);
+        let one = self.b.f32(1.0);
+        let position = self.b.vec4(vec![self.position, one]);
+        let clip_position = self.b.mul_matrix(projection, 4, position);
+
+        let mut components = vec![clip_position, self.position, colors[0], colors[1]];
+        for (tex_gen, coord) in material.tex_gens.iter().zip(tex_coords.iter()) {
+            components.push(if tex_coord_is_2d(tex_gen) { self.b.swizzle(*coord, "xy") } else { *coord });
+        }
+        let output = self.b.compose(self.g.vertex_output, components);
+        self.b.push(Statement::Return { value: Some(output) });
+        Ok(self.b.finish())
+    }
+}
+
+fn generate_vertex(material: &GxMaterial, types: Types, g: &Globals) -> Result<naga::EntryPoint, String> {
+    for (i, tex_gen) in material.tex_gens.iter().enumerate() {
+        if !tex_coord_is_2d(tex_gen) && tex_gen.type_ != tex_gen::MTX3X4 {
+            return Err(format!("texgen {}: bump mapping texgens are not supported", i));
+        }
+    }
+
+    let uses_tex_gen_input = |source: u32| material.tex_gens.iter().any(|t| t.source == source);
+    let uses_light_channel = |i: usize| material.light_channels.get(i).is_some_and(|c| {
+        VertexGenerator::uses_vertex_color(&c.color_channel) || VertexGenerator::uses_vertex_color(&c.alpha_channel)
+    });
+    let uses_normal = material.light_channels.iter().any(|c| {
+        VertexGenerator::uses_normal_channel(&c.color_channel) || VertexGenerator::uses_normal_channel(&c.alpha_channel)
+    });
+    let uses_tex_mtx_idx = |range: std::ops::Range<usize>| range.into_iter().any(|i| material.use_tex_mtx_idx(i));
+
+    let attributes: [(&str, bool, Handle<naga::Type>); ATTR_COUNT] = [
+        ("a_Position", true, types.vec4),
+        ("a_TexMtx0123Idx", uses_tex_mtx_idx(0..4), types.vec4),
+        ("a_TexMtx4567Idx", uses_tex_mtx_idx(4..8), types.vec4),
+        ("a_Normal", uses_normal || uses_tex_gen_input(tex_gen::SRC_NRM), types.vec3),
+        ("a_Binormal", uses_tex_gen_input(tex_gen::SRC_BINRM), types.vec3),
+        ("a_Tangent", uses_tex_gen_input(tex_gen::SRC_TANGENT), types.vec3),
+        ("a_Color0", uses_light_channel(0), types.vec4),
+        ("a_Color1", uses_light_channel(1), types.vec4),
+        ("a_Tex01", uses_tex_gen_input(tex_gen::SRC_TEX0) || uses_tex_gen_input(tex_gen::SRC_TEX0 + 1), types.vec4),
+        ("a_Tex23", uses_tex_gen_input(tex_gen::SRC_TEX0 + 2) || uses_tex_gen_input(tex_gen::SRC_TEX0 + 3), types.vec4),
+        ("a_Tex45", uses_tex_gen_input(tex_gen::SRC_TEX0 + 4) || uses_tex_gen_input(tex_gen::SRC_TEX0 + 5), types.vec4),
+        ("a_Tex67", uses_tex_gen_input(tex_gen::SRC_TEX0 + 6) || uses_tex_gen_input(tex_gen::SRC_TEX0 + 7), types.vec4),
+    ];
+
+    let mut b = FunctionBuilder::new(types, "vs_main");
+    let mut attribute_exprs = [None; ATTR_COUNT];
+    for (i, (name, used, ty)) in attributes.iter().enumerate() {
+        if !used {
+            continue;
+        }
+        let index = b.function.arguments.len() as u32;
+        b.function.arguments.push(naga::FunctionArgument { name: Some(name.to_string()), ty: *ty, binding: location(i as u32) });
+        attribute_exprs[i] = Some(b.argument(index));
+    }
+    b.function.result = Some(naga::FunctionResult { ty: g.vertex_output, binding: None });
+    let placeholder = attribute_exprs[ATTR_POS as usize].unwrap();
+
+    let generator = VertexGenerator {
+        material,
+        g,
+        b,
+        attributes: attribute_exprs,
+        position: placeholder,
+        normal: None,
+    };
+
+    Ok(naga::EntryPoint {
+        name: "vs_main".to_string(),
+        stage: naga::ShaderStage::Vertex,
+        early_depth_test: None,
+        workgroup_size: [0; 3],
+        function: generator.generate()?,
+    })
+}
+
+// Builds a module with a "vs_main" and "fs_main" entry point for the material.
+pub fn generate_module(material: &GxMaterial) -> Result<naga::Module, String> {
+    let (mut module, types, globals) = create_module(material);
+    let vertex = generate_vertex(material, types, &globals)?;
+    let fragment = generate_fragment(material, types, &globals)?;
+    module.entry_points.push(vertex);
+    module.entry_points.push(fragment);
+    Ok(module)
+}
+
+pub fn generate_wgsl(material: &GxMaterial) -> Result<String, String> {
+    let module = generate_module(material)?;
+    let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|e| format!("{}: {}", material.name, e.emit_to_string("")))?;
+    naga::back::wgsl::write_string(&module, &info, naga::back::wgsl::WriterFlags::empty())
+        .map_err(|e| format!("{}: {}", material.name, e))
+}
+
+// material is a GXMaterial object from gx_material.ts.
+#[wasm_bindgen]
+pub fn gx_material_generate_wgsl(material: JsValue) -> Result<String, JsValue> {
+    let material: GxMaterial = serde_wasm_bindgen::from_value(material)?;
+    generate_wgsl(&material).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color_channel(lighting_enabled: bool, lit_mask: u32) -> ColorChannelControl {
+        ColorChannelControl {
+            lighting_enabled,
+            mat_color_source: color_src::VTX,
+            amb_color_source: 0,
+            lit_mask,
+            diffuse_function: diffuse_fn::CLAMP,
+            attenuation_function: atten_fn::SPOT,
+        }
+    }
+
+    fn tev_stage() -> TevStage {
+        TevStage {
+            color_in_a: cc::ZERO, color_in_b: cc::TEXC, color_in_c: cc::RASC, color_in_d: cc::ZERO,
+            color_op: tev_op::ADD, color_bias: 0, color_scale: 0, color_clamp: true, color_reg_id: 0,
+            alpha_in_a: ca::ZERO, alpha_in_b: ca::TEXA, alpha_in_c: ca::RASA, alpha_in_d: ca::ZERO,
+            alpha_op: tev_op::ADD, alpha_bias: 0, alpha_scale: 0, alpha_clamp: true, alpha_reg_id: 0,
+            tex_coord_id: 0, tex_map: 0, channel_id: ras_channel::COLOR0A0,
+            konst_color_sel: 0, konst_alpha_sel: 0,
+            ras_swap_table: None, tex_swap_table: None,
+            ind_tex_stage: 0, ind_tex_format: ind_tex::FORMAT_8, ind_tex_bias_sel: 0, ind_tex_alpha_sel: 0,
+            ind_tex_matrix: ind_tex::MTX_OFF, ind_tex_wrap_s: ind_tex::WRAP_OFF, ind_tex_wrap_t: ind_tex::WRAP_OFF,
+            ind_tex_add_prev: false, ind_tex_use_orig_lod: false,
+        }
+    }
+
+    fn material() -> GxMaterial {
+        GxMaterial {
+            name: "test".to_string(),
+            light_channels: vec![LightChannelControl { color_channel: color_channel(true, 0x01), alpha_channel: color_channel(false, 0) }],
+            tex_gens: vec![TexGen { type_: tex_gen::MTX2X4, source: tex_gen::SRC_TEX0, matrix: tex_gen::TEXMTX0, normalize: false, post_matrix: tex_gen::PTIDENTITY }],
+            tev_stages: vec![tev_stage()],
+            ind_tex_stages: vec![],
+            alpha_test: AlphaTest { op: alpha_op::OR, compare_a: compare::ALWAYS, reference_a: 0.0, compare_b: compare::ALWAYS, reference_b: 0.0 },
+            rop_info: RopInfo { fog_type: fog_type::NONE, dst_alpha: None },
+            use_pn_mtx_idx: None,
+            use_tex_mtx_idx: None,
+            has_post_tex_mtx_block: None,
+            has_lights_block: None,
+            has_fog_block: None,
+            has_dynamic_alpha_test: None,
+        }
+    }
+
+    // generate_wgsl runs naga's validator over the module before writing it out.
+    fn generate(material: &GxMaterial) -> String {
+        generate_wgsl(material).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn with_stage(name: String, edit: impl Fn(&mut TevStage)) -> GxMaterial {
+        let mut material = material();
+        material.name = name;
+        edit(&mut material.tev_stages[0]);
+        material
+    }
+
+    // A second TEV stage reading indirect stage 0, which samples texture 1 with texgen 1.
+    fn indirect_material(name: String, edit: impl Fn(&mut TevStage)) -> GxMaterial {
+        let mut material = material();
+        material.name = name;
+        material.tex_gens.push(TexGen { type_: tex_gen::MTX2X4, source: tex_gen::SRC_TEX0 + 1, matrix: tex_gen::IDENTITY, normalize: false, post_matrix: tex_gen::PTIDENTITY });
+        material.ind_tex_stages.push(IndTexStage { tex_coord_id: 1, texture: 1, scale_s: 1, scale_t: 2 });
+        let mut stage = tev_stage();
+        edit(&mut stage);
+        material.tev_stages.push(stage);
+        material
+    }
+
+    #[test]
+    fn test_simple_material() {
+        let wgsl = generate_wgsl(&material()).unwrap();
+        assert!(wgsl.contains("fn vs_main("));
+        assert!(wgsl.contains("fn fs_main("));
+        assert!(wgsl.contains("@location(6) a_Color0"));
+        assert!(!wgsl.contains("discard"));
+    }
+
+    #[test]
+    fn test_indirect_alpha_test_fog() {
+        let mut material = material();
+        material.tex_gens.push(TexGen { type_: tex_gen::MTX3X4, source: tex_gen::SRC_POS, matrix: tex_gen::IDENTITY, normalize: true, post_matrix: tex_gen::PTTEXMTX0 });
+        material.ind_tex_stages.push(IndTexStage { tex_coord_id: 1, texture: 1, scale_s: 1, scale_t: 2 });
+        let mut stage = tev_stage();
+        stage.color_op = tev_op::COMP_RGB8_GT;
+        stage.alpha_op = tev_op::COMP_GR16_EQ;
+        stage.color_in_d = cc::KONST;
+        stage.konst_color_sel = 0x15;
+        stage.channel_id = ras_channel::ALPHA_BUMP;
+        stage.ind_tex_alpha_sel = ind_tex::ALPHA_S;
+        stage.ind_tex_matrix = ind_tex::MTX_0;
+        stage.ind_tex_bias_sel = 3;
+        stage.ind_tex_wrap_s = 2;
+        stage.ras_swap_table = Some([1, 1, 1, 3]);
+        material.tev_stages.push(stage);
+        material.alpha_test = AlphaTest { op: alpha_op::AND, compare_a: compare::GREATER, reference_a: 0.5, compare_b: compare::ALWAYS, reference_b: 0.0 };
+        material.rop_info = RopInfo { fog_type: fog_type::EXP2, dst_alpha: Some(1.0) };
+        material.has_fog_block = Some(true);
+
+        let wgsl = generate_wgsl(&material).unwrap();
+        assert!(wgsl.contains("discard"));
+        assert!(wgsl.contains("exp2("));
+        assert!(wgsl.contains("t_IndTexCoord0"));
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let mut material = material();
+        material.tex_gens[0].type_ = 2;
+        assert!(generate_wgsl(&material).is_err());
+
+        let mut material = self::material();
+        material.rop_info.fog_type = fog_type::LIN;
+        assert!(generate_wgsl(&material).unwrap_err().contains("hasFogBlock"));
+    }
+
+    // Every option the generator handles, one at a time, through naga's validator.
+    #[test]
+    fn test_validate_all_options() {
+        let mut materials = Vec::new();
+
+        let ops = [tev_op::ADD, tev_op::SUB, tev_op::COMP_R8_GT, tev_op::COMP_R8_EQ, tev_op::COMP_GR16_GT, tev_op::COMP_GR16_EQ,
+            tev_op::COMP_BGR24_GT, tev_op::COMP_BGR24_EQ, tev_op::COMP_RGB8_GT, tev_op::COMP_RGB8_EQ];
+        for op in ops {
+            for bias in 0..3 {
+                for scale in 0..4 {
+                    for clamp in [false, true] {
+                        materials.push(with_stage(format!("op {} bias {} scale {} clamp {}", op, bias, scale, clamp), |s| {
+                            (s.color_op, s.color_bias, s.color_scale, s.color_clamp) = (op, bias, scale, clamp);
+                            (s.alpha_op, s.alpha_bias, s.alpha_scale, s.alpha_clamp) = (op, bias, scale, clamp);
+                        }));
+                    }
+                }
+            }
+        }
+        for slot in 0..4 {
+            for color_in in 0..16 {
+                materials.push(with_stage(format!("color input {} = {}", slot, color_in), |s| {
+                    *[&mut s.color_in_a, &mut s.color_in_b, &mut s.color_in_c, &mut s.color_in_d][slot] = color_in;
+                }));
+            }
+            for alpha_in in 0..8 {
+                materials.push(with_stage(format!("alpha input {} = {}", slot, alpha_in), |s| {
+                    *[&mut s.alpha_in_a, &mut s.alpha_in_b, &mut s.alpha_in_c, &mut s.alpha_in_d][slot] = alpha_in;
+                }));
+            }
+        }
+        for sel in (0x00..=0x07).chain(0x0C..=0x1F) {
+            materials.push(with_stage(format!("konst color {}", sel), |s| (s.color_in_a, s.konst_color_sel) = (cc::KONST, sel)));
+        }
+        for sel in (0x00..=0x07).chain(0x10..=0x1F) {
+            materials.push(with_stage(format!("konst alpha {}", sel), |s| (s.alpha_in_a, s.konst_alpha_sel) = (ca::KONST, sel)));
+        }
+        for reg in 0..4 {
+            materials.push(with_stage(format!("registers {}", reg), |s| {
+                (s.color_in_a, s.alpha_in_a, s.color_reg_id, s.alpha_reg_id) = (cc::CPREV + reg * 2, ca::APREV + reg, reg, 3 - reg);
+            }));
+        }
+        for table in [[0, 1, 2, 3], [3, 2, 1, 0], [1, 1, 1, 3], [2, 2, 2, 2]] {
+            materials.push(with_stage(format!("swap tables {:?}", table), |s| {
+                (s.color_in_a, s.alpha_in_a) = (cc::TEXA, ca::RASA);
+                (s.tex_swap_table, s.ras_swap_table) = (Some(table), Some(table));
+            }));
+        }
+        for channel in [ras_channel::COLOR0A0, ras_channel::COLOR1A1, ras_channel::ALPHA_BUMP, ras_channel::ALPHA_BUMP_N, ras_channel::COLOR_ZERO] {
+            for format in [ind_tex::FORMAT_8, ind_tex::FORMAT_5, ind_tex::FORMAT_4, ind_tex::FORMAT_3] {
+                for sel in [ind_tex::ALPHA_S, ind_tex::ALPHA_T, ind_tex::ALPHA_U] {
+                    materials.push(indirect_material(format!("channel {} format {} alpha sel {}", channel, format, sel), |s| {
+                        (s.channel_id, s.ind_tex_format, s.ind_tex_alpha_sel) = (channel, format, sel);
+                    }));
+                }
+            }
+        }
+        for matrix in [ind_tex::MTX_OFF, ind_tex::MTX_0, ind_tex::MTX_0 + 1, ind_tex::MTX_2] {
+            for bias_sel in 0..8 {
+                for add_prev in [false, true] {
+                    materials.push(indirect_material(format!("ind matrix {} bias {} add prev {}", matrix, bias_sel, add_prev), |s| {
+                        (s.ind_tex_matrix, s.ind_tex_bias_sel, s.ind_tex_add_prev) = (matrix, bias_sel, add_prev);
+                    }));
+                }
+            }
+        }
+        for wrap in 0..=6 {
+            materials.push(indirect_material(format!("ind wrap {}", wrap), |s| (s.ind_tex_wrap_s, s.ind_tex_wrap_t) = (wrap, 6 - wrap)));
+        }
+        materials.push(indirect_material("no texture".to_string(), |s| (s.tex_map, s.tex_coord_id) = (TEXMAP_NULL, TEXCOORD_NULL)));
+
+        let sources = [tex_gen::SRC_POS, tex_gen::SRC_NRM, tex_gen::SRC_BINRM, tex_gen::SRC_TANGENT, tex_gen::SRC_TEX0, tex_gen::SRC_TEX0 + 3,
+            tex_gen::SRC_TEX7, tex_gen::SRC_TEXCOORD0, tex_gen::SRC_COLOR0, tex_gen::SRC_COLOR1];
+        let matrices = [tex_gen::PNMTX0, tex_gen::PNMTX0 + 27, tex_gen::TEXMTX0, tex_gen::TEXMTX0 + 27, tex_gen::IDENTITY];
+        for type_ in [tex_gen::MTX3X4, tex_gen::MTX2X4, tex_gen::SRTG] {
+            for source in sources {
+                for matrix in matrices {
+                    for (normalize, post_matrix) in [(false, tex_gen::PTIDENTITY), (true, tex_gen::PTTEXMTX0), (false, tex_gen::PTTEXMTX0 + 57)] {
+                        for use_tex_mtx_idx in [false, true] {
+                            let mut material = material();
+                            material.name = format!("texgen type {} source {} matrix {} normalize {} post {} idx {}", type_, source, matrix, normalize, post_matrix, use_tex_mtx_idx);
+                            material.tex_gens.push(TexGen { type_, source, matrix, normalize, post_matrix });
+                            material.use_tex_mtx_idx = Some(vec![false, use_tex_mtx_idx]);
+                            material.tev_stages[0].tex_coord_id = 1;
+                            materials.push(material);
+                        }
+                    }
+                }
+            }
+        }
+
+        for lighting_enabled in [false, true] {
+            for (mat_color_source, amb_color_source) in [(0, 0), (0, color_src::VTX), (color_src::VTX, 0)] {
+                for lit_mask in [0x00, 0x01, 0x81, 0xFF] {
+                    for diffuse_function in [diffuse_fn::NONE, diffuse_fn::SIGN, diffuse_fn::CLAMP] {
+                        for attenuation_function in [atten_fn::SPEC, atten_fn::SPOT, atten_fn::NONE] {
+                            let chan = ColorChannelControl { lighting_enabled, mat_color_source, amb_color_source, lit_mask, diffuse_function, attenuation_function };
+                            let mut material = material();
+                            material.name = format!("{:?}", chan);
+                            material.light_channels = vec![
+                                LightChannelControl { color_channel: chan.clone(), alpha_channel: color_channel(false, 0) },
+                                LightChannelControl { color_channel: chan.clone(), alpha_channel: chan },
+                            ];
+                            material.tev_stages[0].channel_id = ras_channel::COLOR1A1;
+                            materials.push(material);
+                        }
+                    }
+                }
+            }
+        }
+
+        for op in [alpha_op::AND, alpha_op::OR, alpha_op::XOR, alpha_op::XNOR] {
+            for compare_a in 0..8 {
+                for compare_b in 0..8 {
+                    for dynamic in [false, true] {
+                        let mut material = material();
+                        material.name = format!("alpha test op {} compare {} {} dynamic {}", op, compare_a, compare_b, dynamic);
+                        material.alpha_test = AlphaTest { op, compare_a, reference_a: 0.25, compare_b, reference_b: 0.75 };
+                        material.has_dynamic_alpha_test = Some(dynamic);
+                        materials.push(material);
+                    }
+                }
+            }
+        }
+        for fog in [fog_type::NONE, fog_type::LIN, fog_type::EXP, fog_type::EXP2, fog_type::REVEXP, fog_type::REVEXP2] {
+            for dst_alpha in [None, Some(0.5)] {
+                let mut material = material();
+                material.name = format!("fog {} dst alpha {:?}", fog, dst_alpha);
+                material.rop_info = RopInfo { fog_type: fog, dst_alpha };
+                material.has_fog_block = Some(true);
+                materials.push(material);
+            }
+        }
+
+        let mut material = material();
+        material.name = "no optional blocks".to_string();
+        material.light_channels[0].color_channel.lighting_enabled = false;
+        (material.use_pn_mtx_idx, material.has_post_tex_mtx_block, material.has_lights_block) = (Some(false), Some(false), Some(false));
+        materials.push(material);
+
+        for material in &materials {
+            generate(material);
+        }
+    }
+
+    #[test]
+    fn test_tev_ops() {
+        // d - lerp(a, b, c), biased by +0.5, doubled and clamped to the 11-bit range.
+        let wgsl = generate(&with_stage("sub".to_string(), |s| {
+            (s.color_op, s.color_bias, s.color_scale, s.color_clamp) = (tev_op::SUB, tev_bias::ADDHALF, tev_scale::SCALE_2, false);
+            (s.alpha_bias, s.alpha_scale) = (tev_bias::SUBHALF, tev_scale::DIVIDE_2);
+        }));
+        assert!(wgsl.contains("clamp((((-(mix(t_TevA0_.xyz, t_TevB0_.xyz, t_TevC0_.xyz)) + t_TevD0_.xyz) + vec3(0.5f)) * 2f), vec3(-4f), vec3(4f))"));
+        assert!(wgsl.contains("saturate((((mix(t_TevA0_.w, t_TevB0_.w, t_TevC0_.w) + t_TevD0_.w) + -0.5f) * 0.5f))"));
+        // A, B and C wrap at 8 bits; D doesn't.
+        assert!(wgsl.contains("let t_TevA0_ = (vec4<f32>((vec4<i32>((vec4<f32>(vec3<f32>(), 0f) * 255f)) & vec4(255i))) * 0.003921569f);"));
+        assert!(wgsl.contains("t_TevD0_ = vec4<f32>(vec3<f32>(), 0f);"));
+
+        // The packed comparisons compare A and B as 8, 16 or 24-bit numbers and select C or zero.
+        let wgsl = generate(&with_stage("comp".to_string(), |s| (s.color_op, s.alpha_op) = (tev_op::COMP_GR16_EQ, tev_op::COMP_R8_GT)));
+        assert!(wgsl.contains("select(vec3<f32>(), t_TevC0_.xyz, (dot(t_TevA0_.xy, vec2<f32>(1f, 256f)) == dot(t_TevB0_.xy, vec2<f32>(1f, 256f)))) + t_TevD0_.xyz"));
+        assert!(wgsl.contains("select(0f, t_TevC0_.w, (t_TevA0_.x > t_TevB0_.x)) + t_TevD0_.w"));
+
+        // RGB8 compares each channel on its own. The stage writes its color and alpha to different registers.
+        let wgsl = generate(&with_stage("rgb8".to_string(), |s| {
+            (s.color_op, s.alpha_op) = (tev_op::COMP_RGB8_EQ, tev_op::COMP_BGR24_GT);
+            (s.color_in_a, s.color_in_b) = (cc::APREV, cc::HALF);
+            (s.color_reg_id, s.alpha_reg_id) = (1, 2);
+        }));
+        assert!(wgsl.contains("t_Color0_ = vec4<f32>(saturate((select(vec3<f32>(), t_TevC0_.xyz, (t_TevA0_.xyz == t_TevB0_.xyz)) + t_TevD0_.xyz))"));
+        assert!(wgsl.contains("(dot(t_TevA0_.xyz, vec3<f32>(1f, 256f, 65536f)) > dot(t_TevB0_.xyz, vec3<f32>(1f, 256f, 65536f)))"));
+        assert!(wgsl.contains(".www, 0f) * 255f)"));
+        assert!(wgsl.contains("vec4<f32>(vec3(0.5f), "));
+    }
+
+    #[test]
+    fn test_konst_and_swap_tables() {
+        let wgsl = generate(&with_stage("konst".to_string(), |s| {
+            (s.color_in_a, s.konst_color_sel, s.alpha_in_a, s.konst_alpha_sel) = (cc::KONST, 0x03, ca::KONST, 0x06);
+            (s.color_in_d, s.alpha_in_d) = (cc::KONST, ca::KONST);
+        }));
+        assert!(wgsl.contains("vec4<f32>(vec3(0.625f), 0.25f)"));
+
+        let wgsl = generate(&with_stage("konst registers".to_string(), |s| {
+            (s.color_in_a, s.konst_color_sel, s.alpha_in_a, s.konst_alpha_sel) = (cc::KONST, 0x0D, ca::KONST, 0x1E);
+        }));
+        // K1's RGB, and K2's alpha.
+        assert!(wgsl.contains("u_KonstColor[1]") && wgsl.contains("u_KonstColor[2]"));
+        assert!(!wgsl.contains("u_KonstColor[0]") && !wgsl.contains("u_KonstColor[3]"));
+
+        let wgsl = generate(&with_stage("swap".to_string(), |s| (s.tex_swap_table, s.ras_swap_table) = (Some([2, 1, 0, 3]), Some([2, 1, 0, 0]))));
+        assert!(wgsl.contains("vec4<f32>(saturate(input.v_Color0_.zyx), saturate(input.v_Color0_.x))"));
+        assert!(wgsl.contains(".zyx, "));
+    }
+
+    #[test]
+    fn test_indirect() {
+        let wgsl = generate(&indirect_material("indirect".to_string(), |s| {
+            (s.ind_tex_matrix, s.ind_tex_bias_sel) = (ind_tex::MTX_0 + 1, 3);
+            (s.ind_tex_wrap_s, s.ind_tex_wrap_t) = (2, ind_tex::WRAP_0);
+            (s.channel_id, s.ind_tex_alpha_sel) = (ras_channel::ALPHA_BUMP_N, ind_tex::ALPHA_T);
+        }));
+        // The indirect stage scales its coordinates and reads the texture as ABG.
+        assert!(wgsl.contains("textureSampleBias(T_u_Texture1_, S_u_Texture1_, (input.v_TexCoord1_ * vec2<f32>(0.5f, 0.25f))"));
+        assert!(wgsl.contains("let t_IndTexCoord0_ = ("));
+        assert!(wgsl.contains(".wzy * 255f)"));
+        // S and T biased by -128 for the 8-bit format, then through the second matrix.
+        assert!(wgsl.contains("(t_IndTexCoord0_ + vec3<f32>(-128f, -128f, 0f))"));
+        assert!(wgsl.contains("u_IndTexMtx[1]") && !wgsl.contains("u_IndTexMtx[0]"));
+        // S wraps at 128 texels, T wraps to zero.
+        assert!(wgsl.contains(" * 0.0078125f)) * 128f)), 0f) + vec2<f32>(dot("));
+        // Alpha bump takes the top 5 bits of T, scaled up for the normalized version.
+        assert!(wgsl.contains("(t_IndTexCoord0_.y * 255f)) & 248i)) * 0.003921569f) * 1.0282258f)"));
+        // The coordinates are in texels, so they are divided back down for sampling.
+        assert!(wgsl.contains("u_TextureSizes[0]"));
+
+        let wgsl = generate(&indirect_material("add prev".to_string(), |s| {
+            (s.ind_tex_matrix, s.ind_tex_add_prev, s.tex_coord_id) = (ind_tex::MTX_0, true, TEXCOORD_NULL);
+        }));
+        assert!(wgsl.contains("t_TexCoord = (_e"));
+        assert!(wgsl.contains(" + vec2<f32>(dot("));
+    }
+
+    #[test]
+    fn test_tex_gens() {
+        let mut material = material();
+        material.tex_gens = vec![
+            TexGen { type_: tex_gen::MTX3X4, source: tex_gen::SRC_POS, matrix: tex_gen::PNMTX0 + 3, normalize: true, post_matrix: tex_gen::PTTEXMTX0 + 6 },
+            TexGen { type_: tex_gen::SRTG, source: tex_gen::SRC_COLOR0, matrix: tex_gen::IDENTITY, normalize: false, post_matrix: tex_gen::PTIDENTITY },
+            TexGen { type_: tex_gen::MTX2X4, source: tex_gen::SRC_TEXCOORD0, matrix: tex_gen::TEXMTX0 + 9, normalize: false, post_matrix: tex_gen::PTIDENTITY },
+            TexGen { type_: tex_gen::MTX3X4, source: tex_gen::SRC_NRM, matrix: tex_gen::TEXMTX0, normalize: false, post_matrix: tex_gen::PTIDENTITY },
+        ];
+        material.use_tex_mtx_idx = Some(vec![false, false, false, true]);
+        let wgsl = generate(&material);
+
+        // PNMTX1, normalized, then post matrix 2.
+        assert!(wgsl.contains("u_PosMtx[1]"));
+        assert!(wgsl.contains("vec4<f32>(normalize(vec3<f32>(dot("));
+        assert!(wgsl.contains("u_PostTexMtx[2]"));
+        // SRTG passes the color through.
+        assert!(wgsl.contains("let t_TexCoord1_ = vec3<f32>(t_Color0_"));
+        // TEXCOORD0 as input through TEXMTX3.
+        assert!(wgsl.contains("vec4<f32>(t_TexCoord0_, 1f)"));
+        assert!(wgsl.contains("u_TexMtx[3]"));
+        // Per-vertex matrix index: position matrices below 10, texture matrices from 10 and identity at 20.
+        assert!(wgsl.contains("@location(1) a_TexMtx0123Idx: vec4<f32>"));
+        assert!(wgsl.contains("u32((a_TexMtx0123Idx.w * 256f))"));
+        assert!(wgsl.contains(" - 10u)]") && wgsl.contains(" >= 10u)") && wgsl.contains(" == 20u)"));
+        // 3x4 texgens are projected in the fragment shader.
+        assert!(wgsl.contains("t_TexCoord0_, t_TexCoord1_.xy, t_TexCoord2_.xy, t_TexCoord3_)"));
+        assert!(wgsl.contains("(input.v_TexCoord0_.xy / vec2(input.v_TexCoord0_.z))"));
+    }
+
+    #[test]
+    fn test_lighting() {
+        let mut material = material();
+        let chan = &mut material.light_channels[0].color_channel;
+        (chan.lit_mask, chan.mat_color_source) = (0x05, 0);
+        (chan.diffuse_function, chan.attenuation_function) = (diffuse_fn::SIGN, atten_fn::SPEC);
+        let wgsl = generate(&material);
+        assert!(wgsl.contains("u_ColorMatReg[0]"));
+        assert!(wgsl.contains("u_LightParams[0]") && wgsl.contains("u_LightParams[2]") && !wgsl.contains("u_LightParams[1]"));
+        // Specular: N.H, but only for lights in front of the surface. Sign diffuse isn't clamped.
+        assert!(wgsl.contains("select(0f, max(0f, dot(t_Normal, "));
+        assert!(wgsl.contains(") >= 0f))"));
+        assert!(wgsl.contains(".xyzw * (dot(t_Normal, "));
+        // The alpha channel is unlit, and comes straight from the vertex color.
+        assert!(wgsl.contains(".xyz, a_Color0_.w)"));
+
+        // Spot: clamped diffuse, and normalized distance attenuation.
+        let wgsl = generate(&self::material());
+        assert!(wgsl.contains(".xyzw * (max(0f, dot(t_Normal, "));
+        assert!(wgsl.contains("dot(normalize("));
+
+        // No attenuation ignores the diffuse function too.
+        let mut material = self::material();
+        let chan = color_channel(true, 0x02);
+        let chan = ColorChannelControl { attenuation_function: atten_fn::NONE, ..chan };
+        material.light_channels.push(LightChannelControl { color_channel: chan.clone(), alpha_channel: chan });
+        let wgsl = generate(&material);
+        assert!(wgsl.contains("@location(7) a_Color1_"));
+        assert!(wgsl.contains("(a_Color1_ * saturate((_e"));
+        assert!(wgsl.contains(".xyzw * (1f * 1f))"));
+
+        let mut material = self::material();
+        material.light_channels[0].color_channel.lighting_enabled = false;
+        let wgsl = generate(&material);
+        assert!(!wgsl.contains("u_LightParams[") && !wgsl.contains("a_Normal"));
+    }
+
+    #[test]
+    fn test_alpha_test_fog() {
+        let mut material = material();
+        material.alpha_test = AlphaTest { op: alpha_op::XOR, compare_a: compare::LESS, reference_a: 0.25, compare_b: compare::GEQUAL, reference_b: 0.75 };
+        material.rop_info = RopInfo { fog_type: fog_type::REVEXP2, dst_alpha: Some(0.5) };
+        material.has_fog_block = Some(true);
+        let wgsl = generate(&material);
+        assert!(wgsl.contains("if !(((t_PixelOut.w < 0.25f) != (t_PixelOut.w >= 0.75f))) {"));
+        assert!(wgsl.contains("discard;"));
+        assert!(wgsl.contains("let t_FogBase = select("));
+        assert!(wgsl.contains("(1f - saturate((t_FogBase - "));
+        assert!(wgsl.contains("let t_Fog = (1f - exp2((("));
+        assert!(wgsl.contains(", t_PixelOut.w).xyz, 0.5f)"));
+
+        material.has_dynamic_alpha_test = Some(true);
+        material.alpha_test.op = alpha_op::XNOR;
+        let wgsl = generate(&material);
+        assert!(wgsl.contains("u_DynamicAlphaParams"));
+        assert!(wgsl.contains(") == (t_PixelOut.w >= "));
+
+        // Neither side of the OR ever passes.
+        material.alpha_test = AlphaTest { op: alpha_op::OR, compare_a: compare::NEVER, reference_a: 0.0, compare_b: compare::NEVER, reference_b: 0.0 };
+        assert!(generate(&material).contains("discard;"));
+    }
+}
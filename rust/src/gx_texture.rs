@@ -1,8 +1,8 @@
 
 use wasm_bindgen::prelude::wasm_bindgen;
-use crate::util;
+use crate::util::{self, ByteReader, OutOfBounds};
 
-fn s3tcblend(a_: u8, b_: u8) -> u8 {
+pub(crate) fn s3tcblend(a_: u8, b_: u8) -> u8 {
     // return (a*3 + b*5) / 8;
     let a = a_ as u32;
     let b = b_ as u32;
@@ -10,7 +10,7 @@ fn s3tcblend(a_: u8, b_: u8) -> u8 {
     return tmp as u8;
 }
 
-fn halfblend(a_: u8, b_: u8) -> u8 {
+pub(crate) fn halfblend(a_: u8, b_: u8) -> u8 {
     let a = a_ as u32;
     let b = b_ as u32;
     let tmp = (a + b) >> 1;
@@ -41,45 +41,45 @@ fn decode_rgb565_to_rgba8(dst: &mut[u8], p: u16) {
 }
 
 trait TiledDecoder {
-    fn decode_single_pixel(self: &Self, src: &[u8], idx: usize, dst: &mut [u8]);
+    // Decodes a whole block, in raster order.
+    fn decode_block(&self, src: &mut ByteReader, dst: &mut [[u8; 4]]) -> Result<(), OutOfBounds>;
     fn block_width() -> usize;
     fn block_height() -> usize;
 }
 
-fn decode_tiled<T: TiledDecoder>(t: T, src: &[u8], w: usize, h: usize) -> Vec<u8> {
-    let mut idx: usize = 0;
+fn decode_tiled<T: TiledDecoder>(t: T, src: &[u8], w: usize, h: usize) -> Result<Vec<u8>, OutOfBounds> {
+    let mut src = ByteReader::be(src);
     let mut dst = vec![0x00; w*h*4];
 
     let bw = T::block_width();
     let bh = T::block_height();
+    let mut block = vec![[0x00; 4]; bw * bh];
     for yy in (0..h).step_by(bh) {
         for xx in (0..w).step_by(bw) {
+            t.decode_block(&mut src, &mut block)?;
             for y in 0..bh {
                 for x in 0..bw {
-                    let dst_px = (yy + y) * w + (xx + x);
-                    let dst_offs = dst_px * 4;
                     if xx + x < w && yy + y < h {
-                        t.decode_single_pixel(src, idx, &mut dst[dst_offs..dst_offs + 4]);
+                        let dst_offs = ((yy + y) * w + (xx + x)) * 4;
+                        dst[dst_offs..dst_offs + 4].copy_from_slice(&block[y * bw + x]);
                     }
-                    idx += 1;
                 }
             }
         }
     }
 
-    dst
+    Ok(dst)
 }
 
 struct TiledDecoderI4 {}
 impl TiledDecoder for TiledDecoderI4 {
-    fn decode_single_pixel(self: &Self, src: &[u8], idx: usize, dst: &mut [u8]) {
-        let ii = src[idx >> 1];
-        let i4 = ii >> (if (idx & 1) != 0 { 0 } else { 4 }) & 0x0F;
-        let i = util::expand_n_to_8(4, i4);
-        dst[0] = i;
-        dst[1] = i;
-        dst[2] = i;
-        dst[3] = i;
+    fn decode_block(&self, src: &mut ByteReader, dst: &mut [[u8; 4]]) -> Result<(), OutOfBounds> {
+        for pair in dst.chunks_exact_mut(2) {
+            let ii = src.read_u8()?;
+            pair[0] = [util::expand_n_to_8(4, ii >> 4); 4];
+            pair[1] = [util::expand_n_to_8(4, ii & 0x0F); 4];
+        }
+        Ok(())
     }
 
     fn block_width() -> usize { 8 }
@@ -88,12 +88,11 @@ impl TiledDecoder for TiledDecoderI4 {
 
 struct TiledDecoderI8 {}
 impl TiledDecoder for TiledDecoderI8 {
-    fn decode_single_pixel(self: &Self, src: &[u8], idx: usize, dst: &mut [u8]) {
-        let i = src[idx];
-        dst[0] = i;
-        dst[1] = i;
-        dst[2] = i;
-        dst[3] = i;
+    fn decode_block(&self, src: &mut ByteReader, dst: &mut [[u8; 4]]) -> Result<(), OutOfBounds> {
+        for px in dst.iter_mut() {
+            *px = [src.read_u8()?; 4];
+        }
+        Ok(())
     }
 
     fn block_width() -> usize { 8 }
@@ -102,14 +101,14 @@ impl TiledDecoder for TiledDecoderI8 {
 
 struct TiledDecoderIA4 {}
 impl TiledDecoder for TiledDecoderIA4 {
-    fn decode_single_pixel(self: &Self, src: &[u8], idx: usize, dst: &mut [u8]) {
-        let ia = src[idx];
-        let a = util::expand_n_to_8(4, ia >> 4);
-        let i = util::expand_n_to_8(4, ia & 0x0F);
-        dst[0] = i;
-        dst[1] = i;
-        dst[2] = i;
-        dst[3] = a;
+    fn decode_block(&self, src: &mut ByteReader, dst: &mut [[u8; 4]]) -> Result<(), OutOfBounds> {
+        for px in dst.iter_mut() {
+            let ia = src.read_u8()?;
+            let a = util::expand_n_to_8(4, ia >> 4);
+            let i = util::expand_n_to_8(4, ia & 0x0F);
+            *px = [i, i, i, a];
+        }
+        Ok(())
     }
 
     fn block_width() -> usize { 8 }
@@ -118,13 +117,13 @@ impl TiledDecoder for TiledDecoderIA4 {
 
 struct TiledDecoderIA8 {}
 impl TiledDecoder for TiledDecoderIA8 {
-    fn decode_single_pixel(self: &Self, src: &[u8], idx: usize, dst: &mut [u8]) {
-        let a = src[idx * 2 + 0];
-        let i = src[idx * 2 + 1];
-        dst[0] = i;
-        dst[1] = i;
-        dst[2] = i;
-        dst[3] = a;
+    fn decode_block(&self, src: &mut ByteReader, dst: &mut [[u8; 4]]) -> Result<(), OutOfBounds> {
+        for px in dst.iter_mut() {
+            let a = src.read_u8()?;
+            let i = src.read_u8()?;
+            *px = [i, i, i, a];
+        }
+        Ok(())
     }
 
     fn block_width() -> usize { 4 }
@@ -133,9 +132,11 @@ impl TiledDecoder for TiledDecoderIA8 {
 
 struct TiledDecoderRGB565 {}
 impl TiledDecoder for TiledDecoderRGB565 {
-    fn decode_single_pixel(self: &Self, src: &[u8], idx: usize, dst: &mut [u8]) {
-        let p = util::get_uint16_be(src, idx * 2);
-        decode_rgb565_to_rgba8(dst, p);
+    fn decode_block(&self, src: &mut ByteReader, dst: &mut [[u8; 4]]) -> Result<(), OutOfBounds> {
+        for px in dst.iter_mut() {
+            decode_rgb565_to_rgba8(px, src.read_u16()?);
+        }
+        Ok(())
     }
 
     fn block_width() -> usize { 4 }
@@ -144,17 +145,19 @@ impl TiledDecoder for TiledDecoderRGB565 {
 
 struct TiledDecoderRGB5A3 {}
 impl TiledDecoder for TiledDecoderRGB5A3 {
-    fn decode_single_pixel(self: &Self, src: &[u8], idx: usize, dst: &mut [u8]) {
-        let p = util::get_uint16_be(src, idx * 2);
-        decode_rgb5a3_to_rgba8(dst, p);
+    fn decode_block(&self, src: &mut ByteReader, dst: &mut [[u8; 4]]) -> Result<(), OutOfBounds> {
+        for px in dst.iter_mut() {
+            decode_rgb5a3_to_rgba8(px, src.read_u16()?);
+        }
+        Ok(())
     }
 
     fn block_width() -> usize { 4 }
     fn block_height() -> usize { 4 }
 }
 
-fn decode_rgba8(src: &[u8], w: usize, h: usize) -> Vec<u8> {
-    let mut src_offs = 0;
+fn decode_rgba8(src: &[u8], w: usize, h: usize) -> Result<Vec<u8>, OutOfBounds> {
+    let mut src = ByteReader::be(src);
     let mut dst = vec![0x00; w*h*4];
 
     // RGBA8 is a bit special, so we hand-code this one.
@@ -165,53 +168,49 @@ fn decode_rgba8(src: &[u8], w: usize, h: usize) -> Vec<u8> {
             for y in 0..bh {
                 for x in 0..bw {
                     let write = xx + x < w && yy + y < h;
+                    let ar = src.read_bytes(2)?;
 
                     if write {
                         let dst_px = (yy + y) * w + (xx + x);
                         let dst_offs = dst_px * 4;
-                        dst[dst_offs + 3] = src[src_offs + 0x00];
-                        dst[dst_offs + 0] = src[src_offs + 0x01];
+                        dst[dst_offs + 3] = ar[0];
+                        dst[dst_offs] = ar[1];
                     }
-
-                    src_offs += 2;
                 }
             }
 
             for y in 0..bh {
                 for x in 0..bw {
                     let write = xx + x < w && yy + y < h;
+                    let gb = src.read_bytes(2)?;
 
                     if write {
                         let dst_px = (yy + y) * w + (xx + x);
                         let dst_offs = dst_px * 4;
-                        dst[dst_offs + 1] = src[src_offs + 0x00];
-                        dst[dst_offs + 2] = src[src_offs + 0x01];
+                        dst[dst_offs + 1] = gb[0];
+                        dst[dst_offs + 2] = gb[1];
                     }
-
-                    src_offs += 2;
                 }
             }
         }
     }
 
-    dst
+    Ok(dst)
 }
 
-fn decode_cmpr(src: &[u8], w: usize, h: usize) -> Vec<u8> {
+fn decode_cmpr(src: &[u8], w: usize, h: usize) -> Result<Vec<u8>, OutOfBounds> {
     // CMPR swizzles macroblocks to be in a 2x2 grid of UL, UR, BL, BR.
-    let mut src_offs = 0;
+    let mut src = ByteReader::be(src);
     let mut dst = vec![0x00; w*h*4];
 
     for yy in (0..h).step_by(8) {
         for xx in (0..w).step_by(8) {
             for yb in (0..8).step_by(4) {
                 for xb in (0..8).step_by(4) {
-                    let src_offs_idx = src_offs;
-                    src_offs += 0x08;
-
                     // CMPR difference: Big-endian color1/2
-                    let color1 = util::get_uint16_be(src, src_offs_idx + 0x00);
-                    let color2 = util::get_uint16_be(src, src_offs_idx + 0x02);
+                    let color1 = src.read_u16()?;
+                    let color2 = src.read_u16()?;
+                    let indices = src.read_bytes(4)?;
 
                     // Fill in first two colors in color table.
                     let mut color_table = [0x00; 16];
@@ -250,8 +249,8 @@ fn decode_cmpr(src: &[u8], w: usize, h: usize) -> Vec<u8> {
                         color_table[15] = 0x00;
                     }
 
-                    for y in 0..4 {
-                        let mut bits = src[src_offs_idx + 0x04 + y];
+                    for (y, &row) in indices.iter().enumerate() {
+                        let mut bits = row;
                         for x in 0..4 {
                             if xx + xb + x >= w || yy + yb + y >= h {
                                 continue;
@@ -270,7 +269,7 @@ fn decode_cmpr(src: &[u8], w: usize, h: usize) -> Vec<u8> {
         }
     }
 
-    dst
+    Ok(dst)
 }
 
 #[wasm_bindgen]
@@ -295,16 +294,17 @@ pub enum PixelFormat {
     C14X2,
 }
 
-fn decode_palette(palette_fmt: PaletteFormat, palette_src: &[u8]) -> Vec<u8> {
+fn decode_palette(palette_fmt: PaletteFormat, palette_src: &[u8]) -> Result<Vec<u8>, OutOfBounds> {
+    let mut src = ByteReader::be(palette_src);
     let palette_count = palette_src.len() / 2;
     let mut dst = vec![0x00; palette_count * 4];
 
     match palette_fmt {
         PaletteFormat::IA8 => {
             for i in 0..palette_count {
-                let aa = palette_src[i * 2 + 0];
-                let ii = palette_src[i * 2 + 1];
-                dst[i * 4 + 0] = ii;
+                let aa = src.read_u8()?;
+                let ii = src.read_u8()?;
+                dst[i * 4] = ii;
                 dst[i * 4 + 1] = ii;
                 dst[i * 4 + 2] = ii;
                 dst[i * 4 + 3] = aa;
@@ -313,20 +313,25 @@ fn decode_palette(palette_fmt: PaletteFormat, palette_src: &[u8]) -> Vec<u8> {
 
         PaletteFormat::RGB565 => {
             for i in 0..palette_count {
-                let p = util::get_uint16_be(palette_src, i * 2);
-                decode_rgb565_to_rgba8(&mut dst[i*4+0 .. i*4+4], p);
+                let p = src.read_u16()?;
+                decode_rgb565_to_rgba8(&mut dst[i*4 .. i*4+4], p);
             }
         },
 
         PaletteFormat::RGB5A3 => {
             for i in 0..palette_count {
-                let p = util::get_uint16_be(palette_src, i * 2);
-                decode_rgb5a3_to_rgba8(&mut dst[i*4+0 .. i*4+4], p);
+                let p = src.read_u16()?;
+                decode_rgb5a3_to_rgba8(&mut dst[i*4 .. i*4+4], p);
             }
         },
     }
 
-    dst
+    Ok(dst)
+}
+
+fn copy_palette_entry(palette: &[u8], idx: usize, dst: &mut [u8; 4]) -> Result<(), OutOfBounds> {
+    dst.copy_from_slice(ByteReader::be(palette).at(idx * 4)?.read_bytes(4)?);
+    Ok(())
 }
 
 struct TiledDecoderC4<'a> {
@@ -334,13 +339,13 @@ struct TiledDecoderC4<'a> {
 }
 
 impl TiledDecoder for TiledDecoderC4<'_> {
-    fn decode_single_pixel(self: &Self, src: &[u8], idx: usize, dst: &mut [u8]) {
-        let ii = src[idx >> 1];
-        let idx = (ii >> (if (idx & 1) != 0 { 0 } else { 4 }) & 0x0F) as usize;
-        dst[0] = self.palette[idx * 4 + 0];
-        dst[1] = self.palette[idx * 4 + 1];
-        dst[2] = self.palette[idx * 4 + 2];
-        dst[3] = self.palette[idx * 4 + 3];
+    fn decode_block(&self, src: &mut ByteReader, dst: &mut [[u8; 4]]) -> Result<(), OutOfBounds> {
+        for pair in dst.chunks_exact_mut(2) {
+            let ii = src.read_u8()?;
+            copy_palette_entry(self.palette, (ii >> 4) as usize, &mut pair[0])?;
+            copy_palette_entry(self.palette, (ii & 0x0F) as usize, &mut pair[1])?;
+        }
+        Ok(())
     }
 
     fn block_width() -> usize { 8 }
@@ -352,12 +357,11 @@ struct TiledDecoderC8<'a> {
 }
 
 impl TiledDecoder for TiledDecoderC8<'_> {
-    fn decode_single_pixel(self: &Self, src: &[u8], idx: usize, dst: &mut [u8]) {
-        let idx = src[idx] as usize;
-        dst[0] = self.palette[idx * 4 + 0];
-        dst[1] = self.palette[idx * 4 + 1];
-        dst[2] = self.palette[idx * 4 + 2];
-        dst[3] = self.palette[idx * 4 + 3];
+    fn decode_block(&self, src: &mut ByteReader, dst: &mut [[u8; 4]]) -> Result<(), OutOfBounds> {
+        for px in dst.iter_mut() {
+            copy_palette_entry(self.palette, src.read_u8()? as usize, px)?;
+        }
+        Ok(())
     }
 
     fn block_width() -> usize { 8 }
@@ -369,21 +373,28 @@ struct TiledDecoderC14X2<'a> {
 }
 
 impl TiledDecoder for TiledDecoderC14X2<'_> {
-    fn decode_single_pixel(self: &Self, src: &[u8], idx: usize, dst: &mut [u8]) {
-        let idx = (util::get_uint16_be(src, idx * 2) as usize) & 0x3FFF;
-        dst[0] = self.palette[idx * 4 + 0];
-        dst[1] = self.palette[idx * 4 + 1];
-        dst[2] = self.palette[idx * 4 + 2];
-        dst[3] = self.palette[idx * 4 + 3];
+    fn decode_block(&self, src: &mut ByteReader, dst: &mut [[u8; 4]]) -> Result<(), OutOfBounds> {
+        for px in dst.iter_mut() {
+            copy_palette_entry(self.palette, (src.read_u16()? & 0x3FFF) as usize, px)?;
+        }
+        Ok(())
     }
 
     fn block_width() -> usize { 4 }
     fn block_height() -> usize { 4 }
 }
 
+fn decode_palette_src(palette_fmt: Option<PaletteFormat>, palette_src: Option<Box<[u8]>>) -> Result<Vec<u8>, String> {
+    match (palette_fmt, palette_src) {
+        (Some(palette_fmt), Some(palette_src)) => Ok(decode_palette(palette_fmt, &palette_src)?),
+        _ => Err("Palette format and data are required for color-indexed textures".to_string()),
+    }
+}
+
+// Fails if the texture or palette data is too short for its size.
 #[wasm_bindgen]
-pub fn decode_texture(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src: &[u8], palette_src: Option<Box<[u8]>>, w: usize, h: usize) -> Vec<u8> {
-    match fmt {
+pub fn decode_texture(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src: &[u8], palette_src: Option<Box<[u8]>>, w: usize, h: usize) -> Result<Vec<u8>, String> {
+    let dst = match fmt {
         PixelFormat::I4 => decode_tiled(TiledDecoderI4{}, src, w, h),
         PixelFormat::I8 => decode_tiled(TiledDecoderI8{}, src, w, h),
         PixelFormat::IA4 => decode_tiled(TiledDecoderIA4{}, src, w, h),
@@ -393,16 +404,77 @@ pub fn decode_texture(fmt: PixelFormat, palette_fmt: Option<PaletteFormat>, src:
         PixelFormat::RGBA8 => decode_rgba8(src, w, h),
         PixelFormat::CMPR => decode_cmpr(src, w, h),
         PixelFormat::C4 => {
-            let palette = decode_palette(palette_fmt.unwrap(), &palette_src.unwrap());
+            let palette = decode_palette_src(palette_fmt, palette_src)?;
             decode_tiled(TiledDecoderC4{ palette: &palette }, src, w, h)
         },
         PixelFormat::C8 => {
-            let palette = decode_palette(palette_fmt.unwrap(), &palette_src.unwrap());
+            let palette = decode_palette_src(palette_fmt, palette_src)?;
             decode_tiled(TiledDecoderC8{ palette: &palette }, src, w, h)
         },
         PixelFormat::C14X2 => {
-            let palette = decode_palette(palette_fmt.unwrap(), &palette_src.unwrap());
+            let palette = decode_palette_src(palette_fmt, palette_src)?;
             decode_tiled(TiledDecoderC14X2{ palette: &palette }, src, w, h)
         },
+    };
+    Ok(dst?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_cmpr() {
+        // One 8x8 tile: white, black, a 3-color block with transparency, and a gradient.
+        let src = [
+            0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0xFF, 0xFF, 0x00, 0x00, 0x55, 0x55, 0x55, 0x55,
+            0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0xFF, 0xFF, 0x00, 0x00, 0x1B, 0x1B, 0x1B, 0x1B,
+        ];
+        let dst = decode_texture(PixelFormat::CMPR, None, &src, None, 8, 8).unwrap();
+        let pixel = |x: usize, y: usize| &dst[(y * 8 + x) * 4..(y * 8 + x) * 4 + 4];
+        assert_eq!(pixel(0, 0), [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(pixel(7, 0), [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(0, 7), [0x7F, 0x7F, 0x7F, 0x00]);
+        assert_eq!((4..8).map(|x| pixel(x, 4)[0]).collect::<Vec<_>>(), [0xFF, 0x00, 0x9F, 0x5F]);
+
+        // Smaller textures still take a whole tile.
+        assert_eq!(decode_texture(PixelFormat::CMPR, None, &src, None, 4, 4).unwrap().len(), 4 * 4 * 4);
+        assert!(decode_texture(PixelFormat::CMPR, None, &src[..24], None, 4, 4).is_err());
+    }
+
+    #[test]
+    fn test_decode_tiled() {
+        // I4 packs two pixels per byte, high nibble first; an 8x8 tile is 32 bytes.
+        let dst = decode_texture(PixelFormat::I4, None, &[0x1F; 32], None, 4, 2).unwrap();
+        assert_eq!(dst.chunks_exact(4).map(|px| px[0]).collect::<Vec<_>>(), [0x11, 0xFF, 0x11, 0xFF, 0x11, 0xFF, 0x11, 0xFF]);
+        assert!(dst.chunks_exact(4).all(|px| px[0] == px[3]));
+
+        // IA8 is alpha then intensity, in 4x4 tiles; the second tile holds pixels 4-7 of each row.
+        let src: Vec<u8> = (0..32).flat_map(|i| [0x80, i]).collect();
+        let dst = decode_texture(PixelFormat::IA8, None, &src, None, 8, 4).unwrap();
+        assert_eq!(&dst[0..8], [0, 0, 0, 0x80, 1, 1, 1, 0x80]);
+        assert_eq!(&dst[4 * 4..4 * 4 + 4], [16, 16, 16, 0x80]);
+        assert_eq!(&dst[8 * 4..8 * 4 + 4], [4, 4, 4, 0x80]);
+
+        // RGB5A3: opaque RGB555 red, then 3-bit alpha over RGB444 green.
+        let src = [[0xFC, 0x00], [0x40, 0xF0]].repeat(8).concat();
+        let dst = decode_texture(PixelFormat::RGB5A3, None, &src, None, 4, 4).unwrap();
+        assert_eq!(&dst[0..8], [0xFF, 0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0x92]);
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        assert!(decode_texture(PixelFormat::RGBA8, None, &[0; 63], None, 4, 4).is_err());
+        assert!(decode_texture(PixelFormat::RGB565, None, &[0; 32], None, 4, 4).is_ok());
+        assert!(decode_texture(PixelFormat::RGB565, None, &[0; 31], None, 4, 4).is_err());
+
+        // C8 indices past the end of the palette, and a missing palette.
+        let palette: Box<[u8]> = Box::new([0x80, 0xFF, 0x00, 0x10]);
+        let dst = decode_texture(PixelFormat::C8, Some(PaletteFormat::IA8), &[1; 32], Some(palette.clone()), 8, 4).unwrap();
+        assert_eq!(&dst[..4], [0x10, 0x10, 0x10, 0x00]);
+        assert!(decode_texture(PixelFormat::C8, Some(PaletteFormat::IA8), &[2; 32], Some(palette), 8, 4).is_err());
+        assert!(decode_texture(PixelFormat::C8, None, &[0; 32], None, 8, 4).is_err());
     }
 }
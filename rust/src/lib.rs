@@ -1,12 +1,20 @@
 
+pub mod bc_texture;
+pub mod etc_texture;
+pub mod bntx;
+pub mod color;
 pub mod compression;
 pub mod glsl_compile;
+pub mod gx_shader;
 pub mod gx_texture;
 pub mod halo;
+pub mod mipmap;
 pub mod tegra_texture;
+pub mod texture_export;
 pub mod unity;
 pub mod util;
 pub mod yaz0;
+pub mod zip;
 pub mod wow;
 pub mod geometry;
 pub mod spline;
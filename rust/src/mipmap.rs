@@ -0,0 +1,270 @@
+use wasm_bindgen::prelude::*;
+
+use crate::color::{self, MipFilter};
+
+#[wasm_bindgen(js_name = "MipOptions")]
+#[derive(Clone, Copy, Debug)]
+pub struct MipOptions {
+    pub filter: MipFilter,
+    // Filter RGB in linear light. Alpha is always linear.
+    pub srgb: bool,
+    // For alpha-tested textures, scale each level's alpha so the fraction of pixels at or
+    // above the cutoff matches the base level, which keeps foliage from thinning out.
+    pub alpha_cutoff: Option<f32>,
+    // Treat RGB as a unit vector and renormalise it after filtering.
+    pub normal_map: bool,
+}
+
+impl Default for MipOptions {
+    fn default() -> Self {
+        MipOptions { filter: MipFilter::Box, srgb: false, alpha_cutoff: None, normal_map: false }
+    }
+}
+
+#[wasm_bindgen(js_class = "MipOptions")]
+impl MipOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MipLevel {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+// RGBA8 levels, largest first, down to 1x1.
+#[wasm_bindgen(js_name = "MipChain")]
+#[derive(Debug, Clone)]
+pub struct MipChain {
+    pub(crate) levels: Vec<MipLevel>,
+}
+
+impl MipChain {
+    pub fn levels(&self) -> &[MipLevel] {
+        &self.levels
+    }
+}
+
+#[wasm_bindgen(js_class = "MipChain")]
+impl MipChain {
+    pub fn get_num_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn get_level_width(&self, level: usize) -> usize {
+        self.levels[level].width
+    }
+
+    pub fn get_level_height(&self, level: usize) -> usize {
+        self.levels[level].height
+    }
+
+    pub fn get_level_data(&self, level: usize) -> Vec<u8> {
+        self.levels[level].data.clone()
+    }
+
+    // Every level, packed one after another.
+    pub fn into_data(self) -> Vec<u8> {
+        self.levels.into_iter().flat_map(|level| level.data).collect()
+    }
+}
+
+fn alpha_coverage(pixels: &[f32], cutoff: f32, scale: f32) -> f32 {
+    let covered = pixels.chunks_exact(4).filter(|p| p[3] * scale >= cutoff).count();
+    covered as f32 / (pixels.len() / 4) as f32
+}
+
+// The smallest alpha scale that brings a level's coverage up to `target`, from Castaño's
+// "Computing Alpha Mipmaps".
+fn coverage_scale(pixels: &[f32], cutoff: f32, target: f32) -> f32 {
+    let (mut lo, mut hi) = (0.0, 4.0);
+    for _ in 0..16 {
+        let mid = (lo + hi) * 0.5;
+        if alpha_coverage(pixels, cutoff, mid) >= target {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    hi
+}
+
+// Normal maps aren't premultiplied, so only alpha changes.
+fn scale_alpha(pixels: &mut [f32], scale: f32) {
+    for p in pixels.chunks_exact_mut(4) {
+        p[3] = (p[3] * scale).min(1.0);
+    }
+}
+
+// Scales alpha while keeping the unpremultiplied color.
+fn scale_alpha_premultiplied(pixels: &mut [f32], scale: f32) {
+    for p in pixels.chunks_exact_mut(4) {
+        let alpha = (p[3] * scale).min(1.0);
+        let factor = if p[3] > 0.0 { alpha / p[3] } else { 0.0 };
+        for c in p.iter_mut().take(3) {
+            *c *= factor;
+        }
+        p[3] = alpha;
+    }
+}
+
+fn renormalize(pixels: &mut [f32]) {
+    for p in pixels.chunks_exact_mut(4) {
+        let v = [p[0] * 2.0 - 1.0, p[1] * 2.0 - 1.0, p[2] * 2.0 - 1.0];
+        let len = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+        let v = if len > 0.0 { v.map(|c| c / len) } else { [0.0, 0.0, 1.0] };
+        for (c, v) in p.iter_mut().zip(v) {
+            *c = v * 0.5 + 0.5;
+        }
+    }
+}
+
+// Each level is filtered from the previous one at full precision, not from its RGBA8.
+pub fn generate_mip_chain(src: &[u8], width: usize, height: usize, options: &MipOptions) -> Result<MipChain, String> {
+    if width == 0 || height == 0 || src.len() != width * height * 4 {
+        return Err(format!("{} bytes isn't a {}x{} RGBA8 image", src.len(), width, height));
+    }
+    if let Some(cutoff) = options.alpha_cutoff {
+        if !(0.0..=1.0).contains(&cutoff) {
+            return Err(format!("alpha cutoff {} isn't between 0 and 1", cutoff));
+        }
+    }
+
+    let mut current = if options.normal_map {
+        src.iter().copied().map(color::unorm8_to_f32).collect()
+    } else {
+        color::rgba8_to_linear_premultiplied(src, options.srgb)
+    };
+    let coverage = options.alpha_cutoff.map(|cutoff| (cutoff, alpha_coverage(&current, cutoff, 1.0)));
+
+    let mut levels = vec![MipLevel { width, height, data: src.to_vec() }];
+    let (mut width, mut height) = (width, height);
+    while width > 1 || height > 1 {
+        (current, width, height) = color::downsample_f32(&current, width, height, options.filter);
+        let mut pixels = current.clone();
+        if let Some((cutoff, target)) = coverage {
+            let scale = coverage_scale(&pixels, cutoff, target);
+            if options.normal_map {
+                scale_alpha(&mut pixels, scale);
+            } else {
+                scale_alpha_premultiplied(&mut pixels, scale);
+            }
+        }
+        let data = if options.normal_map {
+            renormalize(&mut pixels);
+            pixels.iter().copied().map(color::f32_to_unorm8).collect()
+        } else {
+            color::linear_premultiplied_to_rgba8(&pixels, options.srgb)
+        };
+        levels.push(MipLevel { width, height, data });
+    }
+    Ok(MipChain { levels })
+}
+
+#[wasm_bindgen(js_name = "generate_mip_chain")]
+pub fn js_generate_mip_chain(src: &[u8], width: usize, height: usize, options: &MipOptions) -> Result<MipChain, String> {
+    generate_mip_chain(src, width, height, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sizes(chain: &MipChain) -> Vec<(usize, usize)> {
+        chain.levels().iter().map(|level| (level.width, level.height)).collect()
+    }
+
+    #[test]
+    fn test_mip_chain_sizes() {
+        let options = MipOptions::new();
+        let chain = generate_mip_chain(&[0x40; 8 * 4 * 4], 8, 4, &options).unwrap();
+        assert_eq!(sizes(&chain), [(8, 4), (4, 2), (2, 1), (1, 1)]);
+        assert!(chain.levels().iter().all(|level| level.data.iter().all(|&b| b == 0x40)));
+        assert_eq!(chain.clone().into_data().len(), (32 + 8 + 2 + 1) * 4);
+
+        let chain = generate_mip_chain(&[0; 5 * 3 * 4], 5, 3, &options).unwrap();
+        assert_eq!(sizes(&chain), [(5, 3), (2, 1), (1, 1)]);
+        assert_eq!(generate_mip_chain(&[1, 2, 3, 4], 1, 1, &options).unwrap().into_data(), [1, 2, 3, 4]);
+
+        assert!(generate_mip_chain(&[0; 15], 2, 2, &options).is_err());
+        assert!(generate_mip_chain(&[], 0, 0, &options).is_err());
+        let options = MipOptions { alpha_cutoff: Some(1.5), ..options };
+        assert!(generate_mip_chain(&[0; 16], 2, 2, &options).is_err());
+    }
+
+    #[test]
+    fn test_mip_chain_srgb() {
+        let checker: Vec<u8> = (0..64).flat_map(|i| {
+            let v = if (i % 8 + i / 8) % 2 == 0 { 255 } else { 0 };
+            [v, v, v, 255]
+        }).collect();
+        // The Kaiser filter rings a little at the clamped edges.
+        for (filter, tolerance) in [(MipFilter::Box, 1), (MipFilter::Kaiser, 4)] {
+            let options = MipOptions { filter, srgb: true, ..MipOptions::new() };
+            let chain = generate_mip_chain(&checker, 8, 8, &options).unwrap();
+            for level in &chain.levels()[1..] {
+                assert!(level.data.chunks_exact(4).all(|p| (p[0] as i32 - 188).abs() <= tolerance && p[3] == 255), "{:?}", level.data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mip_chain_normal_map() {
+        // Columns tilted 45 degrees left and right average to straight up.
+        let (a, b) = ([218, 128, 218, 255], [37, 128, 218, 255]);
+        let normals: Vec<u8> = (0..16).flat_map(|i| if i % 2 == 0 { a } else { b }).collect();
+        let options = MipOptions { normal_map: true, ..MipOptions::new() };
+        let chain = generate_mip_chain(&normals, 4, 4, &options).unwrap();
+        for level in &chain.levels()[1..] {
+            for p in level.data.chunks_exact(4) {
+                assert!((p[0] as i32 - 128).abs() <= 1 && p[1] == 128 && p[2] == 255 && p[3] == 255, "{:?}", p);
+            }
+        }
+        let plain = generate_mip_chain(&normals, 4, 4, &MipOptions::new()).unwrap();
+        assert_eq!(plain.levels()[1].data[2], 218);
+
+        // Alpha-tested normal maps keep their coverage without the normals being scaled.
+        let normals: Vec<u8> = (0..64).flat_map(|i| {
+            let [x, y, z, _] = if i % 2 == 0 { a } else { b };
+            [x, y, z, if i % 4 == 0 { 255 } else { 0 }]
+        }).collect();
+        let unscaled = generate_mip_chain(&normals, 8, 8, &options).unwrap();
+        let options = MipOptions { alpha_cutoff: Some(0.5), ..options };
+        let chain = generate_mip_chain(&normals, 8, 8, &options).unwrap();
+        for (level, unscaled) in chain.levels()[1..].iter().zip(&unscaled.levels()[1..]) {
+            for (p, q) in level.data.chunks_exact(4).zip(unscaled.data.chunks_exact(4)) {
+                assert!(p[..3] == q[..3] && p[3] >= q[3], "{:?} {:?}", p, q);
+            }
+            assert!(level.data.chunks_exact(4).any(|p| p[3] >= 128));
+        }
+    }
+
+    #[test]
+    fn test_mip_chain_alpha_coverage() {
+        // Mostly transparent foliage: alpha skewed towards zero.
+        let mut seed = 3u32;
+        let pixels: Vec<u8> = (0..64 * 64).flat_map(|_| {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            let r = (seed >> 8) as f32 / (1 << 24) as f32;
+            [40, 120, 30, (r * r * r * 255.0) as u8]
+        }).collect();
+        let coverage = |data: &[u8]| data.chunks_exact(4).filter(|p| p[3] >= 128).count() as f32 / (data.len() / 4) as f32;
+        let base = coverage(&pixels);
+        assert!(base > 0.15 && base < 0.25);
+
+        let plain = generate_mip_chain(&pixels, 64, 64, &MipOptions::new()).unwrap();
+        assert!(coverage(&plain.levels()[2].data) < base / 4.0);
+
+        let options = MipOptions { alpha_cutoff: Some(128.0 / 255.0), srgb: true, ..MipOptions::new() };
+        let chain = generate_mip_chain(&pixels, 64, 64, &options).unwrap();
+        for level in &chain.levels()[1..4] {
+            assert!((coverage(&level.data) - base).abs() < 0.05, "{} {}", coverage(&level.data), base);
+            // Scaling alpha leaves the color alone.
+            assert!(level.data.chunks_exact(4).filter(|p| p[3] > 8).all(|p| (p[0] as i32 - 40).abs() <= 2));
+        }
+    }
+}
@@ -1,44 +1,87 @@
-
 use wasm_bindgen::prelude::wasm_bindgen;
 use crate::util;
 
 const GOB_SIZE_X: usize = 64;
 const GOB_SIZE_Y: usize = 8;
+const GOB_SIZE: usize = GOB_SIZE_X * GOB_SIZE_Y;
 
 #[wasm_bindgen]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum CompressionType {
     Bc1,
     Bc2,
     Bc3,
     Bc4,
     Bc5,
+    Bc6h,
+    Bc7,
+    Astc4x4,
+    Astc5x4,
+    Astc5x5,
+    Astc6x5,
+    Astc6x6,
+    Astc8x5,
+    Astc8x6,
+    Astc8x8,
+    Astc10x5,
+    Astc10x6,
+    Astc10x8,
+    Astc10x10,
+    Astc12x10,
+    Astc12x12,
+    Rgba8,
+    Rgb565,
+    R8,
+    Rg8,
 }
 
-const fn get_format_bytes_per_block(channel_format: CompressionType) -> usize {
+pub const fn get_format_bytes_per_block(channel_format: CompressionType) -> usize {
     use CompressionType::*;
     match channel_format {
         Bc1 | Bc4 => 8,
-        Bc2 | Bc3 | Bc5 => 16,
+        Bc2 | Bc3 | Bc5 | Bc6h | Bc7 => 16,
+        Astc4x4 | Astc5x4 | Astc5x5 | Astc6x5 | Astc6x6 | Astc8x5 | Astc8x6 | Astc8x8 |
+        Astc10x5 | Astc10x6 | Astc10x8 | Astc10x10 | Astc12x10 | Astc12x12 => 16,
+        Rgba8 => 4,
+        Rgb565 | Rg8 => 2,
+        R8 => 1,
     }
 }
 
-const fn get_format_block_width(channel_format: CompressionType) -> usize {
+pub const fn get_format_block_width(channel_format: CompressionType) -> usize {
     use CompressionType::*;
     match channel_format {
-        Bc1 | Bc2 | Bc3 | Bc4 | Bc5 => 4,
+        Bc1 | Bc2 | Bc3 | Bc4 | Bc5 | Bc6h | Bc7 => 4,
+        Astc4x4 => 4,
+        Astc5x4 | Astc5x5 => 5,
+        Astc6x5 | Astc6x6 => 6,
+        Astc8x5 | Astc8x6 | Astc8x8 => 8,
+        Astc10x5 | Astc10x6 | Astc10x8 | Astc10x10 => 10,
+        Astc12x10 | Astc12x12 => 12,
+        Rgba8 | Rgb565 | R8 | Rg8 => 1,
     }
 }
 
-const fn get_format_block_height(channel_format: CompressionType) -> usize {
+pub const fn get_format_block_height(channel_format: CompressionType) -> usize {
     use CompressionType::*;
     match channel_format {
-        Bc1 | Bc2 | Bc3 | Bc4 | Bc5 => 4,
+        Bc1 | Bc2 | Bc3 | Bc4 | Bc5 | Bc6h | Bc7 => 4,
+        Astc4x4 | Astc5x4 => 4,
+        Astc5x5 | Astc6x5 | Astc8x5 | Astc10x5 => 5,
+        Astc6x6 | Astc8x6 | Astc10x6 => 6,
+        Astc8x8 | Astc10x8 => 8,
+        Astc10x10 | Astc12x10 => 10,
+        Astc12x12 => 12,
+        Rgba8 | Rgb565 | R8 | Rg8 => 1,
     }
 }
 
+fn align(n: usize, alignment: usize) -> usize {
+    n.div_ceil(alignment) * alignment
+}
+
 fn get_addr_block_linear(mut x: usize, y: usize, w: usize, bpp: usize, block_height: usize, base_addr: usize) -> usize {
-    let width_in_gobs = ((w * bpp) + GOB_SIZE_X - 1) / GOB_SIZE_X;
+    let width_in_gobs = (w * bpp).div_ceil(GOB_SIZE_X);
     let mut gob_addr = base_addr;
 
     gob_addr += (y / (GOB_SIZE_Y * block_height)) * 512 * block_height * width_in_gobs;
@@ -47,39 +90,275 @@ fn get_addr_block_linear(mut x: usize, y: usize, w: usize, bpp: usize, block_hei
 
     x *= bpp;
     let mut addr = gob_addr;
-    addr += (((x % 64) / 32) | 0) * 256;
-    addr += (((y % 8) / 2) | 0) * 64;
-    addr += (((x % 32) / 16) | 0) * 32;
+    addr += ((x % 64) / 32) * 256;
+    addr += ((y % 8) / 2) * 64;
+    addr += ((x % 32) / 16) * 32;
     addr += (y % 2) * 16;
     addr += x % 16;
-    return addr;
+    addr
 }
 
-#[wasm_bindgen]
-pub fn tegra_deswizzle(src: &[u8], compression_type: CompressionType, w: usize, h: usize, block_height_log2: usize) -> Vec<u8> {
-    let format_block_width = get_format_block_width(compression_type);
-    let format_block_height = get_format_block_height(compression_type);
-
-    let width_in_blocks = (w + format_block_width - 1) / format_block_width;
-    let height_in_blocks = (h + format_block_height - 1) / format_block_height;
-
+// Adjust block height down per mip to fit the image.
+fn get_level_block_height(height_in_blocks: usize, block_height_log2: usize) -> usize {
     let mut block_height = 1 << block_height_log2;
-    let bpp = get_format_bytes_per_block(compression_type);
-
-    // Adjust block height down per mip to fit the image.
     while block_height > 1 && (util::next_pow2(height_in_blocks) < (GOB_SIZE_Y * block_height)) {
         block_height >>= 1;
     };
+    block_height
+}
+
+#[derive(Debug, Clone)]
+pub struct MipLevelLayout {
+    pub width_in_blocks: usize,
+    pub height_in_blocks: usize,
+    pub block_height: usize,
+    // Offset of this level from the start of its layer in the swizzled surface.
+    pub offset: usize,
+    // Size of this level in the swizzled surface, including GOB padding.
+    pub size: usize,
+}
+
+impl MipLevelLayout {
+    pub fn linear_size(&self, bpp: usize) -> usize {
+        self.width_in_blocks * self.height_in_blocks * bpp
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SurfaceLayout {
+    pub compression_type: CompressionType,
+    pub levels: Vec<MipLevelLayout>,
+    // Stride between array layers (or cubemap faces) in the swizzled surface.
+    pub layer_size: usize,
+    pub layer_count: usize,
+}
+
+impl SurfaceLayout {
+    pub fn new(compression_type: CompressionType, w: usize, h: usize, block_height_log2: usize, mip_count: usize, layer_count: usize) -> Self {
+        let format_block_width = get_format_block_width(compression_type);
+        let format_block_height = get_format_block_height(compression_type);
+        let bpp = get_format_bytes_per_block(compression_type);
+
+        let mut levels = Vec::with_capacity(mip_count);
+        let mut offset = 0;
+        for i in 0..mip_count.max(1) {
+            let level_w = (w >> i).max(1);
+            let level_h = (h >> i).max(1);
+            let width_in_blocks = level_w.div_ceil(format_block_width);
+            let height_in_blocks = level_h.div_ceil(format_block_height);
+            let block_height = get_level_block_height(height_in_blocks, block_height_log2);
+
+            let width_in_gobs = (width_in_blocks * bpp).div_ceil(GOB_SIZE_X);
+            let height_in_gob_blocks = height_in_blocks.div_ceil(GOB_SIZE_Y * block_height);
+            let size = width_in_gobs * height_in_gob_blocks * GOB_SIZE * block_height;
+
+            levels.push(MipLevelLayout { width_in_blocks, height_in_blocks, block_height, offset, size });
+            offset += size;
+        }
+
+        // Layers are aligned to the GOB block of the first mip level.
+        let layer_size = align(offset, GOB_SIZE * levels[0].block_height);
+
+        SurfaceLayout { compression_type, levels, layer_size, layer_count: layer_count.max(1) }
+    }
+
+    pub fn bytes_per_block(&self) -> usize {
+        get_format_bytes_per_block(self.compression_type)
+    }
+
+    pub fn swizzled_size(&self) -> usize {
+        self.layer_size * self.layer_count
+    }
+
+    pub fn linear_layer_size(&self) -> usize {
+        let bpp = self.bytes_per_block();
+        self.levels.iter().map(|level| level.linear_size(bpp)).sum()
+    }
+
+    pub fn linear_size(&self) -> usize {
+        self.linear_layer_size() * self.layer_count
+    }
+
+    // Offset of the given layer and mip level in the linear (deswizzled) surface.
+    pub fn linear_offset(&self, layer: usize, level: usize) -> usize {
+        let bpp = self.bytes_per_block();
+        let level_offs: usize = self.levels[0..level].iter().map(|level| level.linear_size(bpp)).sum();
+        layer * self.linear_layer_size() + level_offs
+    }
+
+    // Offset of the given layer and mip level in the swizzled surface.
+    pub fn swizzled_offset(&self, layer: usize, level: usize) -> usize {
+        layer * self.layer_size + self.levels[level].offset
+    }
+}
+
+fn for_each_block<F: FnMut(usize, usize)>(level: &MipLevelLayout, bpp: usize, swizzled_base: usize, linear_base: usize, mut f: F) {
+    for y in 0..level.height_in_blocks {
+        for x in 0..level.width_in_blocks {
+            let swizzled_offs = get_addr_block_linear(x, y, level.width_in_blocks, bpp, level.block_height, swizzled_base);
+            let linear_offs = linear_base + ((y * level.width_in_blocks) + x) * bpp;
+            f(swizzled_offs, linear_offs);
+        }
+    }
+}
 
-    let mut dst = vec![0x00; src.len() as usize];
+fn check_size(what: &str, src: &[u8], needed: usize) -> Result<(), String> {
+    if src.len() < needed {
+        return Err(format!("{} is {} bytes, needs {}", what, src.len(), needed));
+    }
+    Ok(())
+}
+
+pub fn deswizzle_level(src: &[u8], layout: &SurfaceLayout, layer: usize, level: usize) -> Result<Vec<u8>, String> {
+    let bpp = layout.bytes_per_block();
+    let level_layout = &layout.levels[level];
+    let src_base = layout.swizzled_offset(layer, level);
+    let needed = src_base.checked_add(level_layout.size).ok_or_else(|| format!("level {} offset is out of range", level))?;
+    check_size("swizzled surface", src, needed)?;
+
+    let mut dst = vec![0x00; level_layout.linear_size(bpp)];
+    for_each_block(level_layout, bpp, src_base, 0, |src_offs, dst_offs| {
+        dst[dst_offs..dst_offs + bpp].copy_from_slice(&src[src_offs..src_offs + bpp]);
+    });
+    Ok(dst)
+}
+
+pub fn deswizzle_surface(src: &[u8], layout: &SurfaceLayout) -> Result<Vec<u8>, String> {
+    let mut dst = Vec::with_capacity(layout.linear_size());
+    for layer in 0..layout.layer_count {
+        for level in 0..layout.levels.len() {
+            dst.extend_from_slice(&deswizzle_level(src, layout, layer, level)?);
+        }
+    }
+    Ok(dst)
+}
+
+pub fn swizzle_surface(src: &[u8], layout: &SurfaceLayout) -> Result<Vec<u8>, String> {
+    check_size("linear surface", src, layout.linear_size())?;
+    let bpp = layout.bytes_per_block();
+    let mut dst = vec![0x00; layout.swizzled_size()];
+    for layer in 0..layout.layer_count {
+        for (i, level) in layout.levels.iter().enumerate() {
+            let src_base = layout.linear_offset(layer, i);
+            let dst_base = layout.swizzled_offset(layer, i);
+            for_each_block(level, bpp, dst_base, src_base, |dst_offs, src_offs| {
+                dst[dst_offs..dst_offs + bpp].copy_from_slice(&src[src_offs..src_offs + bpp]);
+            });
+        }
+    }
+    Ok(dst)
+}
+
+#[wasm_bindgen]
+pub fn tegra_deswizzle(src: &[u8], compression_type: CompressionType, w: usize, h: usize, block_height_log2: usize) -> Result<Vec<u8>, String> {
+    let layout = SurfaceLayout::new(compression_type, w, h, block_height_log2, 1, 1);
+    let level = &layout.levels[0];
+    check_size("swizzled surface", src, level.size)?;
+
+    // The output is as large as the input, with the linear image at the start.
+    let bpp = layout.bytes_per_block();
+    let mut dst = vec![0x00; src.len()];
+    for_each_block(level, bpp, 0, 0, |src_offs, dst_offs| {
+        dst[dst_offs..dst_offs + bpp].copy_from_slice(&src[src_offs..src_offs + bpp]);
+    });
+    Ok(dst)
+}
+
+#[wasm_bindgen]
+pub fn tegra_swizzle(src: &[u8], compression_type: CompressionType, w: usize, h: usize, block_height_log2: usize) -> Result<Vec<u8>, String> {
+    let layout = SurfaceLayout::new(compression_type, w, h, block_height_log2, 1, 1);
+    swizzle_surface(src, &layout)
+}
 
-    for y in 0..height_in_blocks {
-        for x in 0..width_in_blocks {
-            let src_offs = get_addr_block_linear(x, y, width_in_blocks, bpp, block_height, 0);
-            let dst_offs = ((y * width_in_blocks) + x) * bpp;
-            dst[dst_offs..dst_offs + bpp].copy_from_slice(&src[src_offs..src_offs + bpp]);
+// Deswizzles every mip level of every array layer (six for cubemaps) into a tightly
+// packed buffer, ordered layer-major and then by mip level.
+#[wasm_bindgen]
+pub fn tegra_deswizzle_surface(src: &[u8], compression_type: CompressionType, w: usize, h: usize, block_height_log2: usize, mip_count: usize, layer_count: usize) -> Result<Vec<u8>, String> {
+    let layout = SurfaceLayout::new(compression_type, w, h, block_height_log2, mip_count, layer_count);
+    deswizzle_surface(src, &layout)
+}
+
+#[wasm_bindgen]
+pub fn tegra_swizzle_surface(src: &[u8], compression_type: CompressionType, w: usize, h: usize, block_height_log2: usize, mip_count: usize, layer_count: usize) -> Result<Vec<u8>, String> {
+    let layout = SurfaceLayout::new(compression_type, w, h, block_height_log2, mip_count, layer_count);
+    swizzle_surface(src, &layout)
+}
+
+#[wasm_bindgen]
+pub fn tegra_get_swizzled_size(compression_type: CompressionType, w: usize, h: usize, block_height_log2: usize, mip_count: usize, layer_count: usize) -> usize {
+    SurfaceLayout::new(compression_type, w, h, block_height_log2, mip_count, layer_count).swizzled_size()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swizzle_round_trip() {
+        let layout = SurfaceLayout::new(CompressionType::Bc1, 100, 60, 4, 4, 6);
+        let linear: Vec<u8> = (0..layout.linear_size()).map(|i| (i * 7) as u8).collect();
+        let swizzled = swizzle_surface(&linear, &layout).unwrap();
+        assert_eq!(swizzled.len(), layout.swizzled_size());
+        assert_eq!(deswizzle_surface(&swizzled, &layout).unwrap(), linear);
+
+        assert!(deswizzle_surface(&swizzled[..swizzled.len() - 1], &layout).is_err());
+        assert!(swizzle_surface(&linear[..linear.len() - 1], &layout).is_err());
+        assert!(tegra_deswizzle(&[0; 511], CompressionType::R8, 64, 8, 0).is_err());
+    }
+
+    #[test]
+    fn test_gob_layout() {
+        // Byte offsets of each 16-byte sector of a GOB (64 bytes by 8 rows), from the GOB
+        // diagram in the Tegra X1 TRM.
+        const GOB_SECTORS: [[usize; 4]; 8] = [
+            [0, 32, 256, 288],
+            [16, 48, 272, 304],
+            [64, 96, 320, 352],
+            [80, 112, 336, 368],
+            [128, 160, 384, 416],
+            [144, 176, 400, 432],
+            [192, 224, 448, 480],
+            [208, 240, 464, 496],
+        ];
+        let linear: Vec<u8> = (0..512).map(|i| (i % 251) as u8).collect();
+        let swizzled = tegra_swizzle(&linear, CompressionType::R8, 64, 8, 0).unwrap();
+        for (y, row) in GOB_SECTORS.iter().enumerate() {
+            for (sector, &offs) in row.iter().enumerate() {
+                let start = y * 64 + sector * 16;
+                assert_eq!(swizzled[offs..offs + 16], linear[start..start + 16], "row {} sector {}", y, sector);
+            }
+        }
+    }
+
+    #[test]
+    fn test_block_linear_addresses() {
+        // GOBs stack vertically within a block, then blocks run left to right, then down.
+        // R8 128x64 with four-GOB-tall blocks: two GOBs wide, two blocks tall.
+        let layout = SurfaceLayout::new(CompressionType::R8, 128, 64, 2, 1, 1);
+        let level = &layout.levels[0];
+        assert_eq!((level.block_height, level.size), (4, 8192));
+        let mut linear = vec![0u8; 128 * 64];
+        let points = [((0, 0), 0), ((0, 8), 512), ((0, 24), 1536), ((64, 0), 2048), ((64, 8), 2560), ((0, 32), 4096), ((127, 63), 8191), ((20, 3), 116)];
+        for (i, &((x, y), _)) in points.iter().enumerate() {
+            linear[y * 128 + x] = i as u8 + 1;
+        }
+        let swizzled = swizzle_surface(&linear, &layout).unwrap();
+        for (i, &(_, offs)) in points.iter().enumerate() {
+            assert_eq!(swizzled[offs], i as u8 + 1, "point {}", i);
+        }
+
+        // BC1 16x16: 4x4 blocks of 8 bytes, all in one GOB.
+        let linear: Vec<u8> = (0..16).flat_map(|block| [block as u8; 8]).collect();
+        let swizzled = tegra_swizzle(&linear, CompressionType::Bc1, 16, 16, 0).unwrap();
+        for (block, offs) in [(1, 8), (2, 32), (4, 16), (15, 120)] {
+            assert_eq!(swizzled[offs..offs + 8], [block; 8]);
         }
     }
 
-    dst
+    #[test]
+    fn test_level_block_height() {
+        let layout = SurfaceLayout::new(CompressionType::Rgba8, 256, 256, 4, 9, 1);
+        let block_heights: Vec<usize> = layout.levels.iter().map(|level| level.block_height).collect();
+        assert_eq!(block_heights, vec![16, 16, 8, 4, 2, 1, 1, 1, 1]);
+    }
 }
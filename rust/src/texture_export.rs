@@ -0,0 +1,326 @@
+use wasm_bindgen::prelude::*;
+
+use crate::color::mip_size;
+use crate::compression::{crc32, deflate_compress};
+use crate::tegra_texture::{get_format_block_height, get_format_block_width, get_format_bytes_per_block, CompressionType};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn filter_row(filter: u8, row: &[u8], prev: &[u8], bpp: usize, dst: &mut Vec<u8>) {
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let c = if i >= bpp { prev[i - bpp] } else { 0 };
+        let b = prev[i];
+        let predicted = match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            _ => paeth(a, b, c),
+        };
+        dst.push(row[i].wrapping_sub(predicted));
+    }
+}
+
+fn write_png_chunk(dst: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    dst.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = dst.len();
+    dst.extend_from_slice(kind);
+    dst.extend_from_slice(data);
+    let crc = crc32(0, &dst[start..]);
+    dst.extend_from_slice(&crc.to_be_bytes());
+}
+
+// Writes an 8-bit RGBA image, or RGB if every pixel is opaque. level is as in deflate_compress.
+#[wasm_bindgen]
+pub fn encode_png(rgba: &[u8], width: usize, height: usize, level: u32) -> Result<Vec<u8>, String> {
+    let size = width.checked_mul(height).and_then(|n| n.checked_mul(4));
+    if width == 0 || height == 0 || width > i32::MAX as usize || height > i32::MAX as usize || size != Some(rgba.len()) {
+        return Err(format!("{} bytes isn't a {}x{} RGBA8 image", rgba.len(), width, height));
+    }
+
+    let opaque = rgba.chunks_exact(4).all(|p| p[3] == 0xFF);
+    let (color_type, bpp) = if opaque { (2, 3) } else { (6, 4) };
+    let pixels: Vec<u8> = if opaque {
+        rgba.chunks_exact(4).flat_map(|p| [p[0], p[1], p[2]]).collect()
+    } else {
+        rgba.to_vec()
+    };
+
+    let stride = width * bpp;
+    let zero = vec![0; stride];
+    let mut filtered = Vec::with_capacity((stride + 1) * height);
+    let mut candidate = Vec::with_capacity(stride);
+    for y in 0..height {
+        let row = &pixels[y * stride..][..stride];
+        let prev = if y > 0 { &pixels[(y - 1) * stride..][..stride] } else { &zero[..] };
+        // Pick the filter with the smallest sum of absolute differences, as libpng does.
+        let mut best = (u64::MAX, 0);
+        for filter in 0..5 {
+            candidate.clear();
+            filter_row(filter, row, prev, bpp, &mut candidate);
+            let cost = candidate.iter().map(|&b| (b as i8).unsigned_abs() as u64).sum();
+            if cost < best.0 {
+                best = (cost, filter);
+            }
+        }
+        filtered.push(best.1);
+        filter_row(best.1, row, prev, bpp, &mut filtered);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+    let idat = deflate_compress(&filtered, level);
+    let mut dst = Vec::with_capacity(PNG_SIGNATURE.len() + idat.len() + 57);
+    dst.extend_from_slice(&PNG_SIGNATURE);
+    write_png_chunk(&mut dst, b"IHDR", &header);
+    write_png_chunk(&mut dst, b"IDAT", &idat);
+    write_png_chunk(&mut dst, b"IEND", &[]);
+    Ok(dst)
+}
+
+const DDSD_CAPS: u32 = 0x1;
+const DDSD_HEIGHT: u32 = 0x2;
+const DDSD_WIDTH: u32 = 0x4;
+const DDSD_PITCH: u32 = 0x8;
+const DDSD_PIXELFORMAT: u32 = 0x1000;
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDSD_LINEARSIZE: u32 = 0x80000;
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDSCAPS_COMPLEX: u32 = 0x8;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS_MIPMAP: u32 = 0x400000;
+const D3D10_RESOURCE_DIMENSION_TEXTURE2D: u32 = 3;
+
+fn dxgi_format(format: CompressionType, srgb: bool, is_signed: bool) -> Result<u32, String> {
+    use CompressionType::*;
+    let dxgi = match (format, is_signed) {
+        (Rgba8, false) => if srgb { 29 } else { 28 },
+        (Bc1, false) => if srgb { 72 } else { 71 },
+        (Bc2, false) => if srgb { 75 } else { 74 },
+        (Bc3, false) => if srgb { 78 } else { 77 },
+        (Bc4, _) if !srgb => if is_signed { 81 } else { 80 },
+        (Bc5, _) if !srgb => if is_signed { 84 } else { 83 },
+        (Bc6h, _) if !srgb => if is_signed { 96 } else { 95 },
+        (Bc7, false) => if srgb { 99 } else { 98 },
+        _ => return Err(format!("cannot write {:?} (srgb {}, signed {}) to DDS", format, srgb, is_signed)),
+    };
+    Ok(dxgi)
+}
+
+// The pre-DX10 FourCC, for readers that don't understand the DX10 extension header.
+fn legacy_four_cc(format: CompressionType) -> Option<&'static [u8; 4]> {
+    match format {
+        CompressionType::Bc1 => Some(b"DXT1"),
+        CompressionType::Bc2 => Some(b"DXT3"),
+        CompressionType::Bc3 => Some(b"DXT5"),
+        CompressionType::Bc4 => Some(b"ATI1"),
+        CompressionType::Bc5 => Some(b"ATI2"),
+        _ => None,
+    }
+}
+
+fn level_size(format: CompressionType, width: usize, height: usize) -> usize {
+    width.div_ceil(get_format_block_width(format)) * height.div_ceil(get_format_block_height(format)) * get_format_bytes_per_block(format)
+}
+
+// data holds num_levels levels of linear (deswizzled) RGBA8 or BCn data, largest first, as
+// from MipChain::into_data.
+#[wasm_bindgen]
+pub fn encode_dds(format: CompressionType, data: &[u8], width: usize, height: usize, num_levels: usize, srgb: bool, is_signed: bool) -> Result<Vec<u8>, String> {
+    let dxgi = dxgi_format(format, srgb, is_signed)?;
+    if width == 0 || height == 0 || width > u32::MAX as usize || height > u32::MAX as usize {
+        return Err(format!("bad DDS size {}x{}", width, height));
+    }
+    let max_levels = (usize::BITS - width.max(height).leading_zeros()) as usize;
+    if num_levels == 0 || num_levels > max_levels {
+        return Err(format!("{}x{} can't have {} mip levels", width, height, num_levels));
+    }
+    let (mut w, mut h) = (width, height);
+    let mut needed = 0;
+    for _ in 0..num_levels {
+        needed += level_size(format, w, h);
+        (w, h) = (mip_size(w), mip_size(h));
+    }
+    if data.len() != needed {
+        return Err(format!("{:?} data for {}x{} with {} levels needs {} bytes, got {}", format, width, height, num_levels, needed, data.len()));
+    }
+
+    let uncompressed = format == CompressionType::Rgba8;
+    let four_cc = if srgb || is_signed { None } else { legacy_four_cc(format) };
+    let dx10 = (srgb || !uncompressed) && four_cc.is_none();
+
+    let mut flags = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT;
+    flags |= if uncompressed { DDSD_PITCH } else { DDSD_LINEARSIZE };
+    let pitch_or_linear_size = if uncompressed { width * 4 } else { level_size(format, width, height) };
+    let mut caps = DDSCAPS_TEXTURE;
+    if num_levels > 1 {
+        flags |= DDSD_MIPMAPCOUNT;
+        caps |= DDSCAPS_COMPLEX | DDSCAPS_MIPMAP;
+    }
+
+    let mut header = [0u32; 31];
+    header[0] = 124;
+    header[1] = flags;
+    header[2] = height as u32;
+    header[3] = width as u32;
+    header[4] = pitch_or_linear_size as u32;
+    header[6] = num_levels as u32;
+    // DDS_PIXELFORMAT
+    header[18] = 32;
+    if dx10 {
+        header[19] = DDPF_FOURCC;
+        header[20] = u32::from_le_bytes(*b"DX10");
+    } else if let Some(four_cc) = four_cc {
+        header[19] = DDPF_FOURCC;
+        header[20] = u32::from_le_bytes(*four_cc);
+    } else {
+        header[19] = DDPF_RGB | DDPF_ALPHAPIXELS;
+        header[21] = 32;
+        header[22..26].copy_from_slice(&[0x000000FF, 0x0000FF00, 0x00FF0000, 0xFF000000]);
+    }
+    header[26] = caps;
+
+    let mut dst = Vec::with_capacity(4 + 124 + 20 + data.len());
+    dst.extend_from_slice(b"DDS ");
+    dst.extend(header.iter().flat_map(|v| v.to_le_bytes()));
+    if dx10 {
+        let header_dx10 = [dxgi, D3D10_RESOURCE_DIMENSION_TEXTURE2D, 0, 1, 0];
+        dst.extend(header_dx10.iter().flat_map(|v| v.to_le_bytes()));
+    }
+    dst.extend_from_slice(data);
+    Ok(dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+    use crate::compression::deflate_decompress;
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    // Returns the IHDR fields and unfiltered pixels.
+    fn decode_png(png: &[u8]) -> (u32, u32, u8, Vec<u8>) {
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        let mut pos = 8;
+        let mut chunks = Vec::new();
+        while pos < png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let body = &png[pos + 4..pos + 8 + len];
+            let crc = u32::from_be_bytes(png[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+            assert_eq!(crc32(0, body), crc);
+            chunks.push((body[..4].to_vec(), body[4..].to_vec()));
+            pos += 12 + len;
+        }
+        let kinds: Vec<&[u8]> = chunks.iter().map(|c| &c.0[..]).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+
+        let ihdr = &chunks[0].1;
+        let width = u32::from_be_bytes(ihdr[0..4].try_into().unwrap());
+        let height = u32::from_be_bytes(ihdr[4..8].try_into().unwrap());
+        let color_type = ihdr[9];
+        let bpp = if color_type == 6 { 4 } else { 3 };
+        let stride = width as usize * bpp;
+        let filtered = deflate_decompress(&chunks[1].1).unwrap();
+        assert_eq!(filtered.len(), (stride + 1) * height as usize);
+
+        let mut pixels: Vec<u8> = Vec::new();
+        for (y, line) in filtered.chunks_exact(stride + 1).enumerate() {
+            for i in 0..stride {
+                let a = if i >= bpp { pixels[y * stride + i - bpp] } else { 0 };
+                let b = if y > 0 { pixels[(y - 1) * stride + i] } else { 0 };
+                let c = if i >= bpp && y > 0 { pixels[(y - 1) * stride + i - bpp] } else { 0 };
+                let predicted = match line[0] {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    4 => paeth(a, b, c),
+                    filter => panic!("bad filter {}", filter),
+                };
+                pixels.push(line[1 + i].wrapping_add(predicted));
+            }
+        }
+        (width, height, color_type, pixels)
+    }
+
+    #[test]
+    fn test_encode_png() {
+        let mut seed = 7u32;
+        let noise: Vec<u8> = (0..13 * 7 * 4).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        }).collect();
+        for level in [0, 6] {
+            let (width, height, color_type, pixels) = decode_png(&encode_png(&noise, 13, 7, level).unwrap());
+            assert_eq!((width, height, color_type), (13, 7, 6));
+            assert_eq!(pixels, noise);
+        }
+
+        let gradient: Vec<u8> = (0..32 * 16).flat_map(|i| [(i % 32 * 8) as u8, (i / 32 * 16) as u8, 0x80, 0xFF]).collect();
+        let png = encode_png(&gradient, 32, 16, 9).unwrap();
+        let (width, height, color_type, pixels) = decode_png(&png);
+        assert_eq!((width, height, color_type), (32, 16, 2));
+        assert!(pixels.chunks_exact(3).eq(gradient.chunks_exact(4).map(|p| &p[..3])));
+        // Filtering turns the gradient into runs.
+        assert!(png.len() < 200);
+
+        assert!(encode_png(&[0; 12], 2, 2, 6).is_err());
+        assert!(encode_png(&[], 0, 1, 6).is_err());
+        // The size wraps to 0 on 32-bit targets.
+        assert!(encode_png(&[], 0x10000, 0x10000, 6).is_err());
+    }
+
+    #[test]
+    fn test_encode_dds() {
+        let bc1 = vec![0xAB; 32 + 8 + 8 + 8];
+        let dds = encode_dds(CompressionType::Bc1, &bc1, 8, 8, 4, false, false).unwrap();
+        assert_eq!(&dds[..4], b"DDS ");
+        assert_eq!(dds.len(), 128 + bc1.len());
+        assert_eq!(u32_at(&dds, 4), 124);
+        assert_eq!(u32_at(&dds, 8), DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT | DDSD_LINEARSIZE | DDSD_MIPMAPCOUNT);
+        assert_eq!((u32_at(&dds, 12), u32_at(&dds, 16), u32_at(&dds, 20)), (8, 8, 32));
+        assert_eq!(u32_at(&dds, 28), 4);
+        assert_eq!(u32_at(&dds, 76), 32);
+        assert_eq!(&dds[84..88], b"DXT1");
+        assert_eq!(u32_at(&dds, 108), DDSCAPS_TEXTURE | DDSCAPS_COMPLEX | DDSCAPS_MIPMAP);
+        assert_eq!(dds[128..], bc1[..]);
+
+        // BC7 and sRGB need the DX10 header.
+        let dds = encode_dds(CompressionType::Bc7, &[0; 32], 5, 3, 1, true, false).unwrap();
+        assert_eq!(&dds[84..88], b"DX10");
+        assert_eq!((u32_at(&dds, 128), u32_at(&dds, 132), u32_at(&dds, 140)), (99, 3, 1));
+        assert_eq!(dds.len(), 148 + 32);
+
+        let rgba = vec![0; (4 * 2 + 2 + 1) * 4];
+        let dds = encode_dds(CompressionType::Rgba8, &rgba, 4, 2, 3, false, false).unwrap();
+        assert_eq!(u32_at(&dds, 80), DDPF_RGB | DDPF_ALPHAPIXELS);
+        assert_eq!((u32_at(&dds, 20), u32_at(&dds, 88), u32_at(&dds, 104)), (16, 32, 0xFF000000));
+        assert_eq!(dds.len(), 128 + rgba.len());
+
+        assert!(encode_dds(CompressionType::Bc1, &bc1, 8, 8, 3, false, false).is_err());
+        assert!(encode_dds(CompressionType::Bc1, &bc1[..48], 8, 8, 5, false, false).is_err());
+        assert!(encode_dds(CompressionType::Bc4, &[0; 8], 4, 4, 1, true, false).is_err());
+        assert!(encode_dds(CompressionType::Astc4x4, &[0; 16], 4, 4, 1, false, false).is_err());
+    }
+}
@@ -1,5 +1,10 @@
+use std::convert::TryInto;
+use std::fmt;
+
 use wasm_bindgen::prelude::*;
 
+use crate::color;
+
 // http://www.mindcontrol.org/~hplus/graphics/expand-bits.html
 pub fn expand_n_to_8(v: u8, n: u8) -> u8 {
     match v {
@@ -24,38 +29,13 @@ pub fn next_pow2(value: usize) -> usize {
     }
 }
 
-pub fn srgb_to_linear(value: u8) -> f32 {
-    let value = (value as f32) / 255.0;
-    if value <= 0.0404482362771082 {
-        value / 12.92
-    } else {
-        ((value + 0.055)/1.055).powf(2.4)
-    }
-}
-
-pub fn linear_to_srgb(value: f32) -> u8 {
-    if value.is_nan() || value <= 0.0 {
-        0
-    } else if value >= 1.0 {
-        255
-    } else {
-        let value = if value < 0.00313066844250063 {
-            value * 12.92
-        } else {
-            value.powf(1.0/2.4) * 1.055 - 0.055
-        };
-        let value = value / 255.0;
-        (value + 0.5) as u8
-    }
-}
-
 pub type BlendFunction<T, U> = fn(T, T, U, U) -> T;
 
 pub fn blend_srgb_u8(a: u8, b: u8, weight_a: u32, weight_b: u32) -> u8 {
-    let a = srgb_to_linear(a);
-    let b = srgb_to_linear(b);
+    let a = color::srgb_to_linear_u8(a);
+    let b = color::srgb_to_linear_u8(b);
     let v = (a * (weight_a as f32) + b * (weight_b as f32)) / ((weight_a as f32) + (weight_b as f32));
-    linear_to_srgb(v)
+    color::linear_to_srgb_u8(v)
 }
 
 pub fn blend_linear_u8(a: u8, b: u8, weight_a: u32, weight_b: u32) -> u8 {
@@ -66,28 +46,219 @@ pub fn blend_linear_i8(a: i8, b: i8, weight_a: i32, weight_b: i32) -> i8 {
     (((a as i32) * weight_a + (b as i32) * weight_b) / (weight_a + weight_b)) as i8
 }
 
-pub fn get_uint16_le(src: &[u8], offs: usize) -> u16 {
-    (src[offs] as u16) | ((src[offs+1] as u16) << 8)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
 }
 
-pub fn get_uint24_le(src: &[u8], offs: usize) -> u32 {
-    (src[offs] as u32) | ((src[offs+1] as u32) << 8) | ((src[offs+1] as u32) << 16)
+// A read of `len` bytes at `offset` that ran past the end of the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfBounds {
+    pub offset: usize,
+    pub len: usize,
+    pub size: usize,
 }
 
-pub fn get_uint32_le(src: &[u8], offs: usize) -> u32 {
-    (src[offs] as u32) | ((src[offs+1] as u32) << 8) | ((src[offs+1] as u32) << 16) | ((src[offs+1] as u32) << 24)
+impl fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "read of {} bytes at {:#x} is past the end of {:#x} bytes", self.len, self.offset, self.size)
+    }
 }
 
-pub fn get_uint16_be(src: &[u8], offs: usize) -> u16 {
-    ((src[offs] as u16) << 8) | (src[offs+1] as u16)
+impl From<OutOfBounds> for String {
+    fn from(err: OutOfBounds) -> String {
+        err.to_string()
+    }
 }
 
-pub fn get_uint24_be(src: &[u8], offs: usize) -> u32 {
-    ((src[offs] as u32) << 16) | ((src[offs+1] as u32) << 8) | (src[offs+1] as u32)
+// A bounds-checked cursor over a byte slice. Reads advance the position; a read that
+// doesn't fit returns an error and leaves the position alone.
+#[derive(Debug, Clone)]
+pub struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    endian: Endian,
 }
 
-pub fn get_uint32_be(src: &[u8], offs: usize) -> u32 {
-    ((src[offs] as u32) << 24) | ((src[offs+1] as u32) << 16) | ((src[offs+1] as u32) << 8) | (src[offs+1] as u32)
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8], endian: Endian) -> Self {
+        ByteReader { data, pos: 0, endian }
+    }
+
+    pub fn le(data: &'a [u8]) -> Self {
+        ByteReader::new(data, Endian::Little)
+    }
+
+    pub fn be(data: &'a [u8]) -> Self {
+        ByteReader::new(data, Endian::Big)
+    }
+
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    pub fn set_endian(&mut self, endian: Endian) {
+        self.endian = endian;
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn out_of_bounds(&self, offset: usize, len: usize) -> OutOfBounds {
+        OutOfBounds { offset, len, size: self.data.len() }
+    }
+
+    // Seeking to the end is allowed; reading there isn't.
+    pub fn seek(&mut self, pos: usize) -> Result<(), OutOfBounds> {
+        if pos > self.data.len() {
+            return Err(self.out_of_bounds(pos, 0));
+        }
+        self.pos = pos;
+        Ok(())
+    }
+
+    pub fn skip(&mut self, n: usize) -> Result<(), OutOfBounds> {
+        self.read_bytes(n).map(|_| ())
+    }
+
+    // `alignment` must be nonzero.
+    pub fn align(&mut self, alignment: usize) -> Result<(), OutOfBounds> {
+        assert!(alignment != 0, "ByteReader::align: alignment must be nonzero");
+        self.seek(self.pos.next_multiple_of(alignment))
+    }
+
+    // A reader over the same data at another position.
+    pub fn at(&self, pos: usize) -> Result<ByteReader<'a>, OutOfBounds> {
+        let mut reader = self.clone();
+        reader.seek(pos)?;
+        Ok(reader)
+    }
+
+    // Runs `read` and then rewinds, whether or not it succeeded.
+    pub fn peek<T, E>(&mut self, read: impl FnOnce(&mut Self) -> Result<T, E>) -> Result<T, E> {
+        let pos = self.pos;
+        let result = read(self);
+        self.pos = pos;
+        result
+    }
+
+    pub fn peek_bytes(&self, n: usize) -> Result<&'a [u8], OutOfBounds> {
+        self.pos.checked_add(n)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(|| self.out_of_bounds(self.pos, n))
+    }
+
+    pub fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], OutOfBounds> {
+        let bytes = self.peek_bytes(n)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], OutOfBounds> {
+        Ok(self.read_bytes(N)?.try_into().unwrap())
+    }
+
+    fn pick<T>(&self, le: T, be: T) -> T {
+        match self.endian {
+            Endian::Little => le,
+            Endian::Big => be,
+        }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, OutOfBounds> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub fn read_i8(&mut self) -> Result<i8, OutOfBounds> {
+        Ok(self.read_u8()? as i8)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, OutOfBounds> {
+        let b = self.read_array()?;
+        Ok(self.pick(u16::from_le_bytes(b), u16::from_be_bytes(b)))
+    }
+
+    pub fn read_i16(&mut self) -> Result<i16, OutOfBounds> {
+        Ok(self.read_u16()? as i16)
+    }
+
+    pub fn read_u24(&mut self) -> Result<u32, OutOfBounds> {
+        let [a, b, c] = self.read_array()?;
+        Ok(self.pick(u32::from_le_bytes([a, b, c, 0]), u32::from_be_bytes([0, a, b, c])))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, OutOfBounds> {
+        let b = self.read_array()?;
+        Ok(self.pick(u32::from_le_bytes(b), u32::from_be_bytes(b)))
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, OutOfBounds> {
+        Ok(self.read_u32()? as i32)
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, OutOfBounds> {
+        let b = self.read_array()?;
+        Ok(self.pick(u64::from_le_bytes(b), u64::from_be_bytes(b)))
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64, OutOfBounds> {
+        Ok(self.read_u64()? as i64)
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32, OutOfBounds> {
+        Ok(f32::from_bits(self.read_u32()?))
+    }
+
+    pub fn read_f64(&mut self) -> Result<f64, OutOfBounds> {
+        Ok(f64::from_bits(self.read_u64()?))
+    }
+
+    // Fixed-point values with `frac_bits` fractional bits, like GX's s16 vertex formats or
+    // 16.16 matrices. `frac_bits` can't be more than the width of the value.
+    pub fn read_fixed_u16(&mut self, frac_bits: u32) -> Result<f32, OutOfBounds> {
+        assert!(frac_bits <= 16, "ByteReader::read_fixed_u16: {} fractional bits", frac_bits);
+        Ok(self.read_u16()? as f32 / (1u32 << frac_bits) as f32)
+    }
+
+    pub fn read_fixed_i16(&mut self, frac_bits: u32) -> Result<f32, OutOfBounds> {
+        assert!(frac_bits <= 16, "ByteReader::read_fixed_i16: {} fractional bits", frac_bits);
+        Ok(self.read_i16()? as f32 / (1u32 << frac_bits) as f32)
+    }
+
+    pub fn read_fixed_i32(&mut self, frac_bits: u32) -> Result<f32, OutOfBounds> {
+        assert!(frac_bits <= 32, "ByteReader::read_fixed_i32: {} fractional bits", frac_bits);
+        Ok((self.read_i32()? as f64 / (1u64 << frac_bits) as f64) as f32)
+    }
+
+    // A fixed-size string field, cut at the first NUL.
+    pub fn read_string(&mut self, len: usize) -> Result<String, OutOfBounds> {
+        let bytes = self.read_bytes(len)?;
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(len);
+        Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    }
+
+    // A NUL-terminated string. The terminator is consumed.
+    pub fn read_cstring(&mut self) -> Result<String, OutOfBounds> {
+        let rest = &self.data[self.pos..];
+        let len = rest.iter().position(|&b| b == 0).ok_or_else(|| self.out_of_bounds(self.pos, rest.len() + 1))?;
+        let s = self.read_string(len)?;
+        self.pos += 1;
+        Ok(s)
+    }
 }
 
 #[wasm_bindgen]
@@ -97,3 +268,129 @@ extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     pub fn log(s: &str);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blend() {
+        assert_eq!(blend_srgb_u8(0, 255, 1, 1), 188);
+        assert_eq!(blend_srgb_u8(64, 64, 3, 1), 64);
+        assert_eq!(blend_linear_u8(0, 255, 1, 1), 127);
+        assert_eq!(blend_linear_i8(-128, 127, 1, 1), 0);
+    }
+
+    #[test]
+    fn test_byte_reader_integers() {
+        let data = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        let mut le = ByteReader::le(&data);
+        assert_eq!(le.read_u8(), Ok(0x01));
+        assert_eq!(le.read_u16(), Ok(0x0302));
+        assert_eq!(le.read_u24(), Ok(0x060504));
+        assert_eq!(le.position(), 6);
+        assert_eq!(le.remaining(), 2);
+
+        let mut be = ByteReader::be(&data);
+        assert_eq!(be.read_u24(), Ok(0x010203));
+        assert_eq!(be.read_u32(), Ok(0x04050607));
+        be.seek(0).unwrap();
+        assert_eq!(be.read_u64(), Ok(0x0102030405060708));
+        le.seek(0).unwrap();
+        assert_eq!(le.read_u64(), Ok(0x0807060504030201));
+        le.seek(0).unwrap();
+        assert_eq!(le.read_u32(), Ok(0x04030201));
+
+        let signed = [0xFF, 0xFE, 0xFF, 0xFF, 0xFF, 0x80];
+        let mut r = ByteReader::be(&signed);
+        assert_eq!(r.read_i8(), Ok(-1));
+        assert_eq!(r.read_i16(), Ok(-257));
+        r.set_endian(Endian::Little);
+        assert_eq!(r.endian(), Endian::Little);
+        assert_eq!(r.read_i16(), Ok(-1));
+        assert_eq!(r.read_i8(), Ok(-128));
+        let ones = [0xFF; 8];
+        assert_eq!(ByteReader::le(&ones).read_i32(), Ok(-1));
+        assert_eq!(ByteReader::le(&ones).read_i64(), Ok(-1));
+    }
+
+    #[test]
+    fn test_byte_reader_floats_and_fixed() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&1.5f32.to_be_bytes());
+        data.extend_from_slice(&(-0.25f64).to_be_bytes());
+        data.extend_from_slice(&(-0x180i16).to_be_bytes());
+        data.extend_from_slice(&0x8000u16.to_be_bytes());
+        data.extend_from_slice(&(-0x18000i32).to_be_bytes());
+        let mut r = ByteReader::be(&data);
+        assert_eq!(r.read_f32(), Ok(1.5));
+        assert_eq!(r.read_f64(), Ok(-0.25));
+        assert_eq!(r.read_fixed_i16(8), Ok(-1.5));
+        assert_eq!(r.read_fixed_u16(15), Ok(1.0));
+        assert_eq!(r.read_fixed_i32(16), Ok(-1.5));
+        assert!(!r.is_empty() && r.remaining() == 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "32 fractional bits")]
+    fn test_byte_reader_fixed_frac_bits() {
+        let _ = ByteReader::be(&[0, 0]).read_fixed_u16(32);
+    }
+
+    #[test]
+    #[should_panic(expected = "alignment must be nonzero")]
+    fn test_byte_reader_align_zero() {
+        let _ = ByteReader::be(&[0, 0]).align(0);
+    }
+
+    #[test]
+    fn test_byte_reader_bounds() {
+        let data = [0x10, 0x20, 0x30];
+        let mut r = ByteReader::le(&data);
+        r.skip(2).unwrap();
+        assert_eq!(r.read_u16(), Err(OutOfBounds { offset: 2, len: 2, size: 3 }));
+        assert_eq!(r.position(), 2);
+        assert_eq!(r.read_u8(), Ok(0x30));
+        assert!(r.read_u8().is_err());
+        assert!(r.skip(1).is_err());
+        assert!(r.seek(3).is_ok());
+        assert!(r.seek(4).is_err());
+        assert!(r.at(4).is_err());
+        assert!(r.peek_bytes(usize::MAX).is_err());
+        assert!(ByteReader::le(&[]).read_u8().is_err());
+
+        let message: String = OutOfBounds { offset: 2, len: 2, size: 3 }.into();
+        assert_eq!(message, "read of 2 bytes at 0x2 is past the end of 0x3 bytes");
+    }
+
+    #[test]
+    fn test_byte_reader_seek_and_peek() {
+        let data = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+        let mut r = ByteReader::be(&data);
+        r.skip(1).unwrap();
+        assert_eq!(r.peek(|r| r.read_u16()), Ok(0x1122));
+        assert_eq!(r.peek(|r| r.read_u64()), Err(OutOfBounds { offset: 1, len: 8, size: 8 }));
+        assert_eq!(r.position(), 1);
+        assert_eq!(r.peek_bytes(2), Ok(&data[1..3]));
+        r.align(4).unwrap();
+        assert_eq!(r.position(), 4);
+        r.align(4).unwrap();
+        assert_eq!(r.position(), 4);
+        assert_eq!(r.at(6).unwrap().read_u16(), Ok(0x6677));
+        assert_eq!(r.read_bytes(2), Ok(&data[4..6]));
+        assert_eq!(r.len(), 8);
+    }
+
+    #[test]
+    fn test_byte_reader_strings() {
+        let data = b"abc\0def\0\0\0gh";
+        let mut r = ByteReader::le(data);
+        assert_eq!(r.read_cstring().unwrap(), "abc");
+        assert_eq!(r.read_string(6).unwrap(), "def");
+        assert_eq!(r.position(), 10);
+        assert_eq!(r.peek(|r| r.read_string(2)).unwrap(), "gh");
+        assert!(r.read_cstring().is_err());
+        assert_eq!(r.position(), 10);
+        assert_eq!(ByteReader::le(b"\xFFz\0").read_cstring().unwrap(), "\u{FFFD}z");
+    }
+}
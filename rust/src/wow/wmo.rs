@@ -220,7 +220,10 @@ impl Wmo {
             }
 
             if portal.in_frustum(frustum) || portal.aabb_contains_point(eye) {
-                let portal_frustum = portal.clip_frustum(eye, frustum);
+                let portal_frustum = match portal.clip_frustum(eye, frustum) {
+                    Some(portal_frustum) => portal_frustum,
+                    None => continue,
+                };
                 let other_group = self.get_group(other_group_id);
                 // if this portal causes us to look outside, save its frustum for culling the outdoors
                 if !group.flags.exterior && other_group.flags.exterior {
@@ -536,30 +539,12 @@ impl PortalData {
         frustum.contains_aabb(&self.aabb)
     }
 
-    fn clip_frustum(&self, eye: &Vec3, frustum: &ConvexHull) -> ConvexHull {
-        let mut result = frustum.clone();
-        for i in 0..self.vertices.len() {
-            let a = &self.vertices[i];
-            let b = if i == self.vertices.len() - 1 {
-                &self.vertices[0]
-            } else {
-                &self.vertices[i + 1]
-            };
-            let test_point = if i == 0 {
-                &self.vertices[self.vertices.len() - 1]
-            } else {
-                &self.vertices[i - 1]
-            };
-
-            let mut plane = Plane::default();
-            plane.set_tri(eye, &a, &b);
-            if plane.distance(&test_point) < 0.0 {
-                plane.negate();
-            }
-            assert!(plane.distance(&test_point) >= 0.0);
-            result.planes.push(plane);
+    fn clip_frustum(&self, eye: &Vec3, frustum: &ConvexHull) -> Option<ConvexHull> {
+        // standing in the portal, it can't narrow down what we see
+        if self.aabb_contains_point(eye) {
+            return Some(frustum.clone());
         }
-        result
+        frustum.clip_to_portal(eye, &self.vertices)
     }
 
     fn aabb_contains_point(&self, p: &Vec3) -> bool {
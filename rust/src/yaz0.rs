@@ -15,68 +15,172 @@
 //           If Length = 0, then read additional byte, add 16, and add it to Length.
 //         Offset: bits 5-15
 //         Copy Length+2 bytes from Offset back in the output buffer.
+//
+// Yay0 is the same scheme with the streams split apart. After the 16-byte header
+// (magic, uncompressed size, offset to the LZ77 stream, offset to the data stream),
+// the flags stream starts at 0x10. LZ77 pairs come from the LZ77 stream, while literals
+// and the extra length bytes come from the data stream.
 
 use wasm_bindgen::prelude::wasm_bindgen;
 
-use std::convert::TryInto;
+use crate::compression::{corrupt, DecompressError};
 
-fn get_u32_be(src: &[u8], i: usize) -> u32 {
-    u32::from_be_bytes(src[i..i+4].try_into().unwrap())
+fn get_u8(src: &[u8], i: usize) -> Result<u8, DecompressError> {
+    src.get(i).copied().ok_or(DecompressError::Truncated)
 }
 
-fn get_u16_be(src: &[u8], i: usize) -> u16 {
-    u16::from_be_bytes(src[i..i+2].try_into().unwrap())
+fn get_u16_be(src: &[u8], i: usize) -> Result<u16, DecompressError> {
+    Ok(((get_u8(src, i)? as u16) << 8) | (get_u8(src, i + 1)? as u16))
 }
 
-#[wasm_bindgen]
-pub fn yaz0dec(src: &[u8]) -> Vec<u8> {
-    let magic = &src[0..4];
+fn get_u32_be(src: &[u8], i: usize) -> Result<u32, DecompressError> {
+    Ok(((get_u16_be(src, i)? as u32) << 16) | (get_u16_be(src, i + 2)? as u32))
+}
+
+// The header size can't be trusted for the allocation; no command expands to more than 0x111 bytes.
+fn alloc_dst(src: &[u8], uncompressed_size: usize) -> Vec<u8> {
+    Vec::with_capacity(uncompressed_size.min(src.len().saturating_mul(0x111)))
+}
+
+fn copy_window(dst: &mut Vec<u8>, tmp: u16, window_length: usize) -> Result<(), DecompressError> {
+    let window_offset = ((tmp & 0x0FFF) + 1) as usize;
+    if window_offset > dst.len() {
+        return corrupt("back-reference before start of data");
+    }
+
+    let copy_offs = dst.len() - window_offset;
+    for i in 0..window_length {
+        dst.push(dst[copy_offs + i]);
+    }
+    Ok(())
+}
 
-    if magic != b"Yaz0" {
-        panic!("bad header instead of good header");
+#[wasm_bindgen]
+pub fn yaz0dec(src: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    if src.get(0..4) != Some(b"Yaz0") {
+        return corrupt("bad Yaz0 header");
     }
 
-    let mut uncompressed_size = get_u32_be(src, 0x04);
-    let mut dst = vec![0x00; uncompressed_size as usize];
+    let uncompressed_size = get_u32_be(src, 0x04)? as usize;
+    let mut dst = alloc_dst(src, uncompressed_size);
 
     let mut src_offs = 0x10;
-    let mut dst_offs = 0x00;
-    loop {
-        let command_byte = src[src_offs];
+    while dst.len() < uncompressed_size {
+        let command_byte = get_u8(src, src_offs)?;
         src_offs += 1;
 
         for i in (0..8).rev() {
+            if dst.len() >= uncompressed_size {
+                break;
+            }
+
             if (command_byte & (1 << i)) != 0 {
                 // Literal.
-                dst[dst_offs] = src[src_offs];
+                dst.push(get_u8(src, src_offs)?);
                 src_offs += 1;
-                dst_offs += 1;
-                uncompressed_size -= 1;
             } else {
-                let tmp = get_u16_be(src, src_offs);
+                let tmp = get_u16_be(src, src_offs)?;
                 src_offs += 2;
 
-                let window_offset = (tmp & 0x0FFF) + 1;
-                let mut window_length = (tmp >> 12) + 2;
+                let mut window_length = ((tmp >> 12) + 2) as usize;
                 if window_length == 2 {
-                    window_length += (src[src_offs] as u16) + 0x10;
+                    window_length += get_u8(src, src_offs)? as usize + 0x10;
                     src_offs += 1;
                 }
 
-                assert!(window_length >= 3 && window_length <= 0x111);
+                copy_window(&mut dst, tmp, window_length)?;
+            }
+        }
+    }
 
-                let mut copy_offs = dst_offs - (window_offset as usize);
-                for _ in 0..window_length {
-                    dst[dst_offs] = dst[copy_offs];
-                    dst_offs += 1;
-                    copy_offs += 1;
-                    uncompressed_size -= 1;
-                }
+    // The last copy may run past the end.
+    dst.truncate(uncompressed_size);
+    Ok(dst)
+}
+
+#[wasm_bindgen]
+pub fn yay0dec(src: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    if src.get(0..4) != Some(b"Yay0") {
+        return corrupt("bad Yay0 header");
+    }
+
+    let uncompressed_size = get_u32_be(src, 0x04)? as usize;
+    let mut lengths_offs = get_u32_be(src, 0x08)? as usize;
+    let mut data_offs = get_u32_be(src, 0x0C)? as usize;
+    let mut flags_offs = 0x10;
+    let mut dst = alloc_dst(src, uncompressed_size);
+
+    while dst.len() < uncompressed_size {
+        let command_byte = get_u8(src, flags_offs)?;
+        flags_offs += 1;
+
+        for i in (0..8).rev() {
+            if dst.len() >= uncompressed_size {
+                break;
             }
 
-            if uncompressed_size <= 0 {
-                return dst
+            if (command_byte & (1 << i)) != 0 {
+                // Literal.
+                dst.push(get_u8(src, data_offs)?);
+                data_offs += 1;
+            } else {
+                let tmp = get_u16_be(src, lengths_offs)?;
+                lengths_offs += 2;
+
+                let mut window_length = ((tmp >> 12) + 2) as usize;
+                if window_length == 2 {
+                    window_length += get_u8(src, data_offs)? as usize + 0x10;
+                    data_offs += 1;
+                }
+
+                copy_window(&mut dst, tmp, window_length)?;
             }
         }
     }
+
+    dst.truncate(uncompressed_size);
+    Ok(dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(magic: &[u8], size: u32, lengths_offs: u32, data_offs: u32) -> Vec<u8> {
+        let mut dst = magic.to_vec();
+        dst.extend_from_slice(&size.to_be_bytes());
+        dst.extend_from_slice(&lengths_offs.to_be_bytes());
+        dst.extend_from_slice(&data_offs.to_be_bytes());
+        dst
+    }
+
+    #[test]
+    fn test_yaz0() {
+        // Three literals and a 9-byte copy.
+        let mut src = header(b"Yaz0", 12, 0, 0);
+        src.extend_from_slice(&[0xE0, b'a', b'b', b'c', 0x70, 0x02]);
+        assert_eq!(yaz0dec(&src).unwrap(), b"abcabcabcabc");
+
+        // A literal and a long run using the extra length byte.
+        let mut src = header(b"Yaz0", 40, 0, 0);
+        src.extend_from_slice(&[0x80, b'a', 0x00, 0x00, 0x15]);
+        assert_eq!(yaz0dec(&src).unwrap(), [b'a'; 40]);
+
+        assert_eq!(yaz0dec(&src[..src.len() - 1]), Err(DecompressError::Truncated));
+        src[0x10] = 0x00;
+        assert!(matches!(yaz0dec(&src), Err(DecompressError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_yay0() {
+        let mut src = header(b"Yay0", 12, 0x14, 0x18);
+        src.extend_from_slice(&[0xE0, 0x00, 0x00, 0x00, 0x70, 0x02, 0x00, 0x00, b'a', b'b', b'c']);
+        assert_eq!(yay0dec(&src).unwrap(), b"abcabcabcabc");
+
+        let mut src = header(b"Yay0", 40, 0x14, 0x18);
+        src.extend_from_slice(&[0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, b'a', 0x15]);
+        assert_eq!(yay0dec(&src).unwrap(), [b'a'; 40]);
+
+        assert_eq!(yay0dec(&src[..src.len() - 1]), Err(DecompressError::Truncated));
+    }
 }
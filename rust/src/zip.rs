@@ -0,0 +1,552 @@
+// ZIP archives, following PKWARE's APPNOTE.TXT.
+// https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+//
+// The reader works from the central directory, so entries written with data descriptors (where
+// the local header has no sizes) are handled the same as any other. ZIP64 extensions are read
+// and written where sizes, offsets or the entry count need them.
+
+use wasm_bindgen::prelude::*;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+
+use crate::compression::{crc32, deflate_raw_compress, deflate_raw_decompress, lzma_compress, lzma_decompress, DecompressError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZipError {
+    // The archive ends before a structure it references.
+    Truncated,
+    // The archive structure is invalid.
+    Corrupt(String),
+    // Valid, but uses a feature we don't implement (encryption, other compression methods).
+    Unsupported(String),
+    // An entry failed to decompress.
+    Decompress(DecompressError),
+}
+
+impl fmt::Display for ZipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZipError::Truncated => write!(f, "ZIP archive is truncated"),
+            ZipError::Corrupt(msg) => write!(f, "ZIP archive is corrupt: {}", msg),
+            ZipError::Unsupported(msg) => write!(f, "unsupported ZIP feature: {}", msg),
+            ZipError::Decompress(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ZipError {}
+
+impl From<DecompressError> for ZipError {
+    fn from(err: DecompressError) -> Self {
+        ZipError::Decompress(err)
+    }
+}
+
+impl From<ZipError> for JsValue {
+    fn from(err: ZipError) -> Self {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
+fn corrupt<T>(msg: &str) -> Result<T, ZipError> {
+    Err(ZipError::Corrupt(msg.to_string()))
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZipMethod {
+    Stored = 0,
+    Deflate = 8,
+    Lzma = 14,
+}
+
+const LOCAL_HEADER_MAGIC: u32 = 0x04034B50;
+const CENTRAL_HEADER_MAGIC: u32 = 0x02014B50;
+const END_MAGIC: u32 = 0x06054B50;
+const ZIP64_END_MAGIC: u32 = 0x06064B50;
+const ZIP64_LOCATOR_MAGIC: u32 = 0x07064B50;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+const FLAG_ENCRYPTED: u16 = 0x0001;
+const FLAG_LZMA_EOS: u16 = 0x0002;
+const FLAG_UTF8: u16 = 0x0800;
+
+const END_SIZE: usize = 0x16;
+const ZIP64_LOCATOR_SIZE: usize = 0x14;
+
+// Bounds-checked little endian reads.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, offs: usize, size: usize) -> Result<&'a [u8], ZipError> {
+        offs.checked_add(size).and_then(|end| self.data.get(offs..end)).ok_or(ZipError::Truncated)
+    }
+
+    fn u16(&self, offs: usize) -> Result<u16, ZipError> {
+        Ok(u16::from_le_bytes(self.bytes(offs, 2)?.try_into().unwrap()))
+    }
+
+    fn u32(&self, offs: usize) -> Result<u32, ZipError> {
+        Ok(u32::from_le_bytes(self.bytes(offs, 4)?.try_into().unwrap()))
+    }
+
+    fn u64(&self, offs: usize) -> Result<u64, ZipError> {
+        Ok(u64::from_le_bytes(self.bytes(offs, 8)?.try_into().unwrap()))
+    }
+}
+
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{A0}";
+
+// Names are UTF-8 if the flag says so, and otherwise code page 437. Plenty of tools write UTF-8
+// without setting the flag, so valid UTF-8 is taken as-is.
+fn decode_name(name: &[u8], flags: u16) -> String {
+    if flags & FLAG_UTF8 != 0 || std::str::from_utf8(name).is_ok() {
+        return String::from_utf8_lossy(name).into_owned();
+    }
+    name.iter().map(|&b| if b < 0x80 { b as char } else { CP437_HIGH.chars().nth(b as usize - 0x80).unwrap() }).collect()
+}
+
+struct ZipEntry {
+    name: String,
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    local_header_offset: u64,
+}
+
+#[wasm_bindgen(js_name = "ZipArchive")]
+pub struct ZipArchive {
+    data: Vec<u8>,
+    entries: Vec<ZipEntry>,
+}
+
+#[wasm_bindgen(js_class = "ZipArchive")]
+impl ZipArchive {
+    pub fn new(data: Vec<u8>) -> Result<ZipArchive, ZipError> {
+        let entries = read_central_directory(&Reader { data: &data })?;
+        Ok(Self { data, entries })
+    }
+
+    pub fn get_num_entries(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get_name(&self, index: usize) -> Option<String> {
+        self.entries.get(index).map(|entry| entry.name.clone())
+    }
+
+    pub fn get_method(&self, index: usize) -> Option<u16> {
+        self.entries.get(index).map(|entry| entry.method)
+    }
+
+    pub fn get_compressed_size(&self, index: usize) -> Option<u64> {
+        self.entries.get(index).map(|entry| entry.compressed_size)
+    }
+
+    pub fn get_uncompressed_size(&self, index: usize) -> Option<u64> {
+        self.entries.get(index).map(|entry| entry.uncompressed_size)
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.name == name)
+    }
+
+    // Decompresses an entry and checks it against its CRC.
+    pub fn read(&self, index: usize) -> Result<Vec<u8>, ZipError> {
+        let entry = self.entries.get(index).ok_or_else(|| ZipError::Corrupt(format!("no entry {}", index)))?;
+        if entry.flags & FLAG_ENCRYPTED != 0 {
+            return Err(ZipError::Unsupported(format!("{} is encrypted", entry.name)));
+        }
+
+        let data = self.raw_data(entry)?;
+        let dst = match entry.method {
+            0 => data.to_vec(),
+            8 => deflate_raw_decompress(data)?,
+            14 => {
+                // LZMA SDK version, properties size, properties, then the raw stream.
+                let reader = Reader { data };
+                if reader.u16(2)? != 5 {
+                    return corrupt("bad LZMA properties size");
+                }
+                let props = reader.bytes(4, 5)?;
+                let (p, dict_size) = (props[0] as u32, u32::from_le_bytes(props[1..5].try_into().unwrap()));
+                if p >= 9 * 5 * 5 {
+                    return corrupt("bad LZMA properties");
+                }
+                lzma_decompress(&data[9..], p % 9, (p / 9) % 5, p / 45, dict_size, Some(entry.uncompressed_size))?
+            },
+            method => return Err(ZipError::Unsupported(format!("compression method {}", method))),
+        };
+
+        if dst.len() as u64 != entry.uncompressed_size {
+            return Err(DecompressError::SizeMismatch { expected: entry.uncompressed_size as usize, actual: dst.len() }.into());
+        }
+        if crc32(0, &dst) != entry.crc {
+            return Err(ZipError::Corrupt(format!("CRC mismatch in {}", entry.name)));
+        }
+        Ok(dst)
+    }
+}
+
+impl ZipArchive {
+    // The entry's data as stored, following its local header.
+    fn raw_data(&self, entry: &ZipEntry) -> Result<&[u8], ZipError> {
+        let reader = Reader { data: &self.data };
+        let offs = usize::try_from(entry.local_header_offset).map_err(|_| ZipError::Truncated)?;
+        if reader.u32(offs)? != LOCAL_HEADER_MAGIC {
+            return corrupt("bad local header magic");
+        }
+        let data_offs = offs + 0x1E + reader.u16(offs + 0x1A)? as usize + reader.u16(offs + 0x1C)? as usize;
+        reader.bytes(data_offs, usize::try_from(entry.compressed_size).map_err(|_| ZipError::Truncated)?)
+    }
+}
+
+fn find_end_record(reader: &Reader) -> Result<usize, ZipError> {
+    let len = reader.data.len();
+    if len < END_SIZE {
+        return Err(ZipError::Truncated);
+    }
+    // The end record is followed by a comment of up to 64KB.
+    let min = len.saturating_sub(END_SIZE + 0xFFFF);
+    (min..=len - END_SIZE).rev()
+        .find(|&offs| reader.u32(offs).ok() == Some(END_MAGIC) && offs + END_SIZE + reader.u16(offs + 0x14).unwrap_or(0) as usize <= len)
+        .ok_or_else(|| ZipError::Corrupt("no end of central directory record".to_string()))
+}
+
+fn read_central_directory(reader: &Reader) -> Result<Vec<ZipEntry>, ZipError> {
+    let end = find_end_record(reader)?;
+    let mut num_entries = reader.u16(end + 0x0A)? as u64;
+    let mut cd_size = reader.u32(end + 0x0C)? as u64;
+    let mut cd_offs = reader.u32(end + 0x10)? as u64;
+
+    if end >= ZIP64_LOCATOR_SIZE && reader.u32(end - ZIP64_LOCATOR_SIZE)? == ZIP64_LOCATOR_MAGIC {
+        let offs = reader.u64(end - ZIP64_LOCATOR_SIZE + 0x08)?;
+        let offs = usize::try_from(offs).map_err(|_| ZipError::Truncated)?;
+        if reader.u32(offs)? != ZIP64_END_MAGIC {
+            return corrupt("bad ZIP64 end of central directory magic");
+        }
+        num_entries = reader.u64(offs + 0x20)?;
+        cd_size = reader.u64(offs + 0x28)?;
+        cd_offs = reader.u64(offs + 0x30)?;
+    }
+
+    let cd_offs = usize::try_from(cd_offs).map_err(|_| ZipError::Truncated)?;
+    let cd_size = usize::try_from(cd_size).map_err(|_| ZipError::Truncated)?;
+    let cd = Reader { data: reader.bytes(cd_offs, cd_size)? };
+    // Each entry takes at least 0x2E bytes, which bounds the allocation for bogus counts.
+    if num_entries > (cd_size / 0x2E) as u64 {
+        return corrupt("central directory is too small for its entries");
+    }
+
+    let mut entries = Vec::with_capacity(num_entries as usize);
+    let mut offs = 0;
+    for _ in 0..num_entries {
+        if cd.u32(offs)? != CENTRAL_HEADER_MAGIC {
+            return corrupt("bad central directory header magic");
+        }
+        let flags = cd.u16(offs + 0x08)?;
+        let name_size = cd.u16(offs + 0x1C)? as usize;
+        let extra_size = cd.u16(offs + 0x1E)? as usize;
+        let comment_size = cd.u16(offs + 0x20)? as usize;
+        let mut entry = ZipEntry {
+            name: decode_name(cd.bytes(offs + 0x2E, name_size)?, flags),
+            flags,
+            method: cd.u16(offs + 0x0A)?,
+            crc: cd.u32(offs + 0x10)?,
+            compressed_size: cd.u32(offs + 0x14)? as u64,
+            uncompressed_size: cd.u32(offs + 0x18)? as u64,
+            local_header_offset: cd.u32(offs + 0x2A)? as u64,
+        };
+
+        // The ZIP64 extra field holds, in order, only the values that overflowed.
+        let extra = Reader { data: cd.bytes(offs + 0x2E + name_size, extra_size)? };
+        let mut extra_offs = 0;
+        while extra_offs + 4 <= extra.data.len() {
+            let id = extra.u16(extra_offs)?;
+            let size = extra.u16(extra_offs + 2)? as usize;
+            if id == ZIP64_EXTRA_ID {
+                let field = Reader { data: extra.bytes(extra_offs + 4, size)? };
+                let mut field_offs = 0;
+                for value in [&mut entry.uncompressed_size, &mut entry.compressed_size, &mut entry.local_header_offset] {
+                    if *value == 0xFFFFFFFF {
+                        *value = field.u64(field_offs)?;
+                        field_offs += 8;
+                    }
+                }
+            }
+            extra_offs += 4 + size;
+        }
+
+        entries.push(entry);
+        offs += 0x2E + name_size + extra_size + comment_size;
+    }
+    Ok(entries)
+}
+
+struct ZipWriterEntry {
+    name: Vec<u8>,
+    method: u16,
+    flags: u16,
+    crc: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    local_header_offset: u64,
+}
+
+impl ZipWriterEntry {
+    fn version_needed(&self, zip64: bool) -> u16 {
+        match self.method {
+            14 => 63,
+            _ if zip64 => 45,
+            8 => 20,
+            _ => 10,
+        }
+    }
+}
+
+// 1980-01-01 00:00, the earliest DOS date.
+const DOS_DATE: u16 = 0x0021;
+const DEFLATE_LEVEL: u32 = 6;
+
+// Writes an archive in one pass. Entries are compressed as they're added, and stored instead
+// if compression doesn't make them any smaller.
+#[wasm_bindgen(js_name = "ZipWriter")]
+pub struct ZipWriter {
+    out: Vec<u8>,
+    entries: Vec<ZipWriterEntry>,
+    // Use ZIP64 records even when nothing overflows.
+    force_zip64: bool,
+}
+
+#[wasm_bindgen(js_class = "ZipWriter")]
+impl ZipWriter {
+    pub fn new() -> Self {
+        Self { out: Vec::new(), entries: Vec::new(), force_zip64: false }
+    }
+
+    pub fn add_file(&mut self, name: &str, data: &[u8], method: ZipMethod) -> Result<(), ZipError> {
+        let mut flags = if name.is_ascii() { 0 } else { FLAG_UTF8 };
+        let compressed = match method {
+            ZipMethod::Stored => None,
+            ZipMethod::Deflate => Some(deflate_raw_compress(data, DEFLATE_LEVEL)),
+            ZipMethod::Lzma => {
+                let dict_size = data.len().clamp(0x1000, 0x800000).next_power_of_two() as u32;
+                // LZMA SDK version 9.4, then the properties: lc=3, lp=0, pb=2 and the dictionary size.
+                let mut stream = vec![9, 4, 5, 0, 93];
+                stream.extend_from_slice(&dict_size.to_le_bytes());
+                stream.extend(lzma_compress(data, 3, 0, 2, dict_size, true).map_err(ZipError::Corrupt)?);
+                flags |= FLAG_LZMA_EOS;
+                Some(stream)
+            },
+        };
+        let (method, payload) = match &compressed {
+            Some(compressed) if compressed.len() < data.len() => (method as u16, &compressed[..]),
+            _ => {
+                flags &= !FLAG_LZMA_EOS;
+                (ZipMethod::Stored as u16, data)
+            },
+        };
+
+        let entry = ZipWriterEntry {
+            name: name.as_bytes().to_vec(),
+            method,
+            flags,
+            crc: crc32(0, data),
+            compressed_size: payload.len() as u64,
+            uncompressed_size: data.len() as u64,
+            local_header_offset: self.out.len() as u64,
+        };
+        if entry.name.len() > 0xFFFF {
+            return Err(ZipError::Unsupported("file name is too long".to_string()));
+        }
+
+        // The local header has to hold both sizes in the ZIP64 field if either overflows.
+        let zip64 = self.force_zip64 || entry.compressed_size >= 0xFFFFFFFF || entry.uncompressed_size >= 0xFFFFFFFF;
+        let out = &mut self.out;
+        out.extend_from_slice(&LOCAL_HEADER_MAGIC.to_le_bytes());
+        out.extend_from_slice(&entry.version_needed(zip64).to_le_bytes());
+        out.extend_from_slice(&entry.flags.to_le_bytes());
+        out.extend_from_slice(&entry.method.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&DOS_DATE.to_le_bytes());
+        out.extend_from_slice(&entry.crc.to_le_bytes());
+        if zip64 {
+            out.extend_from_slice(&[0xFF; 8]);
+        } else {
+            out.extend_from_slice(&(entry.compressed_size as u32).to_le_bytes());
+            out.extend_from_slice(&(entry.uncompressed_size as u32).to_le_bytes());
+        }
+        out.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(if zip64 { 20u16 } else { 0 }).to_le_bytes());
+        out.extend_from_slice(&entry.name);
+        if zip64 {
+            out.extend_from_slice(&ZIP64_EXTRA_ID.to_le_bytes());
+            out.extend_from_slice(&16u16.to_le_bytes());
+            out.extend_from_slice(&entry.uncompressed_size.to_le_bytes());
+            out.extend_from_slice(&entry.compressed_size.to_le_bytes());
+        }
+        out.extend_from_slice(payload);
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let force_zip64 = self.force_zip64;
+        let mut out = self.out;
+        let cd_offs = out.len() as u64;
+        for entry in &self.entries {
+            let overflow = |v: u64| force_zip64 || v >= 0xFFFFFFFF;
+            let zip64_values: Vec<u64> = [entry.uncompressed_size, entry.compressed_size, entry.local_header_offset].iter().copied().filter(|&v| overflow(v)).collect();
+            let field = |v: u64| if overflow(v) { 0xFFFFFFFF } else { v as u32 };
+            let version = entry.version_needed(!zip64_values.is_empty());
+
+            out.extend_from_slice(&CENTRAL_HEADER_MAGIC.to_le_bytes());
+            out.extend_from_slice(&version.to_le_bytes());
+            out.extend_from_slice(&version.to_le_bytes());
+            out.extend_from_slice(&entry.flags.to_le_bytes());
+            out.extend_from_slice(&entry.method.to_le_bytes());
+            out.extend_from_slice(&0u16.to_le_bytes());
+            out.extend_from_slice(&DOS_DATE.to_le_bytes());
+            out.extend_from_slice(&entry.crc.to_le_bytes());
+            out.extend_from_slice(&field(entry.compressed_size).to_le_bytes());
+            out.extend_from_slice(&field(entry.uncompressed_size).to_le_bytes());
+            out.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            let extra_size = if zip64_values.is_empty() { 0 } else { 4 + zip64_values.len() * 8 };
+            out.extend_from_slice(&(extra_size as u16).to_le_bytes());
+            // Comment size, disk number, internal and external attributes.
+            out.extend_from_slice(&[0; 10]);
+            out.extend_from_slice(&field(entry.local_header_offset).to_le_bytes());
+            out.extend_from_slice(&entry.name);
+            if !zip64_values.is_empty() {
+                out.extend_from_slice(&ZIP64_EXTRA_ID.to_le_bytes());
+                out.extend_from_slice(&((zip64_values.len() * 8) as u16).to_le_bytes());
+                for value in zip64_values {
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+
+        let cd_size = out.len() as u64 - cd_offs;
+        let num_entries = self.entries.len() as u64;
+        let zip64 = force_zip64 || num_entries >= 0xFFFF || cd_size >= 0xFFFFFFFF || cd_offs >= 0xFFFFFFFF;
+        if zip64 {
+            let zip64_end_offs = out.len() as u64;
+            out.extend_from_slice(&ZIP64_END_MAGIC.to_le_bytes());
+            out.extend_from_slice(&44u64.to_le_bytes());
+            out.extend_from_slice(&45u16.to_le_bytes());
+            out.extend_from_slice(&45u16.to_le_bytes());
+            out.extend_from_slice(&[0; 8]);
+            out.extend_from_slice(&num_entries.to_le_bytes());
+            out.extend_from_slice(&num_entries.to_le_bytes());
+            out.extend_from_slice(&cd_size.to_le_bytes());
+            out.extend_from_slice(&cd_offs.to_le_bytes());
+
+            out.extend_from_slice(&ZIP64_LOCATOR_MAGIC.to_le_bytes());
+            out.extend_from_slice(&0u32.to_le_bytes());
+            out.extend_from_slice(&zip64_end_offs.to_le_bytes());
+            out.extend_from_slice(&1u32.to_le_bytes());
+        }
+
+        let count = if zip64 { 0xFFFF } else { num_entries as u16 };
+        out.extend_from_slice(&END_MAGIC.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&(if zip64 { 0xFFFFFFFF } else { cd_size as u32 }).to_le_bytes());
+        out.extend_from_slice(&(if zip64 { 0xFFFFFFFF } else { cd_offs as u32 }).to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out
+    }
+}
+
+impl Default for ZipWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Written by Python's zipfile to an unseekable stream: a deflated entry with a data
+    // descriptor, and a ZIP64 extra field in the local header.
+    const DATA_DESCRIPTOR_ZIP: [u8; 177] = [80, 75, 3, 4, 45, 0, 8, 0, 8, 0, 0, 0, 33, 0, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 9, 0, 20, 0, 104, 101, 108, 108, 111, 46, 116, 120, 116, 1, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 243, 72, 205, 201, 201, 215, 81, 168, 202, 44, 80, 84, 240, 32, 130, 13, 0, 80, 75, 7, 8, 235, 93, 243, 19, 17, 0, 0, 0, 0, 0, 0, 0, 48, 0, 0, 0, 0, 0, 0, 0, 80, 75, 1, 2, 45, 3, 45, 0, 8, 0, 8, 0, 0, 0, 33, 0, 235, 93, 243, 19, 17, 0, 0, 0, 48, 0, 0, 0, 9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 128, 1, 0, 0, 0, 0, 104, 101, 108, 108, 111, 46, 116, 120, 116, 80, 75, 5, 6, 0, 0, 0, 0, 1, 0, 1, 0, 55, 0, 0, 0, 100, 0, 0, 0, 0, 0];
+
+    fn write_test_archive(force_zip64: bool) -> (Vec<u8>, Vec<(&'static str, Vec<u8>)>) {
+        let mut state = 0x12345678u32;
+        let noise: Vec<u8> = (0..5000).map(|_| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 24) as u8
+        }).collect();
+        let files = vec![
+            ("stored.txt", b"Stored as-is".to_vec()),
+            ("textures/deflate.bin", b"Super Mario Galaxy ".repeat(200)),
+            ("models/lzma.bin", b"Comet Observatory ".repeat(300)),
+            ("noise.bin", noise),
+            ("empty", Vec::new()),
+            ("\u{30AE}\u{30E3}\u{30E9}\u{30AF}\u{30B7}\u{30FC}.txt", b"Galaxy".to_vec()),
+        ];
+        let methods = [ZipMethod::Stored, ZipMethod::Deflate, ZipMethod::Lzma, ZipMethod::Deflate, ZipMethod::Lzma, ZipMethod::Deflate];
+
+        let mut writer = ZipWriter::new();
+        writer.force_zip64 = force_zip64;
+        for ((name, data), &method) in files.iter().zip(&methods) {
+            writer.add_file(name, data, method).unwrap();
+        }
+        (writer.finish(), files)
+    }
+
+    #[test]
+    fn test_read_data_descriptor() {
+        let archive = ZipArchive::new(DATA_DESCRIPTOR_ZIP.to_vec()).unwrap();
+        assert_eq!(archive.get_num_entries(), 1);
+        assert_eq!(archive.find("hello.txt"), Some(0));
+        assert_eq!(archive.get_method(0), Some(ZipMethod::Deflate as u16));
+        assert_eq!(archive.read(0).unwrap(), b"Hello, zip! ".repeat(4));
+
+        let mut bad = DATA_DESCRIPTOR_ZIP;
+        bad[60] ^= 1;
+        assert!(ZipArchive::new(bad.to_vec()).unwrap().read(0).is_err());
+        for len in 0..DATA_DESCRIPTOR_ZIP.len() {
+            assert!(ZipArchive::new(DATA_DESCRIPTOR_ZIP[..len].to_vec()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for force_zip64 in [false, true] {
+            let (zip, files) = write_test_archive(force_zip64);
+            let archive = ZipArchive::new(zip).unwrap();
+            assert_eq!(archive.get_num_entries(), files.len());
+            for (i, (name, data)) in files.iter().enumerate() {
+                assert_eq!(archive.get_name(i).as_deref(), Some(*name));
+                assert_eq!(archive.get_uncompressed_size(i), Some(data.len() as u64));
+                assert_eq!(archive.read(i).unwrap(), *data);
+            }
+
+            // Compressible entries use the method asked for, and the rest fall back to stored.
+            let methods: Vec<u16> = (0..files.len()).map(|i| archive.get_method(i).unwrap()).collect();
+            assert_eq!(methods, [0, 8, 14, 0, 0, 0]);
+            assert_eq!(archive.get_name(files.len()), None);
+        }
+
+        // Flipping a byte of compressed data fails the CRC check or the decoder.
+        let (mut zip, _) = write_test_archive(false);
+        let archive = ZipArchive::new(zip.clone()).unwrap();
+        let offs = archive.entries[1].local_header_offset as usize + 0x1E + "textures/deflate.bin".len() + 10;
+        zip[offs] ^= 0x10;
+        assert!(ZipArchive::new(zip).unwrap().read(1).is_err());
+    }
+
+    #[test]
+    fn test_cp437_names() {
+        assert_eq!(decode_name(b"caf\x82.txt", 0), "caf\u{E9}.txt");
+        assert_eq!(decode_name("caf\u{E9}.txt".as_bytes(), FLAG_UTF8), "caf\u{E9}.txt");
+        assert_eq!(CP437_HIGH.chars().count(), 128);
+    }
+}
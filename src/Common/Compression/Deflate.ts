@@ -11,3 +11,8 @@ export function decompress_raw(srcBuffer: ArrayBufferSlice): ArrayBufferSlice {
     return ArrayBufferSlice.fromView(bufView);
 }
 
+
+export function decompress_gzip(srcBuffer: ArrayBufferSlice): ArrayBufferSlice {
+    const bufView = rust.gzip_decompress(srcBuffer.createTypedArray(Uint8Array));
+    return ArrayBufferSlice.fromView(bufView);
+}
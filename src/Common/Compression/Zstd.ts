@@ -0,0 +1,12 @@
+import ArrayBufferSlice from '../../ArrayBufferSlice.js';
+import { rust } from '../../rustlib.js';
+
+export function decompress(srcBuffer: ArrayBufferSlice, dictionary: rust.ZstdDictionary | null = null): ArrayBufferSlice {
+    const src = srcBuffer.createTypedArray(Uint8Array);
+    const bufView = dictionary !== null ? rust.zstd_decompress_with_dictionary(src, dictionary) : rust.zstd_decompress(src);
+    return ArrayBufferSlice.fromView(bufView);
+}
+
+export function loadDictionary(buffer: ArrayBufferSlice): rust.ZstdDictionary {
+    return rust.ZstdDictionary.new(buffer.createTypedArray(Uint8Array));
+}
@@ -2,7 +2,7 @@
 import { vec3, ReadonlyVec3, ReadonlyMat4, vec4, ReadonlyVec4, mat4 } from "gl-matrix";
 import { GfxClipSpaceNearZ } from "./gfx/platform/GfxPlatform.js";
 import { nArray } from "./util.js";
-import type { ConvexHull } from "../rust/pkg/noclip_support";
+import type { Bvh, ConvexHull } from "../rust/pkg/noclip_support";
 import { rust } from "./rustlib.js";
 import { getMatrixTranslation, vec3SetAll } from "./MathHelpers.js";
 
@@ -309,6 +309,7 @@ export enum IntersectionState {
 }
 
 export class Frustum {
+    private static scratchAABB = new Float32Array(6);
     private convexHull: ConvexHull;
 
     constructor(convexHull?: ConvexHull) {
@@ -316,22 +317,8 @@ export class Frustum {
     }
 
     public updateClipFrustum(m: ReadonlyMat4, clipSpaceNearZ: GfxClipSpaceNearZ): void {
-        // http://www8.cs.umu.se/kurser/5DV051/HT12/lab/plane_extraction.pdf
-
-        const h = this.convexHull;
-        h.clear();
-        h.push_plane(m[3] + m[0], m[7] + m[4], m[11] + m[8], m[15] + m[12]); // Left
-        h.push_plane(m[3] + m[1], m[7] + m[5], m[11] + m[9], m[15] + m[13]); // Top
-        h.push_plane(m[3] - m[0], m[7] - m[4], m[11] - m[8], m[15] - m[12]); // Right
-        h.push_plane(m[3] - m[1], m[7] - m[5], m[11] - m[9], m[15] - m[13]); // Bottom
-
-        if (clipSpaceNearZ === GfxClipSpaceNearZ.NegativeOne) {
-            h.push_plane(m[3] + m[2], m[7] + m[6], m[11] + m[10], m[15] + m[14]); // Near
-        } else if (clipSpaceNearZ === GfxClipSpaceNearZ.Zero) {
-            h.push_plane(m[2], m[6], m[10], m[14]); // Near
-        }
-
-        h.push_plane(m[3] - m[2], m[7] - m[6], m[11] - m[10], m[15] - m[14]); // Far
+        const nearZ = clipSpaceNearZ === GfxClipSpaceNearZ.Zero ? rust.ClipSpaceNearZ.Zero : rust.ClipSpaceNearZ.NegativeOne;
+        this.convexHull.js_set_view_projection(m as Float32Array, nearZ);
     }
 
     public copy(o: Frustum): void {
@@ -347,6 +334,15 @@ export class Frustum {
         return this.convexHull.js_intersect_aabb(aabb.min[0], aabb.min[1], aabb.min[2], aabb.max[0], aabb.max[1], aabb.max[2]);
     }
 
+    // Tests a model-space AABB under a model matrix as an oriented box, which stays tight
+    // when the model is rotated, unlike transforming the AABB.
+    public intersectTransformed(aabb: AABB, m: ReadonlyMat4): IntersectionState {
+        const v = Frustum.scratchAABB;
+        v[0] = aabb.min[0]; v[1] = aabb.min[1]; v[2] = aabb.min[2];
+        v[3] = aabb.max[0]; v[4] = aabb.max[1]; v[5] = aabb.max[2];
+        return this.convexHull.js_intersect_transformed_aabb(m as Float32Array, v);
+    }
+
     public contains(aabb: AABB): boolean {
         return this.convexHull.js_contains_aabb(aabb.min[0], aabb.min[1], aabb.min[2], aabb.max[0], aabb.max[1], aabb.max[2]);
     }
@@ -367,6 +363,11 @@ export class Frustum {
     public getRust(): ConvexHull {
         return this.convexHull;
     }
+
+    // Returns the IDs of every entry in the BVH that is at least partially visible.
+    public cullBvh(bvh: Bvh): Uint32Array {
+        return bvh.js_cull(this.convexHull);
+    }
 }
 
 /**
@@ -4,8 +4,7 @@ import ArrayBufferSlice from "./ArrayBufferSlice.js";
 import { GfxDevice } from "./gfx/platform/GfxPlatform.js";
 import { readString, flatten } from "./util.js";
 
-import * as Yaz0 from './Common/Compression/Yaz0.js';
-import * as CX from './Common/Compression/CX.js';
+import { rust } from './rustlib.js';
 
 import * as J3D from './j3d/scenes.js';
 import * as JPAExplorer from './InteractiveExamples/JPAExplorer.js';
@@ -36,13 +35,12 @@ function loadFileAsPromise(file: File, dataFetcher: DataFetcher): Promise<NamedA
 }
 
 export function decompressArbitraryFile(buffer: ArrayBufferSlice): ArrayBufferSlice {
-    const magic = readString(buffer, 0x00, 0x04);
-    if (magic === 'Yaz0')
-        return Yaz0.decompress(buffer);
-    else if (magic.charCodeAt(0) === 0x10 || magic.charCodeAt(0) === 0x11)
-        return CX.decompress(buffer);
-    else
+    const result = rust.auto_decompress(buffer.createTypedArray(Uint8Array));
+    if (result.get_format() === rust.CompressionFormat.None) {
+        result.free();
         return buffer;
+    }
+    return ArrayBufferSlice.fromView(result.into_data());
 }
 
 async function loadArbitraryFile(context: SceneContext, buffer: ArrayBufferSlice): Promise<SceneGfx> {
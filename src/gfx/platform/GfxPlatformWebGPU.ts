@@ -1188,6 +1188,11 @@ class GfxImplP_WebGPU implements GfxSwapChain, GfxDevice {
             code = this.glsl_compile(sourceText, shaderStage, validationEnabled);
         } catch (e) {
             console.error(prependLineNo(sourceText));
+            // Anything but a list of diagnostics (such as a panic in the compiler) is passed on as is.
+            if (!Array.isArray(e))
+                throw e;
+            for (const diagnostic of e as { place: string, message: string, line?: number, column?: number, chain: string[] }[])
+                console.error(`${shaderStage} ${diagnostic.place}: ${diagnostic.message}`, diagnostic.line !== undefined ? `(at line ${diagnostic.line}:${diagnostic.column})` : '', ...diagnostic.chain);
             throw "whoops";
         }
 